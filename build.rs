@@ -58,6 +58,29 @@ fn main() {
     for file in FILES.iter() {
         println!("cargo:rerun-if-changed={}", file);
     }
+    build_info();
+}
+
+/// Passes the git commit and build time on to the `/cynthia/build` endpoint.
+fn build_info() {
+    let commit = std::process::Command::new("git")
+        .args(["rev-parse", "--short", "HEAD"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string())
+        .filter(|hash| !hash.is_empty())
+        .unwrap_or_else(|| String::from("unknown"));
+    let timestamp = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    println!("cargo:rustc-env=CYNTHIA_GIT_COMMIT={}", commit);
+    println!("cargo:rustc-env=CYNTHIA_BUILD_TIMESTAMP={}", timestamp);
+    if std::path::Path::new(".git/HEAD").exists() {
+        println!("cargo:rerun-if-changed=.git/HEAD");
+        println!("cargo:rerun-if-changed=.git/refs");
+    }
 }
 #[cfg(feature = "js_runtime")]
 fn exec_runx(args: &[&str], if_fails: &str) {
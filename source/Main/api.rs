@@ -0,0 +1,80 @@
+/*
+ * Copyright (c) 2024, MLC 'Strawmelonjuice' Bloeiman
+ *
+ * Licensed under the GNU AFFERO GENERAL PUBLIC LICENSE Version 3, see the LICENSE file for more information.
+ */
+//! Errors for clients that asked for JSON, answered as JSON instead of an HTML error page:
+//! `{ "error": { "code": "not_found", "message": "..." } }`.
+use std::fmt;
+
+use actix_web::http::StatusCode;
+use actix_web::{HttpResponse, ResponseError};
+use serde_json::json;
+
+#[derive(Debug)]
+pub(crate) enum ApiError {
+    NotFound(String),
+    BadRequest(String),
+    Unauthorized(String),
+    MethodNotAllowed(String),
+    Internal(String),
+}
+
+impl ApiError {
+    /// A stable name for the kind of error, for clients to match on.
+    fn code(&self) -> &'static str {
+        match self {
+            ApiError::NotFound(_) => "not_found",
+            ApiError::BadRequest(_) => "bad_request",
+            ApiError::Unauthorized(_) => "unauthorized",
+            ApiError::MethodNotAllowed(_) => "method_not_allowed",
+            ApiError::Internal(_) => "internal",
+        }
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ApiError::NotFound(message)
+            | ApiError::BadRequest(message)
+            | ApiError::Unauthorized(message)
+            | ApiError::MethodNotAllowed(message)
+            | ApiError::Internal(message) => f.write_str(message),
+        }
+    }
+}
+
+impl ResponseError for ApiError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ApiError::NotFound(_) => StatusCode::NOT_FOUND,
+            ApiError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            ApiError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            ApiError::MethodNotAllowed(_) => StatusCode::METHOD_NOT_ALLOWED,
+            ApiError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let mut response = HttpResponse::build(self.status_code());
+        match self {
+            ApiError::Unauthorized(_) => {
+                response.append_header(("WWW-Authenticate", "Bearer"));
+            }
+            ApiError::MethodNotAllowed(_) => {
+                response.append_header(("Allow", crate::requestresponse::ALLOWED_METHODS));
+            }
+            _ => {}
+        }
+        response
+            // The same URL answers with HTML for browsers.
+            .append_header(("Vary", "Accept"))
+            .json(json!({
+                "error": {
+                    "code": self.code(),
+                    "message": self.to_string(),
+                }
+            }))
+    }
+}
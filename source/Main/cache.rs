@@ -5,11 +5,13 @@
  */
 use std::fs;
 use std::path::PathBuf;
+use std::sync::Arc;
 use std::time::{SystemTime, UNIX_EPOCH};
 
 use log::{debug, trace};
 use normalize_path::NormalizePath;
 
+use crate::publications::{CynthiaPublicationList, LIST_CACHE_KEY};
 use crate::ServerContext;
 
 pub(super) type CynthiaCache = Vec<CynthiaCacheObject>;
@@ -20,7 +22,7 @@ pub(super) struct CynthiaCacheObject {
     timestamp: (u64, u64),
 }
 #[derive(Debug, Clone)]
-pub(crate) struct CynthiaCacheExtraction(pub(crate) Vec<u8>, #[allow(dead_code)] pub(crate) u64);
+pub(crate) struct CynthiaCacheExtraction(pub(crate) Vec<u8>, pub(crate) u64);
 impl ServerContext {
     pub(crate) fn store_cache(
         &mut self,
@@ -93,8 +95,37 @@ impl ServerContext {
             .retain(|x| x.timestamp.1 > now || x.timestamp.1 == 0);
         debug!("Total cache size: {} bytes", self.estimate_cache_size());
     }
+    /// The publication list kept by `CynthiaPublicationListTrait::load`, unless it was read more
+    /// than `max_age` seconds ago (`0` being no limit).
+    pub(crate) fn get_published(&self, max_age: u64) -> Option<Arc<CynthiaPublicationList>> {
+        let (list, read) = self.published.as_ref()?;
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        (max_age == 0 || now.saturating_sub(*read) < max_age).then(|| list.clone())
+    }
+    pub(crate) fn store_published(&mut self, list: Arc<CynthiaPublicationList>) {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        self.published = Some((list, now));
+    }
+    /// Drops every cache entry for which `affected` returns true, keeping the rest warm. The
+    /// publication list goes with them when [`LIST_CACHE_KEY`] does.
+    /// Returns how many entries were invalidated and how many were retained.
+    pub(crate) fn invalidate_cache(&mut self, affected: impl Fn(&str) -> bool) -> (usize, usize) {
+        if affected(LIST_CACHE_KEY) {
+            self.published = None;
+        }
+        let before = self.cache.len();
+        self.cache.retain(|x| !affected(&x.id));
+        (before - self.cache.len(), self.cache.len())
+    }
     pub fn clear_cache(&mut self) {
         self.cache.clear();
+        self.published = None;
     }
     pub fn estimate_cache_size(&self) -> usize {
         self.cache.iter().map(|x| x.content.len()).sum()
@@ -1,7 +1,14 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
 use log::{error, info, warn};
 use serde::{Deserialize, Serialize};
 use serde_dhall::StaticType;
 
+use crate::publications::{ContentType, CynthiaPublication};
+#[cfg(feature = "js_runtime")]
+use crate::tell::CynthiaColors;
+
 #[derive(Debug, PartialEq, Serialize, Deserialize, StaticType)]
 // #[serde(rename_all = "camelCase")]
 pub(crate) struct CynthiaConf {
@@ -31,6 +38,86 @@ pub(crate) struct CynthiaConf {
     pub(crate) scenes: SceneCollection,
     #[serde(default = "c_plugins")]
     pub(crate) plugins: Vec<Plugin>,
+    #[serde(alias = "plugin-options")]
+    #[serde(default)]
+    pub(crate) plugin_options: PluginOptions,
+    /// Seconds a single page render may take before a `503` is returned instead, `0` disables the deadline.
+    #[serde(alias = "render-timeout")]
+    #[serde(default = "c_render_timeout")]
+    pub(crate) render_timeout: u64,
+    /// How to treat a trailing slash in page URLs, see [`TrailingSlash`].
+    #[serde(alias = "trailing-slash")]
+    #[serde(default)]
+    pub(crate) trailing_slash: TrailingSlash,
+    /// Security headers added to every response, see [`Security`].
+    #[serde(default)]
+    pub(crate) security: Security,
+    /// Number of worker threads serving requests, defaults to one per CPU core.
+    #[serde(default)]
+    pub(crate) workers: Option<u16>,
+    /// Debugging aids for operators, see [`DebugOptions`].
+    #[serde(default)]
+    pub(crate) debug: DebugOptions,
+    /// Directories served as they are under their own URL prefix, see [`StaticMount`].
+    #[serde(alias = "static-mounts")]
+    #[serde(default)]
+    pub(crate) static_mounts: Vec<StaticMount>,
+    /// How connections are handled, see [`ServerOptions`].
+    #[serde(default)]
+    pub(crate) server: ServerOptions,
+    /// A directory of pre-rendered pages, served instead of rendering while they are up to date, see [`crate::renders::prerendered_page`].
+    #[serde(alias = "prerender-dir")]
+    #[serde(default)]
+    pub(crate) prerender_dir: Option<String>,
+    /// The IANA name of the timezone dates are shown in, like `Europe/Amsterdam`, see [`timezone`].
+    #[serde(default = "c_timezone")]
+    pub(crate) timezone: String,
+    /// The character set rendered pages are sent in, see [`charset`].
+    #[serde(default = "c_charset")]
+    pub(crate) charset: String,
+    /// Answers page requests with a maintenance page while enabled, see [`crate::requestresponse::maintenance`].
+    #[serde(default)]
+    pub(crate) maintenance: MaintenanceOptions,
+    /// The directory the site lives in, relative to the configuration file. Cynthia moves into it on start,
+    /// so `cynthiaFiles`, `cynthiaPlugins` and every relative path in the configuration are found from there.
+    #[serde(alias = "root-dir")]
+    #[serde(default = "c_root_dir")]
+    pub(crate) root_dir: String,
+    /// Placeholders for publications whose local content file is missing, see [`MissingContent`].
+    #[serde(alias = "missing-content")]
+    #[serde(default)]
+    pub(crate) missing_content: MissingContent,
+    /// Peers whose `X-Forwarded-For`, `-Proto` and `-Host` headers are believed, by address or range.
+    #[serde(alias = "trusted-proxies")]
+    #[serde(default = "c_trusted_proxies")]
+    pub(crate) trusted_proxies: Vec<String>,
+    /// Files and inline content served at `/.well-known/{name}`, ahead of plugins and publications.
+    #[serde(alias = "well-known")]
+    #[serde(default)]
+    pub(crate) well_known: HashMap<String, WellKnown>,
+    /// Endpoints for other systems to notify Cynthia through, see [`Webhooks`].
+    #[serde(default)]
+    pub(crate) webhooks: Webhooks,
+    /// The id of the publication served at `/`, instead of the page with the id `root`.
+    #[serde(default)]
+    pub(crate) home: Option<String>,
+    /// Site-wide values for templates, like social links or a copyright line, as `{{site.name}}`.
+    #[serde(alias = "site-context")]
+    #[serde(default)]
+    pub(crate) site_context: HashMap<String, String>,
+    /// Counting how often each publication is visited, see [`VisitOptions`].
+    #[serde(default)]
+    pub(crate) visits: VisitOptions,
+    /// Smaller copies of the images pages show, see [`ImageOptions`].
+    #[serde(default)]
+    pub(crate) images: ImageOptions,
+    /// Cleaning up the HTML of publications, see [`Sanitize`].
+    #[serde(default)]
+    pub(crate) sanitize: Sanitize,
+    /// The site's own pages for errors, see [`ErrorPages`].
+    #[serde(alias = "error-pages")]
+    #[serde(default)]
+    pub(crate) error_pages: ErrorPages,
 }
 
 impl Default for CynthiaConf {
@@ -43,9 +130,300 @@ impl Default for CynthiaConf {
             scenes: c_emptyscenelist(),
             runtimes: Runtimes::default(),
             plugins: c_plugins(),
+            plugin_options: PluginOptions::default(),
+            render_timeout: c_render_timeout(),
+            trailing_slash: TrailingSlash::default(),
+            security: Security::default(),
+            workers: None,
+            debug: DebugOptions::default(),
+            static_mounts: vec![],
+            server: ServerOptions::default(),
+            prerender_dir: None,
+            timezone: c_timezone(),
+            charset: c_charset(),
+            maintenance: MaintenanceOptions::default(),
+            root_dir: c_root_dir(),
+            missing_content: MissingContent::default(),
+            trusted_proxies: c_trusted_proxies(),
+            well_known: HashMap::new(),
+            site_context: HashMap::new(),
+            webhooks: Webhooks::default(),
+            home: None,
+            visits: VisitOptions::default(),
+            images: ImageOptions::default(),
+            sanitize: Sanitize::default(),
+            error_pages: ErrorPages::default(),
+        }
+    }
+}
+
+/// Debugging aids for live instances, all off by default.
+#[derive(Clone, Default, PartialEq, Serialize, Deserialize, StaticType)]
+pub(crate) struct DebugOptions {
+    /// A token that, when set, enables `/cynthia/logs`. Requests need an `Authorization: Bearer {token}` header.
+    #[serde(alias = "log-endpoint")]
+    #[serde(default)]
+    pub(crate) log_endpoint: Option<String>,
+    /// Adds `X-Cynthia-Render-Time` and `X-Cynthia-Plugin-Time` headers, in milliseconds, to every response.
+    #[serde(alias = "timing-header")]
+    #[serde(default)]
+    pub(crate) timing_header: bool,
+}
+// Written by hand so the token doesn't end up in the logs along with the rest of the configuration.
+impl std::fmt::Debug for DebugOptions {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("DebugOptions")
+            .field(
+                "log_endpoint",
+                &self.log_endpoint.as_ref().map(|_| "<redacted>"),
+            )
+            .field("timing_header", &self.timing_header)
+            .finish()
+    }
+}
+
+/// Endpoints for other systems, like a CMS that pushes content, to notify Cynthia through. All off by default.
+#[derive(Clone, Default, PartialEq, Serialize, Deserialize, StaticType)]
+pub(crate) struct Webhooks {
+    /// A shared secret that, when set, enables `POST /cynthia/reload`. Requests need an
+    /// `Authorization: Bearer {secret}` header.
+    #[serde(default)]
+    pub(crate) reload: Option<String>,
+}
+// Written by hand so the secret doesn't end up in the logs along with the rest of the configuration.
+impl std::fmt::Debug for Webhooks {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Webhooks")
+            .field("reload", &self.reload.as_ref().map(|_| "<redacted>"))
+            .finish()
+    }
+}
+
+/// Counting how often each publication is visited, off by default.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, StaticType)]
+pub(crate) struct VisitOptions {
+    /// Counts page visits, served at `/api/stats` and to templates as `meta.visits`.
+    #[serde(default)]
+    pub(crate) enabled: bool,
+    /// Where the counts are kept between runs, relative to the site's directory. Counts are only kept
+    /// in memory when this is empty.
+    #[serde(default = "c_visits_file")]
+    pub(crate) file: String,
+    /// Seconds between writing the counts to `file`, `0` only writes them when Cynthia stops.
+    #[serde(alias = "flush-interval")]
+    #[serde(default = "c_visits_flush_interval")]
+    pub(crate) flush_interval: u64,
+}
+impl Default for VisitOptions {
+    fn default() -> Self {
+        VisitOptions {
+            enabled: false,
+            file: c_visits_file(),
+            flush_interval: c_visits_flush_interval(),
+        }
+    }
+}
+fn c_visits_file() -> String {
+    String::from("visits.json")
+}
+fn c_visits_flush_interval() -> u64 {
+    60
+}
+
+/// Templates for the pages Cynthia shows when something goes wrong, instead of its plain built-in ones.
+/// Named like scene templates, they're found in `cynthiaFiles/templates/error/`.
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize, StaticType)]
+pub(crate) struct ErrorPages {
+    /// The template for `500 Internal Server Error` pages, shown when a page fails to render.
+    #[serde(alias = "server-error")]
+    #[serde(default)]
+    pub(crate) server_error: Option<String>,
+}
+
+/// Smaller copies of the local images pages show, made with ImageMagick. Off by default.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, StaticType)]
+pub(crate) struct ImageOptions {
+    /// Rewrites `<img>` tags for local JPEG and PNG images into `<picture>` tags offering the copies.
+    #[serde(default)]
+    pub(crate) enabled: bool,
+    /// The formats to make copies in, `avif` and `webp`, the one browsers should prefer first.
+    #[serde(default = "c_image_formats")]
+    pub(crate) formats: Vec<String>,
+    /// Copies are scaled down to at most this many pixels wide, `0` keeps their size.
+    #[serde(alias = "max-width")]
+    #[serde(default = "c_image_max_width")]
+    pub(crate) max_width: u16,
+    /// Also makes copies 480 and 960 pixels wide, offered with their widths so browsers pick the one the page needs.
+    #[serde(default)]
+    pub(crate) responsive: bool,
+    /// From 1 to 100, higher is larger and looks better.
+    #[serde(default = "c_image_quality")]
+    pub(crate) quality: u16,
+    /// The ImageMagick command, `magick` for version 7 or `convert` for version 6.
+    #[serde(default = "c_image_converter")]
+    pub(crate) converter: String,
+    /// Where the copies are kept, relative to the site's directory. Named after their source, so they're made once.
+    #[serde(alias = "cache-dir")]
+    #[serde(default = "c_image_cache_dir")]
+    pub(crate) cache_dir: String,
+}
+impl Default for ImageOptions {
+    fn default() -> Self {
+        ImageOptions {
+            enabled: false,
+            formats: c_image_formats(),
+            max_width: c_image_max_width(),
+            responsive: false,
+            quality: c_image_quality(),
+            converter: c_image_converter(),
+            cache_dir: c_image_cache_dir(),
+        }
+    }
+}
+/// Strips what's not allowed from the HTML of publications, for sites with content from authors who
+/// shouldn't run scripts on them. Off by default, for sites where every author is trusted.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, StaticType)]
+pub(crate) struct Sanitize {
+    #[serde(default)]
+    pub(crate) enabled: bool,
+    /// The tags content may use, empty for ammonia's list of harmless ones.
+    #[serde(default)]
+    pub(crate) tags: Vec<String>,
+    /// The attributes content may use on every tag, empty for `lang` and `title`. Links, images and
+    /// other tags keep the attributes they need, like `href` and `src`, either way.
+    #[serde(default)]
+    pub(crate) attributes: Vec<String>,
+}
+fn c_image_formats() -> Vec<String> {
+    vec![String::from("avif"), String::from("webp")]
+}
+fn c_image_max_width() -> u16 {
+    1600
+}
+fn c_image_quality() -> u16 {
+    80
+}
+fn c_image_converter() -> String {
+    String::from("magick")
+}
+fn c_image_cache_dir() -> String {
+    String::from(".cynthiaImages")
+}
+
+/// How Cynthia handles connections.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, StaticType)]
+pub(crate) struct ServerOptions {
+    /// Seconds an idle connection is kept open for another request, `0` closes it after every response.
+    #[serde(alias = "keep-alive")]
+    #[serde(default = "c_keep_alive")]
+    pub(crate) keep_alive: u64,
+    /// Also accept HTTP/2 without TLS (h2c), for a TLS-terminating proxy that talks HTTP/2 to Cynthia.
+    #[serde(default)]
+    pub(crate) http2: bool,
+    /// Redirects requests for any other host to the host of `site.site_baseurl`, and plain HTTP requests
+    /// to HTTPS when the base URL uses it and the proxy says so in `X-Forwarded-Proto`.
+    #[serde(alias = "canonical-redirect")]
+    #[serde(default)]
+    pub(crate) canonical_redirect: bool,
+}
+impl Default for ServerOptions {
+    fn default() -> Self {
+        ServerOptions {
+            keep_alive: c_keep_alive(),
+            http2: false,
+            canonical_redirect: false,
+        }
+    }
+}
+
+/// Taking the site offline for a moment, without stopping Cynthia.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, StaticType)]
+pub(crate) struct MaintenanceOptions {
+    /// Answers requests with a `503` and the maintenance page. Flip it and send `SIGHUP` to toggle it while running.
+    #[serde(default)]
+    pub(crate) enabled: bool,
+    /// An HTML file to answer with, instead of the built-in maintenance page.
+    #[serde(default)]
+    pub(crate) page: Option<String>,
+    /// Keeps serving assets, static mounts, the favicon and the client script, so the maintenance page can use them.
+    #[serde(default = "c_bool_true")]
+    pub(crate) assets: bool,
+}
+impl Default for MaintenanceOptions {
+    fn default() -> Self {
+        MaintenanceOptions {
+            enabled: false,
+            page: None,
+            assets: true,
+        }
+    }
+}
+
+/// Something served at `/.well-known/{name}`: a file (or a directory of them), or inline content.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, StaticType)]
+pub(crate) struct WellKnown {
+    /// Relative to the site's directory. A directory serves the files in it at `/.well-known/{name}/{file}`.
+    #[serde(default)]
+    pub(crate) file: Option<String>,
+    #[serde(default)]
+    pub(crate) content: Option<String>,
+    /// Guessed from the file's extension when left out, inline content is plain text.
+    #[serde(alias = "content-type")]
+    #[serde(default)]
+    pub(crate) content_type: Option<String>,
+}
+
+/// What a publication whose local content file doesn't exist (yet) is shown with, instead of failing to render.
+/// Placeholders are content files in `cynthiaFiles/publications/`, one per mode.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, StaticType)]
+pub(crate) struct MissingContent {
+    #[serde(default)]
+    pub(crate) markdown: Option<String>,
+    #[serde(default)]
+    pub(crate) html: Option<String>,
+    #[serde(default)]
+    pub(crate) plaintext: Option<String>,
+    /// The status code a page with a placeholder is served with.
+    #[serde(default = "c_missing_content_status")]
+    pub(crate) status: u16,
+}
+impl Default for MissingContent {
+    fn default() -> Self {
+        MissingContent {
+            markdown: None,
+            html: None,
+            plaintext: None,
+            status: c_missing_content_status(),
+        }
+    }
+}
+impl MissingContent {
+    /// The placeholder for content in the same mode as `content`.
+    pub(crate) fn placeholder_for(&self, content: &ContentType) -> Option<&String> {
+        match content {
+            ContentType::Markdown(_) => self.markdown.as_ref(),
+            ContentType::Html(_) => self.html.as_ref(),
+            ContentType::PlainText(_) => self.plaintext.as_ref(),
         }
     }
 }
+fn c_missing_content_status() -> u16 {
+    200
+}
+
+/// Which form of a page URL is canonical, the other form is redirected to it.
+/// Defaults to `remove`, so `/about/` redirects to `/about`, which is what clean URLs usually look like.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize, StaticType)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum TrailingSlash {
+    /// Serve both forms as they are.
+    Keep,
+    /// Redirect `/about` to `/about/`.
+    Add,
+    /// Redirect `/about/` to `/about`.
+    #[default]
+    Remove,
+}
 
 fn c_logs() -> Option<Logging> {
     Some(Logging {
@@ -125,13 +503,146 @@ impl Default for Runtimes {
 pub(crate) type SceneCollection = Vec<Scene>;
 pub(crate) trait SceneCollectionTrait {
     fn get_by_name(&self, name: &str) -> Option<Scene>;
-    fn get_default(&self) -> Scene;
+    fn get_default(&self, site: &Site) -> Scene;
+    fn for_publication(&self, publication: &CynthiaPublication, site: &Site) -> Option<Scene>;
     fn validate(&self) -> bool;
+    fn validate_scene_names(&self, site: &Site) -> Result<(), String>;
+    fn validate_category_templates(&self) -> Result<(), String>;
 }
 impl Scene {
     pub(crate) fn get_name(&self) -> String {
         self.name.to_string()
     }
+    /// The client script pages in this scene get: its own `script`, or `site.default_script` when it
+    /// has none. An empty `script` means no script at all.
+    pub(crate) fn client_script(&self, site: &Site) -> Option<String> {
+        match &self.script {
+            Some(script) if script.is_empty() => None,
+            Some(script) => Some(script.clone()),
+            None => site.default_script.clone(),
+        }
+    }
+    /// The site-wide headers merged with this scene's, the scene's winning when both set one.
+    pub(crate) fn response_headers(
+        &self,
+        site_headers: &HashMap<String, String>,
+    ) -> Vec<(String, String)> {
+        let mut headers: Vec<(String, String)> = site_headers
+            .iter()
+            .filter(|(name, _)| !self.headers.keys().any(|n| n.eq_ignore_ascii_case(name)))
+            .chain(self.headers.iter())
+            .map(|(name, value)| (name.clone(), value.clone()))
+            .collect();
+        headers.sort();
+        headers
+    }
+}
+
+/// A directory served as it is, like `static/` under `/static`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, StaticType)]
+pub(crate) struct StaticMount {
+    /// The URL path the directory is served under.
+    pub(crate) prefix: String,
+    /// The directory to serve, relative to the site's folder.
+    pub(crate) path: String,
+}
+
+/// URL prefixes Cynthia serves everything under itself, a static mount under one of these never sees a request.
+const OWN_PREFIXES: [&str; 6] = ["/assets", "/category", "/cat", "/c", "/tag", "/t"];
+
+/// Normalises the prefixes of static mounts and checks their directories exist. Returns warnings
+/// about prefixes that collide with each other or with Cynthia's own routes, and about mounts that
+/// would serve the plugin folders in `plugins_dir`.
+pub(crate) fn validate_static_mounts(
+    mounts: &mut [StaticMount],
+    plugins_dir: &std::path::Path,
+) -> Result<Vec<String>, String> {
+    for mount in mounts.iter_mut() {
+        mount.prefix = format!("/{}", mount.prefix.trim_matches('/'));
+        if mount.prefix == "/" {
+            return Err(format!(
+                "Static mount `{}` can't be served at the root, that's where pages live.",
+                mount.path
+            ));
+        }
+        if !std::path::Path::new(&mount.path).is_dir() {
+            return Err(format!(
+                "Static mount `{}` points to `{}`, which is not a directory.",
+                mount.prefix, mount.path
+            ));
+        }
+    }
+    let is_under =
+        |prefix: &str, route: &str| prefix == route || prefix.starts_with(&format!("{}/", route));
+    let mut warnings = vec![];
+    for (i, mount) in mounts.iter().enumerate() {
+        if let Some(route) = OWN_PREFIXES
+            .iter()
+            .find(|route| is_under(&mount.prefix, route))
+        {
+            warnings.push(format!(
+                "Static mount `{}` is shadowed by Cynthia's own `{}` route, it will not be reachable.",
+                mount.prefix, route
+            ));
+        }
+        if let Some(earlier) = mounts[..i]
+            .iter()
+            .find(|earlier| is_under(&mount.prefix, &earlier.prefix))
+        {
+            warnings.push(format!(
+                "Static mount `{}` is shadowed by the earlier mount `{}`.",
+                mount.prefix, earlier.prefix
+            ));
+        }
+        // Plugin folders hold the plugins' code and settings, a mount reaching into them publishes those.
+        if let (Ok(directory), Ok(plugins)) = (
+            std::path::Path::new(&mount.path).canonicalize(),
+            plugins_dir.canonicalize(),
+        ) {
+            if directory.starts_with(&plugins) || plugins.starts_with(&directory) {
+                warnings.push(format!(
+                    "Static mount `{}` serves `{}`, which holds plugin folders. Their files will be public.",
+                    mount.prefix, mount.path
+                ));
+            }
+        }
+    }
+    Ok(warnings)
+}
+
+/// Checks that every header can actually be sent, reporting the first one that can't.
+pub(crate) fn validate_headers(headers: &HashMap<String, String>) -> Result<(), String> {
+    use actix_web::http::header::{HeaderName, HeaderValue};
+    for (name, value) in headers {
+        if HeaderName::from_bytes(name.as_bytes()).is_err() {
+            return Err(format!("`{}` is not a valid header name.", name));
+        }
+        if HeaderValue::from_str(value).is_err() {
+            return Err(format!("The value of header `{}` is not valid.", name));
+        }
+    }
+    Ok(())
+}
+/// The timezone with this IANA name, like `Europe/Amsterdam`.
+pub(crate) fn timezone(name: &str) -> Result<chrono_tz::Tz, String> {
+    name.parse::<chrono_tz::Tz>().map_err(|_| {
+        format!(
+            "`{}` is not a known timezone, use an IANA name like `UTC` or `Europe/Amsterdam`.",
+            name
+        )
+    })
+}
+/// The encoding with this label, like `utf-8` or `iso-8859-1`. Only ones pages can be written in:
+/// UTF-16 can't, as every byte of the markup around the content would change with it.
+pub(crate) fn charset(label: &str) -> Result<&'static encoding_rs::Encoding, String> {
+    encoding_rs::Encoding::for_label(label.trim().as_bytes())
+        .filter(|encoding| encoding.output_encoding() == *encoding)
+        .ok_or_else(|| {
+            format!(
+                "`{}` is not a character set pages can be sent in, use a label like `utf-8` or `iso-8859-1`.",
+                label
+            )
+        })
 }
 impl SceneCollectionTrait for SceneCollection {
     fn get_by_name(&self, name: &str) -> Option<Scene> {
@@ -142,9 +653,10 @@ impl SceneCollectionTrait for SceneCollection {
         }
         None
     }
-    fn get_default(&self) -> Scene {
+    /// The scene named by `site.default_scene`, or the first one when there is no scene by that name.
+    fn get_default(&self, site: &Site) -> Scene {
         for scene in self {
-            if scene.get_name() == "default" {
+            if scene.get_name() == site.default_scene {
                 return scene.clone();
             }
         }
@@ -154,11 +666,72 @@ impl SceneCollectionTrait for SceneCollection {
         }
         self[0].clone()
     }
+    /// Picks the scene a publication is rendered in: the one it names itself, then the one set for its
+    /// category in `site.category_scenes`, then the default scene. `None` when a named scene doesn't exist.
+    fn for_publication(&self, publication: &CynthiaPublication, site: &Site) -> Option<Scene> {
+        if let Some(name) = publication.get_scene_name() {
+            return self.get_by_name(&name);
+        }
+        let category_scene = publication.get_category().and_then(|category| {
+            site.category_scenes
+                .iter()
+                .find(|(c, _)| c.to_lowercase() == category.to_lowercase())
+        });
+        match category_scene {
+            Some((_, name)) => self.get_by_name(name),
+            None => Some(self.get_default(site)),
+        }
+    }
+    /// Checks the scenes named in `site` exist. A missing `default` scene is fine, the first scene stands in for it.
+    fn validate_scene_names(&self, site: &Site) -> Result<(), String> {
+        if site.default_scene != c_default_scene()
+            && self.get_by_name(&site.default_scene).is_none()
+        {
+            return Err(format!(
+                "The default scene `{}` does not exist.",
+                site.default_scene
+            ));
+        }
+        for (category, name) in &site.category_scenes {
+            if self.get_by_name(name).is_none() {
+                return Err(format!(
+                    "The scene `{}` set for category `{}` does not exist.",
+                    name, category
+                ));
+            }
+        }
+        Ok(())
+    }
+    /// Checks the post templates set per category exist, in `./cynthiaFiles/templates/post/`.
+    fn validate_category_templates(&self) -> Result<(), String> {
+        for scene in self {
+            for (category, template) in &scene.templates.categories {
+                let path = std::path::Path::new("./cynthiaFiles/templates/post")
+                    .join(format!("{}.hbs", template));
+                if !path.is_file() {
+                    return Err(format!(
+                        "The template `{}` set for category `{}` in scene `{}` does not exist, expected it at {}.",
+                        template,
+                        category,
+                        scene.name,
+                        path.display()
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
     fn validate(&self) -> bool {
         if self.is_empty() {
             error!("No scenes found in the configuration file");
             return false;
         }
+        for scene in self {
+            if let Err(e) = validate_headers(&scene.headers) {
+                error!("Invalid headers in scene \"{}\": {}", scene.name, e);
+                return false;
+            }
+        }
         true
     }
 }
@@ -170,48 +743,261 @@ pub(crate) enum Plugin {
     JsPlugin {
         plugin_name: String,
         plugin_enabled: bool,
+        /// Plugins with a lower `order` run first, and so change a page before the ones after them.
+        /// Plugins with the same `order` run in alphabetical order.
+        #[serde(default)]
+        order: i32,
+        /// Whether the plugin always changes a page the same way for the same input, so its results
+        /// can be cached, see [`crate::externalpluginservers::result_cache_key`].
+        #[serde(default)]
+        cacheable: bool,
     },
 }
 
-fn c_plugins() -> Vec<Plugin> {
-    vec![]
+impl Plugin {
+    pub(crate) fn get_name(&self) -> String {
+        match self {
+            Plugin::JsPlugin { plugin_name, .. } => plugin_name.to_string(),
+        }
+    }
+
+    fn get_order(&self) -> i32 {
+        match self {
+            Plugin::JsPlugin { order, .. } => *order,
+        }
+    }
+
+    /// Whether the plugin is enabled and permitted to run by `plugin_options`.
+    pub(crate) fn runs(&self, plugin_options: &PluginOptions) -> bool {
+        match self {
+            Plugin::JsPlugin { plugin_enabled, .. } => {
+                *plugin_enabled && plugin_options.permits(&self.get_name())
+            }
+        }
+    }
+
+    pub(crate) fn is_cacheable(&self) -> bool {
+        match self {
+            Plugin::JsPlugin { cacheable, .. } => *cacheable,
+        }
+    }
 }
 
-/// A clone of the CynthiaConf struct
-pub(crate) struct CynthiaConfClone {
-    pub(crate) port: u16,
-    pub(crate) cache: Cache,
-    pub(crate) site: Site,
-    pub(crate) logs: Option<Logging>,
-    pub(crate) scenes: SceneCollection,
-    pub(crate) runtimes: Runtimes,
-    pub(crate) plugins: Vec<Plugin>,
+/// Puts plugins in the order they run in: by `order`, then by name.
+pub(crate) fn sort_plugins(plugins: &mut [Plugin]) {
+    plugins.sort_by(|a, b| {
+        a.get_order()
+            .cmp(&b.get_order())
+            .then_with(|| a.get_name().cmp(&b.get_name()))
+    });
 }
 
-impl CynthiaConfig for CynthiaConfClone {
-    fn hard_clone(&self) -> CynthiaConf {
-        CynthiaConf {
-            port: self.port,
-            cache: self.cache.clone(),
-            site: self.site.clone(),
-            logs: self.logs.clone(),
-            scenes: self.scenes.clone(),
-            runtimes: self.runtimes.clone(),
-            plugins: self.plugins.clone(),
+/// Checks once, before serving, that the JavaScript runtime works when plugins need it, so requests don't
+/// each have to find out. Without one, Cynthia doesn't start, unless `plugin_options.optional` is set, then
+/// the runtime is disabled and the plugins are skipped.
+#[cfg(feature = "js_runtime")]
+pub(crate) fn check_js_runtime(config: &mut CynthiaConf) -> Result<(), String> {
+    let runtime = config.runtimes.ext_js_rt.clone();
+    if runtime != "disabled" && runtime.validate().is_ok() {
+        return Ok(());
+    }
+    let problem = if runtime == "disabled" {
+        String::from("none was found or `runtimes.node` is set to `disabled`")
+    } else {
+        format!("`{}` doesn't run", runtime)
+    };
+    config.runtimes.ext_js_rt = String::from("disabled");
+    let needed = config
+        .plugins
+        .iter()
+        .filter(|p| match p {
+            Plugin::JsPlugin { plugin_enabled, .. } => *plugin_enabled,
+        })
+        .map(Plugin::get_name)
+        .filter(|name| config.plugin_options.permits(name))
+        .collect::<Vec<String>>();
+    if needed.is_empty() {
+        return Ok(());
+    }
+    if !config.plugin_options.optional {
+        return Err(format!(
+            "The plugins {} need a JavaScript runtime, but {}! Install Node.js, Bun or Deno, or point `runtimes.node` at one, or set `plugin_options.optional = true` to run without these plugins.",
+            needed.join(", "),
+            problem
+        ));
+    }
+    eprintln!(
+        "{} No JavaScript runtime: {}. Running WITHOUT the plugins {}, pages are served as if they weren't installed.",
+        "warning:".color_yellow(),
+        problem,
+        needed.join(", ")
+    );
+    Ok(())
+}
+
+/// Security headers Cynthia adds to every response that doesn't set them itself.
+/// Setting a header to an empty string leaves it out.
+///
+/// Pages inline their scene's scripts and styles unless `site.inline_scripts` is off, and shortcodes
+/// embed from YouTube and GitHub, so the default policy allows inline scripts and styles and https frames.
+/// A stricter policy can be set here, or per scene through its `headers`, which take precedence.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, StaticType)]
+pub(crate) struct Security {
+    #[serde(default = "c_bool_false")]
+    pub(crate) enabled: bool,
+    #[serde(alias = "content-type-options")]
+    #[serde(default = "c_content_type_options")]
+    pub(crate) content_type_options: String,
+    #[serde(alias = "referrer-policy")]
+    #[serde(default = "c_referrer_policy")]
+    pub(crate) referrer_policy: String,
+    #[serde(alias = "content-security-policy")]
+    #[serde(alias = "csp")]
+    #[serde(default = "c_content_security_policy")]
+    pub(crate) content_security_policy: String,
+    #[serde(alias = "frame-options")]
+    #[serde(default = "c_frame_options")]
+    pub(crate) frame_options: String,
+    /// Give the scripts and styles Cynthia inlines a fresh nonce on every response, and allow only
+    /// those in the policy instead of `'unsafe-inline'`.
+    #[serde(default = "c_bool_false")]
+    pub(crate) nonce: bool,
+}
+impl Default for Security {
+    fn default() -> Self {
+        Security {
+            enabled: false,
+            content_type_options: c_content_type_options(),
+            referrer_policy: c_referrer_policy(),
+            content_security_policy: c_content_security_policy(),
+            frame_options: c_frame_options(),
+            nonce: false,
         }
     }
-    fn clone(&self) -> CynthiaConfClone {
-        CynthiaConfClone {
-            port: self.port,
-            cache: self.cache.clone(),
-            site: self.site.clone(),
-            logs: self.logs.clone(),
-            scenes: self.scenes.clone(),
-            runtimes: self.runtimes.clone(),
-            plugins: self.plugins.clone(),
+}
+impl Security {
+    /// The headers to add, none when disabled.
+    pub(crate) fn headers(&self) -> HashMap<String, String> {
+        if !self.enabled {
+            return HashMap::new();
+        }
+        [
+            ("X-Content-Type-Options", &self.content_type_options),
+            ("Referrer-Policy", &self.referrer_policy),
+            ("Content-Security-Policy", &self.content_security_policy),
+            ("X-Frame-Options", &self.frame_options),
+        ]
+        .into_iter()
+        .filter(|(_, value)| !value.is_empty())
+        .map(|(name, value)| (name.to_string(), value.clone()))
+        .collect()
+    }
+    pub(crate) fn uses_nonce(&self) -> bool {
+        self.enabled && self.nonce
+    }
+    /// `content_security_policy` with `'nonce-{nonce}'` as the way to allow inline scripts and styles.
+    pub(crate) fn policy_with_nonce(&self, nonce: &str) -> String {
+        let source = format!("'nonce-{}'", nonce);
+        let mut directives = vec![];
+        let mut missing = vec!["script-src", "style-src"];
+        for directive in self
+            .content_security_policy
+            .split(';')
+            .map(str::trim)
+            .filter(|d| !d.is_empty())
+        {
+            let name = directive.split_whitespace().next().unwrap_or_default();
+            if missing.contains(&name) {
+                missing.retain(|m| *m != name);
+                let sources: Vec<&str> = directive
+                    .split_whitespace()
+                    .filter(|s| *s != "'unsafe-inline'")
+                    .collect();
+                directives.push(format!("{} {}", sources.join(" "), source));
+            } else {
+                directives.push(directive.to_string());
+            }
+        }
+        for name in missing {
+            directives.push(format!("{} 'self' {}", name, source));
+        }
+        directives.join("; ")
+    }
+}
+
+/// Operator-side control over which plugins get to run.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, StaticType)]
+pub(crate) struct PluginOptions {
+    /// Global kill switch, no plugin runs when this is false.
+    #[serde(default = "c_bool_true")]
+    pub(crate) enabled: bool,
+    /// When not empty, only plugins named here run.
+    #[serde(alias = "allowlist")]
+    #[serde(default)]
+    pub(crate) allow: Vec<String>,
+    /// Plugins named here never run, even when allowed.
+    #[serde(alias = "denylist")]
+    #[serde(default)]
+    pub(crate) deny: Vec<String>,
+    /// Stop Cynthia from starting when a plugin's `onStart` hook fails, instead of just logging it.
+    #[serde(alias = "abort-on-start-failure")]
+    #[serde(default = "c_bool_false")]
+    pub(crate) abort_on_start_failure: bool,
+    /// How many plugin requests may be in flight at once, others queue until one finishes. `0` means no limit.
+    #[serde(alias = "max-concurrent")]
+    #[serde(default)]
+    pub(crate) max_concurrent: u16,
+    /// The most bytes a single plugin response may take, larger ones are dropped as if no plugin answered.
+    /// `0` means no limit.
+    #[serde(alias = "max-output-bytes")]
+    #[serde(default = "c_max_output_bytes")]
+    pub(crate) max_output_bytes: u64,
+    /// Run without the plugins when there's no working JavaScript runtime, instead of refusing to start.
+    #[serde(default = "c_bool_false")]
+    pub(crate) optional: bool,
+    /// Logs what every plugin runner is given and returns, at trace level, for plugin authors.
+    #[serde(default = "c_bool_false")]
+    pub(crate) debug: bool,
+    /// Logs how long each plugin's runners took per request, at debug level.
+    #[serde(default = "c_bool_false")]
+    pub(crate) timing: bool,
+    /// A directory to write every command sent to the plugin runtime to, as a JSON file. Off when unset.
+    #[serde(alias = "dump-commands")]
+    #[serde(default)]
+    pub(crate) dump_commands: Option<String>,
+}
+impl Default for PluginOptions {
+    fn default() -> Self {
+        PluginOptions {
+            enabled: true,
+            allow: vec![],
+            deny: vec![],
+            abort_on_start_failure: false,
+            max_concurrent: 0,
+            max_output_bytes: c_max_output_bytes(),
+            optional: false,
+            debug: false,
+            timing: false,
+            dump_commands: None,
         }
     }
 }
+impl PluginOptions {
+    pub(crate) fn permits(&self, plugin_name: &str) -> bool {
+        self.enabled
+            && !self.deny.iter().any(|p| p == plugin_name)
+            && (self.allow.is_empty() || self.allow.iter().any(|p| p == plugin_name))
+    }
+}
+
+fn c_plugins() -> Vec<Plugin> {
+    vec![]
+}
+
+/// The configuration as requests see it: shared and read-only, so taking one is cheap and a reload
+/// swaps in a new one without touching those in use.
+pub(crate) type CynthiaConfClone = Arc<CynthiaConf>;
+
 impl CynthiaConfig for CynthiaConf {
     fn hard_clone(&self) -> CynthiaConf {
         CynthiaConf {
@@ -222,18 +1008,33 @@ impl CynthiaConfig for CynthiaConf {
             scenes: self.scenes.clone(),
             runtimes: self.runtimes.clone(),
             plugins: self.plugins.clone(),
+            plugin_options: self.plugin_options.clone(),
+            render_timeout: self.render_timeout,
+            trailing_slash: self.trailing_slash,
+            security: self.security.clone(),
+            workers: self.workers,
+            debug: self.debug.clone(),
+            static_mounts: self.static_mounts.clone(),
+            server: self.server.clone(),
+            prerender_dir: self.prerender_dir.clone(),
+            timezone: self.timezone.clone(),
+            charset: self.charset.clone(),
+            maintenance: self.maintenance.clone(),
+            root_dir: self.root_dir.clone(),
+            missing_content: self.missing_content.clone(),
+            trusted_proxies: self.trusted_proxies.clone(),
+            well_known: self.well_known.clone(),
+            site_context: self.site_context.clone(),
+            webhooks: self.webhooks.clone(),
+            home: self.home.clone(),
+            visits: self.visits.clone(),
+            images: self.images.clone(),
+            sanitize: self.sanitize.clone(),
+            error_pages: self.error_pages.clone(),
         }
     }
     fn clone(&self) -> CynthiaConfClone {
-        CynthiaConfClone {
-            port: self.port,
-            cache: self.cache.clone(),
-            site: self.site.clone(),
-            logs: self.logs.clone(),
-            scenes: self.scenes.clone(),
-            runtimes: self.runtimes.clone(),
-            plugins: self.plugins.clone(),
-        }
+        Arc::new(self.hard_clone())
     }
 }
 pub(crate) trait CynthiaConfig {
@@ -243,15 +1044,78 @@ pub(crate) trait CynthiaConfig {
 
 impl CynthiaConf {
     pub(crate) fn clone(&self) -> CynthiaConfClone {
-        CynthiaConfClone {
-            port: self.port,
-            cache: self.cache.clone(),
-            site: self.site.clone(),
-            logs: self.logs.clone(),
-            scenes: self.scenes.clone(),
-            runtimes: self.runtimes.clone(),
-            plugins: self.plugins.clone(),
-        }
+        Arc::new(self.hard_clone())
+    }
+
+    /// The optional features and whether this configuration turns them on, by the setting that
+    /// does. Every toggle stays in its own section, this is the one place that lists them all.
+    /// Plugin routes are only known once the plugins started, they're logged then.
+    pub(crate) fn features(&self) -> Vec<(&'static str, bool)> {
+        // Taken apart without `..`, so a new setting doesn't compile until it's either listed
+        // below or set aside here as not being a feature.
+        let CynthiaConf {
+            port: _,
+            cache: _,
+            site,
+            logs: _,
+            runtimes: _,
+            scenes: _,
+            plugins,
+            plugin_options,
+            render_timeout: _,
+            trailing_slash: _,
+            security,
+            workers: _,
+            debug,
+            static_mounts,
+            server,
+            prerender_dir,
+            timezone: _,
+            charset: _,
+            maintenance,
+            root_dir: _,
+            missing_content: _,
+            trusted_proxies: _,
+            well_known,
+            webhooks,
+            home,
+            site_context,
+            visits,
+            images,
+            sanitize,
+            error_pages,
+        } = self;
+        vec![
+            (
+                "plugins",
+                cfg!(feature = "js_runtime") && plugins.iter().any(|p| p.runs(plugin_options)),
+            ),
+            ("plugin_options.debug", plugin_options.debug),
+            ("plugin_options.timing", plugin_options.timing),
+            ("security", security.enabled),
+            ("security.nonce", security.uses_nonce()),
+            ("server.http2", server.http2),
+            ("server.canonical_redirect", server.canonical_redirect),
+            ("prerender_dir", prerender_dir.is_some()),
+            ("maintenance", maintenance.enabled),
+            ("visits", visits.enabled),
+            ("images", images.enabled),
+            ("images.responsive", images.enabled && images.responsive),
+            ("sanitize", sanitize.enabled),
+            ("site.emoji", site.emoji),
+            ("site.inline_scripts", site.inline_scripts),
+            ("site_context", !site_context.is_empty()),
+            ("static_mounts", !static_mounts.is_empty()),
+            ("well_known", !well_known.is_empty()),
+            ("home", home.is_some()),
+            (
+                "error_pages.server_error",
+                error_pages.server_error.is_some(),
+            ),
+            ("webhooks.reload", webhooks.reload.is_some()),
+            ("debug.log_endpoint", debug.log_endpoint.is_some()),
+            ("debug.timing_header", debug.timing_header),
+        ]
     }
 }
 
@@ -311,7 +1175,10 @@ pub(crate) struct Site {
     #[serde(default = "c_404")]
     pub(crate) notfound_page: String,
 
+    /// The absolute URL the site is reachable at, used for canonical links and `og:url`.
     #[serde(alias = "site-baseurl")]
+    #[serde(alias = "base-url")]
+    #[serde(alias = "base_url")]
     #[serde(default = "c_emptystring")]
     pub(crate) site_baseurl: String,
 
@@ -321,6 +1188,61 @@ pub(crate) struct Site {
     pub(crate) og_sitename: String,
 
     pub(crate) meta: Meta,
+
+    /// Inline scene scripts into every page, or serve them from `/cynthia/client.js` instead.
+    #[serde(alias = "inline-scripts")]
+    #[serde(default = "c_bool_true")]
+    pub(crate) inline_scripts: bool,
+
+    /// Path to the site icon, relative to `./cynthiaFiles/assets/`, served at `/favicon.ico`.
+    #[serde(default)]
+    pub(crate) favicon: Option<String>,
+
+    /// Start every page with a comment crediting Cynthia.
+    #[serde(alias = "generator-comment")]
+    #[serde(default = "c_bool_true")]
+    pub(crate) generator_comment: bool,
+
+    /// The language served when a translation isn't available in the requested one.
+    #[serde(alias = "default-lang")]
+    #[serde(default = "c_default_lang")]
+    pub(crate) default_lang: String,
+
+    /// Delimiters around shortcodes in publication content.
+    #[serde(default)]
+    pub(crate) shortcodes: ShortcodeDelimiters,
+
+    /// Excerpts for posts without a `short`.
+    #[serde(default)]
+    pub(crate) excerpts: ExcerptOptions,
+
+    /// Replace emoji shortcodes like `:smile:` in content with their emoji, see [`crate::emoji`].
+    #[serde(default)]
+    pub(crate) emoji: bool,
+
+    /// Headers sent with every rendered page, scenes can override these.
+    #[serde(default)]
+    pub(crate) headers: HashMap<String, String>,
+
+    /// The scene for publications that don't pick one, and for which `category_scenes` has none.
+    #[serde(alias = "default-scene")]
+    #[serde(default = "c_default_scene")]
+    pub(crate) default_scene: String,
+
+    /// Scenes for the posts in a category (and its listing) by category name, unless a post picks its own.
+    #[serde(alias = "category-scenes")]
+    #[serde(default)]
+    pub(crate) category_scenes: HashMap<String, String>,
+
+    /// The avatar shown for authors without a `thumbnail` of their own.
+    #[serde(alias = "default-author-thumbnail")]
+    #[serde(default)]
+    pub(crate) default_author_thumbnail: Option<String>,
+
+    /// The client script for scenes that don't set a `script` of their own, relative to `./cynthiaFiles/assets/`.
+    #[serde(alias = "default-script")]
+    #[serde(default)]
+    pub(crate) default_script: Option<String>,
 }
 
 impl Default for Site {
@@ -330,9 +1252,85 @@ impl Default for Site {
             site_baseurl: String::new(),
             og_sitename: String::new(),
             meta: Meta { enable_tags: false },
+            inline_scripts: true,
+            favicon: None,
+            generator_comment: true,
+            default_lang: c_default_lang(),
+            shortcodes: ShortcodeDelimiters::default(),
+            excerpts: ExcerptOptions::default(),
+            emoji: false,
+            headers: HashMap::new(),
+            default_scene: c_default_scene(),
+            category_scenes: HashMap::new(),
+            default_author_thumbnail: None,
+            default_script: None,
+        }
+    }
+}
+
+impl Site {
+    /// Validates a base URL and strips its trailing slashes, so paths can be appended with a `/`.
+    pub(crate) fn normalise_baseurl(url: &str) -> Result<String, String> {
+        let url = url.trim().trim_end_matches('/');
+        let host = url
+            .strip_prefix("https://")
+            .or_else(|| url.strip_prefix("http://"))
+            .ok_or_else(|| format!("`{}` is not an absolute http(s) URL.", url))?;
+        if host.is_empty() || host.starts_with('/') {
+            return Err(format!("`{}` does not contain a host.", url));
+        }
+        Ok(url.to_string())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, StaticType)]
+pub(crate) struct ShortcodeDelimiters {
+    #[serde(default = "c_shortcode_open")]
+    pub(crate) open: String,
+    #[serde(default = "c_shortcode_close")]
+    pub(crate) close: String,
+}
+impl ShortcodeDelimiters {
+    /// Checks both delimiters are set, an empty one would be found everywhere.
+    pub(crate) fn check(&self) -> Result<(), String> {
+        if self.open.is_empty() || self.close.is_empty() {
+            return Err(String::from(
+                "`site.shortcodes.open` and `site.shortcodes.close` can't be empty.",
+            ));
+        }
+        Ok(())
+    }
+}
+impl Default for ShortcodeDelimiters {
+    fn default() -> Self {
+        ShortcodeDelimiters {
+            open: c_shortcode_open(),
+            close: c_shortcode_close(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, StaticType)]
+pub(crate) struct ExcerptOptions {
+    /// Characters of text an excerpt is cut after, when the post has no `<!--more-->` marker.
+    #[serde(default = "c_excerpt_length")]
+    pub(crate) length: usize,
+    /// Plain text excerpts, or excerpts keeping the post's markup.
+    #[serde(alias = "strip-html")]
+    #[serde(default = "c_bool_true")]
+    pub(crate) strip_html: bool,
+}
+impl Default for ExcerptOptions {
+    fn default() -> Self {
+        ExcerptOptions {
+            length: c_excerpt_length(),
+            strip_html: true,
         }
     }
 }
+fn c_excerpt_length() -> usize {
+    300
+}
 
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize, StaticType)]
 pub(crate) struct Meta {
@@ -367,6 +1365,9 @@ pub(crate) struct Scene {
     pub(crate) stylefile: Option<String>,
     pub(crate) script: Option<String>,
     pub(crate) templates: Templates,
+    /// Headers sent with pages rendered in this scene, on top of `site.headers`.
+    #[serde(default)]
+    pub(crate) headers: HashMap<String, String>,
 }
 impl Default for Scene {
     fn default() -> Self {
@@ -379,7 +1380,9 @@ impl Default for Scene {
                 post: String::from("default"),
                 page: String::from("default"),
                 postlist: String::from("default"),
+                categories: HashMap::new(),
             },
+            headers: HashMap::new(),
         }
     }
 }
@@ -390,14 +1393,45 @@ pub(crate) struct Templates {
     pub(crate) post: String,
     pub(crate) page: String,
     pub(crate) postlist: String,
+    /// Post templates by category name, used instead of `post` for the posts in that category.
+    #[serde(default)]
+    pub(crate) categories: HashMap<String, String>,
+}
+impl Templates {
+    /// The template for a post in `category`: the one set for it in `categories`, or else `post`.
+    pub(crate) fn for_post(&self, category: Option<&str>) -> &str {
+        category
+            .and_then(|category| {
+                self.categories
+                    .iter()
+                    .find(|(c, _)| c.to_lowercase() == category.to_lowercase())
+            })
+            .map(|(_, template)| template.as_str())
+            .unwrap_or(&self.post)
+    }
 }
 
+fn c_root_dir() -> String {
+    String::from(".")
+}
+fn c_timezone() -> String {
+    String::from("UTC")
+}
+fn c_charset() -> String {
+    String::from("utf-8")
+}
+fn c_trusted_proxies() -> Vec<String> {
+    vec![String::from("127.0.0.1"), String::from("::1")]
+}
 fn c_port() -> u16 {
     3000
 }
 fn c_bool_false() -> bool {
     false
 }
+fn c_bool_true() -> bool {
+    true
+}
 fn c_emptystring() -> String {
     String::from("")
 }
@@ -414,6 +1448,41 @@ fn c_cache_lifetime_served() -> u64 {
     50
 }
 
+fn c_render_timeout() -> u64 {
+    30
+}
+fn c_default_lang() -> String {
+    String::from("en")
+}
+fn c_default_scene() -> String {
+    String::from("default")
+}
+/// 16 MiB, far more than any page should need.
+fn c_max_output_bytes() -> u64 {
+    16 * 1024 * 1024
+}
+/// Same as actix's own default.
+fn c_keep_alive() -> u64 {
+    5
+}
+fn c_content_type_options() -> String {
+    String::from("nosniff")
+}
+fn c_referrer_policy() -> String {
+    String::from("strict-origin-when-cross-origin")
+}
+fn c_content_security_policy() -> String {
+    String::from("default-src 'self'; script-src 'self' 'unsafe-inline' https://gist.github.com; style-src 'self' 'unsafe-inline' https:; img-src 'self' data: https:; frame-src https:")
+}
+fn c_frame_options() -> String {
+    String::from("SAMEORIGIN")
+}
+fn c_shortcode_open() -> String {
+    String::from("{{<")
+}
+fn c_shortcode_close() -> String {
+    String::from(">}}")
+}
 fn c_404() -> String {
     String::from("404")
 }
@@ -421,3 +1490,83 @@ fn c_emptyscenelist() -> Vec<Scene> {
     vec![Scene::default()]
 }
 pub(crate) mod actions;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scenes_pick_their_client_script() {
+        let site = Site {
+            default_script: Some(String::from("/scripts/client.js")),
+            ..Site::default()
+        };
+        let scene = |script: Option<&str>| Scene {
+            script: script.map(String::from),
+            ..Scene::default()
+        };
+        assert_eq!(
+            scene(Some("/scripts/gallery.js")).client_script(&site),
+            Some(String::from("/scripts/gallery.js"))
+        );
+        assert_eq!(
+            scene(None).client_script(&site),
+            Some(String::from("/scripts/client.js"))
+        );
+        assert_eq!(scene(Some("")).client_script(&site), None);
+        assert_eq!(scene(None).client_script(&Site::default()), None);
+    }
+
+    #[test]
+    fn lists_active_features() {
+        let mut config = CynthiaConf::default();
+        config.images.enabled = true;
+        config.visits.enabled = true;
+        let features = config.features();
+        let on = |name: &str| features.iter().any(|(n, active)| *n == name && *active);
+        assert!(on("images") && on("visits"));
+        assert!(!on("images.responsive") && !on("sanitize"));
+        config.images.responsive = true;
+        assert!(config.features().contains(&("images.responsive", true)));
+        config
+            .site_context
+            .insert(String::from("tagline"), String::from("Hi"));
+        assert!(config.features().contains(&("site_context", true)));
+        assert!(config.features().contains(&("well_known", false)));
+    }
+
+    #[test]
+    fn posts_pick_their_category_template() {
+        let templates = Templates {
+            post: String::from("default"),
+            categories: HashMap::from([(String::from("Recipes"), String::from("recipe"))]),
+            ..Templates::default()
+        };
+        assert_eq!(templates.for_post(Some("recipes")), "recipe");
+        assert_eq!(templates.for_post(Some("travel")), "default");
+        assert_eq!(templates.for_post(None), "default");
+    }
+
+    #[test]
+    fn warns_about_mounts_serving_plugin_folders() {
+        let dir = std::env::temp_dir().join(format!("cynthia-mounts-{}", std::process::id()));
+        let plugins = dir.join("cynthiaPlugins");
+        std::fs::create_dir_all(plugins.join("comments")).unwrap();
+        std::fs::create_dir_all(dir.join("static")).unwrap();
+        let mount = |prefix: &str, path: &std::path::Path| StaticMount {
+            prefix: String::from(prefix),
+            path: path.to_string_lossy().into_owned(),
+        };
+        let mut mounts = vec![
+            mount("static/", &dir.join("static")),
+            mount("/comments", &plugins.join("comments")),
+            mount("/site", &dir),
+        ];
+        let warnings = validate_static_mounts(&mut mounts, &plugins).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(mounts[0].prefix, "/static");
+        assert_eq!(warnings.len(), 2);
+        assert!(warnings[0].starts_with("Static mount `/comments`"));
+        assert!(warnings[1].starts_with("Static mount `/site`"));
+    }
+}
@@ -8,7 +8,9 @@ use super::{CynthiaConf, CynthiaConfig};
 use crate::jsrun;
 use crate::jsrun::RunJSAndDeserializeResult;
 use crate::tell::CynthiaColors;
-use std::path::PathBuf;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
 use std::{fs, process};
 
 const CONFIG_LOCATIONS: [&str; 4] = [
@@ -33,6 +35,14 @@ impl ConfigLocations {
             ConfigLocations::JsonC(p) => ConfigLocations::JsonC(p.clone()),
         }
     }
+    fn path(&self) -> &PathBuf {
+        match self {
+            ConfigLocations::Js(p) => p,
+            ConfigLocations::Dhall(p) => p,
+            ConfigLocations::Toml(p) => p,
+            ConfigLocations::JsonC(p) => p,
+        }
+    }
     fn exists(&self) -> bool {
         match self {
             ConfigLocations::Js(p) => p.exists(),
@@ -43,14 +53,55 @@ impl ConfigLocations {
     }
 }
 
+static CONFIG_DIR: OnceLock<PathBuf> = OnceLock::new();
+
+/// Where the configuration is read from: the working directory Cynthia was started in, also after
+/// [`enter_root_dir`] moved it elsewhere.
+fn config_dir() -> PathBuf {
+    CONFIG_DIR
+        .get_or_init(|| std::env::current_dir().unwrap())
+        .clone()
+}
+
+/// The absolute path of `root_dir`, which is relative to the configuration's directory.
+pub(crate) fn resolve_root_dir(root_dir: &str) -> Result<String, String> {
+    let path = config_dir().join(root_dir);
+    let root = path
+        .canonicalize()
+        .map_err(|e| format!("Could not find root directory `{}`: {}", path.display(), e))?;
+    if !root.is_dir() {
+        return Err(format!(
+            "Root directory `{}` is not a directory!",
+            root.display()
+        ));
+    }
+    if !root.join("cynthiaFiles").is_dir() {
+        return Err(format!(
+            "Root directory `{}` has no `cynthiaFiles` directory, is `root_dir` pointing at a Cynthia site?",
+            root.display()
+        ));
+    }
+    Ok(root.to_string_lossy().replace("\\\\?\\", ""))
+}
+
+/// Makes `root_dir` the working directory, so content, templates, plugins and the relative paths in the
+/// configuration are found there. Leaves `root_dir` set to its absolute path.
+pub(crate) fn enter_root_dir(config: &mut CynthiaConf) -> Result<(), String> {
+    let root = resolve_root_dir(&config.root_dir)?;
+    std::env::set_current_dir(Path::new(&root))
+        .map_err(|e| format!("Could not enter root directory `{}`: {}", root, e))?;
+    config.root_dir = root;
+    Ok(())
+}
+
 fn choose_config_location() -> ConfigLocations {
     let unfound = || {
         eprintln!("Could not find cynthia-configuration at `{}`! Have you initialised a Cynthia setup here? To do so, run `{}`.",
-                  std::env::current_dir().unwrap().clone().to_string_lossy().replace("\\\\?\\", "").color_bright_cyan(),
+                  config_dir().to_string_lossy().replace("\\\\?\\", "").color_bright_cyan(),
                   "cynthiaweb init".color_lime());
         process::exit(1);
     };
-    let cd = std::env::current_dir().unwrap();
+    let cd = config_dir();
     // In order of preference for Cynthia. I personally prefer TOML, but Cynthia would prefer Dhall. Besides, Dhall is far more powerful.
     // JS, Dhall, TOML, jsonc
     let config_locations: [ConfigLocations; 4] = [
@@ -80,249 +131,123 @@ fn choose_config_location() -> ConfigLocations {
     chosen_config_location
 }
 
-pub(crate) fn load_config() -> CynthiaConf {
-    use jsonc_parser::parse_to_serde_value as preparse_jsonc;
-    let chosen_config_location = choose_config_location();
-    return match chosen_config_location {
-        ConfigLocations::JsonC(cynthiaconfpath) => {
-            println!(
-                "{} Loading: {}",
-                "[Config]".color_lime(),
-                cynthiaconfpath
-                    .clone()
-                    .to_string_lossy()
-                    .replace("\\\\?\\", "")
-                    .color_bright_cyan()
-            );
-            let unparsed_json = match fs::read_to_string(cynthiaconfpath.clone()) {
-                Ok(t) => t,
-                Err(e) => {
-                    eprintln!(
-                        "{}\n\nReason:\n{}",
-                        format!(
-                            "Could not interpret cynthia-configuration at `{}`!",
-                            cynthiaconfpath
-                                .clone()
-                                .to_string_lossy()
-                                .replace("\\\\?\\", "")
-                        )
-                        .color_bright_red(),
-                        e
-                    );
-                    process::exit(1);
-                }
-            };
+/// Whether the configuration is read from standard input, with `--config-stdin`, instead of from a file.
+fn config_from_stdin() -> bool {
+    std::env::args().any(|a| a == "--config-stdin")
+}
 
-            let preparsed: Option<serde_json::Value> =
-                match preparse_jsonc(unparsed_json.as_str(), &Default::default()) {
-                    Ok(t) => t,
-                    Err(e) => {
-                        eprintln!(
-                            "{}\n\nReason:\n{}",
-                            format!(
-                                "Could not interpret cynthia-configuration at `{}`!",
-                                cynthiaconfpath
-                                    .clone()
-                                    .to_string_lossy()
-                                    .replace("\\\\?\\", "")
-                            )
-                            .color_bright_red(),
-                            e
-                        );
-                        process::exit(1);
-                    }
-                };
-            match preparsed {
-                Some(g) => match serde_json::from_value(g) {
-                    Ok(p) => p,
-                    Err(e) => {
-                        eprintln!(
-                            "{}\n\nReason:\n{}",
-                            format!(
-                                "Could not interpret cynthia-configuration at `{}`!",
-                                cynthiaconfpath
-                                    .clone()
-                                    .to_string_lossy()
-                                    .replace("\\\\?\\", "")
-                            )
-                            .color_bright_red(),
-                            e
-                        );
-                        process::exit(1);
-                    }
-                },
-                None => {
-                    eprintln!(
-                        "{}\n\nReason:\n{}",
-                        format!(
-                            "Could not interpret cynthia-configuration at `{}`!",
-                            cynthiaconfpath
-                                .clone()
-                                .to_string_lossy()
-                                .replace("\\\\?\\", "")
-                        )
-                        .color_error_red(),
-                        "ERROR: ".color_bright_red()
-                    );
-                    process::exit(1);
-                }
-            }
-        }
-        ConfigLocations::Toml(cynthiaconfpath) => {
-            println!(
-                "{} Loading: {}",
-                "[Config]".color_lime(),
-                cynthiaconfpath
-                    .clone()
-                    .to_string_lossy()
-                    .replace("\\\\?\\", "")
-                    .color_bright_cyan()
+/// Reads a TOML configuration from standard input, for orchestrators that inject it without writing a file.
+/// There's no file to find the site next to, so `--root-dir` has to say where it is.
+fn load_config_from_stdin() -> CynthiaConf {
+    if crate::arg_value("--root-dir").is_none() {
+        eprintln!(
+            "{} `--config-stdin` needs `--root-dir` to tell where the site is.",
+            "error:".color_red()
+        );
+        process::exit(1);
+    }
+    println!(
+        "{} Loading: {}",
+        "[Config]".color_lime(),
+        "standard input".color_bright_cyan()
+    );
+    let mut toml = String::new();
+    let parsed = std::io::stdin()
+        .read_to_string(&mut toml)
+        .map_err(|e| e.to_string())
+        .and_then(|_| toml::from_str::<CynthiaConf>(&toml).map_err(|e| e.to_string()));
+    match parsed {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!(
+                "{}\n\nReason:\n{}",
+                "Could not interpret cynthia-configuration from standard input!".color_bright_red(),
+                e
             );
-            match fs::read_to_string(cynthiaconfpath.clone()) {
-                Ok(g) => match toml::from_str(&g) {
-                    Ok(p) => p,
-                    Err(e) => {
-                        eprintln!(
-                            "{}\n\nReason:\n{}",
-                            format!(
-                                "Could not interpret cynthia-configuration at `{}`!",
-                                cynthiaconfpath
-                                    .clone()
-                                    .to_string_lossy()
-                                    .replace("\\\\?\\", "")
-                            )
-                            .color_bright_red(),
-                            e
-                        );
-                        process::exit(1);
-                    }
-                },
-                Err(e) => {
-                    eprintln!(
-                        "{}\n\nReason:\n{}",
-                        format!(
-                            "Could not interpret cynthia-configuration at `{}`!",
-                            cynthiaconfpath
-                                .clone()
-                                .to_string_lossy()
-                                .replace("\\\\?\\", "")
-                        )
-                        .color_bright_red(),
-                        format!("{}", e).color_error_red()
-                    );
-                    process::exit(1);
-                }
-            }
+            process::exit(1);
         }
-        ConfigLocations::Dhall(cynthiaconfpath) => {
-            println!(
-                "{} Loading: {}",
-                "[Config]".color_lime(),
-                cynthiaconfpath
-                    .clone()
-                    .to_string_lossy()
-                    .replace("\\\\?\\", "")
-                    .color_bright_cyan()
+    }
+}
+
+/// Loads the configuration, exiting with the reason when it can't. `--root-dir` overrides its `root_dir`.
+pub(crate) fn load_config() -> CynthiaConf {
+    let mut config = if config_from_stdin() {
+        load_config_from_stdin()
+    } else {
+        load_config_file()
+    };
+    override_root_dir(&mut config);
+    config
+}
+
+/// Applies `--root-dir`, on startup and on every reload alike.
+fn override_root_dir(config: &mut CynthiaConf) {
+    if let Some(root_dir) = crate::arg_value("--root-dir") {
+        config.root_dir = root_dir;
+    }
+}
+
+fn load_config_file() -> CynthiaConf {
+    let chosen_config_location = choose_config_location();
+    let cynthiaconfpath = chosen_config_location
+        .path()
+        .to_string_lossy()
+        .replace("\\\\?\\", "");
+    println!(
+        "{} Loading: {}",
+        "[Config]".color_lime(),
+        cynthiaconfpath.clone().color_bright_cyan()
+    );
+    match parse_config(&chosen_config_location) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!(
+                "{}\n\nReason:\n{}",
+                format!(
+                    "Could not interpret cynthia-configuration at `{}`!",
+                    cynthiaconfpath
+                )
+                .color_bright_red(),
+                e
             );
-            match fs::read_to_string(cynthiaconfpath.clone()) {
-                Ok(g) => match serde_dhall::from_str(&g).parse() {
-                    Ok(p) => p,
-                    Err(e) => {
-                        eprintln!(
-                            "{}\n\nReason:\n{}",
-                            format!(
-                                "Could not interpret cynthia-configuration at `{}`!",
-                                cynthiaconfpath
-                                    .clone()
-                                    .to_string_lossy()
-                                    .replace("\\\\?\\", "")
-                            )
-                            .color_bright_red(),
-                            e
-                        );
-                        process::exit(1);
-                    }
-                },
-                Err(e) => {
-                    eprintln!(
-                        "{}\n\nReason:\n{}",
-                        format!(
-                            "Could not interpret cynthia-configuration at `{}`!",
-                            cynthiaconfpath
-                                .clone()
-                                .to_string_lossy()
-                                .replace("\\\\?\\", "")
-                        )
-                        .color_bright_red(),
-                        format!("{}", e).color_error_red()
-                    );
-                    process::exit(1);
-                }
-            }
+            process::exit(1);
         }
-        ConfigLocations::Js(cynthiaconfpath) => {
-            println!(
-                "{} Loading: {}",
-                "[Config]".color_lime(),
-                cynthiaconfpath
-                    .clone()
-                    .to_string_lossy()
-                    .replace("\\\\?\\", "")
-                    .color_bright_cyan()
-            );
-            let unparsed_js = match fs::read_to_string(cynthiaconfpath.clone()) {
-                Ok(t) => t,
-                Err(e) => {
-                    eprintln!(
-                        "{}\n\nReason:\n{}",
-                        format!(
-                            "Could not interpret cynthia-configuration at `{}`!",
-                            cynthiaconfpath
-                                .clone()
-                                .to_string_lossy()
-                                .replace("\\\\?\\", "")
-                        )
-                        .color_bright_red(),
-                        e
-                    );
-                    process::exit(1);
-                }
-            };
-            match jsrun::run_js_and_deserialize::<CynthiaConf>(unparsed_js.as_str()) {
-                RunJSAndDeserializeResult::Ok(p) => p,
-                RunJSAndDeserializeResult::JsError(e) => {
-                    eprintln!(
-                        "{}\n\nReason:\n{}",
-                        format!(
-                            "Could not interpret cynthia-configuration at `{}`!",
-                            cynthiaconfpath
-                                .clone()
-                                .to_string_lossy()
-                                .replace("\\\\?\\", "")
-                        )
-                        .color_bright_red(),
-                        e
-                    );
-                    process::exit(1);
-                }
-                RunJSAndDeserializeResult::SerdeError(e) => {
-                    eprintln!(
-                        "{}\n\nReason:\n{}",
-                        format!(
-                            "Could not interpret cynthia-configuration at `{}`!",
-                            cynthiaconfpath
-                                .clone()
-                                .to_string_lossy()
-                                .replace("\\\\?\\", "")
-                        )
-                        .color_bright_red(),
-                        e
-                    );
-                    process::exit(1);
-                }
+    }
+}
+
+/// Loads the configuration again, without exiting when it can't, so a running Cynthia can keep using the one it has.
+pub(crate) fn reload_config() -> Result<CynthiaConf, String> {
+    if config_from_stdin() {
+        return Err(String::from(
+            "The configuration was read from standard input, restart Cynthia to change it.",
+        ));
+    }
+    let chosen_config_location = choose_config_location_option()
+        .ok_or_else(|| String::from("No cynthia-configuration found."))?;
+    let mut config = parse_config(&chosen_config_location)?;
+    // Otherwise `root_dir` would seem to change on every reload of a site started with `--root-dir`.
+    override_root_dir(&mut config);
+    Ok(config)
+}
+
+fn parse_config(location: &ConfigLocations) -> Result<CynthiaConf, String> {
+    use jsonc_parser::parse_to_serde_value as preparse_jsonc;
+    let g = fs::read_to_string(location.path()).map_err(|e| e.to_string())?;
+    match location {
+        ConfigLocations::JsonC(_) => {
+            match preparse_jsonc(g.as_str(), &Default::default()).map_err(|e| e.to_string())? {
+                Some(g) => serde_json::from_value(g).map_err(|e| e.to_string()),
+                None => Err(String::from("The file does not contain a configuration.")),
             }
         }
-    };
+        ConfigLocations::Toml(_) => toml::from_str(&g).map_err(|e| e.to_string()),
+        ConfigLocations::Dhall(_) => serde_dhall::from_str(&g).parse().map_err(|e| e.to_string()),
+        ConfigLocations::Js(_) => match jsrun::run_js_and_deserialize::<CynthiaConf>(g.as_str()) {
+            RunJSAndDeserializeResult::Ok(p) => Ok(p),
+            RunJSAndDeserializeResult::JsError(e) => Err(e.to_string()),
+            RunJSAndDeserializeResult::SerdeError(e) => Err(e.to_string()),
+        },
+    }
 }
 
 pub(crate) fn save_config(to_ex: &str, config: CynthiaConf) -> PathBuf {
@@ -379,8 +304,30 @@ pub(crate) fn save_config(to_ex: &str, config: CynthiaConf) -> PathBuf {
     let args: Vec<String> = std::env::args().collect();
     let cd = std::env::current_dir().unwrap();
     // as a tuple, the first element is the key, the second is the comment, the third is the key in the config.
-    let comments: [(&str, &str, &str); 32] = [
+    let comments: [(&str, &str, &str); 62] = [
         ("port", "The port on which Cynthia hosts, since Cynthia was designed to be reverse-proxied, this port is usually higher than 1000.", "port"),
+        ("render_timeout", "Seconds a single page render may take before Cynthia gives up with a 503, 0 disables this deadline.", "render_timeout"),
+        ("workers", "Number of threads handling requests. Leave it out to use one per CPU core, lower it on small servers.", "workers"),
+        ("prerender_dir", "A directory with pre-rendered pages: `{id}.html`, and `index.html` for the home page. Cynthia serves these as they are, unless the publication list or the page's content file changed after they were written. Leave it out to always render.", "prerender_dir"),
+        ("timezone", "The timezone dates are shown in, by its IANA name like `Europe/Amsterdam` or `America/New_York`. Defaults to `UTC`.", "timezone"),
+        ("charset", "The character set pages are sent in, declared in their `Content-Type` header and `<meta charset>`. Defaults to `utf-8`, only change it when something reading the site can't handle UTF-8: characters the set doesn't have are sent as HTML character references.", "charset"),
+        ("root_dir", "The directory the site is in: the one holding `cynthiaFiles` and `cynthiaPlugins`. Relative to this configuration file, and every other relative path in here is relative to it. Defaults to `.`, the directory of this file.", "root_dir"),
+        ("home", "The id of the publication served at the site's root, `/`, like a post list to show the latest posts on the home page. Leave it out to serve the page with the id `root`.", "home"),
+        ("visits", "Counts how often each publication is visited when `enabled`, for `/api/stats` and as `meta.visits` in templates. The counts are written to `file` (relative to the site's directory, `visits.json` by default, an empty string keeps them in memory only) every `flush_interval` seconds and when Cynthia stops. 0 only writes them when stopping.", "visits"),
+        ("images", "Smaller copies of local images, made with ImageMagick. With `enabled = true`, `<img>` tags for JPEG and PNG files in the assets folder or a static mount become `<picture>` tags offering copies in `formats` (\"avif\" and \"webp\", preferred first), at most `max_width` pixels wide (0 keeps the size) and at `quality` (1 to 100). With `responsive = true`, copies 480 and 960 pixels wide are offered too, so phones don't download the full-width one. `converter` is the ImageMagick command, `magick` or `convert`. Copies are kept in `cache_dir`, and only made again when their source changes.", "images"),
+        ("sanitize", "Cleans up the HTML of publications, for sites with content from authors who shouldn't be able to put scripts on them. With `enabled = true`, tags and attributes that aren't allowed are stripped, scripts and styles with their content. `tags` lists the tags content may use, `attributes` the attributes it may use on every tag. Empty lists keep ammonia's defaults of harmless tags, and `lang` and `title`.", "sanitize"),
+        ("error_pages", "Templates in `cynthiaFiles/templates/error/` for the pages shown when something goes wrong, instead of Cynthia's plain built-in ones. `server_error` is shown when a page fails to render, with `status`, `message` and (with `--dev`) `details` to show. Leave it out for the built-in page.", "error_pages"),
+        ("webhooks", "Endpoints for systems that push content to Cynthia. Setting `reload` to a long random secret enables `POST /cynthia/reload`, which rereads the publication list and drops the cached pages that changed, for requests with an `Authorization: Bearer {secret}` header.", "webhooks"),
+        ("well_known", "What to serve under `/.well-known/`, by the path after it: `\"security.txt\" = { content = \"Contact: mailto:me@example.com\" }` or `acme-challenge = { file = \"acme/\" }`. A `file` that is a directory also serves the files in it. The content type follows the extension, or `content_type`. These go before plugins and publications.", "well_known"),
+        ("site_context", "Site-wide values for every template, like `description = \"Things I made\"` or `mastodon = \"https://mastodon.social/@me\"`. Templates read them as `{{site.description}}`, or as `{{description}}` when the publication has no value of that name itself.", "site_context"),
+        ("trusted_proxies", "The addresses (like `127.0.0.1`) or ranges (like `10.0.0.0/8`) of the reverse proxies in front of Cynthia. Only their `X-Forwarded-For`, `X-Forwarded-Proto` and `X-Forwarded-Host` headers are used, for logging and `server.canonical_redirect`, other requests have them removed. Defaults to the loopback addresses.", "trusted_proxies"),
+        ("missing_content", "Placeholder content files (in `cynthiaFiles/publications/`) per mode, shown instead of an error when a publication's local content file is missing, like a \"coming soon\" note. `markdown`, `html` and `plaintext` each name one, `status` is the status code the page is served with then (200 by default). The missing path is logged either way.", "missing_content"),
+        ("maintenance", "Takes the site offline for a moment: with `enabled = true`, requests get a `503` with the page in `page` (an HTML file), or a built-in one. Assets, static mounts and the favicon stay available unless `assets` is false, `/healthz` always does. Send Cynthia `SIGHUP` after changing it to apply it without a restart.", "maintenance"),
+        ("trailing_slash", "Which page URLs are canonical: \"remove\" redirects `/about/` to `/about`, \"add\" does the opposite and \"keep\" serves both.", "trailing_slash"),
+        ("security", "Security headers added to every response when `enabled`: `content_type_options`, `referrer_policy`, `content_security_policy` and `frame_options`. An empty string leaves that header out.\nThe default policy allows inline scripts and styles, since pages inline their scene's by default. Tighten it if you serve scripts from files (`site.inline_scripts = false`), or set `nonce = true` to allow only what Cynthia inlines itself.", "security"),
+        ("server", "How connections are handled. `keep_alive` is how many seconds an idle connection stays open for another request (0 closes it after every response). `http2` also accepts HTTP/2 without TLS (h2c), for a TLS-terminating proxy that talks HTTP/2 to Cynthia.\n`canonical_redirect` permanently redirects requests for other hosts (like `www.`) to the host of `site.site_baseurl`, and plain HTTP to HTTPS when the base URL uses it and the proxy sends `X-Forwarded-Proto`.", "server"),
+        ("debug", "Debugging aids for live instances. Setting `log_endpoint` to a long random token enables `/cynthia/logs`, which streams recent log lines to requests with an `Authorization: Bearer {token}` header. `timing_header = true` adds the milliseconds spent rendering and in plugins to every response, as `X-Cynthia-Render-Time` and `X-Cynthia-Plugin-Time`. Keep it off on public sites.", "debug"),
+        ("static_mounts", "Directories served as they are, each with a `prefix` (the URL path, like `/static`) and a `path` (the directory, relative to this file). Browsers may cache their files for `cache.lifetimes.assets` seconds.", "static_mounts"),
         ("cache", "The cache configuration for Cynthia.", "cache"),
             ("lifetimes", "These rules are set for a reason: The higher they are set, the less requests we have to do to Node, external servers, etc.\nHigher caching might consume a lot of memory or storage and crash the system.\nCaching can speed up Cynthia a whole lot, so think wisely before you change any of these numbers!", "cache.lifetimes"),
                 ("stylesheets", "How long (in seconds) to cache a CSS file after having minified and served it.", "cache.lifetimes.stylesheets"),
@@ -399,6 +346,18 @@ pub(crate) fn save_config(to_ex: &str, config: CynthiaConf) -> PathBuf {
                 ("enable_atom", "Whether to enable Atom or not. If enabled, Atom will be used to generate pages.", "site.meta.enable_atom"),
             ("site_baseurl", "The base URL of the site, used for generating links.", "site.site_baseurl"),
             ("og_sitename", "Site name for the site, this is different than the site name set in scenes, as it is mostly used for embeds, and so get's cached on url.", "site.og_sitename"),
+            ("inline_scripts", "Inline scene scripts into every page (true), or serve them from `/cynthia/client.js` so browsers can cache them (false).", "site.inline_scripts"),
+            ("favicon", "Path to the site's icon, relative to the assets folder. It is served at /favicon.ico and linked from every page.", "site.favicon"),
+            ("generator_comment", "Start every generated page with an HTML comment crediting Cynthia.", "site.generator_comment"),
+            ("default_lang", "The language served at /{lang}/{translation_key} when a publication has no translation in the requested language.", "site.default_lang"),
+            ("default_script", "The client script (in `cynthiaFiles/assets/`) for scenes that don't set a `script` of their own. A scene with `script = \"\"` gets no script at all.", "site.default_script"),
+            ("default_scene", "The scene for publications that don't set one, and that aren't in a category listed in category_scenes.", "site.default_scene"),
+            ("default_author_thumbnail", "The avatar shown for authors that don't have a thumbnail of their own. Must not be a javascript: URL.", "site.default_author_thumbnail"),
+            ("category_scenes", "Scenes for the posts in a category, and for its listing at /category/{name}, by category name. A post's own `scene` still wins.", "site.category_scenes"),
+            ("headers", "Extra headers sent with rendered pages, like `Content-Security-Policy`. Set site-wide under `site`, a scene's own `headers` override those with the same name.", "site.headers"),
+            ("shortcodes", "Delimiters around shortcodes in publication content, like `{{< youtube dQw4w9WgXcQ >}}`.", "site.shortcodes"),
+            ("excerpts", "Excerpts for posts without a `short`, shown in post lists: the post up to a `<!--more-->` marker, or its first `length` characters. With `strip_html` (the default) they are plain text, otherwise they keep the post's markup.", "site.excerpts"),
+            ("emoji", "Replace emoji shortcodes like `:smile:` and `:tada:` in publication content with the emoji they stand for, using GitHub's names. Code, preformatted text and names that aren't emoji are left alone.", "site.emoji"),
         ("logs", "The log configuration for Cynthia.", "logs"),
             ("term_loglevel", "The minimum level of importance (1-5) before Cynthia logs to the terminal.", "logs.term_loglevel"),
             ("file_loglevel", "The minimum level of importance (1-5) before Cynthia logs to a file.", "logs.file_loglevel"),
@@ -406,12 +365,14 @@ pub(crate) fn save_config(to_ex: &str, config: CynthiaConf) -> PathBuf {
         ("scenes", "Scenes allow Cynthia to switch it's behaviour and themes completely for certain pages.", "scenes"),
                 ("name", "The id of the scene, used for linking. Set to `default` for the default scene.", "scenes.name"),
                 ("sitename", "The name Cynthia uses for presenting the site when using this scene.", "scenes.sitename"),
-                ("script", "(Optional) A script that is served on pages using this scene.", "scenes.script"),
+                ("script", "(Optional) A script that is served on pages using this scene. Left out, `site.default_script` is used, an empty string serves no script.", "scenes.script"),
                 ("stylefile", "(Optional) A CSS file that is served on pages using this scene.", "scenes.stylefile"),
                 ("templates", "The template of the scene, used for display.", "scenes.templates"),
                     ("page", "The handlebars template for serving pages using this sceme", "scenes.templates.page"),
                     ("post", "The handlebars template for serving posts using this sceme", "scenes.templates.post"),
                     ("postlist", "The handlebars template for serving postlist pages using this sceme", "scenes.templates.postlist"),
+                    ("categories", "(Optional) Post templates by category name, used instead of `post` for the posts in that category.", "scenes.templates.categories"),
+        ("plugin_options", "Operator control over which plugins run. `enabled = false` stops all plugins, `allow` (when not empty) and `deny` list plugins by name.\nWith `abort_on_start_failure`, a failing `onStart` hook stops Cynthia from starting.\n`max_concurrent` caps how many plugin requests are handled at once, so a burst of renders queues instead of piling up on the runtime. 0 means no limit.\n`max_output_bytes` is the largest a single plugin response may be, larger ones are dropped with an error as if no plugin answered. 0 means no limit.\nPlugins change pages one after another, lowest `order` (set per plugin, 0 by default) first, and alphabetically for the same `order`.\nA plugin that changes a page the same way every time it's given the same page can set `cacheable = true`. When every plugin that runs is cacheable, rendered templates and shortcodes are kept for `cache.lifetimes.forwarded` seconds instead of asking the plugins again.\nWithout a working JavaScript runtime, Cynthia refuses to start when plugins are configured, unless `optional` is set: then it runs without them.\n`debug = true` logs what each plugin runner is given and returns, shortened, at trace level (`logs.file_loglevel = 5`). For writing plugins, not for live sites.\n`timing = true` logs how long each plugin's runners took, added up per request, at debug level (`logs.file_loglevel = 4`).\n`dump_commands` names a directory to write every command sent to the plugin runtime to, one JSON file each. Off when unset.", "plugin_options"),
     ];
     // JSONC is generated multiple times, so we need to make a function for it.
     // This function is used to generate JSONC.
@@ -437,6 +398,28 @@ pub(crate) fn save_config(to_ex: &str, config: CynthiaConf) -> PathBuf {
         serde_json::to_string_pretty(&config)
             .unwrap()
             .replace("\"port\":", &comment_this("port"))
+            .replace("\"render_timeout\":", &comment_this("render_timeout"))
+            .replace("\"workers\":", &comment_this("workers"))
+            .replace("\"prerender_dir\":", &comment_this("prerender_dir"))
+            .replace("\"timezone\":", &comment_this("timezone"))
+            .replace("\"charset\":", &comment_this("charset"))
+            .replace("\"root_dir\":", &comment_this("root_dir"))
+            .replace("\"home\":", &comment_this("home"))
+            .replace("\"webhooks\":", &comment_this("webhooks"))
+            .replace("\"visits\":", &comment_this("visits"))
+            .replace("\"images\":", &comment_this("images"))
+            .replace("\"sanitize\":", &comment_this("sanitize"))
+            .replace("\"error_pages\":", &comment_this("error_pages"))
+            .replace("\"well_known\":", &comment_this("well_known"))
+            .replace("\"site_context\":", &comment_this("site_context"))
+            .replace("\"trusted_proxies\":", &comment_this("trusted_proxies"))
+            .replace("\"missing_content\":", &comment_this("missing_content"))
+            .replace("\"maintenance\":", &comment_this("maintenance"))
+            .replace("\"trailing_slash\":", &comment_this("trailing_slash"))
+            .replace("\"security\":", &comment_this("security"))
+            .replace("\"server\":", &comment_this("server"))
+            .replace("\"debug\":", &comment_this("debug"))
+            .replace("\"static_mounts\":", &comment_this("static_mounts"))
             .replace("\"cache\":", &comment_this("cache"))
             .replace("\"lifetimes\":", &comment_this("cache.lifetimes"))
             .replace("\"forwarded\":", &comment_this("cache.lifetimes.forwarded"))
@@ -468,6 +451,27 @@ pub(crate) fn save_config(to_ex: &str, config: CynthiaConf) -> PathBuf {
             .replace("\"enable_atom\":", &comment_this("site.meta.enable_atom"))
             .replace("\"site_baseurl\":", &comment_this("site.site_baseurl"))
             .replace("\"og_sitename\":", &comment_this("site.og_sitename"))
+            .replace("\"inline_scripts\":", &comment_this("site.inline_scripts"))
+            .replace("\"favicon\":", &comment_this("site.favicon"))
+            .replace("\"emoji\":", &comment_this("site.emoji"))
+            .replace(
+                "\"generator_comment\":",
+                &comment_this("site.generator_comment"),
+            )
+            .replace("\"default_lang\":", &comment_this("site.default_lang"))
+            .replace("\"default_scene\":", &comment_this("site.default_scene"))
+            .replace("\"default_script\":", &comment_this("site.default_script"))
+            .replace(
+                "\"default_author_thumbnail\":",
+                &comment_this("site.default_author_thumbnail"),
+            )
+            .replace(
+                "\"category_scenes\":",
+                &comment_this("site.category_scenes"),
+            )
+            .replace("\"headers\":", &comment_this("site.headers"))
+            .replace("\"shortcodes\":", &comment_this("site.shortcodes"))
+            .replace("\"excerpts\":", &comment_this("site.excerpts"))
             .replace("\"logs\":", &comment_this("logs"))
             .replace("\"term_loglevel\":", &comment_this("logs.term_loglevel"))
             .replace("\"file_loglevel\":", &comment_this("logs.file_loglevel"))
@@ -481,6 +485,7 @@ pub(crate) fn save_config(to_ex: &str, config: CynthiaConf) -> PathBuf {
             .replace("\"page\":", &comment_this("scenes.templates.page"))
             .replace("\"post\":", &comment_this("scenes.templates.post"))
             .replace("\"postlist\":", &comment_this("scenes.templates.postlist"))
+            .replace("\"plugin_options\":", &comment_this("plugin_options"))
     };
 
     let config_serialised: String = match to {
@@ -525,6 +530,28 @@ pub(crate) fn save_config(to_ex: &str, config: CynthiaConf) -> PathBuf {
                     .replace("}", "\n}\n")
                     .replace("\n", "\n ")
                     .replace(" port =", &comment_this("port"))
+                    .replace(" render_timeout =", &comment_this("render_timeout"))
+                    .replace(" workers =", &comment_this("workers"))
+                    .replace(" prerender_dir =", &comment_this("prerender_dir"))
+                    .replace(" timezone =", &comment_this("timezone"))
+                    .replace(" charset =", &comment_this("charset"))
+                    .replace(" root_dir =", &comment_this("root_dir"))
+                    .replace(" home =", &comment_this("home"))
+                    .replace(" webhooks =", &comment_this("webhooks"))
+                    .replace(" visits =", &comment_this("visits"))
+                    .replace(" images =", &comment_this("images"))
+                    .replace(" sanitize =", &comment_this("sanitize"))
+                    .replace(" error_pages =", &comment_this("error_pages"))
+                    .replace(" well_known =", &comment_this("well_known"))
+                    .replace(" site_context =", &comment_this("site_context"))
+                    .replace(" trusted_proxies =", &comment_this("trusted_proxies"))
+                    .replace(" missing_content =", &comment_this("missing_content"))
+                    .replace(" maintenance =", &comment_this("maintenance"))
+                    .replace(" trailing_slash =", &comment_this("trailing_slash"))
+                    .replace(" security =", &comment_this("security"))
+                    .replace(" server =", &comment_this("server"))
+                    .replace(" debug =", &comment_this("debug"))
+                    .replace(" static_mounts =", &comment_this("static_mounts"))
                     .replace(" cache =", &comment_this("cache"))
                     .replace(
                         " lifetimes =",
@@ -547,6 +574,18 @@ pub(crate) fn save_config(to_ex: &str, config: CynthiaConf) -> PathBuf {
                             .replace(" enable_atom =", &comment_this("site.meta.enable_atom"))
                         .replace(" site_baseurl =", &comment_this("site.site_baseurl"))
                         .replace(" og_sitename =", &comment_this("site.og_sitename"))
+                        .replace(" inline_scripts =", &comment_this("site.inline_scripts"))
+                        .replace(" favicon =", &comment_this("site.favicon"))
+                        .replace(" emoji =", &comment_this("site.emoji"))
+                        .replace(" generator_comment =", &comment_this("site.generator_comment"))
+                        .replace(" default_lang =", &comment_this("site.default_lang"))
+                        .replace(" default_scene =", &comment_this("site.default_scene"))
+                        .replace(" default_script =", &comment_this("site.default_script"))
+                        .replace(" default_author_thumbnail =", &comment_this("site.default_author_thumbnail"))
+                        .replace(" category_scenes =", &comment_this("site.category_scenes"))
+                        .replace(" headers =", &comment_this("site.headers"))
+                        .replace(" shortcodes =", &comment_this("site.shortcodes"))
+                        .replace(" excerpts =", &comment_this("site.excerpts"))
                     .replace(" logs =", &comment_this("logs"))
                         .replace(" term_loglevel =", &comment_this("logs.term_loglevel"))
                         .replace(" file_loglevel =", &comment_this("logs.file_loglevel"))
@@ -560,6 +599,7 @@ pub(crate) fn save_config(to_ex: &str, config: CynthiaConf) -> PathBuf {
                             .replace(" page =", &comment_this("scenes.templates.page"))
                             .replace(" post =", &comment_this("scenes.templates.post"))
                             .replace(" postlist =", &comment_this("scenes.templates.postlist"))
+                    .replace(" plugin_options =", &comment_this("plugin_options"))
             )
         }
         "toml" => {
@@ -584,6 +624,88 @@ pub(crate) fn save_config(to_ex: &str, config: CynthiaConf) -> PathBuf {
                 .unwrap()
                 .replace("\n","\n ")
                 .replace(" port = ", &comment_this("port"))
+                .replace(" render_timeout = ", &comment_this("render_timeout"))
+                .replace(" workers = ", &comment_this("workers"))
+                .replace(" prerender_dir = ", &comment_this("prerender_dir"))
+                .replace(" timezone = ", &comment_this("timezone"))
+                .replace(" charset = ", &comment_this("charset"))
+                .replace(" root_dir = ", &comment_this("root_dir"))
+                .replace(" home = ", &comment_this("home"))
+                .replace(" trusted_proxies = ", &comment_this("trusted_proxies"))
+                .replace(" trailing_slash = ", &comment_this("trailing_slash"))
+                .replace(" static_mounts = ", &comment_this("static_mounts"))
+                .replace(
+                    " [security]",
+                    comment_this("security")
+                        .replace("security = ", "[security]")
+                        .as_str(),
+                )
+                .replace(
+                    " [server]",
+                    comment_this("server")
+                        .replace("server = ", "[server]")
+                        .as_str(),
+                )
+                .replace(
+                    " [well_known]",
+                    comment_this("well_known")
+                        .replace("well_known = ", "[well_known]")
+                        .as_str(),
+                )
+                .replace(
+                    " [site_context]",
+                    comment_this("site_context")
+                        .replace("site_context = ", "[site_context]")
+                        .as_str(),
+                )
+                .replace(
+                    " [maintenance]",
+                    comment_this("maintenance")
+                        .replace("maintenance = ", "[maintenance]")
+                        .as_str(),
+                )
+                .replace(
+                    " [missing_content]",
+                    comment_this("missing_content")
+                        .replace("missing_content = ", "[missing_content]")
+                        .as_str(),
+                )
+                .replace(
+                    " [webhooks]",
+                    comment_this("webhooks")
+                        .replace("webhooks = ", "[webhooks]")
+                        .as_str(),
+                )
+                .replace(
+                    " [visits]",
+                    comment_this("visits")
+                        .replace("visits = ", "[visits]")
+                        .as_str(),
+                )
+                .replace(
+                    " [images]",
+                    comment_this("images")
+                        .replace("images = ", "[images]")
+                        .as_str(),
+                )
+                .replace(
+                    " [sanitize]",
+                    comment_this("sanitize")
+                        .replace("sanitize = ", "[sanitize]")
+                        .as_str(),
+                )
+                .replace(
+                    " [error_pages]",
+                    comment_this("error_pages")
+                        .replace("error_pages = ", "[error_pages]")
+                        .as_str(),
+                )
+                .replace(
+                    " [debug]",
+                    comment_this("debug")
+                        .replace("debug = ", "[debug]")
+                        .as_str(),
+                )
                 .replace(
                     " [cache.lifetimes]",
                     comment_this("cache.lifetimes")
@@ -627,6 +749,38 @@ pub(crate) fn save_config(to_ex: &str, config: CynthiaConf) -> PathBuf {
                 .replace(" enable_atom = ", &comment_this("site.meta.enable_atom"))
                 .replace(" site_baseurl = ", &comment_this("site.site_baseurl"))
                 .replace(" og_sitename = ", &comment_this("site.og_sitename"))
+                .replace(" inline_scripts = ", &comment_this("site.inline_scripts"))
+                .replace(" favicon = ", &comment_this("site.favicon"))
+                .replace(" emoji = ", &comment_this("site.emoji"))
+                .replace(" generator_comment = ", &comment_this("site.generator_comment"))
+                .replace(" default_lang = ", &comment_this("site.default_lang"))
+                .replace(" default_scene = ", &comment_this("site.default_scene"))
+                .replace(" default_script = ", &comment_this("site.default_script"))
+                .replace(" default_author_thumbnail = ", &comment_this("site.default_author_thumbnail"))
+                .replace(
+                    " [site.headers]",
+                    comment_this("site.headers")
+                        .replace("headers = ", "[site.headers]")
+                        .as_str(),
+                )
+                .replace(
+                    " [site.category_scenes]",
+                    comment_this("site.category_scenes")
+                        .replace("category_scenes = ", "[site.category_scenes]")
+                        .as_str(),
+                )
+                .replace(
+                    " [site.shortcodes]",
+                    comment_this("site.shortcodes")
+                        .replace("shortcodes = ", "[site.shortcodes]")
+                        .as_str(),
+                )
+                .replace(
+                    " [site.excerpts]",
+                    comment_this("site.excerpts")
+                        .replace("excerpts = ", "[site.excerpts]")
+                        .as_str(),
+                )
                 .replace(
                     " [logs]",
                     comment_this("logs")
@@ -650,6 +804,18 @@ pub(crate) fn save_config(to_ex: &str, config: CynthiaConf) -> PathBuf {
                         .replace(" page = ", &comment_this("scenes.templates.page"))
                         .replace(" post = ", &comment_this("scenes.templates.post"))
                         .replace(" postlist = ", &comment_this("scenes.templates.postlist"))
+                .replace(
+                    " [scenes.headers]",
+                    comment_this("site.headers")
+                        .replace("headers = ", "[scenes.headers]")
+                        .as_str(),
+                )
+                .replace(
+                    " [plugin_options]",
+                    comment_this("plugin_options")
+                        .replace("plugin_options = ", "[plugin_options]")
+                        .as_str(),
+                )
             )
         }
         "jsonc" => {
@@ -670,7 +836,7 @@ pub(crate) fn save_config(to_ex: &str, config: CynthiaConf) -> PathBuf {
     } else {
         cd.join("Cynthia.".to_string() + to)
     };
-    match fs::write(to_file.clone(), config_serialised) {
+    match crate::helpers::atomic_write(&to_file, config_serialised) {
         Ok(_) => {
             if args.get(1) == Some(&String::from("convert")) {
                 println!(
@@ -730,7 +896,7 @@ pub(crate) fn save_config(to_ex: &str, config: CynthiaConf) -> PathBuf {
 }
 
 pub(crate) fn choose_config_location_option() -> Option<ConfigLocations> {
-    let cd = std::env::current_dir().unwrap();
+    let cd = config_dir();
     // In order of preference for Cynthia. I personally prefer TOML, but Cynthia would prefer Dhall. Besides, Dhall is far more powerful.
     // JS, Dhall, TOML, jsonc
     let config_locations: [ConfigLocations; 4] = [
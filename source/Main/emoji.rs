@@ -0,0 +1,91 @@
+/*
+ * Copyright (c) 2024, MLC 'Strawmelonjuice' Bloeiman
+ *
+ * Licensed under the GNU AFFERO GENERAL PUBLIC LICENSE Version 3, see the LICENSE file for more information.
+ */
+//! Emoji shortcodes like `:smile:` in publication content, replaced with the emoji they stand for.
+//! The names are GitHub's, the ones most authors know.
+use std::sync::LazyLock;
+
+use regex::Regex;
+
+/// Markup, and the elements whose text isn't prose.
+static SKIPPED: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(
+        r"(?is)<pre\b.*?</pre>|<code\b.*?</code>|<script\b.*?</script>|<style\b.*?</style>|<textarea\b.*?</textarea>|<[^>]*>",
+    )
+    .unwrap()
+});
+
+/// Replaces the emoji shortcodes in rendered `html` with their emoji. Markup, and text in code,
+/// preformatted blocks, scripts, styles and text areas, is left as it is, and so are names that aren't
+/// emoji, like the `:30:` in `12:30:45`.
+pub(crate) fn replace(html: &str) -> String {
+    let mut replaced = String::with_capacity(html.len());
+    let mut last = 0;
+    for skipped in SKIPPED.find_iter(html) {
+        replaced.push_str(&replace_in_text(&html[last..skipped.start()]));
+        replaced.push_str(skipped.as_str());
+        last = skipped.end();
+    }
+    replaced.push_str(&replace_in_text(&html[last..]));
+    replaced
+}
+
+/// Replaces the shortcodes in text without markup. The colon closing a name that isn't an emoji
+/// can still open the next one, as in `12:30:tada:`.
+fn replace_in_text(text: &str) -> String {
+    let mut replaced = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find(':') {
+        replaced.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        let emoji = after.find(':').and_then(|end| {
+            let name = &after[..end];
+            if name.is_empty()
+                || !name
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || "_+-".contains(c))
+            {
+                return None;
+            }
+            emojis::get_by_shortcode(name).map(|emoji| (emoji, end))
+        });
+        match emoji {
+            Some((emoji, end)) => {
+                replaced.push_str(emoji.as_str());
+                rest = &after[end + 1..];
+            }
+            None => {
+                replaced.push(':');
+                rest = after;
+            }
+        }
+    }
+    replaced.push_str(rest);
+    replaced
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replaces_known_shortcodes() {
+        assert_eq!(
+            replace("<p>Done :tada: :+1:</p>"),
+            "<p>Done \u{1F389} \u{1F44D}</p>"
+        );
+        assert_eq!(replace("<p>:tada::smile:</p>"), "<p>\u{1F389}\u{1F604}</p>");
+        assert_eq!(replace("<p>12:30:tada:</p>"), "<p>12:30\u{1F389}</p>");
+        // Unknown names, and shortcodes in code or markup, stay.
+        for html in [
+            "<p>Meet at 12:30:45, :not-an-emoji:</p>",
+            "<p>Type <code>:smile:</code> for a smile.</p>",
+            "<pre><code>let face = \":smile:\";</code></pre>",
+            "<img alt=\":smile:\" src=\"/smile.png\">",
+        ] {
+            assert_eq!(replace(html), html);
+        }
+    }
+}
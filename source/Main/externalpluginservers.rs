@@ -13,6 +13,11 @@
 use crate::config::ConfigExternalJavascriptRuntime;
 use crate::config::CynthiaConfClone;
 
+/// The version of the protocol Cynthia speaks with its plugin runners over STDIO.
+/// Bump this whenever the shape of requests or responses changes, and keep it in sync with
+/// `ProtocolVersion` in `node-plugin-api/main.ts`.
+pub(crate) const EPS_PROTOCOL_VERSION: u32 = 5;
+
 #[cfg(feature = "js_runtime")]
 #[derive(Debug)]
 pub(crate) struct EPSCommunicationData {
@@ -25,6 +30,12 @@ pub(crate) struct EPSCommunicationData {
     /// The IDs that have been sent to the external plugin servers but have not been returned yet.
     #[cfg(feature = "js_runtime")]
     unreturned_ids: Vec<EPSCommunicationsID>,
+    /// Limits the requests in flight to `plugin_options.max_concurrent`, along with the limit it was made for.
+    #[cfg(feature = "js_runtime")]
+    permits: Option<(u16, Arc<tokio::sync::Semaphore>)>,
+    /// The prefixes of the plugins' `handleRoute` runners, as the runtime told when it started.
+    #[cfg(feature = "js_runtime")]
+    routes: Option<Vec<String>>,
 }
 
 #[cfg(feature = "js_runtime")]
@@ -35,6 +46,32 @@ impl EPSCommunicationData {
             sender,
             response_queue: vec![],
             unreturned_ids: vec![],
+            permits: None,
+            routes: None,
+        }
+    }
+    /// Whether a plugin route may hold `path`. Only `false` once the runtime told which prefixes its
+    /// plugins handle, and `path` isn't under any of them.
+    pub(crate) fn may_route(&self, path: &str) -> bool {
+        self.routes.as_ref().is_none_or(|routes| {
+            routes
+                .iter()
+                .any(|prefix| path == prefix || path.starts_with(&format!("{}/", prefix)))
+        })
+    }
+    /// The semaphore for `max_concurrent`, remade when the limit changed, or none without a limit.
+    fn permits(&mut self, max_concurrent: u16) -> Option<Arc<tokio::sync::Semaphore>> {
+        if max_concurrent == 0 {
+            self.permits = None;
+            return None;
+        }
+        match &self.permits {
+            Some((limit, semaphore)) if *limit == max_concurrent => Some(semaphore.clone()),
+            _ => {
+                let semaphore = Arc::new(tokio::sync::Semaphore::new(max_concurrent as usize));
+                self.permits = Some((max_concurrent, semaphore.clone()));
+                Some(semaphore)
+            }
         }
     }
 }
@@ -44,8 +81,6 @@ use std::process::Command;
 use std::sync::Arc;
 
 use actix_web::web::Data;
-#[cfg(feature = "js_runtime")]
-use interactive_process::InteractiveProcess;
 
 #[cfg(feature = "js_runtime")]
 use log::warn;
@@ -65,12 +100,21 @@ use crate::{cache::tempfolder, config::CynthiaConfig};
 #[derive(Serialize, Deserialize, Debug)]
 pub(crate) struct EPSRequest {
     id: EPSCommunicationsID,
+    /// The id of the web request this is made for, so the runtime can put it in its log lines.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    request_id: Option<String>,
     pub(crate) body: EPSRequestBody,
 }
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(tag = "for")]
 pub(crate) enum EPSRequestBody {
     Close,
+    /// Runs the `onStart` hooks of the loaded plugins.
+    StartRequest,
+    /// Restarts the plugin runtime with the current configuration. Handled by Cynthia itself,
+    /// this never reaches the runtime.
+    Reload,
     Test {
         test: String,
     },
@@ -87,6 +131,23 @@ pub(crate) enum EPSRequestBody {
         headers: Vec<(String, String)>, // Name, Value
         method: String,
     },
+    /// Asks the plugins to expand a shortcode Cynthia doesn't know itself.
+    ShortcodeRequest {
+        shortcode: crate::shortcodes::Shortcode,
+    },
+    /// Runs the plugins' `modifyResponseHTML` runners over a whole rendered page.
+    OutputRequest {
+        html: String,
+        meta: crate::renders::PageLikePublicationTemplateDataMeta,
+    },
+    /// Passes a request on to the plugin whose `handleRoute` prefix the path is under, if any.
+    RouteRequest {
+        method: String,
+        path: String,
+        query: String,
+        headers: Vec<(String, String)>,
+        body: String,
+    },
 }
 #[derive(Serialize, Deserialize, Debug, Clone)]
 struct EPSResponse {
@@ -110,6 +171,18 @@ pub(crate) enum EPSResponseBody {
     Error {
         message: Option<String>,
     },
+    /// A plugin's `handleRoute` answer, served as it is.
+    RouteResponse {
+        status: u16,
+        headers: Vec<(String, String)>,
+        body: String,
+    },
+    /// The answer to a `StartRequest`: the prefixes the plugins' routes took, and the `onStart`
+    /// hooks that failed.
+    Started {
+        routes: Vec<String>,
+        failures: Vec<String>,
+    },
     Disabled,
 }
 
@@ -123,24 +196,19 @@ pub(crate) async fn main(
     server_context_mutex: Arc<Mutex<ServerContext>>,
     mut eps_r: Receiver<EPSRequest>,
 ) {
-    let config_clone = {
+    let mut config_clone = {
         // We need to clone the config because we can't hold the lock while we're in the tokio runtime.
         let server_context = server_context_mutex.lock().await;
         server_context.config.clone()
     };
     // We gotta write the javascript to a temporary file and then run it.
-    let jstempfolder = tempfolder().join("js");
-    std::fs::create_dir_all(&jstempfolder).unwrap();
-    let jsfile = include_bytes!("../../target/generated/js/plugins-runtime.js");
-    std::fs::write(jstempfolder.join("main.mjs"), jsfile).unwrap();
+    write_runner().unwrap();
     // now we can run the javascript
-    let external_js_runtime_binary: &str = config_clone.runtimes.ext_js_rt.as_ref();
-
     let p = Arc::new(std::sync::Mutex::new(String::new()));
     let ctx_clone = server_context_mutex.clone();
     let mut proc = new_proc(
         fun_name(
-            external_js_runtime_binary,
+            config_clone.runtimes.ext_js_rt.as_ref(),
             &config_clone,
             &mut eps_r,
             &server_context_mutex,
@@ -158,7 +226,33 @@ pub(crate) async fn main(
         // It needs to wait before sending thru, though, because multiple lines will overflow the js buffer.
 
         if let Some(o) = eps_r.recv().await {
+            if let EPSRequestBody::Reload = o.body {
+                config_clone = server_context_mutex.lock().await.config.clone();
+                let mut old = proc.close();
+                let _ = old.kill();
+                let _ = old.wait();
+                proc = new_proc(
+                    fun_name(
+                        config_clone.runtimes.ext_js_rt.as_ref(),
+                        &config_clone,
+                        &mut eps_r,
+                        &server_context_mutex,
+                    )
+                    .await,
+                    p.clone(),
+                    ctx_clone.clone(),
+                    config_clone.clone(),
+                );
+                info!("Restarted the plugin runtime.");
+                let done = EPSResponse {
+                    id: o.id,
+                    body: EPSResponseBody::NoneOk,
+                };
+                and_now(done, server_context_mutex.clone()).await;
+                continue;
+            }
             wait.tick().await;
+            dump_command(config_clone.plugin_options.dump_commands.as_deref(), &o);
             let mut s = String::from("parse: ");
             s.push_str(serde_json::to_string(&o).unwrap().as_str());
             debug!("Sending to JsPluginRuntime: `{}`", s);
@@ -170,7 +264,7 @@ pub(crate) async fn main(
                     // The process has died. Restart it.
                     proc = new_proc(
                         fun_name(
-                            external_js_runtime_binary,
+                            config_clone.runtimes.ext_js_rt.as_ref(),
                             &config_clone,
                             &mut eps_r,
                             &server_context_mutex,
@@ -197,14 +291,152 @@ pub(crate) async fn main(
     }
 }
 
+/// Writes a command for the plugin runtime to the `plugin_options.dump_commands` directory, if set, as
+/// `{milliseconds since the epoch}-{id}-{kind}.json`. Returns the file it was written to, failures are only logged.
+#[cfg(feature = "js_runtime")]
+fn dump_command(dir: Option<&str>, request: &EPSRequest) -> Option<std::path::PathBuf> {
+    let dir = std::path::Path::new(dir?);
+    let json = serde_json::to_value(request).ok()?;
+    let kind = json["body"]["for"]
+        .as_str()
+        .unwrap_or("Unknown")
+        .to_string();
+    let millis = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_millis())
+        .unwrap_or_default();
+    let file = dir.join(format!("{}-{}-{}.json", millis, request.id, kind));
+    let written = std::fs::create_dir_all(dir).and_then(|_| {
+        std::fs::write(
+            &file,
+            serde_json::to_string_pretty(&json).unwrap_or_default(),
+        )
+    });
+    match written {
+        Ok(_) => Some(file),
+        Err(e) => {
+            warn!(
+                "Could not write the plugin command to {}: {}",
+                file.display(),
+                e
+            );
+            None
+        }
+    }
+}
+
+/// Writes the bundled plugin runner to the temporary folder, returning where it went.
+#[cfg(feature = "js_runtime")]
+fn write_runner() -> std::io::Result<std::path::PathBuf> {
+    let jstempfolder = tempfolder().join("js");
+    std::fs::create_dir_all(&jstempfolder)?;
+    let jsfile = include_bytes!("../../target/generated/js/plugins-runtime.js");
+    let runner = jstempfolder.join("main.mjs");
+    std::fs::write(&runner, jsfile)?;
+    Ok(runner)
+}
+
+/// The command running the plugin runner at `runner` with `external_js_runtime_binary`.
+#[cfg(feature = "js_runtime")]
+fn runtime_command(
+    external_js_runtime_binary: &str,
+    runner: &std::path::Path,
+    config_clone: &CynthiaConfClone,
+) -> Command {
+    let mut r = Command::new(external_js_runtime_binary);
+    if external_js_runtime_binary.contains("deno") {
+        r.arg("run");
+        r.arg("--allow-read");
+    }
+    if external_js_runtime_binary.contains("node") {
+        r.arg("--expose-gc");
+    } else if external_js_runtime_binary.contains("deno") {
+        r.arg("--v8-flags=--predictable-gc-schedule");
+    }
+    r.arg(runner);
+    r.args([
+        "--config",
+        serde_json::to_string(&runtime_config(config_clone))
+            .unwrap()
+            .as_str(),
+    ]);
+    r.args(["--protocol", EPS_PROTOCOL_VERSION.to_string().as_str()]);
+    r
+}
+
+/// Starts the plugin runner without any plugins and sends it a `Test` request, checking its answer
+/// makes it back. Used by `cynthiaweb check-plugins`.
+#[cfg(feature = "js_runtime")]
+pub(crate) fn roundtrip(config: &crate::config::CynthiaConf) -> Result<(), String> {
+    let binary = config.runtimes.ext_js_rt.clone();
+    let mut config = config.hard_clone();
+    config.plugins.clear();
+    let runner = write_runner().map_err(|e| format!("could not write the plugin runner: {}", e))?;
+    let (lines, answers) = std::sync::mpsc::channel();
+    let mut process = RuntimeProcess::new(
+        &mut runtime_command(&binary, &runner, &Arc::new(config)),
+        usize::MAX,
+        move |line| {
+            if let Ok(RuntimeLine::Whole(line)) = line {
+                let _ = lines.send(line);
+            }
+        },
+    )
+    .map_err(|e| format!("could not run `{}`: {}", binary, e))?;
+    let request = EPSRequest {
+        id: 0,
+        request_id: None,
+        body: EPSRequestBody::Test {
+            test: String::from("roundtrip"),
+        },
+    };
+    let sent = process.send(&format!(
+        "parse: {}",
+        serde_json::to_string(&request).unwrap()
+    ));
+    let deadline = std::time::Instant::now() + std::time::Duration::from_secs(10);
+    let mut buffer = String::new();
+    let mut answer = None;
+    while sent.is_ok() && answer.is_none() {
+        let left = deadline.saturating_duration_since(std::time::Instant::now());
+        let Ok(line) = answers.recv_timeout(left) else {
+            break;
+        };
+        if let Some(output) = line.strip_prefix("parse: ") {
+            if let RuntimeOutput::Response(response) = take_output(&mut buffer, output, 0) {
+                answer = Some(response);
+            }
+        }
+    }
+    let mut child = process.close();
+    let _ = child.kill();
+    let _ = child.wait();
+    match answer.map(|response| response.body) {
+        Some(EPSResponseBody::OkString { value }) if value.contains("\"roundtrip\"") => Ok(()),
+        Some(body) => Err(format!(
+            "the plugin runner answered a test request with {:?}",
+            body
+        )),
+        None => Err(format!(
+            "the plugin runner did not answer a test request through `{}`",
+            binary
+        )),
+    }
+}
+
+#[cfg(not(feature = "js_runtime"))]
+pub(crate) fn roundtrip(_: &crate::config::CynthiaConf) -> Result<(), String> {
+    Err(String::from(
+        "this Cynthia is built without the JavaScript runtime",
+    ))
+}
+
 async fn fun_name(
     external_js_runtime_binary: &str,
     config_clone: &CynthiaConfClone,
     eps_r: &mut Receiver<EPSRequest>,
     server_context_mutex: &Arc<Mutex<ServerContext>>,
 ) -> Command {
-    let jstempfolder = tempfolder().join("js");
-    let mut r = Command::new(external_js_runtime_binary);
     if config_clone.runtimes.ext_js_rt.validate().is_err() {
         error!("Invalid node runtime path. Plugins will not run.");
         loop {
@@ -229,23 +461,56 @@ async fn fun_name(
             }
         }
     };
-    if external_js_runtime_binary.contains("deno") {
-        r.arg("run");
-        r.arg("--allow-read");
+    runtime_command(
+        external_js_runtime_binary,
+        &tempfolder().join("js").join("main.mjs"),
+        config_clone,
+    )
+}
+
+/// The configuration as passed on to the plugin runtime.
+#[derive(Serialize)]
+struct RuntimeConfig {
+    #[serde(flatten)]
+    config: crate::config::CynthiaConf,
+    /// The paths Cynthia answers itself and its static mounts, which plugin routes can't take over.
+    reserved_paths: Vec<String>,
+}
+
+/// The configuration as passed on to the plugin runtime, with only the plugins that are permitted to run.
+fn runtime_config(config_clone: &CynthiaConfClone) -> RuntimeConfig {
+    let mut config = config_clone.hard_clone();
+    // Plugins have no business with the log endpoint's token, or the webhook secrets.
+    config.debug.log_endpoint = None;
+    config.webhooks.reload = None;
+    if !config.plugin_options.enabled && !config.plugins.is_empty() {
+        info!("Plugins are disabled through `plugin_options.enabled`, none of them will run.");
     }
-    if external_js_runtime_binary.contains("node") {
-        r.arg("--expose-gc");
-    } else if external_js_runtime_binary.contains("deno") {
-        r.arg("--v8-flags=--predictable-gc-schedule");
+    let plugin_options = config.plugin_options.clone();
+    config.plugins.retain(|plugin| {
+        let permitted = plugin_options.permits(&plugin.get_name());
+        if !permitted && plugin_options.enabled {
+            info!(
+                "Plugin `{}` is not permitted to run by `plugin_options`, skipping its runners.",
+                plugin.get_name()
+            );
+        }
+        permitted
+    });
+    let reserved_paths = crate::requestresponse::RESERVED_PATHS
+        .iter()
+        .map(|path| path.to_string())
+        .chain(
+            config
+                .static_mounts
+                .iter()
+                .map(|mount| mount.prefix.clone()),
+        )
+        .collect();
+    RuntimeConfig {
+        config,
+        reserved_paths,
     }
-    r.arg(jstempfolder.join("main.mjs"));
-    r.args([
-        "--config",
-        serde_json::to_string(&config_clone.hard_clone())
-            .unwrap()
-            .as_str(),
-    ]);
-    r
 }
 
 fn new_proc(
@@ -253,23 +518,66 @@ fn new_proc(
     p: Arc<std::sync::Mutex<String>>,
     ctx_clone: Arc<Mutex<ServerContext>>,
     config_clone: CynthiaConfClone,
-) -> InteractiveProcess {
+) -> RuntimeProcess {
     use crate::tell::CynthiaColors;
     let rt = tokio::runtime::Runtime::new().unwrap();
 
-    InteractiveProcess::new(&mut r, move |line| {
+    let order = config_clone
+        .plugins
+        .iter()
+        .filter(|plugin| plugin.runs(&config_clone.plugin_options))
+        .map(|plugin| plugin.get_name())
+        .collect::<Vec<String>>();
+    if !order.is_empty() {
+        info!("Plugins run in this order: {}", order.join(", "));
+    }
+    let max_output_bytes = config_clone.plugin_options.max_output_bytes;
+    // A response line can't hold more than the limit, and isn't read further than that.
+    let max_line_bytes = match max_output_bytes {
+        0 => usize::MAX,
+        max => usize::try_from(max)
+            .unwrap_or(usize::MAX)
+            .saturating_add("parse: ".len()),
+    };
+    RuntimeProcess::new(&mut r, max_line_bytes, move |line| {
         let y = p.clone();
-        if let Ok(o) = line {
+        if let Ok(line) = line {
+            let (o, cut) = match line {
+                RuntimeLine::Whole(o) => (o, false),
+                RuntimeLine::Cut(start) => (start, true),
+            };
             if o.starts_with("parse: ") {
                 let l = o.split("parse: ").collect::<Vec<&str>>()[1];
                 let mut z = y.lock().unwrap();
-                z.push_str(l);
-                debug!("JsPluginRuntime is now parsing `{l}` of `{z}`");
-                let q = from_str::<EPSResponse>(z.as_str());
-                if let Ok(o) = q {
-                    debug!("JsPluginRuntime parsed a response: {:?}", o);
-                    rt.spawn(and_now(o, ctx_clone.clone()));
-                    z.clear();
+                let output = if cut {
+                    oversized(&mut z, l)
+                } else {
+                    take_output(&mut z, l, max_output_bytes)
+                };
+                match output {
+                    RuntimeOutput::Incomplete => {
+                        debug!("JsPluginRuntime is now parsing `{l}` of `{z}`");
+                    }
+                    RuntimeOutput::Response(o) => {
+                        debug!("JsPluginRuntime parsed a response: {:?}", o);
+                        rt.spawn(and_now(o, ctx_clone.clone()));
+                    }
+                    RuntimeOutput::Oversized(id) => {
+                        error!(
+                            "A plugin response was larger than `plugin_options.max_output_bytes` ({} bytes) and was dropped.",
+                            max_output_bytes
+                        );
+                        // Answered as if no plugin did anything, so Cynthia carries on without them.
+                        if let Some(id) = id {
+                            rt.spawn(and_now(
+                                EPSResponse {
+                                    id,
+                                    body: EPSResponseBody::NoneOk,
+                                },
+                                ctx_clone.clone(),
+                            ));
+                        }
+                    }
                 }
             } else if o.replace("\n", "").is_empty() {
                 //     Just wait for the next line
@@ -298,6 +606,8 @@ fn new_proc(
                             .to_string()
                             .color_orange()
                     );
+                } else if let Some(line) = o.strip_prefix("trace: ") {
+                    log::trace!("[JsPluginRuntime]: {}", line);
                 } else if o.starts_with("log: ") {
                     config_clone.clone().tell(format!(
                         "[JsPluginRuntime]: {}",
@@ -310,6 +620,244 @@ fn new_proc(
     .unwrap()
 }
 
+/// The plugin runtime's process, with its output read line by line on a thread of its own.
+#[cfg(feature = "js_runtime")]
+struct RuntimeProcess {
+    child: std::process::Child,
+    stdin: std::process::ChildStdin,
+}
+
+#[cfg(feature = "js_runtime")]
+impl RuntimeProcess {
+    /// Starts `command`, calling `on_line` with every line it writes, until its output ends. Lines
+    /// longer than `max_line_bytes` are cut, see [`read_line`].
+    fn new(
+        command: &mut Command,
+        max_line_bytes: usize,
+        on_line: impl Fn(std::io::Result<RuntimeLine>) + Send + 'static,
+    ) -> std::io::Result<Self> {
+        let mut child = command
+            .stdin(std::process::Stdio::piped())
+            .stdout(std::process::Stdio::piped())
+            .spawn()?;
+        let stdout = child.stdout.take().expect("stdout is piped");
+        let stdin = child.stdin.take().expect("stdin is piped");
+        std::thread::spawn(move || {
+            let mut reader = std::io::BufReader::new(stdout);
+            loop {
+                match read_line(&mut reader, max_line_bytes) {
+                    Ok(Some(line)) => on_line(Ok(line)),
+                    Ok(None) => break,
+                    Err(e) => {
+                        on_line(Err(e));
+                        break;
+                    }
+                }
+            }
+        });
+        Ok(Self { child, stdin })
+    }
+
+    fn send(&mut self, data: &str) -> std::io::Result<()> {
+        use std::io::Write;
+        writeln!(self.stdin, "{}", data)?;
+        self.stdin.flush()
+    }
+
+    /// Closes the runtime's input, handing back the process to be stopped.
+    fn close(self) -> std::process::Child {
+        drop(self.stdin);
+        self.child
+    }
+}
+
+/// A line of output from the plugin runtime.
+#[cfg(feature = "js_runtime")]
+#[derive(Debug, PartialEq)]
+enum RuntimeLine {
+    Whole(String),
+    /// Longer than allowed, with only its first [`CUT_LINE_START`] bytes kept: enough for the id
+    /// of a response, or the start of a log line.
+    Cut(String),
+}
+
+#[cfg(feature = "js_runtime")]
+const CUT_LINE_START: usize = 256;
+
+/// Reads the next line from `reader`, without its line ending, or `None` once the output ended. A line
+/// longer than `max_bytes` is cut: the rest of it is skipped as it comes in, never held in memory.
+#[cfg(feature = "js_runtime")]
+fn read_line(
+    reader: &mut impl std::io::BufRead,
+    max_bytes: usize,
+) -> std::io::Result<Option<RuntimeLine>> {
+    let mut line = Vec::new();
+    let mut cut = false;
+    loop {
+        let available = reader.fill_buf()?;
+        if available.is_empty() {
+            if line.is_empty() && !cut {
+                return Ok(None);
+            }
+            break;
+        }
+        let (chunk, ends) = match available.iter().position(|b| *b == b'\n') {
+            Some(end) => (&available[..end], true),
+            None => (available, false),
+        };
+        if !cut && line.len() + chunk.len() > max_bytes {
+            cut = true;
+        }
+        if cut {
+            let room = CUT_LINE_START.saturating_sub(line.len()).min(chunk.len());
+            line.extend_from_slice(&chunk[..room]);
+            line.truncate(CUT_LINE_START);
+        } else {
+            line.extend_from_slice(chunk);
+        }
+        let used = chunk.len() + usize::from(ends);
+        reader.consume(used);
+        if ends {
+            break;
+        }
+    }
+    if line.ends_with(b"\r") {
+        line.pop();
+    }
+    let line = String::from_utf8_lossy(&line).into_owned();
+    Ok(Some(if cut {
+        RuntimeLine::Cut(line)
+    } else {
+        RuntimeLine::Whole(line)
+    }))
+}
+
+/// What the response output of the plugin runtime amounts to, after another line of it.
+#[cfg(feature = "js_runtime")]
+#[derive(Debug)]
+enum RuntimeOutput {
+    /// Not a whole response yet.
+    Incomplete,
+    Response(EPSResponse),
+    /// Went past the size limit, with the id of the request it answered when that could be read.
+    Oversized(Option<EPSCommunicationsID>),
+}
+
+/// Adds a line of response output to the ones before it, parsing them once they make up a whole response.
+/// Output beyond `max_bytes` (`0` being no limit) is thrown away rather than kept around.
+#[cfg(feature = "js_runtime")]
+fn take_output(buffer: &mut String, line: &str, max_bytes: u64) -> RuntimeOutput {
+    if max_bytes != 0 && (buffer.len() + line.len()) as u64 > max_bytes {
+        return oversized(buffer, line);
+    }
+    buffer.push_str(line);
+    // Only packed output needs a look at the JSON before it becomes a response. Inside a string,
+    // the quotes of `"encoding":` would be escaped.
+    let parsed = if buffer.contains("\"encoding\":") {
+        from_str::<serde_json::Value>(buffer).and_then(|response| {
+            serde_json::from_value::<EPSResponse>(decoded(response, max_bytes))
+        })
+    } else {
+        from_str::<EPSResponse>(buffer)
+    };
+    match parsed {
+        Ok(response) => {
+            buffer.clear();
+            RuntimeOutput::Response(response)
+        }
+        Err(_) => RuntimeOutput::Incomplete,
+    }
+}
+
+/// Throws away the output so far, along with the `start` of the line that took it past the limit.
+#[cfg(feature = "js_runtime")]
+fn oversized(buffer: &mut String, start: &str) -> RuntimeOutput {
+    buffer.push_str(start);
+    // Responses are serialised with their id first: `{"id":123,"body":...}`.
+    let id = buffer
+        .strip_prefix("{\"id\":")
+        .map(|rest| {
+            rest.chars()
+                .take_while(char::is_ascii_digit)
+                .collect::<String>()
+        })
+        .and_then(|id| id.parse().ok());
+    buffer.clear();
+    buffer.shrink_to_fit();
+    RuntimeOutput::Oversized(id)
+}
+
+/// Undoes the `encoding` of a response: the runtime sends big output gzipped and base64-encoded,
+/// marked `"encoding": "gzip-base64"`, to keep what goes through the pipe small. Output that doesn't
+/// decode, or unpacks to more than `max_bytes` (`0` being no limit), turns the response into an error,
+/// so Cynthia carries on as it does when a plugin fails.
+#[cfg(feature = "js_runtime")]
+fn decoded(mut response: serde_json::Value, max_bytes: u64) -> serde_json::Value {
+    let Some(body) = response
+        .get_mut("body")
+        .and_then(serde_json::Value::as_object_mut)
+    else {
+        return response;
+    };
+    let Some(encoding) = body.remove("encoding") else {
+        return response;
+    };
+    // The field holding the output, per kind of response.
+    let field = match body.get("as").and_then(serde_json::Value::as_str) {
+        Some("OkString") | Some("Json") => "value",
+        Some("WebResponse") => "response_body",
+        Some("RouteResponse") => "body",
+        _ => return response,
+    };
+    let output = match encoding.as_str() {
+        Some("gzip-base64") => body
+            .get(field)
+            .and_then(serde_json::Value::as_str)
+            .ok_or_else(|| format!("`{field}` isn't a string"))
+            .and_then(|encoded| gunzip_base64(encoded, max_bytes)),
+        _ => Err(format!("it's in an unknown encoding, {encoding}")),
+    };
+    match output {
+        Ok(output) => {
+            body.insert(field.to_string(), serde_json::Value::String(output));
+        }
+        Err(e) => {
+            error!("Could not decode the output of a plugin: {e}");
+            response["body"] = serde_json::json!({
+                "as": "Error",
+                "message": format!("Could not decode the output: {e}"),
+            });
+        }
+    }
+    response
+}
+
+/// Unpacks `encoded`, reading no more than `max_bytes` (`0` being no limit) of what it unpacks to.
+#[cfg(feature = "js_runtime")]
+fn gunzip_base64(encoded: &str, max_bytes: u64) -> Result<String, String> {
+    use base64::Engine;
+    use std::io::Read;
+    let bytes = base64::engine::general_purpose::STANDARD
+        .decode(encoded)
+        .map_err(|e| e.to_string())?;
+    let limit = if max_bytes == 0 {
+        u64::MAX
+    } else {
+        max_bytes + 1
+    };
+    let mut unpacked = vec![];
+    flate2::read::GzDecoder::new(bytes.as_slice())
+        .take(limit)
+        .read_to_end(&mut unpacked)
+        .map_err(|e| e.to_string())?;
+    if unpacked.len() as u64 >= limit {
+        return Err(format!(
+            "it unpacks to more than `plugin_options.max_output_bytes` ({max_bytes} bytes)"
+        ));
+    }
+    String::from_utf8(unpacked).map_err(|e| e.to_string())
+}
+
 #[cfg(feature = "js_runtime")]
 async fn and_now(res: EPSResponse, _server_context_mutex: Arc<Mutex<ServerContext>>) {
     let mut server_context = _server_context_mutex.lock().await;
@@ -320,9 +868,77 @@ async fn and_now(res: EPSResponse, _server_context_mutex: Arc<Mutex<ServerContex
     debug!("Added response to external plugin server queue.");
     // panic!("The function runs! Finally! It runs!");
 }
+/// Cache entries holding plugin results start with this, see [`result_cache_key`].
+#[cfg_attr(not(feature = "js_runtime"), allow(dead_code))]
+pub(crate) const RESULT_CACHE_PREFIX: &str = "plugin-result:";
+
+/// The key a plugin result is cached under, `None` when it can't be cached. Only when every plugin
+/// that runs is `cacheable`, and only for requests whose result depends on nothing but what's sent:
+/// rendering a template, whose contents go into the key too, and expanding a shortcode.
+#[cfg(feature = "js_runtime")]
+pub(crate) fn result_cache_key(config: &CynthiaConfClone, req: &EPSRequestBody) -> Option<String> {
+    let template = match req {
+        EPSRequestBody::ContentRenderRequest { template_path, .. }
+        | EPSRequestBody::PostlistRenderRequest { template_path, .. } => {
+            std::fs::read(template_path).ok()?
+        }
+        EPSRequestBody::ShortcodeRequest { .. } | EPSRequestBody::OutputRequest { .. } => vec![],
+        _ => return None,
+    };
+    let mut running = config
+        .plugins
+        .iter()
+        .filter(|plugin| plugin.runs(&config.plugin_options))
+        .peekable();
+    // Opt-in: without plugins there's nothing that says results can be reused.
+    running.peek()?;
+    if !running.all(|plugin| plugin.is_cacheable()) {
+        return None;
+    }
+    let request = serde_json::to_vec(req).ok()?;
+    Some(format!(
+        "{}{}",
+        RESULT_CACHE_PREFIX,
+        crate::helpers::content_hash(&[request, template].concat())
+    ))
+}
+
+/// Takes a plugin result from the cache when there is one under `key`, or else `invoke`s the plugins,
+/// keeping what they return for `lifetime` seconds. Without a key, the plugins are always asked.
+#[cfg(feature = "js_runtime")]
+async fn cached_result(
+    server_context_mutex: &Arc<Mutex<ServerContext>>,
+    key: Option<String>,
+    lifetime: u64,
+    invoke: impl std::future::Future<Output = EPSResponseBody>,
+) -> EPSResponseBody {
+    use crate::LockCallback;
+    let Some(key) = key else {
+        return invoke.await;
+    };
+    if let Some(cached) = server_context_mutex
+        .lock_callback(|a| a.get_cache(&key, lifetime))
+        .await
+    {
+        debug!("Plugin result taken from the cache.");
+        return EPSResponseBody::OkString {
+            value: String::from_utf8_lossy(&cached.0).into_owned(),
+        };
+    }
+    let response = invoke.await;
+    // Errors aren't kept, the next request tries again.
+    if let EPSResponseBody::OkString { value } = &response {
+        let _ = server_context_mutex
+            .lock_callback(|a| a.store_cache(&key, value.as_bytes(), lifetime))
+            .await;
+    }
+    response
+}
+
 /**
 This function sends a request over mpsc to the externalpluginservers::main function, then periodically locks the server mutex and checks if a corresponding response (matched by `id`) is added, if not, it will try again.
 It is recommended to use this function instead of other methods of sending requests to the external plugin server.
+Results of `cacheable` plugins are reused, see [`result_cache_key`].
 */
 #[cfg(feature = "js_runtime")]
 pub(crate) async fn contact_eps(
@@ -330,20 +946,51 @@ pub(crate) async fn contact_eps(
     req: EPSRequestBody,
 ) -> EPSResponseBody {
     use crate::LockCallback;
-    if server_context_mutex
-        .lock_callback(|server_context| -> Option<EPSResponseBody> {
-            if server_context.config.runtimes.ext_js_rt.validate().is_err()
-                || server_context.config.runtimes.ext_js_rt == "disabled"
-            {
-                Some(EPSResponseBody::Disabled)
-            } else {
-                None
-            }
-        })
-        .await
-        .is_some()
-    {
+    let _stopwatch = crate::timing::Stopwatch::start(crate::timing::Part::Plugins);
+    // The key reads the template and serializes the request, so it's made after the lock is released.
+    let config = server_context_mutex
+        .lock_callback(|a| a.config.clone())
+        .await;
+    // Whether the runtime works was checked when the configuration was loaded.
+    if config.runtimes.ext_js_rt == "disabled" {
         return EPSResponseBody::Disabled;
+    }
+    let key = result_cache_key(&config, &req);
+    let lifetime = config.cache.lifetimes.forwarded;
+    cached_result(
+        &server_context_mutex,
+        key,
+        lifetime,
+        ask_eps(server_context_mutex.clone(), req),
+    )
+    .await
+}
+
+/// Sends a request to the plugin runtime and waits for its response.
+#[cfg(feature = "js_runtime")]
+async fn ask_eps(
+    server_context_mutex: Data<Arc<Mutex<ServerContext>>>,
+    req: EPSRequestBody,
+) -> EPSResponseBody {
+    use crate::LockCallback;
+    let permits = server_context_mutex
+        .lock_callback(|server_context| {
+            let max_concurrent = server_context.config.plugin_options.max_concurrent;
+            server_context
+                .external_plugin_server
+                .permits(max_concurrent)
+        })
+        .await;
+    // Held until the response is in. Waiting for a permit counts towards the render deadline,
+    // which drops this future when it runs out.
+    let _permit = match permits {
+        Some(semaphore) => {
+            if semaphore.available_permits() == 0 {
+                debug!("Plugin requests are at `plugin_options.max_concurrent`, queueing.");
+            }
+            Some(semaphore.acquire_owned().await.unwrap())
+        }
+        None => None,
     };
     let random_id = {
         let mut d: EPSCommunicationsID;
@@ -377,6 +1024,7 @@ pub(crate) async fn contact_eps(
     match eps_r
         .send(EPSRequest {
             id: random_id,
+            request_id: crate::requestid::current(),
             body: req,
         })
         .await
@@ -440,6 +1088,61 @@ pub(crate) async fn contact_eps(
     }
 }
 
+/// Runs the plugins' `onStart` hooks, before the server starts accepting requests.
+/// Failures are logged, and only stop Cynthia when `plugin_options.abort_on_start_failure` is set.
+pub(crate) async fn run_start_hooks(server_context_mutex: Data<Arc<Mutex<ServerContext>>>) {
+    use crate::LockCallback;
+    let abort = server_context_mutex
+        .lock_callback(|a| a.config.plugin_options.abort_on_start_failure)
+        .await;
+    if let Some(failure) = start_plugins(server_context_mutex).await {
+        error!("Plugin start hooks failed: {}", failure);
+        if abort {
+            error!("Not starting, as `plugin_options.abort_on_start_failure` is set.");
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Restarts the plugin runtime after the configuration was reloaded, and runs the `onStart` hooks again.
+/// Failing hooks are only logged, Cynthia is already running by then.
+pub(crate) async fn reload_plugins(server_context_mutex: Data<Arc<Mutex<ServerContext>>>) {
+    if let EPSResponseBody::Disabled =
+        contact_eps(server_context_mutex.clone(), EPSRequestBody::Reload).await
+    {
+        return;
+    }
+    if let Some(failure) = start_plugins(server_context_mutex).await {
+        error!("Plugin start hooks failed after reloading: {}", failure);
+    }
+}
+
+/// Sends a `StartRequest`, keeping the prefixes of the plugin routes it answers with. Returns what
+/// failed, if anything did.
+async fn start_plugins(server_context_mutex: Data<Arc<Mutex<ServerContext>>>) -> Option<String> {
+    match contact_eps(server_context_mutex.clone(), EPSRequestBody::StartRequest).await {
+        EPSResponseBody::Started { routes, failures } => {
+            if !routes.is_empty() {
+                info!("Plugin routes: {}", routes.join(", "));
+            }
+            #[cfg(feature = "js_runtime")]
+            {
+                use crate::LockCallback;
+                server_context_mutex
+                    .lock_callback(|a| a.external_plugin_server.routes = Some(routes))
+                    .await;
+            }
+            #[cfg(not(feature = "js_runtime"))]
+            let _ = routes;
+            (!failures.is_empty()).then(|| failures.join("; "))
+        }
+        EPSResponseBody::Error { message } => {
+            Some(message.unwrap_or_else(|| String::from("no reason given")))
+        }
+        _ => None,
+    }
+}
+
 #[cfg(not(feature = "js_runtime"))]
 pub(crate) async fn contact_eps(
     _: Data<Arc<Mutex<ServerContext>>>,
@@ -447,3 +1150,233 @@ pub(crate) async fn contact_eps(
 ) -> EPSResponseBody {
     EPSResponseBody::Disabled
 }
+
+#[cfg(all(test, feature = "js_runtime"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn oversized_output_is_dropped() {
+        let mut buffer = String::new();
+        let small = r#"{"id":7,"body":{"as":"OkString","value":"hi"}}"#;
+        assert!(matches!(
+            take_output(&mut buffer, small, 64),
+            RuntimeOutput::Response(EPSResponse { id: 7, .. })
+        ));
+        assert!(buffer.is_empty());
+
+        // A plugin that writes a whole page's worth of output, spread over two lines.
+        let huge = "a".repeat(1000);
+        let first = format!(r#"{{"id":8,"body":{{"as":"OkString","value":"{huge}"#);
+        assert!(matches!(
+            take_output(&mut buffer, &first[..40], 256),
+            RuntimeOutput::Incomplete
+        ));
+        assert!(matches!(
+            take_output(&mut buffer, &format!("{}\"}}}}", &first[40..]), 256),
+            RuntimeOutput::Oversized(Some(8))
+        ));
+        assert!(buffer.is_empty());
+
+        // Without a limit, it goes through.
+        let whole = format!(r#"{{"id":9,"body":{{"as":"OkString","value":"{huge}"}}}}"#);
+        assert!(matches!(
+            take_output(&mut buffer, &whole, 0),
+            RuntimeOutput::Response(EPSResponse { id: 9, .. })
+        ));
+    }
+
+    #[test]
+    fn cuts_long_lines_while_reading() {
+        let long = "x".repeat(1000);
+        let output = format!("short\n{long}\nnext\r\nlast");
+        // A small buffer, so lines come in over several reads.
+        let mut reader = std::io::BufReader::with_capacity(16, output.as_bytes());
+        let mut read = || read_line(&mut reader, 100).unwrap();
+        assert_eq!(read(), Some(RuntimeLine::Whole(String::from("short"))));
+        assert_eq!(
+            read(),
+            Some(RuntimeLine::Cut(long[..CUT_LINE_START].to_string()))
+        );
+        assert_eq!(read(), Some(RuntimeLine::Whole(String::from("next"))));
+        assert_eq!(read(), Some(RuntimeLine::Whole(String::from("last"))));
+        assert_eq!(read(), None);
+
+        // What's kept of a cut response still tells which request it answered.
+        let mut buffer = String::new();
+        assert!(matches!(
+            oversized(
+                &mut buffer,
+                r#"{"id":12,"body":{"as":"OkString","value":"aaaa"#
+            ),
+            RuntimeOutput::Oversized(Some(12))
+        ));
+        assert!(buffer.is_empty());
+    }
+
+    #[test]
+    fn decodes_gzipped_output() {
+        use base64::Engine;
+        use std::io::Write;
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(b"<p>Big page</p>").unwrap();
+        let encoded = base64::engine::general_purpose::STANDARD.encode(encoder.finish().unwrap());
+        let mut buffer = String::new();
+        let gzipped = format!(
+            r#"{{"id":3,"body":{{"as":"OkString","value":"{encoded}","encoding":"gzip-base64"}}}}"#
+        );
+        assert!(matches!(
+            take_output(&mut buffer, &gzipped, 0),
+            RuntimeOutput::Response(EPSResponse {
+                body: EPSResponseBody::OkString { value },
+                ..
+            }) if value == "<p>Big page</p>"
+        ));
+
+        // Output that doesn't decode is answered like a failing plugin.
+        let broken = r#"{"id":4,"body":{"as":"RouteResponse","status":200,"headers":[],"body":"nope!","encoding":"gzip-base64"}}"#;
+        assert!(matches!(
+            take_output(&mut buffer, broken, 0),
+            RuntimeOutput::Response(EPSResponse {
+                id: 4,
+                body: EPSResponseBody::Error { .. },
+            })
+        ));
+        assert!(buffer.is_empty());
+
+        // Nor is output that unpacks to more than the limit, however small it is packed.
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::best());
+        encoder.write_all(&vec![b'a'; 100_000]).unwrap();
+        let encoded = base64::engine::general_purpose::STANDARD.encode(encoder.finish().unwrap());
+        let bomb = format!(
+            r#"{{"id":5,"body":{{"as":"OkString","value":"{encoded}","encoding":"gzip-base64"}}}}"#
+        );
+        assert!(bomb.len() < 4096);
+        assert!(matches!(
+            take_output(&mut buffer, &bomb, 4096),
+            RuntimeOutput::Response(EPSResponse {
+                id: 5,
+                body: EPSResponseBody::Error { .. },
+            })
+        ));
+    }
+
+    #[test]
+    fn dumps_commands_only_when_enabled() {
+        let dir = std::env::temp_dir().join(format!("cynthia-dump-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let request = EPSRequest {
+            id: 7,
+            request_id: None,
+            body: EPSRequestBody::Test {
+                test: String::from("echo"),
+            },
+        };
+        let config = crate::config::CynthiaConf::default();
+        assert_eq!(
+            dump_command(config.plugin_options.dump_commands.as_deref(), &request),
+            None
+        );
+        assert!(!dir.exists());
+        let file = dump_command(dir.to_str(), &request).unwrap();
+        assert!(file.to_string_lossy().ends_with("-7-Test.json"));
+        let dumped: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&file).unwrap()).unwrap();
+        assert_eq!(dumped["body"]["test"], "echo");
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn only_asks_about_paths_under_plugin_routes() {
+        let (sender, _receiver) = tokio::sync::mpsc::channel(1);
+        let mut eps = EPSCommunicationData::new(sender);
+        // Before the runtime told which routes it has, it's always asked.
+        assert!(eps.may_route("/anything"));
+        eps.routes = Some(vec![String::from("/comments")]);
+        assert!(eps.may_route("/comments"));
+        assert!(eps.may_route("/comments/42"));
+        assert!(!eps.may_route("/commentsfeed"));
+        assert!(!eps.may_route("/"));
+        eps.routes = Some(vec![]);
+        assert!(!eps.may_route("/comments"));
+    }
+
+    #[test]
+    fn tells_the_runtime_about_reserved_paths() {
+        let config = crate::config::CynthiaConf {
+            static_mounts: vec![crate::config::StaticMount {
+                prefix: String::from("/downloads"),
+                path: String::from("files"),
+            }],
+            ..crate::config::CynthiaConf::default()
+        };
+        let json = serde_json::to_value(runtime_config(&config.clone())).unwrap();
+        let reserved = json["reserved_paths"].as_array().unwrap();
+        assert!(reserved.contains(&serde_json::json!("/api")));
+        assert!(reserved.contains(&serde_json::json!("/downloads")));
+        // The configuration itself is passed on at the top level, as before.
+        assert_eq!(json["static_mounts"][0]["prefix"], "/downloads");
+    }
+
+    #[actix_web::test]
+    async fn reuses_results_of_cacheable_plugins() {
+        use crate::config::{CynthiaConf, Plugin};
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let plugin = |cacheable| Plugin::JsPlugin {
+            plugin_name: String::from("shouting"),
+            plugin_enabled: true,
+            order: 0,
+            cacheable,
+        };
+        let mut config = CynthiaConf {
+            plugins: vec![plugin(true)],
+            ..CynthiaConf::default()
+        };
+        let shortcode = |name: &str| EPSRequestBody::ShortcodeRequest {
+            shortcode: crate::shortcodes::Shortcode {
+                name: name.to_string(),
+                positional: vec![],
+                named: Default::default(),
+            },
+        };
+        let key = result_cache_key(&config.clone(), &shortcode("shout"));
+        assert!(key.as_ref().unwrap().starts_with(RESULT_CACHE_PREFIX));
+        assert_ne!(
+            key,
+            result_cache_key(&config.clone(), &shortcode("whisper"))
+        );
+        assert!(result_cache_key(&config.clone(), &EPSRequestBody::StartRequest).is_none());
+        config.plugins.push(Plugin::JsPlugin {
+            plugin_name: String::from("clock"),
+            ..plugin(false)
+        });
+        assert!(result_cache_key(&config.clone(), &shortcode("shout")).is_none());
+
+        let (sender, _receiver) = tokio::sync::mpsc::channel(1);
+        let server_context_mutex = Arc::new(Mutex::new(ServerContext {
+            config: Arc::new(config),
+            cache: vec![],
+            published: None,
+            request_count: 0,
+            start_time: 0,
+            visits: Arc::default(),
+            external_plugin_server: EPSCommunicationData::new(sender),
+        }));
+        let invocations = AtomicUsize::new(0);
+        let invoke = || async {
+            invocations.fetch_add(1, Ordering::SeqCst);
+            EPSResponseBody::OkString {
+                value: String::from("HELLO"),
+            }
+        };
+        for _ in 0..2 {
+            let response = cached_result(&server_context_mutex, key.clone(), 60, invoke()).await;
+            assert!(matches!(response, EPSResponseBody::OkString { value } if value == "HELLO"));
+        }
+        assert_eq!(invocations.load(Ordering::SeqCst), 1);
+        // Without a key, the plugins are asked every time.
+        cached_result(&server_context_mutex, None, 60, invoke()).await;
+        assert_eq!(invocations.load(Ordering::SeqCst), 2);
+    }
+}
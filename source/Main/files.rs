@@ -3,13 +3,12 @@
  *
  * Licensed under the GNU AFFERO GENERAL PUBLIC LICENSE Version 3, see the LICENSE file for more information.
  */
-use crate::config::CynthiaConfClone;
 use crate::ServerContext;
 use std::path::PathBuf;
 use tokio::sync::MutexGuard;
 
-fn get_lifetime(pr: FilePriority, config_clone: CynthiaConfClone) -> u64 {
-    let normal_cache_lifetime = config_clone.cache.lifetimes.assets;
+pub(crate) fn get_lifetime(pr: FilePriority, assets_lifetime: u64) -> u64 {
+    let normal_cache_lifetime = assets_lifetime;
     let step = 4;
     match pr {
         FilePriority::Permanent => 0,
@@ -77,7 +76,7 @@ pub(crate) fn fs_get(
     path: PathBuf,
     priority: FilePriority,
 ) -> Result<Vec<u8>, String> {
-    let cttl = get_lifetime(priority, ctx.config.clone());
+    let cttl = get_lifetime(priority, ctx.config.cache.lifetimes.assets);
     let file_cache_id = format!("fs:{}", path.to_string_lossy());
     let file_cache = ctx.get_cache(&file_cache_id, cttl);
     // Check if cache hit
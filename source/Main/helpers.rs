@@ -7,3 +7,367 @@ pub(crate) fn decompress_folder(compressed_folder: &[u8], output_folder: PathBuf
     let mut archive = tar::Archive::new(decompressed_folder.as_slice());
     archive.unpack(output_folder).unwrap();
 }
+
+/// A short, non-cryptographic hash of some content, used for cache-busting and validators.
+pub(crate) fn content_hash(content: &[u8]) -> String {
+    use std::hash::{DefaultHasher, Hash, Hasher};
+    let mut hasher = DefaultHasher::new();
+    content.hash(&mut hasher);
+    format!("{:x}", hasher.finish())
+}
+
+/// Normalises a publication id or URL path for matching, so `My Post` and `my-post` are the same.
+/// Lowercases, turns whitespace and underscores into hyphens and drops anything that isn't
+/// alphanumeric, a hyphen, a dot or a slash.
+pub(crate) fn slugify(id: &str) -> String {
+    let mut slug = String::with_capacity(id.len());
+    for c in id.trim().chars().flat_map(char::to_lowercase) {
+        match c {
+            c if c.is_whitespace() || c == '_' || c == '-' => {
+                if !slug.is_empty() && !slug.ends_with(['-', '/']) {
+                    slug.push('-');
+                }
+            }
+            '/' => {
+                let trimmed = slug.trim_end_matches('-').len();
+                slug.truncate(trimmed);
+                slug.push('/');
+            }
+            c if c.is_alphanumeric() || c == '.' => slug.push(c),
+            _ => {}
+        }
+    }
+    slug.trim_end_matches('-').to_string()
+}
+
+/// The URL of the page with this id, `baseurl` being empty for a path on this site. The id as routing
+/// matches it, see [`slugify`], percent-encoded, and with a slash at the end when `trailing_slash`
+/// adds one.
+pub(crate) fn page_url(
+    baseurl: &str,
+    id: &str,
+    trailing_slash: &crate::config::TrailingSlash,
+) -> String {
+    if matches!(id, "root" | "" | "/") {
+        return format!("{}/", baseurl);
+    }
+    let path = slugify(id)
+        .split('/')
+        .map(urlencoding::encode)
+        .collect::<Vec<_>>()
+        .join("/");
+    if *trailing_slash == crate::config::TrailingSlash::Add {
+        format!("{}/{}/", baseurl, path)
+    } else {
+        format!("{}/{}", baseurl, path)
+    }
+}
+
+/// Escapes text for use in HTML, inside elements as well as in quoted attributes.
+pub(crate) fn escape_html(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('"', "&quot;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+/// Where an author ends a post's excerpt. Markdown rendered without raw HTML keeps it as escaped text.
+const EXCERPT_MARKERS: [&str; 3] = [
+    "<!--more-->",
+    "<p>&lt;!--more--&gt;</p>",
+    "&lt;!--more--&gt;",
+];
+
+/// Removes the excerpt marker where the content's renderer escaped it, so it doesn't show up as text.
+pub(crate) fn hide_excerpt_marker(html: &str) -> String {
+    EXCERPT_MARKERS[1..]
+        .iter()
+        .fold(html.to_string(), |html, marker| html.replace(marker, ""))
+}
+
+/// Elements without a closing tag, which an excerpt doesn't need to close.
+const VOID_ELEMENTS: [&str; 13] = [
+    "area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "source", "track",
+    "wbr",
+];
+
+/// The character an HTML entity like `&amp;` or `&#8212;` stands for, for the ones content has.
+fn decode_entity(entity: &str) -> Option<char> {
+    let name = entity.strip_prefix('&')?.strip_suffix(';')?;
+    if let Some(number) = name.strip_prefix('#') {
+        let code = match number.strip_prefix(['x', 'X']) {
+            Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+            None => number.parse().ok()?,
+        };
+        return char::from_u32(code);
+    }
+    Some(match name {
+        "amp" => '&',
+        "lt" => '<',
+        "gt" => '>',
+        "quot" => '"',
+        "apos" => '\'',
+        "nbsp" => ' ',
+        "hellip" => '…',
+        "mdash" => '—',
+        "ndash" => '–',
+        "lsquo" => '‘',
+        "rsquo" => '’',
+        "ldquo" => '“',
+        "rdquo" => '”',
+        "laquo" => '«',
+        "raquo" => '»',
+        "copy" => '©',
+        "reg" => '®',
+        "trade" => '™',
+        "deg" => '°',
+        "euro" => '€',
+        "times" => '×',
+        "middot" => '·',
+        "bull" => '•',
+        _ => return None,
+    })
+}
+
+/// An excerpt of rendered content: everything before a `<!--more-->` marker, or else the first `length`
+/// characters of text, finishing the word it ends in. With `strip_html`, the excerpt is plain text with
+/// whitespace collapsed, otherwise it keeps the markup, with every element it cuts through closed.
+pub(crate) fn excerpt(html: &str, length: usize, strip_html: bool) -> String {
+    let (html, length) = match EXCERPT_MARKERS.iter().find_map(|m| html.split_once(m)) {
+        Some((before, _)) => (before, usize::MAX),
+        None => (html, length),
+    };
+    let mut excerpt = String::new();
+    let mut open: Vec<String> = Vec::new();
+    let mut count = 0;
+    let mut cut = false;
+    let mut rest = html;
+    while let Some(c) = rest.chars().next() {
+        if c == '<' {
+            let end = rest.find('>').map_or(rest.len(), |e| e + 1);
+            let (tag, after) = rest.split_at(end);
+            rest = after;
+            if strip_html {
+                continue;
+            }
+            let name = tag
+                .trim_start_matches(['<', '/'])
+                .split(|c: char| c.is_whitespace() || c == '>' || c == '/')
+                .next()
+                .unwrap_or_default()
+                .to_ascii_lowercase();
+            if tag.starts_with("</") {
+                if let Some(i) = open.iter().rposition(|o| *o == name) {
+                    open.truncate(i);
+                }
+            } else if !(name.is_empty()
+                || tag.starts_with("<!")
+                || tag.ends_with("/>")
+                || VOID_ELEMENTS.contains(&name.as_str()))
+            {
+                open.push(name);
+            }
+            excerpt.push_str(tag);
+            continue;
+        }
+        if count >= length && c.is_whitespace() {
+            cut = true;
+            break;
+        }
+        // An entity is a single character of text.
+        let entity = (c == '&')
+            .then(|| rest.find(';').filter(|e| *e <= 10))
+            .flatten()
+            .map(|e| &rest[..=e]);
+        let taken = entity.map_or(c.len_utf8(), str::len);
+        if strip_html && c.is_whitespace() {
+            if !excerpt.is_empty() && !excerpt.ends_with(' ') {
+                excerpt.push(' ');
+            }
+        } else if let (true, Some(entity)) = (strip_html, entity) {
+            // Templates escape the excerpt again, so whatever isn't decoded here would show as
+            // its code. Entities that aren't known are left out.
+            if let Some(decoded) = decode_entity(entity) {
+                excerpt.push(decoded);
+            }
+        } else {
+            excerpt.push_str(&rest[..taken]);
+        }
+        if !c.is_whitespace() {
+            count += 1;
+        }
+        rest = &rest[taken..];
+    }
+    let mut excerpt = excerpt.trim_end().to_string();
+    if cut {
+        excerpt.push('…');
+    }
+    for name in open.iter().rev() {
+        excerpt.push_str(&format!("</{name}>"));
+    }
+    excerpt.trim_start().to_string()
+}
+
+/// Trims a URL taken from content or configuration, returning `None` for empty ones and for
+/// schemes that would run script when followed (`javascript:`, `vbscript:` and non-image `data:`).
+pub(crate) fn safe_url(url: &str) -> Option<String> {
+    let url = url.trim();
+    // Browsers ignore whitespace and control characters inside a scheme, so this does too.
+    let scheme: String = url
+        .chars()
+        .filter(|c| !c.is_whitespace() && !c.is_control())
+        .take_while(|c| *c != ':' && *c != '/')
+        .collect::<String>()
+        .to_ascii_lowercase();
+    let rest = url
+        .split_once(':')
+        .map(|(_, rest)| rest)
+        .unwrap_or_default()
+        .trim_start();
+    match scheme.as_str() {
+        _ if url.is_empty() => None,
+        "javascript" | "vbscript" => None,
+        "data" if !rest.to_ascii_lowercase().starts_with("image/") => None,
+        _ => Some(url.to_string()),
+    }
+}
+
+/// Writes a file by first writing to a temporary file next to it and then renaming that into place,
+/// so readers never see it half-written. Being in the same directory, the rename never has to cross
+/// filesystems.
+pub(crate) fn atomic_write(
+    path: &std::path::Path,
+    contents: impl AsRef<[u8]>,
+) -> std::io::Result<()> {
+    static WRITES: std::sync::atomic::AtomicU64 = std::sync::atomic::AtomicU64::new(0);
+    let file_name = path
+        .file_name()
+        .map(|f| f.to_string_lossy().to_string())
+        .unwrap_or_default();
+    // Unique within the process too, the same file can be written by two tasks at once.
+    let temp_path = path.with_file_name(format!(
+        ".{}.{}-{}.tmp",
+        file_name,
+        std::process::id(),
+        WRITES.fetch_add(1, std::sync::atomic::Ordering::Relaxed)
+    ));
+    std::fs::write(&temp_path, contents)?;
+    std::fs::rename(&temp_path, path).inspect_err(|_| {
+        let _ = std::fs::remove_file(&temp_path);
+    })
+}
+
+/// Metadata about this build of Cynthia, as embedded by the build script.
+#[derive(serde::Serialize)]
+pub(crate) struct BuildInfo {
+    pub(crate) version: &'static str,
+    pub(crate) commit: &'static str,
+    /// Seconds since the Unix epoch.
+    pub(crate) built: u64,
+}
+
+pub(crate) fn build_info() -> BuildInfo {
+    BuildInfo {
+        version: env!("CARGO_PKG_VERSION"),
+        commit: option_env!("CYNTHIA_GIT_COMMIT").unwrap_or("unknown"),
+        built: option_env!("CYNTHIA_BUILD_TIMESTAMP")
+            .and_then(|t| t.parse().ok())
+            .unwrap_or(0),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn writes_atomically() {
+        let dir = std::env::temp_dir().join(format!("cynthia-atomic-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("visits.json");
+        atomic_write(&file, "{}").unwrap();
+        atomic_write(&file, r#"{"root": 1}"#).unwrap();
+        assert_eq!(std::fs::read_to_string(&file).unwrap(), r#"{"root": 1}"#);
+        // Nothing is left next to it.
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+        assert!(atomic_write(&dir.join("missing/visits.json"), "{}").is_err());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn slugs() {
+        assert_eq!(slugify("My Post"), "my-post");
+        assert_eq!(slugify("my-post"), "my-post");
+        assert_eq!(slugify("  Hello,   World! "), "hello-world");
+        assert_eq!(slugify("docs / Getting_Started"), "docs/getting-started");
+        assert_eq!(slugify("Ünïcode café"), "ünïcode-café");
+        assert_eq!(slugify("v1.2 -- notes"), "v1.2-notes");
+    }
+
+    #[test]
+    fn page_urls() {
+        use crate::config::TrailingSlash;
+        let base = "https://example.com";
+        assert_eq!(
+            page_url(base, "root", &TrailingSlash::Add),
+            "https://example.com/"
+        );
+        assert_eq!(
+            page_url(base, "Über uns", &TrailingSlash::Keep),
+            "https://example.com/%C3%BCber-uns"
+        );
+        assert_eq!(
+            page_url("", "docs/Getting Started", &TrailingSlash::Add),
+            "/docs/getting-started/"
+        );
+    }
+
+    #[test]
+    fn excerpts() {
+        let html = "<p>One <em>two</em> three &amp; four.</p>\n<p>Five six.</p>";
+        assert_eq!(excerpt(html, 100, true), "One two three & four. Five six.");
+        // Every entity is decoded once, as templates escape the excerpt again.
+        let entities = "<p>Wait&hellip; &#8212; &#x2014; &lt;b&gt; &frac12;</p>";
+        assert_eq!(excerpt(entities, 100, true), "Wait… — — <b>");
+        assert_eq!(
+            handlebars::html_escape(&excerpt(entities, 100, true)),
+            "Wait… — — &lt;b&gt;"
+        );
+        assert_eq!(excerpt(html, 6, true), "One two…");
+        assert_eq!(excerpt(html, 6, false), "<p>One <em>two</em>…</p>");
+        assert_eq!(
+            excerpt(html, 12, false),
+            "<p>One <em>two</em> three &amp;…</p>"
+        );
+        let marked = "<p>Intro.</p>\n<!--more-->\n<p>The rest.</p>";
+        assert_eq!(excerpt(marked, 3, true), "Intro.");
+        assert_eq!(excerpt(marked, 3, false), "<p>Intro.</p>");
+        assert_eq!(
+            excerpt("<p>Intro.</p>\n<p>&lt;!--more--&gt;</p>", 300, false),
+            "<p>Intro.</p>"
+        );
+        assert_eq!(
+            hide_excerpt_marker("<p>Intro.</p>\n&lt;!--more--&gt;\n<p>The rest.</p>"),
+            "<p>Intro.</p>\n\n<p>The rest.</p>"
+        );
+    }
+
+    #[test]
+    fn unsafe_urls() {
+        assert_eq!(
+            safe_url(" /assets/me.png "),
+            Some(String::from("/assets/me.png"))
+        );
+        assert_eq!(
+            safe_url("https://example.com/a:b.png"),
+            Some(String::from("https://example.com/a:b.png"))
+        );
+        assert!(safe_url("data:image/png;base64,AAAA").is_some());
+        assert_eq!(safe_url("   "), None);
+        assert_eq!(safe_url("javascript:alert(1)"), None);
+        assert_eq!(safe_url("JavaScript:alert(1)"), None);
+        assert_eq!(safe_url("java\tscript:alert(1)"), None);
+        assert_eq!(safe_url("data:text/html,<script>"), None);
+    }
+}
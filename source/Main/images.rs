@@ -0,0 +1,486 @@
+/*
+ * Copyright (c) 2024, MLC 'Strawmelonjuice' Bloeiman
+ *
+ * Licensed under the GNU AFFERO GENERAL PUBLIC LICENSE Version 3, see the LICENSE file for more information.
+ */
+//! Smaller copies of the local images pages show, made with ImageMagick and offered to browsers
+//! through `<picture>` tags, with the original as the fallback.
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{LazyLock, Mutex};
+use std::time::SystemTime;
+
+use futures::future::join_all;
+use log::{debug, warn};
+use regex::Regex;
+use tokio::process::Command;
+
+use crate::config::{ImageOptions, StaticMount};
+
+/// Where the copies are served from, see [`crate::requestresponse::image_variant`].
+pub(crate) const URL_PREFIX: &str = "/cynthia/images/";
+
+/// The formats copies can be made in, with their content type.
+const FORMATS: [(&str, &str); 2] = [("avif", "image/avif"), ("webp", "image/webp")];
+
+/// The widths of the smaller copies `images.responsive` adds, next to the full-width one.
+const RESPONSIVE_WIDTHS: [u16; 2] = [480, 960];
+
+/// The content type of a copy in `format`, `None` for a format copies aren't made in.
+pub(crate) fn content_type(format: &str) -> Option<&'static str> {
+    FORMATS
+        .iter()
+        .find(|(name, _)| *name == format)
+        .map(|(_, content_type)| *content_type)
+}
+
+/// Checks the formats and that the converter runs. Returns a warning, and turns the copies off,
+/// when it doesn't: pages are then served with the original images.
+pub(crate) fn check(options: &mut ImageOptions) -> Result<Option<String>, String> {
+    if !options.enabled {
+        return Ok(None);
+    }
+    if let Some(format) = options
+        .formats
+        .iter()
+        .find(|format| content_type(format).is_none())
+    {
+        return Err(format!(
+            "Could not make images in `{}`! `images.formats` takes `avif` and `webp`.",
+            format
+        ));
+    }
+    if !(1..=100).contains(&options.quality) {
+        return Err(format!(
+            "Could not set `images.quality` to {}! Use a number from 1 to 100.",
+            options.quality
+        ));
+    }
+    let runs = std::process::Command::new(&options.converter)
+        .arg("-version")
+        .output()
+        .map(|output| output.status.success())
+        .unwrap_or(false);
+    if runs {
+        return Ok(None);
+    }
+    options.enabled = false;
+    Ok(Some(format!(
+        "`{}` doesn't run, images are served as they are. Install ImageMagick, or point `images.converter` at it.",
+        options.converter
+    )))
+}
+
+/// The file an image URL on the page points to, for images in the assets folder or a static mount.
+fn local_file(src: &str, static_mounts: &[StaticMount]) -> Option<PathBuf> {
+    let path = src.split(['?', '#']).next()?;
+    let path = urlencoding::decode(path).ok()?;
+    if !path.starts_with('/') || path.starts_with("//") || path.split('/').any(|p| p == "..") {
+        return None;
+    }
+    if let Some(rest) = path.strip_prefix("/assets/") {
+        return Some(PathBuf::from("cynthiaFiles/assets").join(rest));
+    }
+    static_mounts.iter().find_map(|mount| {
+        let rest = path.strip_prefix(&format!("{}/", mount.prefix))?;
+        Some(PathBuf::from(&mount.path).join(rest))
+    })
+}
+
+/// Only JPEG and PNG images get copies. SVGs don't get smaller, GIFs may be animated and
+/// WebP and AVIF images already are what the copies would be.
+fn optimisable(file: &Path) -> bool {
+    file.extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| matches!(e.to_lowercase().as_str(), "jpg" | "jpeg" | "png"))
+}
+
+/// The width in pixels of a PNG or JPEG image, read from its header.
+fn width(contents: &[u8]) -> Option<u32> {
+    if contents.starts_with(b"\x89PNG\r\n\x1a\n") {
+        return Some(u32::from_be_bytes(contents.get(16..20)?.try_into().ok()?));
+    }
+    if !contents.starts_with(&[0xFF, 0xD8]) {
+        return None;
+    }
+    let mut i = 2;
+    loop {
+        if *contents.get(i)? != 0xFF {
+            return None;
+        }
+        let marker = *contents.get(i + 1)?;
+        if marker == 0xFF {
+            // Padding between segments.
+            i += 1;
+            continue;
+        }
+        // The start of frame segments hold the size, the others in this range are tables.
+        if (0xC0..=0xCF).contains(&marker) && !matches!(marker, 0xC4 | 0xC8 | 0xCC) {
+            return Some(u16::from_be_bytes([*contents.get(i + 7)?, *contents.get(i + 8)?]) as u32);
+        }
+        let length = u16::from_be_bytes([*contents.get(i + 2)?, *contents.get(i + 3)?]);
+        i += 2 + length as usize;
+    }
+}
+
+/// What copies of a source image are named and sized after, see [`source`].
+#[derive(Clone)]
+struct Source {
+    /// The hash of its content.
+    hash: String,
+    /// Its width in pixels, when that could be read.
+    width: Option<u32>,
+}
+
+/// The source images read so far, by their path and when they were last changed, so an image is
+/// only read again after it's changed.
+static SOURCES: LazyLock<Mutex<HashMap<PathBuf, (SystemTime, Source)>>> =
+    LazyLock::new(Default::default);
+
+static TAGS: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?is)<picture\b.*?</picture>|<img\b[^>]*>").unwrap());
+static SRC: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r#"(?i)\ssrc\s*=\s*(?:"([^"]*)"|'([^']*)')"#).unwrap());
+static SIZES: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r#"(?i)\ssizes\s*=\s*(?:"([^"]*)"|'([^']*)')"#).unwrap());
+
+/// Tells apart the temporary files copies are made in, see [`variant`].
+static TEMPORARY: AtomicU64 = AtomicU64::new(0);
+
+/// The hash and width of the image in `file`, read off the async path, or as read before when the
+/// file hasn't changed since.
+async fn source(file: &Path) -> Option<Source> {
+    let modified = std::fs::metadata(file).ok()?.modified().ok()?;
+    let known = SOURCES
+        .lock()
+        .ok()?
+        .get(file)
+        .filter(|(when, _)| *when == modified)
+        .map(|(_, source)| source.clone());
+    if known.is_some() {
+        return known;
+    }
+    let path = file.to_path_buf();
+    let contents = tokio::task::spawn_blocking(move || std::fs::read(path))
+        .await
+        .ok()?
+        .ok()?;
+    let source = Source {
+        hash: crate::helpers::content_hash(&contents),
+        width: width(&contents),
+    };
+    SOURCES
+        .lock()
+        .ok()?
+        .insert(file.to_path_buf(), (modified, source.clone()));
+    Some(source)
+}
+
+/// The file name of the copy of `file` in `format`, at most `max_width` pixels wide (`0` keeps
+/// its size), making it when it isn't there yet. Named after the source's content, the width and
+/// the quality, so changing any of them makes a new one.
+async fn variant(
+    file: &Path,
+    source: &Source,
+    format: &str,
+    max_width: u16,
+    options: &ImageOptions,
+) -> Option<String> {
+    let fingerprint = format!("{}:{}:{}", source.hash, max_width, options.quality);
+    let name = format!(
+        "{}.{}",
+        crate::helpers::content_hash(fingerprint.as_bytes()),
+        format
+    );
+    let dir = PathBuf::from(&options.cache_dir);
+    let copy = dir.join(&name);
+    let exists = {
+        let (dir, copy) = (dir.clone(), copy.clone());
+        tokio::task::spawn_blocking(move || {
+            if copy.is_file() {
+                return Ok(true);
+            }
+            std::fs::create_dir_all(dir).map(|_| false)
+        })
+        .await
+        .ok()?
+        .ok()?
+    };
+    if exists {
+        return Some(name);
+    }
+    // Written under another name first, so a half-made copy is never served. Each render its own,
+    // pages showing the same image can be rendered at the same time.
+    let temporary = dir.join(format!(
+        "{}.{}-{}.tmp",
+        name,
+        std::process::id(),
+        TEMPORARY.fetch_add(1, Ordering::Relaxed)
+    ));
+    let mut command = Command::new(&options.converter);
+    command.arg(file);
+    if max_width != 0 {
+        // `>` only ever makes images smaller.
+        command.args(["-resize", &format!("{}x>", max_width)]);
+    }
+    command
+        .args(["-strip", "-quality", &options.quality.to_string()])
+        .arg(format!("{}:{}", format, temporary.display()));
+    match command.output().await {
+        Ok(output) if output.status.success() => {
+            let made = tokio::task::spawn_blocking(move || {
+                let renamed = std::fs::rename(&temporary, &copy).is_ok();
+                if !renamed {
+                    let _ = std::fs::remove_file(&temporary);
+                }
+                // Another render may have put the same copy in place first.
+                renamed || copy.is_file()
+            })
+            .await
+            .ok()?;
+            if made {
+                debug!("Made {} from {}.", name, file.display());
+            }
+            made.then_some(name)
+        }
+        Ok(output) => {
+            let _ = tokio::task::spawn_blocking(move || std::fs::remove_file(temporary)).await;
+            warn!(
+                "Could not make a {} copy of {}: {}",
+                format,
+                file.display(),
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+            None
+        }
+        Err(e) => {
+            warn!(
+                "Could not make a {} copy of {}: {}",
+                format,
+                file.display(),
+                e
+            );
+            None
+        }
+    }
+}
+
+/// Wraps the `<img>` tags for local JPEG and PNG images in `<picture>` tags offering copies in
+/// `images.formats`, in several widths with `images.responsive`. Images already in a `<picture>`
+/// are left as they are.
+pub(crate) async fn rewrite(
+    html: &str,
+    options: &ImageOptions,
+    static_mounts: &[StaticMount],
+) -> String {
+    let mut rewritten = String::with_capacity(html.len());
+    let mut last = 0;
+    for tag in TAGS.find_iter(html) {
+        rewritten.push_str(&html[last..tag.start()]);
+        match picture(tag.as_str(), options, static_mounts).await {
+            Some(picture) => rewritten.push_str(&picture),
+            None => rewritten.push_str(tag.as_str()),
+        }
+        last = tag.end();
+    }
+    rewritten.push_str(&html[last..]);
+    rewritten
+}
+
+/// `tag` in a `<picture>` with the copies of its image, `None` when it's left as it is.
+async fn picture(
+    tag: &str,
+    options: &ImageOptions,
+    static_mounts: &[StaticMount],
+) -> Option<String> {
+    if tag
+        .get(..8)
+        .is_some_and(|t| t.eq_ignore_ascii_case("<picture"))
+    {
+        return None;
+    }
+    let file = SRC
+        .captures(tag)
+        .and_then(|c| c.get(1).or(c.get(2)))
+        .and_then(|s| local_file(s.as_str(), static_mounts))
+        .filter(|file| optimisable(file) && file.is_file())?;
+    let source = source(&file).await?;
+    // The copies to make, as the width to scale to and the width to tell browsers. With
+    // `images.responsive` a few smaller ones too, otherwise, or when the image's width can't
+    // be read, only the one at `max_width`.
+    let copies: Vec<(u16, Option<u32>)> = match source.width.filter(|_| options.responsive) {
+        Some(width) => {
+            let full = match options.max_width {
+                0 => width,
+                max_width => width.min(max_width as u32),
+            };
+            RESPONSIVE_WIDTHS
+                .iter()
+                .filter(|w| (**w as u32) < full)
+                .map(|w| (*w, Some(*w as u32)))
+                .chain([(options.max_width, Some(full))])
+                .collect()
+        }
+        None => vec![(options.max_width, None)],
+    };
+    // Browsers pick from widths by the `sizes` of the `<img>`, taken along to the sources.
+    let sizes_attribute = match SIZES.captures(tag).and_then(|c| c.get(1).or(c.get(2))) {
+        Some(s) if copies.len() > 1 => {
+            format!(" sizes=\"{}\"", s.as_str().replace('"', "&quot;"))
+        }
+        _ => String::new(),
+    };
+    let mut sources = String::new();
+    for format in &options.formats {
+        let Some(content_type) = content_type(format) else {
+            continue;
+        };
+        // With `images.responsive` an image has a few copies per format, made side by side rather
+        // than one after another.
+        let names = join_all(
+            copies
+                .iter()
+                .map(|(max_width, _)| variant(&file, &source, format, *max_width, options)),
+        )
+        .await;
+        let Some(srcset) = names
+            .into_iter()
+            .zip(&copies)
+            .map(|(name, (_, width))| {
+                Some(match width {
+                    Some(width) if copies.len() > 1 => {
+                        format!("{}{} {}w", URL_PREFIX, name?, width)
+                    }
+                    _ => format!("{}{}", URL_PREFIX, name?),
+                })
+            })
+            .collect::<Option<Vec<String>>>()
+        else {
+            continue;
+        };
+        sources.push_str(&format!(
+            "<source srcset=\"{}\"{} type=\"{}\">",
+            srcset.join(", "),
+            sizes_attribute,
+            content_type
+        ));
+    }
+    if sources.is_empty() {
+        None
+    } else {
+        Some(format!("<picture>{}{}</picture>", sources, tag))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The name [`variant`] gives the copy of an image with `contents`, with the default quality.
+    fn copy_name(contents: &[u8], max_width: u16, format: &str) -> String {
+        let fingerprint = format!(
+            "{}:{}:80",
+            crate::helpers::content_hash(contents),
+            max_width
+        );
+        format!(
+            "{}.{}",
+            crate::helpers::content_hash(fingerprint.as_bytes()),
+            format
+        )
+    }
+
+    #[tokio::test]
+    async fn offers_copies_of_local_images() {
+        let dir = std::env::temp_dir().join(format!("cynthia-images-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("static")).unwrap();
+        std::fs::write(dir.join("static/cat.png"), b"not really a png").unwrap();
+        std::fs::write(dir.join("static/logo.svg"), b"<svg></svg>").unwrap();
+        let options = ImageOptions {
+            enabled: true,
+            formats: vec![String::from("webp")],
+            // Never run, the copy is already there.
+            converter: String::from("cynthia-no-such-converter"),
+            cache_dir: dir.join("cache").to_string_lossy().to_string(),
+            ..ImageOptions::default()
+        };
+        let mounts = vec![StaticMount {
+            prefix: String::from("/static"),
+            path: dir.join("static").to_string_lossy().to_string(),
+        }];
+        let name = copy_name(b"not really a png", 1600, "webp");
+        std::fs::create_dir_all(dir.join("cache")).unwrap();
+        std::fs::write(dir.join("cache").join(&name), b"").unwrap();
+
+        let html = r#"<p><img src="/static/cat.png" alt="A cat"></p>"#;
+        assert_eq!(
+            rewrite(html, &options, &mounts).await,
+            format!(
+                r#"<p><picture><source srcset="/cynthia/images/{}" type="image/webp"><img src="/static/cat.png" alt="A cat"></picture></p>"#,
+                name
+            )
+        );
+        // SVGs, other sites' images and images already in a `<picture>` stay as they are.
+        for html in [
+            r#"<img src="/static/logo.svg">"#,
+            r#"<img src="https://example.com/cat.png">"#,
+            r#"<picture><source srcset="/cat.avif"><img src="/static/cat.png"></picture>"#,
+        ] {
+            assert_eq!(rewrite(html, &options, &mounts).await, html);
+        }
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn offers_smaller_copies_when_responsive() {
+        let dir = std::env::temp_dir().join(format!("cynthia-responsive-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("cache")).unwrap();
+        // Just the header of a PNG image 1200 pixels wide.
+        let png = [
+            b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR".as_slice(),
+            &1200u32.to_be_bytes(),
+            &800u32.to_be_bytes(),
+        ]
+        .concat();
+        assert_eq!(width(&png), Some(1200));
+        let jpeg = [
+            0xFF, 0xD8, 0xFF, 0xE0, 0x00, 0x04, 0x00, 0x00, 0xFF, 0xC0, 0x00, 0x11, 0x08, 0x01,
+            0x2C, 0x02, 0x58,
+        ];
+        assert_eq!(width(&jpeg), Some(600));
+        std::fs::write(dir.join("cat.png"), &png).unwrap();
+        let options = ImageOptions {
+            enabled: true,
+            responsive: true,
+            formats: vec![String::from("webp")],
+            converter: String::from("cynthia-no-such-converter"),
+            cache_dir: dir.join("cache").to_string_lossy().to_string(),
+            ..ImageOptions::default()
+        };
+        let mounts = vec![StaticMount {
+            prefix: String::from("/static"),
+            path: dir.to_string_lossy().to_string(),
+        }];
+        let names: Vec<String> = [480, 960, 1600]
+            .iter()
+            .map(|max_width| {
+                let name = copy_name(&png, *max_width, "webp");
+                std::fs::write(dir.join("cache").join(&name), b"").unwrap();
+                name
+            })
+            .collect();
+
+        let html = r#"<img src="/static/cat.png" sizes="50vw">"#;
+        assert_eq!(
+            rewrite(html, &options, &mounts).await,
+            format!(
+                r#"<picture><source srcset="/cynthia/images/{} 480w, /cynthia/images/{} 960w, /cynthia/images/{} 1200w" sizes="50vw" type="image/webp">{}</picture>"#,
+                names[0], names[1], names[2], html
+            )
+        );
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
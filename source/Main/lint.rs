@@ -0,0 +1,162 @@
+/*
+ * Copyright (c) 2024, MLC 'Strawmelonjuice' Bloeiman
+ *
+ * Licensed under the GNU AFFERO GENERAL PUBLIC LICENSE Version 3, see the LICENSE file for more information.
+ */
+//! Finds internal links in publication content that lead nowhere, before visitors do.
+//! Used by `cynthiaweb lint`.
+use std::path::Path;
+
+use crate::config::{CynthiaConf, StaticMount};
+use crate::publications::{
+    read_content_file, read_published_from_disk, CynthiaPublicationList,
+    CynthiaPublicationListTrait, PublicationContent,
+};
+use crate::tell::CynthiaColors;
+
+/// Routes Cynthia answers itself, whatever follows them.
+const DYNAMIC_PREFIXES: [&str; 6] = ["/category/", "/cat/", "/c/", "/tag/", "/t/", "/cynthia/"];
+
+/// Checks the links in every page and post, and prints those that don't resolve, per publication.
+/// Returns whether no dangling links were found.
+pub(crate) fn lint(config: &CynthiaConf) -> bool {
+    let Some(published) = read_published_from_disk() else {
+        eprintln!(
+            "{} Couldn't read the publication list, `./cynthiaFiles/published.jsonc`, `./cynthiaFiles/published.yaml` (or either gzipped) or the files in `./cynthiaFiles/published/`.",
+            "error:".color_red()
+        );
+        return false;
+    };
+    let mut clean = true;
+    for publication in &published {
+        let Some(content) = publication.get_content() else {
+            continue;
+        };
+        let text = match content {
+            PublicationContent::Inline(c) => Ok(c.get_inner()),
+            PublicationContent::Base64(c) => c.decode_base64(),
+            PublicationContent::Local { source, encoding } => {
+                let path = format!("./cynthiaFiles/publications/{}", source.get_inner());
+                read_content_file(Path::new(&path), encoding.as_deref())
+            }
+            // Could be anywhere, and change at any time.
+            PublicationContent::External { .. } => continue,
+        };
+        let dangling = match text {
+            Ok(text) => internal_links(&text)
+                .into_iter()
+                .filter(|link| !resolves(link, &published, config))
+                .collect::<Vec<String>>(),
+            Err(e) => vec![e],
+        };
+        if dangling.is_empty() {
+            continue;
+        }
+        clean = false;
+        println!("{}", publication.get_id().color_lilac());
+        for link in dangling {
+            println!("\t{}\t{}", "dangling".color_error_red(), link);
+        }
+    }
+    if clean {
+        println!("{}", "No dangling internal links found.".color_ok_green());
+    }
+    clean
+}
+
+/// Every `href` and `src` in HTML and every Markdown link target that starts with a single `/`.
+fn internal_links(text: &str) -> Vec<String> {
+    let mut links = Vec::new();
+    for attribute in ["href=", "src="] {
+        for (at, _) in text.match_indices(attribute) {
+            let rest = &text[at + attribute.len()..];
+            let Some(quote) = rest.chars().next().filter(|c| *c == '"' || *c == '\'') else {
+                continue;
+            };
+            if let Some(end) = rest[1..].find(quote) {
+                links.push(rest[1..=end].to_string());
+            }
+        }
+    }
+    for (at, _) in text.match_indices("](") {
+        let rest = &text[at + 2..];
+        let end = rest
+            .find(|c: char| c == ')' || c.is_whitespace())
+            .unwrap_or(rest.len());
+        links.push(rest[..end].to_string());
+    }
+    links.retain(|link| link.starts_with('/') && !link.starts_with("//"));
+    links.sort();
+    links.dedup();
+    links
+}
+
+/// Whether an internal link leads to a publication, an asset or a static file, or to one of Cynthia's own routes.
+fn resolves(link: &str, published: &CynthiaPublicationList, config: &CynthiaConf) -> bool {
+    let path = link.split(['?', '#']).next().unwrap_or_default();
+    let path = urlencoding::decode(path)
+        .map(|p| p.into_owned())
+        .unwrap_or_else(|_| path.to_string());
+    if path == "/favicon.ico" || DYNAMIC_PREFIXES.iter().any(|p| path.starts_with(p)) {
+        return true;
+    }
+    if let Some(asset) = path.strip_prefix("/assets/") {
+        return Path::new("./cynthiaFiles/assets/").join(asset).is_file();
+    }
+    if let Some(found) = in_static_mounts(&path, &config.static_mounts) {
+        return found;
+    }
+    let id = path.trim_matches('/');
+    if id.is_empty() {
+        return match &config.home {
+            Some(home) => published.get_by_id(home.clone()).is_some(),
+            None => published.get_root().is_some(),
+        };
+    }
+    published.get_by_id(id.to_string()).is_some()
+        || id.split_once('/').is_some_and(|(lang, key)| {
+            published
+                .find_translation(lang, key, &config.site.default_lang)
+                .is_some()
+        })
+}
+
+/// Whether the file behind `path` exists, when `path` falls under one of the static mounts.
+fn in_static_mounts(path: &str, mounts: &[StaticMount]) -> Option<bool> {
+    mounts.iter().find_map(|mount| {
+        let prefix = format!("/{}/", mount.prefix.trim_matches('/'));
+        let file = path.strip_prefix(&prefix)?;
+        Some(Path::new(&mount.path).join(file).is_file())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::publications::tests::publication;
+
+    #[test]
+    fn finds_and_checks_internal_links() {
+        let links = internal_links(
+            "See [the post](/hello-world#top), [elsewhere](https://example.com) and <a href=\"/nope\">this</a>.\n\
+             <img src='/assets/missing.png'> <img src=\"//cdn.example.com/x.png\"> [home](/)",
+        );
+        assert_eq!(
+            links,
+            ["/", "/assets/missing.png", "/hello-world#top", "/nope"]
+        );
+
+        let published: CynthiaPublicationList = vec![
+            publication("page", serde_json::json!({"id": "root"})),
+            publication("post", serde_json::json!({"id": "hello-world"})),
+        ];
+        let config = CynthiaConf::default();
+        let dangling: Vec<&String> = links
+            .iter()
+            .filter(|link| !resolves(link, &published, &config))
+            .collect();
+        assert_eq!(dangling, ["/assets/missing.png", "/nope"]);
+        assert!(resolves("/Hello%20World?ref=x", &published, &config));
+        assert!(resolves("/tag/anything", &published, &config));
+    }
+}
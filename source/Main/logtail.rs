@@ -0,0 +1,70 @@
+/*
+ * Copyright (c) 2024, MLC 'Strawmelonjuice' Bloeiman
+ *
+ * Licensed under the GNU AFFERO GENERAL PUBLIC LICENSE Version 3, see the LICENSE file for more information.
+ */
+//! Keeps the most recent log lines in memory, so they can be tailed over `/cynthia/logs`.
+use std::collections::VecDeque;
+use std::io::Write;
+use std::sync::{Mutex, OnceLock};
+
+use tokio::sync::broadcast;
+
+/// How many lines a new listener gets to see from before it connected.
+const KEPT_LINES: usize = 500;
+
+struct LogTail {
+    lines: Mutex<VecDeque<String>>,
+    sender: broadcast::Sender<String>,
+}
+
+fn tail() -> &'static LogTail {
+    static TAIL: OnceLock<LogTail> = OnceLock::new();
+    TAIL.get_or_init(|| LogTail {
+        lines: Mutex::new(VecDeque::with_capacity(KEPT_LINES)),
+        sender: broadcast::channel(KEPT_LINES).0,
+    })
+}
+
+/// Returns the lines kept so far, and a receiver for the lines logged from now on.
+pub(crate) fn subscribe() -> (Vec<String>, broadcast::Receiver<String>) {
+    let tail = tail();
+    let lines = tail.lines.lock().unwrap();
+    // Subscribing while holding the lock makes sure no line is missed or seen twice.
+    (lines.iter().cloned().collect(), tail.sender.subscribe())
+}
+
+/// A writer for `simplelog::WriteLogger` that feeds the log tail, one line at a time.
+#[derive(Default)]
+pub(crate) struct TailWriter {
+    partial: Vec<u8>,
+}
+
+impl Write for TailWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.partial.extend_from_slice(buf);
+        while let Some(end) = self.partial.iter().position(|b| *b == b'\n') {
+            let line: Vec<u8> = self.partial.drain(..=end).collect();
+            // Stripping the colours would drop the tabs as well, so those are kept apart.
+            let line = String::from_utf8_lossy(&line)
+                .trim_end()
+                .split('\t')
+                .map(strip_ansi_escapes::strip_str)
+                .collect::<Vec<_>>()
+                .join("\t");
+            let tail = tail();
+            let mut lines = tail.lines.lock().unwrap();
+            if lines.len() == KEPT_LINES {
+                lines.pop_front();
+            }
+            lines.push_back(line.clone());
+            // Nobody listening is fine.
+            let _ = tail.sender.send(line);
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
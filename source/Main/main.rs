@@ -4,16 +4,25 @@
  * Licensed under the GNU AFFERO GENERAL PUBLIC LICENSE Version 3, see the LICENSE file for more information.
  */
 
+use actix_files::Files;
+use actix_web::dev::Service;
+use actix_web::http::KeepAlive;
+use actix_web::middleware::{from_fn, Condition, DefaultHeaders};
 use actix_web::web::Data;
-use actix_web::{App, HttpServer};
-use futures::join;
+use actix_web::{web, App, HttpServer};
+use futures::future::{self, Either};
+use futures::{join, TryFutureExt};
 use log::LevelFilter;
 use log::{debug, error};
-use log::{info, trace};
-use requestresponse::{assets_with_cache, category, post, serve, tags};
-use simplelog::{ColorChoice, CombinedLogger, TermLogger, TerminalMode, WriteLogger};
+use log::{info, trace, warn};
+use requestresponse::{
+    assets_with_cache, build_info, category, client_script, favicon, healthz, image_variant, logs,
+    maintenance, post, reload_webhook, serve, stats, tags, taxonomy, unsupported_method,
+};
+use simplelog::{ColorChoice, CombinedLogger, SharedLogger, TermLogger, TerminalMode, WriteLogger};
 use std::fs::File;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use std::time::{SystemTime, UNIX_EPOCH};
 use std::{fs, process};
@@ -24,17 +33,33 @@ use tokio::sync::{Mutex, MutexGuard};
 use crate::cache::CynthiaCache;
 use crate::config::{CynthiaConf, CynthiaConfig, SceneCollectionTrait};
 use crate::externalpluginservers::EPSRequest;
+use crate::publications::CynthiaPublicationList;
 use crate::tell::horizline;
 
+mod api;
 mod cache;
 mod config;
+mod emoji;
 mod externalpluginservers;
 mod files;
 mod helpers;
+mod images;
 mod jsrun;
+mod lint;
+mod logtail;
+mod plugincheck;
+mod proxies;
 mod publications;
 mod renders;
+mod requestid;
 mod requestresponse;
+mod sanitize;
+mod scaffold;
+mod shortcodes;
+mod timing;
+mod visits;
+mod warm;
+mod watcher;
 
 struct LogSets {
     pub file_loglevel: LevelFilter,
@@ -42,17 +67,127 @@ struct LogSets {
     pub logfile: PathBuf,
 }
 
+/// Where `server.canonical_redirect` sends requests: the scheme and host of the base URL.
+#[derive(Clone)]
+struct CanonicalOrigin {
+    https: bool,
+    /// Lowercase, with the port only when it isn't the scheme's default.
+    host: String,
+}
+impl CanonicalOrigin {
+    fn from_base_url(base_url: &str) -> Self {
+        let (https, rest) = match base_url.strip_prefix("https://") {
+            Some(rest) => (true, rest),
+            None => (false, base_url.trim_start_matches("http://")),
+        };
+        let host = rest.split('/').next().unwrap_or_default();
+        CanonicalOrigin {
+            https,
+            host: Self::normalise_host(host, https),
+        }
+    }
+
+    fn normalise_host(host: &str, https: bool) -> String {
+        let host = host.trim().to_ascii_lowercase();
+        let default_port = if https { ":443" } else { ":80" };
+        host.strip_suffix(default_port)
+            .unwrap_or(&host)
+            .trim_end_matches('.')
+            .to_string()
+    }
+
+    /// The redirect for a request that isn't on the canonical origin. Requests without a host, or whose
+    /// scheme the proxy didn't pass on, are let through: guessing there is how redirect loops happen.
+    fn redirect(&self, req: &actix_web::dev::ServiceRequest) -> Option<actix_web::HttpResponse> {
+        let header = |name: &str| {
+            req.headers()
+                .get(name)
+                .and_then(|v| v.to_str().ok())
+                // Proxies in a chain each append theirs, the first is the client's.
+                .and_then(|v| v.split(',').next())
+                .map(|v| v.trim().to_string())
+                .filter(|v| !v.is_empty())
+        };
+        let forwarded_https = header("X-Forwarded-Proto").map(|p| p.eq_ignore_ascii_case("https"));
+        let host = header("X-Forwarded-Host")
+            .or_else(|| header("Host"))
+            .or_else(|| req.uri().authority().map(|a| a.to_string()))?;
+        let request_https = forwarded_https.unwrap_or(self.https);
+        let host = Self::normalise_host(&host, request_https);
+        // A proxy that passes its own upstream address as the host would be redirected forever,
+        // and so would health checks by IP.
+        let name = match host.strip_prefix('[') {
+            Some(ipv6) => ipv6.split(']').next().unwrap_or_default(),
+            None => host
+                .rsplit_once(':')
+                .map_or(host.as_str(), |(name, _)| name),
+        };
+        if name == "localhost" || name.parse::<std::net::IpAddr>().is_ok() {
+            return None;
+        }
+        let wrong_host = host != self.host;
+        // Only ever upgrades to HTTPS, never downgrades.
+        let wrong_scheme = self.https && forwarded_https == Some(false);
+        if !wrong_host && !wrong_scheme {
+            return None;
+        }
+        let location = format!(
+            "{}://{}{}",
+            if self.https { "https" } else { "http" },
+            self.host,
+            req.uri()
+                .path_and_query()
+                .map(|p| p.as_str())
+                .unwrap_or("/")
+        );
+        // 308 keeps the method and body of anything but a plain GET or HEAD.
+        let mut response = if matches!(
+            *req.method(),
+            actix_web::http::Method::GET | actix_web::http::Method::HEAD
+        ) {
+            actix_web::HttpResponse::MovedPermanently()
+        } else {
+            actix_web::HttpResponse::PermanentRedirect()
+        };
+        Some(response.append_header(("Location", location)).finish())
+    }
+}
+
+/// Kept with every connection, so its protocol is only logged for its first request.
+#[derive(Default)]
+struct ProtocolLogged(AtomicBool);
+
 #[derive(Debug)]
 /// Server context, containing the configuration and cache. Also implements a `tell` method for easy logging.
+/// Behind a `Mutex` rather than a `RwLock`: requests write to it too, counting and caching, and only
+/// hold it for a [`LockCallback`], which never awaits.
 struct ServerContext {
-    config: CynthiaConf,
+    /// Taken by requests as a cheap clone of the `Arc`, the lock is only held for that.
+    config: Arc<CynthiaConf>,
     cache: CynthiaCache,
+    /// The merged publication list with when it was read, see `CynthiaPublicationListTrait::load`.
+    published: Option<(Arc<CynthiaPublicationList>, u64)>,
     request_count: u64,
     start_time: u128,
+    /// Visits per publication, shared so counting doesn't need this context locked.
+    visits: Arc<visits::Visits>,
 
     #[cfg(feature = "js_runtime")]
     external_plugin_server: EPSCommunicationData,
 }
+impl ServerContext {
+    /// Puts in a reloaded configuration and empties the cache, its pages were rendered with the old
+    /// scenes and templates. Every request waits while the lock is held, so this only swaps: the old
+    /// configuration and cache are returned, to be compared and freed after it's released. Requests
+    /// that took the old configuration keep it until they're done.
+    fn swap_config(&mut self, config: CynthiaConf) -> (Arc<CynthiaConf>, CynthiaCache) {
+        self.published = None;
+        (
+            std::mem::replace(&mut self.config, Arc::new(config)),
+            std::mem::take(&mut self.cache),
+        )
+    }
+}
 trait LockCallback {
     async fn lock_callback<F, T>(&self, f: F) -> T
     where
@@ -103,10 +238,12 @@ async fn main() {
     );
     println!("{}", horizline().color_purple());
 
+    let build = helpers::build_info();
     println!(
-        "{} - version {}\n by {}{}{} {}!",
+        "{} - version {} ({})\n by {}{}{} {}!",
         "CynthiaWeb".style_bold().color_lilac(),
-        env!("CARGO_PKG_VERSION").to_string().color_green(),
+        build.version.to_string().color_green(),
+        build.commit.to_string().color_lilac(),
         "Straw".color_bright_red(),
         "melon".color_green(),
         "juice".color_bright_yellow(),
@@ -140,7 +277,78 @@ async fn main() {
             println!(
                 "\t{}{}",
                 "start".style_bold().color_yellow(),
-                ": Starts the server.".color_lime()
+                ": Starts the server. Send it `SIGHUP` to reload the configuration and plugins."
+                    .color_lime()
+            );
+            println!(
+                "\t\t{}{}",
+                "--base-url [url]".color_yellow(),
+                ": Overrides the absolute URL the site is reachable at.".color_lime()
+            );
+            println!(
+                "\t\t{}{}",
+                "--config-stdin".color_yellow(),
+                ": Reads the configuration, as TOML, from standard input instead of a file. Needs `--root-dir`."
+                    .color_lime()
+            );
+            println!(
+                "\t\t{}{}",
+                "--root-dir [dir]".color_yellow(),
+                ": Overrides `root_dir`, the directory the site is in.".color_lime()
+            );
+            println!(
+                "\t\t{}{}",
+                "--check-plugins".color_yellow(),
+                ": Checks that the configured plugins can be loaded before serving.".color_lime()
+            );
+            println!(
+                "\t\t{}{}",
+                "--watch".color_yellow(),
+                ": Watches `./cynthiaFiles` and drops cached pages when their files change, reloads changed plugins."
+                    .color_lime()
+            );
+            println!(
+                "\t\t{}{}",
+                "--dev".color_yellow(),
+                ": Development mode, no page caching or minifying, full error details in the browser. Unsafe on public servers."
+                    .color_lime()
+            );
+            println!(
+                "\t\t{}{}",
+                "--trace-render".color_yellow(),
+                ": Annotates rendered pages with comments marking each render stage and its timing. Pages aren't cached."
+                    .color_lime()
+            );
+            println!(
+                "\t{}{}",
+                "warm".style_bold().color_yellow(),
+                ": Renders every publication into `prerender_dir`, so the server starts with them ready. Exits with an error when some don't render."
+                    .color_lime()
+            );
+            println!(
+                "\t\t{}{}",
+                "--jobs [n]".color_yellow(),
+                ": Renders this many publications at the same time, one by default.".color_lime()
+            );
+            println!(
+                "\t{}{}",
+                "check-plugins".style_bold().color_yellow(),
+                ": Checks that the configured plugins can be loaded, exits with an error when one can't."
+                    .color_lime()
+            );
+            println!(
+                "\t{}{}",
+                "lint".style_bold().color_yellow(),
+                ": Lists internal links in pages and posts that lead nowhere, exits with an error when there are any."
+                    .color_lime()
+            );
+            println!(
+                "\t{}{}\n\t\t{}",
+                "new [post|page]".style_bold().color_yellow(),
+                ": Adds an empty publication to `published.jsonc`, with a content file to write it in."
+                    .color_lime(),
+                "Options: `--title [title]`, `--id [id]`, `--mode [markdown|html|plaintext]`, `--kind [post|page]`. Asks for a missing title or id."
+                    .style_clear()
             );
             println!(
                 "\t{}{}\n\t\t{}",
@@ -169,6 +377,24 @@ async fn main() {
             process::exit(0);
         }
         "start" => start().await,
+        "warm" => warm().await,
+        "check-plugins" => {
+            let config = config::actions::load_config();
+            if !plugincheck::check_plugins(&config) {
+                process::exit(1);
+            }
+        }
+        "new" => scaffold::new_publication(&args),
+        "lint" => {
+            let mut config = config::actions::load_config();
+            if let Err(e) = config::actions::enter_root_dir(&mut config) {
+                eprintln!("{} {}", "error:".color_red(), e);
+                process::exit(1);
+            }
+            if !lint::lint(&config) {
+                process::exit(1);
+            }
+        }
         "convert" => {
             if args.len() < 3 {
                 eprintln!(
@@ -513,29 +739,129 @@ async fn interactive_initialiser() {
     }
 }
 
-async fn start() {
-    let cd = std::env::current_dir().unwrap();
-    let config = config::actions::load_config();
-    // Validate the configuration
+/// Validates a freshly loaded configuration and fills in what's derived from it, like the base URL.
+fn prepare_config(config: &mut CynthiaConf) -> Result<(), String> {
     if config.port == 0 {
+        return Err(String::from(
+            "Could not set port to 0! Please set it to a valid port.",
+        ));
+    }
+    if config.workers == Some(0) {
+        return Err(String::from(
+            "Could not set workers to 0! Leave it out to use one per CPU core.",
+        ));
+    }
+    if let Some(base_url) = arg_value("--base-url") {
+        config.site.site_baseurl = base_url;
+    }
+    if config.site.site_baseurl.is_empty() && config.server.canonical_redirect {
+        return Err(String::from(
+            "`server.canonical_redirect` needs a base URL to redirect to! Set `site.site_baseurl` or pass `--base-url`.",
+        ));
+    }
+    if config.site.site_baseurl.is_empty() {
         eprintln!(
-            "{} Could not set port to 0! Please set it to a valid port.",
-            "error:".color_red()
+            "{} No base URL set, falling back to `http://localhost:{}`. Set `site.site_baseurl` or pass `--base-url` when running in production.",
+            "warning:".color_yellow(),
+            config.port
         );
-        process::exit(1);
+        config.site.site_baseurl = format!("http://localhost:{}", config.port);
     }
-    if config.logs.is_none() {
-        eprintln!("No log configuration found, using defaults");
+    // `home` is an id, which is written without slashes around it.
+    config.home = config
+        .home
+        .as_deref()
+        .map(|home| home.trim_matches('/').to_string())
+        .filter(|home| !home.is_empty());
+    // The plugin runner loads plugins, and so applies their changes, in the order they're listed in.
+    config::sort_plugins(&mut config.plugins);
+    #[cfg(feature = "js_runtime")]
+    config::check_js_runtime(config)?;
+    config.site.site_baseurl = config::Site::normalise_baseurl(&config.site.site_baseurl)
+        .map_err(|e| format!("Invalid base URL! {}", e))?;
+    config::timezone(&config.timezone).map_err(|e| format!("Invalid timezone! {}", e))?;
+    config::charset(&config.charset).map_err(|e| format!("Invalid charset! {}", e))?;
+    config
+        .site
+        .shortcodes
+        .check()
+        .map_err(|e| format!("Invalid shortcode delimiters! {}", e))?;
+    proxies::TrustedProxies::parse(&config.trusted_proxies)
+        .map_err(|e| format!("Invalid trusted proxies! {}", e))?;
+    for (name, entry) in &config.well_known {
+        if entry.file.is_some() == entry.content.is_some() {
+            return Err(format!(
+                "`well_known.\"{}\"` needs either a `file` or `content`, not both or neither.",
+                name
+            ));
+        }
     }
-    // Validate scenes
+    if !(200..=599).contains(&config.missing_content.status) {
+        return Err(format!(
+            "Could not set `missing_content.status` to {}! Use a status code from 200 to 599.",
+            config.missing_content.status
+        ));
+    }
+    config::validate_headers(&config.site.headers)
+        .map_err(|e| format!("Invalid site headers! {}", e))?;
+    config::validate_headers(&config.security.headers())
+        .map_err(|e| format!("Invalid security headers! {}", e))?;
     if !config.scenes.validate() {
+        return Err(String::from(
+            "Could not validate scenes! Please check your configuration.",
+        ));
+    }
+    config.scenes.validate_scene_names(&config.site)?;
+    config.scenes.validate_category_templates()?;
+    if let Some(template) = &config.error_pages.server_error {
+        let path = Path::new("./cynthiaFiles/templates/error").join(format!("{}.hbs", template));
+        if !path.is_file() {
+            return Err(format!(
+                "The server error page `{}` does not exist, expected it at {}.",
+                template,
+                path.display()
+            ));
+        }
+    }
+    if let Some(thumbnail) = &config.site.default_author_thumbnail {
+        config.site.default_author_thumbnail =
+            Some(helpers::safe_url(thumbnail).ok_or_else(|| {
+                format!(
+                    "Invalid default author thumbnail! `{}` is not a URL that can be shown.",
+                    thumbnail
+                )
+            })?);
+    }
+    for warning in
+        config::validate_static_mounts(&mut config.static_mounts, Path::new("./cynthiaPlugins"))?
+    {
+        eprintln!("{} {}", "warning:".color_yellow(), warning);
+    }
+    if let Some(warning) = images::check(&mut config.images)? {
+        eprintln!("{} {}", "warning:".color_yellow(), warning);
+    }
+    Ok(())
+}
+
+async fn start() {
+    let mut config = config::actions::load_config();
+    if let Err(e) =
+        config::actions::enter_root_dir(&mut config).and_then(|_| prepare_config(&mut config))
+    {
+        eprintln!("{} {}", "error:".color_red(), e);
+        process::exit(1);
+    }
+    if std::env::args().any(|a| a == "--check-plugins") && !plugincheck::check_plugins(&config) {
         eprintln!(
-            "{} Could not validate scenes! Please check your configuration.",
-            "error:".color_red()
+            "{} Some plugins did not pass their checks, see above.",
+            "warning:".color_yellow()
         );
-        process::exit(1);
+    }
+    if config.logs.is_none() {
+        eprintln!("No log configuration found, using defaults");
     }
     debug!("Configuration: {:?}", config);
+    let cd = std::env::current_dir().unwrap();
     let logsets: LogSets = {
         fn matchlogmode(o: u16) -> LevelFilter {
             match o {
@@ -555,7 +881,7 @@ async fn start() {
                 }
             }
         }
-        match config.clone().logs {
+        match config.logs.clone() {
             None => LogSets {
                 file_loglevel: LevelFilter::Info,
                 term_loglevel: LevelFilter::Warn,
@@ -578,9 +904,19 @@ async fn start() {
         }
     };
 
-    CombinedLogger::init(vec![
+    if dev_mode() {
+        eprintln!(
+            "{} Running in development mode: pages aren't cached and errors are shown in full to anyone visiting. Never use `--dev` on a public server.",
+            "warning:".color_yellow()
+        );
+    }
+    let logger = CombinedLogger::new(vec![
         TermLogger::new(
-            logsets.term_loglevel,
+            if dev_mode() {
+                logsets.term_loglevel.max(LevelFilter::Debug)
+            } else {
+                logsets.term_loglevel
+            },
             simplelog::Config::default(),
             TerminalMode::Mixed,
             ColorChoice::Auto,
@@ -590,17 +926,29 @@ async fn start() {
             simplelog::Config::default(),
             File::create(&logsets.logfile).unwrap(),
         ),
-    ])
-    .unwrap();
+        WriteLogger::new(
+            logsets.file_loglevel,
+            simplelog::Config::default(),
+            logtail::TailWriter::default(),
+        ),
+    ]);
+    log::set_max_level(logger.level());
+    log::set_boxed_logger(Box::new(requestid::TaggingLogger(logger))).unwrap();
     use crate::config::CynthiaConfig;
 
     let (_to_eps_s, to_eps_r) = tokio::sync::mpsc::channel::<EPSRequest>(100);
     // Initialise context
     let server_context: ServerContext = ServerContext {
-        config: config.hard_clone(),
+        config: config.clone(),
         cache: vec![],
+        published: None,
         request_count: 0,
         start_time: 0,
+        visits: Arc::new(if config.visits.enabled && !config.visits.file.is_empty() {
+            visits::Visits::load(std::path::Path::new(&config.visits.file))
+        } else {
+            visits::Visits::default()
+        }),
 
         #[cfg(feature = "js_runtime")]
         external_plugin_server: EPSCommunicationData::new(_to_eps_s),
@@ -614,6 +962,23 @@ async fn start() {
             .to_string_lossy()
             .replace("\\\\?\\", "")
     ));
+    let (on, off): (Vec<_>, Vec<_>) = config.features().into_iter().partition(|(_, on)| *on);
+    let names = |features: Vec<(&str, bool)>| {
+        features
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+    let _ = &server_context.tell(format!(
+        "Features: {}",
+        if on.is_empty() {
+            String::from("none")
+        } else {
+            names(on)
+        }
+    ));
+    debug!("Features turned off: {}", names(off));
     let _ = fs::remove_dir_all("./.cynthiaTemp");
     match fs::create_dir_all("./.cynthiaTemp") {
         Ok(_) => {}
@@ -628,38 +993,255 @@ async fn start() {
     let server_context_arc_mutex: Arc<Mutex<ServerContext>> = Arc::new(Mutex::new(server_context));
     let server_context_data: Data<Arc<Mutex<ServerContext>>> =
         Data::new(server_context_arc_mutex.clone());
-    let main_server = match HttpServer::new(move || {
-        App::new()
-            .service(tags)
-            .service(category)
-            .service(assets_with_cache)
-            .service(serve)
-            .service(post)
-            .app_data(server_context_data.clone())
-    })
-    .bind(("localhost", config.port))
-    {
-        Ok(o) => {
-            println!("Running on http://localhost:{}", config.port);
-            o
-        }
-        Err(s) => {
-            error!(
-                "Could not bind to port {}, error message: {}",
-                config.port, s
-            );
-            process::exit(1);
+    // Same as actix's own default when unset.
+    let workers = config.workers.map(usize::from).unwrap_or_else(|| {
+        std::thread::available_parallelism()
+            .map(|n| n.get())
+            .unwrap_or(1)
+    });
+    let security_headers = config.security.headers();
+    let static_mounts = config.static_mounts.clone();
+    // Static mounts are cached by browsers as long as assets are cached by Cynthia.
+    let static_cache_control = format!("public, max-age={}", config.cache.lifetimes.assets);
+    let server_options = config.server.clone();
+    let canonical_origin = server_options
+        .canonical_redirect
+        .then(|| CanonicalOrigin::from_base_url(&config.site.site_baseurl));
+    // Checked in `prepare_config`.
+    let trusted_proxies =
+        proxies::TrustedProxies::parse(&config.trusted_proxies).unwrap_or_default();
+    let start_hooks_context = server_context_data.clone();
+    let main_server = async move {
+        externalpluginservers::run_start_hooks(start_hooks_context).await;
+        let server = HttpServer::new(move || {
+            // Only adds the headers a response doesn't already have, so scene headers win.
+            let security = security_headers.iter().fold(DefaultHeaders::new(), |d, h| {
+                d.add((h.0.as_str(), h.1.as_str()))
+            });
+            let canonical_origin = canonical_origin.clone();
+            let trusted_proxies = trusted_proxies.clone();
+            let app = App::new()
+                .wrap(from_fn(timing::timing_headers))
+                // Inside the security headers, so the maintenance page gets them too.
+                .wrap(from_fn(maintenance))
+                .wrap(Condition::new(!security_headers.is_empty(), security))
+                .wrap_fn(move |req, srv| {
+                    match canonical_origin
+                        .as_ref()
+                        .and_then(|origin| origin.redirect(&req))
+                    {
+                        Some(redirect) => Either::Left(future::ok(
+                            req.into_response(redirect).map_into_right_body(),
+                        )),
+                        None => Either::Right(
+                            srv.call(req)
+                                .map_ok(actix_web::dev::ServiceResponse::map_into_left_body),
+                        ),
+                    }
+                })
+                .wrap_fn(|req, srv| {
+                    // Once per connection, the first request tells which protocol it speaks.
+                    if req
+                        .conn_data::<ProtocolLogged>()
+                        .is_some_and(|logged| !logged.0.swap(true, Ordering::Relaxed))
+                    {
+                        debug!(
+                            "Connection from {} speaks {:?}.",
+                            req.peer_addr()
+                                .map(|a| a.to_string())
+                                .unwrap_or_else(|| String::from("<unknown IP>")),
+                            req.version()
+                        );
+                    }
+                    srv.call(req)
+                })
+                // Outside everything that looks at the forwarding headers.
+                .wrap_fn(move |mut req, srv| {
+                    trusted_proxies.apply(&mut req);
+                    srv.call(req)
+                })
+                .wrap_fn(|req, srv| {
+                    let id = requestid::for_request(req.headers());
+                    let header = actix_web::http::header::HeaderValue::from_str(&id).ok();
+                    let response = requestid::scope(id, || srv.call(req));
+                    async move {
+                        let mut response = response.await?;
+                        if let Some(header) = header {
+                            response.headers_mut().insert(
+                                actix_web::http::header::HeaderName::from_static(requestid::HEADER),
+                                header,
+                            );
+                        }
+                        Ok(response)
+                    }
+                })
+                .service(tags)
+                .service(category)
+                .service(assets_with_cache)
+                .service(client_script)
+                .service(image_variant)
+                .service(build_info)
+                .service(healthz)
+                .service(taxonomy)
+                .service(stats)
+                .service(logs)
+                .service(reload_webhook)
+                .service(favicon);
+            // Before `serve`, which would otherwise take every request as a page id.
+            static_mounts
+                .iter()
+                .fold(app, |app, mount| {
+                    app.service(
+                        web::scope(&mount.prefix)
+                            .wrap(
+                                DefaultHeaders::new()
+                                    .add(("Cache-Control", static_cache_control.as_str())),
+                            )
+                            .service(Files::new("", &mount.path)),
+                    )
+                })
+                .service(serve)
+                .service(post)
+                .default_service(web::to(unsupported_method))
+                .app_data(server_context_data.clone())
+        })
+        .workers(workers)
+        .keep_alive(if server_options.keep_alive == 0 {
+            KeepAlive::Disabled
+        } else {
+            KeepAlive::Timeout(Duration::from_secs(server_options.keep_alive))
+        })
+        .on_connect(|_, extensions| {
+            extensions.insert(ProtocolLogged::default());
+        });
+        let bound = if server_options.http2 {
+            // HTTP/1 still works, HTTP/2 is recognised by its preface.
+            server.bind_auto_h2c(("localhost", config.port))
+        } else {
+            server.bind(("localhost", config.port))
+        };
+        match bound {
+            Ok(o) => {
+                println!(
+                    "Running on http://localhost:{} with {} worker{}",
+                    config.port,
+                    workers,
+                    if workers == 1 { "" } else { "s" }
+                );
+                o
+            }
+            Err(s) => {
+                error!(
+                    "Could not bind to port {}, error message: {}",
+                    config.port, s
+                );
+                process::exit(1);
+            }
         }
-    }
-    .run();
+        .run()
+        .await
+    };
     let _ = join!(
         main_server,
         close(server_context_arc_mutex.clone()),
+        reload_on_hangup(server_context_arc_mutex.clone()),
         cache_manager(server_context_arc_mutex.clone()),
         start_timer(server_context_arc_mutex.clone()),
-        externalpluginservers::main(server_context_arc_mutex.clone(), to_eps_r)
+        visits::flusher(server_context_arc_mutex.clone()),
+        externalpluginservers::main(server_context_arc_mutex.clone(), to_eps_r),
+        watcher::main(server_context_arc_mutex.clone())
     );
 }
+/// Renders every publication into `prerender_dir`, with the plugins running like they would for `start`.
+async fn warm() {
+    let mut config = config::actions::load_config();
+    if let Err(e) =
+        config::actions::enter_root_dir(&mut config).and_then(|_| prepare_config(&mut config))
+    {
+        eprintln!("{} {}", "error:".color_red(), e);
+        process::exit(1);
+    }
+    let Some(dir) = config.prerender_dir.clone() else {
+        eprintln!(
+            "{} `prerender_dir` is not set, there is nowhere to write the rendered pages to.",
+            "error:".color_red()
+        );
+        process::exit(1);
+    };
+    let jobs = match arg_value("--jobs") {
+        None => 1,
+        Some(jobs) => match jobs.parse::<usize>() {
+            Ok(jobs) if jobs > 0 => jobs,
+            _ => {
+                eprintln!(
+                    "{} `--jobs` takes a number above zero, not `{}`.",
+                    "error:".color_red(),
+                    jobs
+                );
+                process::exit(1);
+            }
+        },
+    };
+    let _ = TermLogger::init(
+        LevelFilter::Warn,
+        simplelog::Config::default(),
+        TerminalMode::Mixed,
+        ColorChoice::Auto,
+    );
+    let (_to_eps_s, to_eps_r) = tokio::sync::mpsc::channel::<EPSRequest>(100);
+    let server_context = ServerContext {
+        config: config.clone(),
+        cache: vec![],
+        published: None,
+        request_count: 0,
+        start_time: 0,
+        // Warming isn't visiting.
+        visits: Arc::default(),
+
+        #[cfg(feature = "js_runtime")]
+        external_plugin_server: EPSCommunicationData::new(_to_eps_s),
+    };
+    let server_context_arc_mutex: Arc<Mutex<ServerContext>> = Arc::new(Mutex::new(server_context));
+    let server_context_data: Data<Arc<Mutex<ServerContext>>> =
+        Data::new(server_context_arc_mutex.clone());
+    let warmed = async {
+        externalpluginservers::run_start_hooks(server_context_data.clone()).await;
+        warm::warm(server_context_data.clone(), &dir, jobs).await
+    };
+    // Without a JS runtime this returns right away, with one it never does.
+    let plugins = async {
+        externalpluginservers::main(server_context_arc_mutex.clone(), to_eps_r).await;
+        future::pending::<()>().await
+    };
+    let warmed = tokio::select! {
+        warmed = warmed => warmed,
+        _ = plugins => false,
+    };
+    // Only this process' own temp folder, a running server keeps its own there.
+    let _ = fs::remove_dir_all(cache::tempfolder());
+    process::exit(if warmed { 0 } else { 1 });
+}
+/// Whether Cynthia was started with `--dev`: no page caching or minifying, and error details in the browser.
+pub(crate) fn dev_mode() -> bool {
+    std::env::args().any(|a| a == "--dev")
+}
+/// Whether Cynthia was started with `--trace-render`: rendered pages are annotated with comments
+/// marking where each render stage's markup starts and ends, and how long each stage took.
+pub(crate) fn trace_render() -> bool {
+    std::env::args().any(|a| a == "--trace-render")
+}
+/// Returns the value following a `--flag` on the command line, also accepting `--flag=value`.
+fn arg_value(flag: &str) -> Option<String> {
+    let args: Vec<String> = std::env::args().collect();
+    let prefix = format!("{}=", flag);
+    args.iter().enumerate().find_map(|(i, a)| {
+        if a == flag {
+            args.get(i + 1).cloned()
+        } else {
+            a.strip_prefix(&prefix).map(|v| v.to_string())
+        }
+    })
+}
 async fn start_timer(server_context_mutex: Arc<Mutex<ServerContext>>) {
     let mut server_context: MutexGuard<ServerContext> = server_context_mutex.lock().await;
     server_context.start_time = SystemTime::now()
@@ -667,8 +1249,30 @@ async fn start_timer(server_context_mutex: Arc<Mutex<ServerContext>>) {
         .unwrap()
         .as_millis();
 }
-async fn close(server_context_mutex: Arc<Mutex<ServerContext>>) {
+/// Waits for Ctrl+C, or for the `SIGTERM` service managers stop Cynthia with.
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                tokio::select! {
+                    _ = tokio::signal::ctrl_c() => {}
+                    _ = terminate.recv() => {}
+                }
+                return;
+            }
+            Err(e) => error!(
+                "Could not listen for SIGTERM, only Ctrl+C closes Cynthia cleanly: {}",
+                e
+            ),
+        }
+    }
     let _ = tokio::signal::ctrl_c().await;
+}
+async fn close(server_context_mutex: Arc<Mutex<ServerContext>>) {
+    shutdown_signal().await;
+    visits::flush(&server_context_mutex).await;
     let server_context: MutexGuard<ServerContext> = server_context_mutex.lock().await;
     // Basically now that we block the main thread, we have all the time lol
     // let _ = server_context
@@ -705,6 +1309,73 @@ async fn close(server_context_mutex: Arc<Mutex<ServerContext>>) {
     println!("{}", horizline().color_lilac());
     process::exit(0);
 }
+/// Reloads the configuration, scenes and plugins on `SIGHUP`, without dropping connections.
+/// When the new configuration doesn't load, the running one is kept.
+#[cfg(unix)]
+async fn reload_on_hangup(server_context_mutex: Arc<Mutex<ServerContext>>) {
+    use tokio::signal::unix::{signal, SignalKind};
+    let mut hangups = match signal(SignalKind::hangup()) {
+        Ok(s) => s,
+        Err(e) => {
+            error!(
+                "Could not listen for SIGHUP, reloading is unavailable: {}",
+                e
+            );
+            return;
+        }
+    };
+    while hangups.recv().await.is_some() {
+        info!("Received SIGHUP, reloading the configuration.");
+        let mut config = match config::actions::reload_config() {
+            Ok(c) => c,
+            Err(e) => {
+                error!(
+                    "Could not reload the configuration, keeping the current one: {}",
+                    e
+                );
+                continue;
+            }
+        };
+        if let Err(e) = prepare_config(&mut config) {
+            error!(
+                "Could not reload the configuration, keeping the current one: {}",
+                e
+            );
+            continue;
+        }
+        let new_root = config::actions::resolve_root_dir(&config.root_dir);
+        let new = config.hard_clone();
+        let (old, cache) = server_context_mutex
+            .lock_callback(|a| {
+                config.root_dir = a.config.root_dir.clone();
+                a.swap_config(config)
+            })
+            .await;
+        if old.port != new.port {
+            warn!("The port changed, this only takes effect after a restart.");
+        }
+        if old.trusted_proxies != new.trusted_proxies {
+            warn!("The trusted proxies changed, these only take effect after a restart.");
+        }
+        if old.security != new.security {
+            warn!("The security headers changed, these only take effect after a restart.");
+        }
+        if new_root.as_deref() != Ok(old.root_dir.as_str()) {
+            warn!("`root_dir` changed, this only takes effect after a restart.");
+        }
+        let invalidated = cache.len();
+        // Freed here, with the lock released.
+        drop((old, cache));
+        externalpluginservers::reload_plugins(Data::new(server_context_mutex.clone())).await;
+        info!(
+            "Configuration reloaded, cleared {} cache entries.",
+            invalidated
+        );
+    }
+}
+/// Windows has no `SIGHUP`, restart Cynthia there to apply configuration changes.
+#[cfg(not(unix))]
+async fn reload_on_hangup(_: Arc<Mutex<ServerContext>>) {}
 use std::time::Duration;
 use tokio::time;
 async fn cache_manager(server_context_mutex: Arc<Mutex<ServerContext>>) {
@@ -745,7 +1416,7 @@ pub(crate) mod tell {
     use log::info;
     use time::{format_description, OffsetDateTime};
 
-    use crate::config::{CynthiaConfClone, Logging};
+    use crate::config::{CynthiaConf, Logging};
     use crate::ServerContext;
 
     const DATE_FORMAT_STR: &str = "[hour]:[minute]:[second]";
@@ -770,11 +1441,16 @@ pub(crate) mod tell {
             let dt1: OffsetDateTime = SystemTime::now().into();
             let dt_fmt = format_description::parse(DATE_FORMAT_STR).unwrap();
             let times = dt1.format(&dt_fmt).unwrap();
-            format!("{} {} {}", times, "[LOG] ".color_magenta(), msg)
+            format!(
+                "{} {} {}",
+                times,
+                "[LOG] ".color_magenta(),
+                crate::requestid::tag(msg)
+            )
         }
     }
     /// For when context is unavailable to be locked, confclone should be able to tell too.
-    impl CynthiaConfClone {
+    impl CynthiaConf {
         pub(crate) fn tell(&self, rmsg: impl AsRef<str>) {
             let msg = rmsg.as_ref();
             match &self.logs.clone() {
@@ -793,7 +1469,12 @@ pub(crate) mod tell {
             let dt1: OffsetDateTime = SystemTime::now().into();
             let dt_fmt = format_description::parse(DATE_FORMAT_STR).unwrap();
             let times = dt1.format(&dt_fmt).unwrap();
-            format!("{} {} {}", times, "[LOG] ".color_magenta(), msg)
+            format!(
+                "{} {} {}",
+                times,
+                "[LOG] ".color_magenta(),
+                crate::requestid::tag(msg)
+            )
         }
     }
     impl Logging {
@@ -805,7 +1486,12 @@ pub(crate) mod tell {
                     let dt1: OffsetDateTime = SystemTime::now().into();
                     let dt_fmt = format_description::parse(DATE_FORMAT_STR).unwrap();
                     let times = dt1.format(&dt_fmt).unwrap();
-                    println!("{} {} {}", times, "[LOG] ".color_magenta(), msg);
+                    println!(
+                        "{} {} {}",
+                        times,
+                        "[LOG] ".color_magenta(),
+                        crate::requestid::tag(msg)
+                    );
                     info!("{}", msg);
                 }
                 Some(s) => {
@@ -817,7 +1503,12 @@ pub(crate) mod tell {
                             let dt1: OffsetDateTime = SystemTime::now().into();
                             let dt_fmt = format_description::parse(DATE_FORMAT_STR).unwrap();
                             let times = dt1.format(&dt_fmt).unwrap();
-                            println!("{} {} {}", times, "[LOG] ".color_magenta(), msg);
+                            println!(
+                                "{} {} {}",
+                                times,
+                                "[LOG] ".color_magenta(),
+                                crate::requestid::tag(msg)
+                            );
                             info!("{}", msg);
                         }
                     }
@@ -1127,3 +1818,120 @@ pub(crate) mod tell {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn redirects_to_the_canonical_origin() {
+        use actix_web::http::{Method, StatusCode};
+        use actix_web::test::TestRequest;
+        let origin = CanonicalOrigin::from_base_url("https://Example.com:443/blog");
+        let redirect = |request: TestRequest| origin.redirect(&request.to_srv_request());
+        let location = |response: actix_web::HttpResponse| {
+            response
+                .headers()
+                .get("Location")
+                .unwrap()
+                .to_str()
+                .unwrap()
+                .to_string()
+        };
+
+        let response = redirect(
+            TestRequest::get()
+                .uri("/about?lang=en")
+                .insert_header(("Host", "www.example.com"))
+                .insert_header(("X-Forwarded-Proto", "https")),
+        )
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::MOVED_PERMANENTLY);
+        assert_eq!(location(response), "https://example.com/about?lang=en");
+        // Upgraded to HTTPS on the right host too, keeping the method of a POST.
+        let response = redirect(
+            TestRequest::default()
+                .method(Method::POST)
+                .uri("/form")
+                .insert_header(("Host", "example.com"))
+                .insert_header(("X-Forwarded-Proto", "http")),
+        )
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::PERMANENT_REDIRECT);
+        assert_eq!(location(response), "https://example.com/form");
+        // The first of a chain of forwarded hosts is the client's.
+        assert!(redirect(
+            TestRequest::get()
+                .insert_header(("Host", "10.0.0.2"))
+                .insert_header(("X-Forwarded-Host", "EXAMPLE.com., proxy.internal"))
+                .insert_header(("X-Forwarded-Proto", "https"))
+        )
+        .is_none());
+        assert!(redirect(
+            TestRequest::get()
+                .insert_header(("Host", "example.com:443"))
+                .insert_header(("X-Forwarded-Proto", "https"))
+        )
+        .is_none());
+    }
+
+    #[test]
+    fn lets_through_what_could_loop() {
+        use actix_web::test::TestRequest;
+        let origin = CanonicalOrigin::from_base_url("https://example.com");
+        let redirect = |request: TestRequest| origin.redirect(&request.to_srv_request());
+        // Without a host there's nothing to compare.
+        assert!(redirect(TestRequest::get().uri("/")).is_none());
+        // A proxy passing its upstream address, or a health check by IP.
+        for host in ["localhost:8080", "127.0.0.1:3000", "10.0.0.5", "[::1]:8080"] {
+            assert!(
+                redirect(TestRequest::get().insert_header(("Host", host))).is_none(),
+                "{host} was redirected"
+            );
+        }
+        // Without `X-Forwarded-Proto` the scheme is taken to be right, only the host is fixed.
+        let response = redirect(TestRequest::get().insert_header(("Host", "www.example.com")));
+        assert!(response.is_some());
+        assert!(redirect(TestRequest::get().insert_header(("Host", "example.com"))).is_none());
+        // Never downgraded to HTTP.
+        let origin = CanonicalOrigin::from_base_url("http://example.com");
+        assert!(origin
+            .redirect(
+                &TestRequest::get()
+                    .insert_header(("Host", "example.com"))
+                    .insert_header(("X-Forwarded-Proto", "https"))
+                    .to_srv_request()
+            )
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn reloads_swap_the_config_without_waiting_for_readers() {
+        let context = Arc::new(Mutex::new(ServerContext {
+            config: Arc::new(CynthiaConf::default()),
+            cache: vec![],
+            published: None,
+            request_count: 0,
+            start_time: 0,
+            visits: Arc::default(),
+            #[cfg(feature = "js_runtime")]
+            external_plugin_server: EPSCommunicationData::new(tokio::sync::mpsc::channel(1).0),
+        }));
+        // A request that took the configuration and is still rendering with it.
+        let taken = context.lock_callback(|a| a.config.clone()).await;
+        let mut reloaded = CynthiaConf::default();
+        reloaded.port = taken.port.wrapping_add(1);
+        let (old, _) = tokio::time::timeout(
+            Duration::from_secs(1),
+            context.lock_callback(|a| a.swap_config(reloaded)),
+        )
+        .await
+        .expect("the reload waited for a reader");
+        assert!(Arc::ptr_eq(&old, &taken));
+        assert_eq!(taken.port, CynthiaConf::default().port);
+        assert_eq!(
+            context.lock_callback(|a| a.config.port).await,
+            taken.port.wrapping_add(1)
+        );
+    }
+}
@@ -0,0 +1,163 @@
+/*
+ * Copyright (c) 2024, MLC 'Strawmelonjuice' Bloeiman
+ *
+ * Licensed under the GNU AFFERO GENERAL PUBLIC LICENSE Version 3, see the LICENSE file for more information.
+ */
+//! Checks that the configured plugins can be loaded, before anything is served.
+//! Used by `cynthiaweb check-plugins` and `cynthiaweb start --check-plugins`.
+use std::path::Path;
+use std::process::Command;
+
+use serde::Deserialize;
+
+use crate::config::{CynthiaConf, Plugin};
+use crate::tell::CynthiaColors;
+
+/// The plugin compatibility version of the bundled plugin runner, as major and minor.
+/// Keep in sync with `Plugincompat` in `node-plugin-runner/src/types/internal_plugins.ts`.
+const PLUGIN_COMPAT: (u32, u32) = (3, 2);
+
+/// The parts of a plugin's `package.json` the plugin runner relies on.
+#[derive(Deserialize)]
+struct PluginPackageJson {
+    #[serde(rename = "cynthia-plugin")]
+    entry: String,
+    #[serde(rename = "cynthia-plugin-compat")]
+    compat: serde_json::Number,
+}
+
+/// The major and minor version in a `cynthia-plugin-compat` like `3.2`.
+fn compat_version(compat: &serde_json::Number) -> Option<(u32, u32)> {
+    let compat = compat.to_string();
+    let (major, minor) = compat.split_once('.').unwrap_or((&compat, "0"));
+    Some((major.parse().ok()?, minor.parse().ok()?))
+}
+
+enum Outcome {
+    Pass,
+    Skipped(String),
+    Fail(String),
+}
+
+/// Checks every configured plugin and prints a table of the results.
+/// Returns whether all of them passed.
+pub(crate) fn check_plugins(config: &CynthiaConf) -> bool {
+    let runtime = check_runtime(config);
+    let any_plugins = config.plugins.iter().any(|plugin| match plugin {
+        Plugin::JsPlugin { plugin_enabled, .. } => {
+            *plugin_enabled && config.plugin_options.permits(&plugin.get_name())
+        }
+    });
+    let mut rows = vec![(
+        String::from("(runtime)"),
+        match &runtime {
+            Ok(()) => Outcome::Pass,
+            // Without plugins to run, there's nothing a missing runtime breaks.
+            Err(e) if !any_plugins => Outcome::Skipped(e.clone()),
+            Err(e) => Outcome::Fail(e.clone()),
+        },
+    )];
+    for plugin in &config.plugins {
+        let name = plugin.get_name();
+        let outcome = match plugin {
+            Plugin::JsPlugin {
+                plugin_enabled: false,
+                ..
+            } => Outcome::Skipped(String::from("disabled in the configuration")),
+            _ if !config.plugin_options.permits(&name) => {
+                Outcome::Skipped(String::from("not permitted by `plugin_options`"))
+            }
+            _ => match (&runtime, check_plugin(&name, &runtime_binary(config))) {
+                (_, Err(e)) => Outcome::Fail(e),
+                (Err(_), Ok(())) => Outcome::Fail(String::from("the runtime doesn't work")),
+                (Ok(()), Ok(())) => Outcome::Pass,
+            },
+        };
+        rows.push((name, outcome));
+    }
+    let width = rows.iter().map(|(name, _)| name.len()).max().unwrap_or(0);
+    let mut passed = true;
+    for (name, outcome) in rows {
+        let (status, detail) = match outcome {
+            Outcome::Pass => ("pass".color_ok_green(), String::new()),
+            Outcome::Skipped(why) => ("skip".color_yellow(), why),
+            Outcome::Fail(why) => {
+                passed = false;
+                ("FAIL".color_error_red(), why)
+            }
+        };
+        println!("\t{:<width$}\t{}\t{}", name, status, detail);
+    }
+    passed
+}
+
+/// Checks a single plugin's manifest, entry file and installer. Also used when `--watch` sees a plugin change.
+pub(crate) fn check_plugin(name: &str, runtime: &str) -> Result<(), String> {
+    let directory = Path::new("./cynthiaPlugins").join(name);
+    let package_json = std::fs::read_to_string(directory.join("package.json"))
+        .map_err(|e| format!("could not read package.json: {}", e))?;
+    let package: PluginPackageJson = serde_json::from_str(&package_json)
+        .map_err(|e| format!("package.json is not a Cynthia plugin manifest: {}", e))?;
+    if compat_version(&package.compat) != Some(PLUGIN_COMPAT) {
+        return Err(format!(
+            "made for plugin compatibility {}, this Cynthia runs {}.{}",
+            package.compat, PLUGIN_COMPAT.0, PLUGIN_COMPAT.1
+        ));
+    }
+    if !directory.join(&package.entry).exists() {
+        return Err(format!("entry file `{}` does not exist", package.entry));
+    }
+    // The runner installs dependencies and runs `onBeforeRun` with npm, or with Bun when that's the runtime.
+    let installer = if runtime.contains("bun") {
+        runtime.to_string()
+    } else {
+        String::from("npm")
+    };
+    if !runs(Command::new(&installer).arg("-v")) {
+        return Err(format!(
+            "`{}` is needed to install its dependencies",
+            installer
+        ));
+    }
+    Ok(())
+}
+
+/// The configured JavaScript runtime, or `disabled`.
+#[cfg(feature = "js_runtime")]
+pub(crate) fn runtime_binary(config: &CynthiaConf) -> String {
+    config.runtimes.ext_js_rt.clone()
+}
+#[cfg(not(feature = "js_runtime"))]
+pub(crate) fn runtime_binary(_: &CynthiaConf) -> String {
+    String::from("disabled")
+}
+
+/// Checks the configured runtime runs the plugin runner, with a request sent through and answered.
+fn check_runtime(config: &CynthiaConf) -> Result<(), String> {
+    if runtime_binary(config) == "disabled" {
+        return Err(String::from("the JavaScript runtime is disabled"));
+    }
+    crate::externalpluginservers::roundtrip(config)
+}
+
+fn runs(command: &mut Command) -> bool {
+    command
+        .output()
+        .map(|output| output.status.success())
+        .unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_compat_versions() {
+        let compat = |json: &str| compat_version(&serde_json::from_str(json).unwrap());
+        assert_eq!(compat("3.2"), Some((3, 2)));
+        assert_eq!(compat("3.20"), Some((3, 2)));
+        assert_eq!(compat("3"), Some((3, 0)));
+        assert_eq!(compat("-3.2"), None);
+        assert_ne!(compat("3.19999"), Some(PLUGIN_COMPAT));
+    }
+}
@@ -0,0 +1,140 @@
+/*
+ * Copyright (c) 2024, MLC 'Strawmelonjuice' Bloeiman
+ *
+ * Licensed under the GNU AFFERO GENERAL PUBLIC LICENSE Version 3, see the LICENSE file for more information.
+ */
+//! Decides whose `X-Forwarded-*` headers to believe. Requests from `trusted_proxies` keep them, with
+//! `X-Forwarded-For` narrowed down to the actual client, all others lose them, so a client can't claim
+//! to be someone else, or to have come in over HTTPS.
+use std::net::IpAddr;
+
+use actix_web::dev::ServiceRequest;
+use actix_web::http::header::{HeaderName, HeaderValue};
+
+/// The headers a proxy tells about the client with. `Forwarded` is always dropped, so these are the only ones
+/// the logs and the canonical redirect go by.
+const FORWARDED_HEADERS: [&str; 3] = ["x-forwarded-for", "x-forwarded-proto", "x-forwarded-host"];
+
+/// The addresses and ranges of `trusted_proxies`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct TrustedProxies(Vec<(IpAddr, u8)>);
+
+impl TrustedProxies {
+    /// Parses addresses like `127.0.0.1` and ranges like `10.0.0.0/8` or `fd00::/8`.
+    pub(crate) fn parse(entries: &[String]) -> Result<Self, String> {
+        entries
+            .iter()
+            .map(|entry| {
+                let (address, prefix) = match entry.split_once('/') {
+                    Some((address, prefix)) => (address, Some(prefix)),
+                    None => (entry.as_str(), None),
+                };
+                let address: IpAddr = address
+                    .trim()
+                    .parse()
+                    .map_err(|_| format!("`{}` is not an IP address or range.", entry))?;
+                let max = if address.is_ipv4() { 32 } else { 128 };
+                let prefix = match prefix {
+                    Some(p) => p
+                        .trim()
+                        .parse::<u8>()
+                        .ok()
+                        .filter(|p| *p <= max)
+                        .ok_or_else(|| format!("`{}` has an invalid prefix length.", entry))?,
+                    None => max,
+                };
+                Ok((address, prefix))
+            })
+            .collect::<Result<Vec<_>, String>>()
+            .map(TrustedProxies)
+    }
+
+    fn trusts(&self, ip: IpAddr) -> bool {
+        let ip = ip.to_canonical();
+        self.0.iter().any(|(range, prefix)| match (range, ip) {
+            (IpAddr::V4(range), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - *prefix as u32).unwrap_or(0);
+                u32::from(*range) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(range), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - *prefix as u32).unwrap_or(0);
+                u128::from(*range) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        })
+    }
+
+    /// The client behind `peer`: each proxy appends the address it got the request from to `X-Forwarded-For`,
+    /// so going back from the end, the first address that isn't a trusted proxy is the client's. Anything
+    /// before it was sent by the client itself.
+    fn client(&self, peer: IpAddr, forwarded_for: Option<&str>) -> Option<IpAddr> {
+        if !self.trusts(peer) {
+            return None;
+        }
+        let mut client = peer;
+        for hop in forwarded_for.unwrap_or_default().rsplit(',') {
+            let Ok(hop) = hop.trim().parse::<IpAddr>() else {
+                break;
+            };
+            client = hop;
+            if !self.trusts(hop) {
+                break;
+            }
+        }
+        Some(client)
+    }
+
+    /// Removes the forwarding headers of requests that didn't come from a trusted proxy, and leaves only the
+    /// client's address in `X-Forwarded-For` of those that did.
+    pub(crate) fn apply(&self, req: &mut ServiceRequest) {
+        let forwarded_for = req
+            .headers()
+            .get(FORWARDED_HEADERS[0])
+            .and_then(|v| v.to_str().ok())
+            .map(str::to_string);
+        let client = req
+            .peer_addr()
+            .and_then(|peer| self.client(peer.ip(), forwarded_for.as_deref()));
+        let headers = req.headers_mut();
+        headers.remove("forwarded");
+        match client {
+            Some(client) => {
+                if let Ok(value) = HeaderValue::from_str(&client.to_string()) {
+                    headers.insert(HeaderName::from_static(FORWARDED_HEADERS[0]), value);
+                }
+            }
+            None => {
+                for header in FORWARDED_HEADERS {
+                    headers.remove(header);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_the_client_behind_trusted_proxies() {
+        let proxies =
+            TrustedProxies::parse(&["127.0.0.1".to_string(), "10.0.0.0/8".to_string()]).unwrap();
+        let ip = |s: &str| s.parse::<IpAddr>().unwrap();
+        assert_eq!(proxies.client(ip("203.0.113.9"), Some("1.2.3.4")), None);
+        assert_eq!(
+            proxies.client(ip("127.0.0.1"), Some("6.6.6.6, 203.0.113.9, 10.1.2.3")),
+            Some(ip("203.0.113.9"))
+        );
+        assert_eq!(
+            proxies.client(ip("::ffff:127.0.0.1"), None),
+            Some(ip("::ffff:127.0.0.1"))
+        );
+        assert_eq!(
+            proxies.client(ip("10.0.0.1"), Some("not an address")),
+            Some(ip("10.0.0.1"))
+        );
+        assert!(TrustedProxies::parse(&["10.0.0.0/33".to_string()]).is_err());
+        assert!(TrustedProxies::parse(&["localhost".to_string()]).is_err());
+    }
+}
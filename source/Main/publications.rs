@@ -4,25 +4,79 @@
  * Licensed under the GNU AFFERO GENERAL PUBLIC LICENSE Version 3, see the LICENSE file for more information.
  */
 
-use crate::config::{CynthiaConfClone, CynthiaConfig};
+use crate::config::{CynthiaConfClone, CynthiaConfig, TrailingSlash};
+use crate::helpers::{safe_url, slugify};
 use crate::ServerContext;
 use actix_web::web::Data;
 use futures::Future;
 use jsonc_parser::parse_to_serde_value as preparse_jsonc;
 use log::{error, warn};
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::path::{Path, PathBuf};
-use std::process;
 use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use tokio::sync::Mutex;
 
 pub(crate) type CynthiaPublicationList = Vec<CynthiaPublication>;
 pub(crate) trait PostLists {
     fn filter(&self, filter: PostListFilter) -> Vec<PostPublication>;
+    /// Counts the posts per tag and per category.
+    fn taxonomy(&self) -> Taxonomy;
+    /// The posts published right before and after the post with this id, overall and within its category.
+    fn adjacent(&self, id: &str, trailing_slash: &TrailingSlash) -> AdjacentPosts;
     #[allow(dead_code)]
     fn get_by_id(&self, id: String) -> Option<CynthiaPublication>;
 }
 impl PostLists for CynthiaPostList {
+    fn adjacent(&self, id: &str, trailing_slash: &TrailingSlash) -> AdjacentPosts {
+        let mut chronological = self.iter().collect::<Vec<&PostPublication>>();
+        chronological.sort_by(|a, b| {
+            a.dates
+                .published
+                .cmp(&b.dates.published)
+                .then_with(|| a.id.cmp(&b.id))
+        });
+        let Some(current) = chronological.iter().position(|p| p.id == id) else {
+            return AdjacentPosts::default();
+        };
+        let category = chronological[current]
+            .category
+            .as_ref()
+            .map(|c| c.to_lowercase());
+        let in_category = |p: &&&PostPublication| {
+            category.is_some() && p.category.as_ref().map(|c| c.to_lowercase()) == category
+        };
+        AdjacentPosts {
+            previous: current
+                .checked_sub(1)
+                .map(|i| AdjacentPost::new(chronological[i], trailing_slash)),
+            next: chronological
+                .get(current + 1)
+                .map(|p| AdjacentPost::new(p, trailing_slash)),
+            previous_in_category: chronological[..current]
+                .iter()
+                .rev()
+                .find(in_category)
+                .map(|p| AdjacentPost::new(p, trailing_slash)),
+            next_in_category: chronological[current + 1..]
+                .iter()
+                .find(in_category)
+                .map(|p| AdjacentPost::new(p, trailing_slash)),
+        }
+    }
+    fn taxonomy(&self) -> Taxonomy {
+        Taxonomy {
+            tags: TermCount::count(self.iter().flat_map(|post| post.tags.iter()), |tag| {
+                tag.to_string()
+            }),
+            // Categories are matched regardless of case, like `PostListFilter::Category` does.
+            categories: TermCount::count(
+                self.iter().filter_map(|post| post.category.as_ref()),
+                str::to_lowercase,
+            ),
+        }
+    }
     fn filter(&self, filter: PostListFilter) -> Vec<PostPublication> {
         match filter {
             PostListFilter::Latest => {
@@ -96,6 +150,10 @@ impl PostLists for CynthiaPostList {
                     author: i.author.clone(),
                     postcontent: i.postcontent.clone(),
                     scene_override: i.scene_override.clone(),
+                    lang: i.lang.clone(),
+                    translation_key: i.translation_key.clone(),
+                    extra_head: i.extra_head.clone(),
+                    canonical: i.canonical.clone(),
                 })
             }
         }
@@ -109,9 +167,16 @@ pub(crate) trait CynthiaPublicationListTrait {
     fn get_root(&self) -> Option<CynthiaPublication>;
     fn get_by_id(&self, id: String) -> Option<CynthiaPublication>;
     fn validate(&self, config: CynthiaConfClone) -> bool;
+    fn get_translations(&self, translation_key: &str) -> Vec<(String, String)>;
+    fn find_translation(
+        &self,
+        lang: &str,
+        translation_key: &str,
+        default_lang: &str,
+    ) -> Option<CynthiaPublication>;
     fn load(
         server_context_mutex: Data<Arc<Mutex<ServerContext>>>,
-    ) -> impl Future<Output = CynthiaPublicationList>;
+    ) -> impl Future<Output = Arc<CynthiaPublicationList>>;
 }
 impl CynthiaPublicationListTrait for CynthiaPublicationList {
     fn only_posts(&self) -> CynthiaPostList {
@@ -128,6 +193,10 @@ impl CynthiaPublicationListTrait for CynthiaPublicationList {
                 author,
                 postcontent,
                 scene_override,
+                lang,
+                translation_key,
+                extra_head,
+                canonical,
             } = i
             {
                 p.push(PostPublication {
@@ -141,6 +210,11 @@ impl CynthiaPublicationListTrait for CynthiaPublicationList {
                     author: author.clone(),
                     postcontent: postcontent.clone(),
                     scene_override: scene_override.clone(),
+                    lang: lang.clone(),
+                    translation_key: translation_key.clone(),
+                    extra_head: extra_head.clone(),
+                    canonical: canonical.clone(),
+                    excerpt: short.clone(),
                 });
             }
         }
@@ -149,7 +223,7 @@ impl CynthiaPublicationListTrait for CynthiaPublicationList {
     fn get_notfound(&self, config: CynthiaConfClone) -> Option<CynthiaPublication> {
         self.iter()
             .find(|x| {
-                let notfound = config.clone().site.notfound_page;
+                let notfound = config.site.notfound_page.clone();
                 if x.get_id() == notfound {
                     match x {
                         CynthiaPublication::Page { .. } => true,
@@ -199,15 +273,48 @@ impl CynthiaPublicationListTrait for CynthiaPublicationList {
                 }
             };
         };
-        self.iter().find(|x| x.get_id() == id).cloned()
+        self.iter()
+            .find(|x| x.get_id() == id)
+            .or_else(|| {
+                // Incoming ids are still percent-encoded, `/My%20Post` should find `my-post` too.
+                let decoded = urlencoding::decode(&id)
+                    .map(|d| d.into_owned())
+                    .unwrap_or(id);
+                let slug = slugify(&decoded);
+                self.iter().find(|x| slugify(&x.get_id()) == slug)
+            })
+            .cloned()
+    }
+    /// Lists the `(lang, id)` of every translation sharing `translation_key`.
+    fn get_translations(&self, translation_key: &str) -> Vec<(String, String)> {
+        self.iter()
+            .filter_map(|x| match x.get_translation() {
+                Some((lang, key)) if key == translation_key => Some((lang, x.get_id())),
+                _ => None,
+            })
+            .collect()
+    }
+    /// Finds the translation of `translation_key` in `lang`, falling back to the one in `default_lang`.
+    fn find_translation(
+        &self,
+        lang: &str,
+        translation_key: &str,
+        default_lang: &str,
+    ) -> Option<CynthiaPublication> {
+        let translations = self.get_translations(translation_key);
+        translations
+            .iter()
+            .find(|(l, _)| l == lang)
+            .or_else(|| translations.iter().find(|(l, _)| l == default_lang))
+            .and_then(|(_, id)| self.get_by_id(id.clone()))
     }
     fn validate(&self, config: CynthiaConfClone) -> bool {
         // Collect validation results in a vector
         let mut valid: Vec<bool> = vec![];
 
         // Check for ids with reserved names or prefixes
-        // - Reserved prefixes: "es/", "category/", "tag/", "virtual:"
-        let reserved_prefixes = vec!["es/", "category/", "tag/", "virtual:"];
+        // - Reserved prefixes: "es/", "category/", "tag/", "virtual:", "cynthia/"
+        let reserved_prefixes = vec!["es/", "category/", "tag/", "virtual:", "cynthia/"];
         let reserved_prefix = self.iter().all(|x| {
             let id = x.get_id();
             if reserved_prefixes.iter().any(|&p| id.starts_with(p)) {
@@ -232,7 +339,22 @@ impl CynthiaPublicationListTrait for CynthiaPublicationList {
             }
         });
         valid.push(duplication);
+        // Ids that only differ in ways their slugs don't can't both be reached by slug.
+        let mut slugs: Vec<(String, String)> = vec![];
+        for id in self.iter().map(|x| x.get_id()) {
+            let slug = slugify(&id);
+            match slugs.iter().find(|(s, _)| *s == slug) {
+                Some((_, other)) if *other != id => warn!(
+                    "Publications \"{}\" and \"{}\" have the same slug \"{}\", only the first can be reached through it.",
+                    other, id, slug
+                ),
+                Some(_) => {}
+                None => slugs.push((slug, id)),
+            }
+        }
         // Checking for required pages:
+        // - Root page, unless `home` names another publication to serve at `/`
+        let root_exists = config.home.is_some() || self.get_root().is_some();
         // - 404 page
         let notfound_exists = self.get_notfound(config).is_some();
         if !notfound_exists {
@@ -240,8 +362,6 @@ impl CynthiaPublicationListTrait for CynthiaPublicationList {
         }
         valid.push(notfound_exists);
 
-        // - Root page
-        let root_exists = self.get_root().is_some();
         if !root_exists {
             error!("Root page not found in publication file: Add a page with id being either \"root\" or \"/\"");
         }
@@ -259,80 +379,276 @@ impl CynthiaPublicationListTrait for CynthiaPublicationList {
         // Return true if all checks passed
         valid.iter().all(|x| *x)
     }
-    async fn load(server_context_mutex: Data<Arc<Mutex<ServerContext>>>) -> CynthiaPublicationList {
-        if Path::new("./cynthiaFiles/published.jsonc").exists() {
-            let unparsed_json = {
-                let res = {
-                    let server_context = server_context_mutex.lock().await;
-                    (|| -> Result<String, String> {
-                        let file_pathbuf = PathBuf::from("./cynthiaFiles/published.jsonc");
-                        match std::str::from_utf8(&crate::files::fs_get(
-                            server_context,
-                            file_pathbuf,
-                            crate::files::FilePriority::High,
-                        )?) {
-                            Ok(t) => Ok(t.to_string()),
-                            Err(e) => Err(format!("{e}")),
-                        }
-                    })()
-                };
-                match res {
-                    Ok(t) => t,
-                    Err(e) => {
-                        error!("Couldn't find or load published.jsonc.\n\n\t\t{e}");
-                        process::exit(1);
-                    }
-                }
-            };
-            // println!("{}", unparsed_json);
-            let preparsed: Option<serde_json::Value> =
-                match preparse_jsonc(unparsed_json.as_str(), &Default::default()) {
-                    Ok(t) => t,
-                    Err(e) => {
-                        error!("Couldn't parse published.jsonc.\n\n\t\t{e}");
-                        process::exit(1);
-                    }
-                };
-            serde_json::from_value(preparsed.into()).unwrap_or_else(|e| {
-                let k = e.line();
-                error!("Published.json contains invalid Cynthia-instructions.\n\n\t\t{e}, {k}",);
-                Vec::new()
+    async fn load(
+        server_context_mutex: Data<Arc<Mutex<ServerContext>>>,
+    ) -> Arc<CynthiaPublicationList> {
+        use crate::LockCallback;
+        // A single request looks its publication up several times, walking `published/` each time
+        // would cost more than serving the cached page. Kept as long as the files it's read from, and
+        // shared rather than copied.
+        let cached = server_context_mutex
+            .lock_callback(|a| {
+                a.get_published(crate::files::get_lifetime(
+                    crate::files::FilePriority::High,
+                    a.config.cache.lifetimes.assets,
+                ))
             })
-        } else if Path::new("./cynthiaFiles/published.yaml").exists() {
-            let unparsed_yaml = {
-                let res = {
-                    let server_context = server_context_mutex.lock().await;
-
-                    (|| -> Result<String, String> {
-                        let file_pathbuf = PathBuf::from("./cynthiaFiles/published.yaml");
-                        match std::str::from_utf8(&crate::files::fs_get(
-                            server_context,
-                            file_pathbuf,
-                            crate::files::FilePriority::High,
-                        )?) {
-                            Ok(t) => Ok(t.to_string()),
-                            Err(e) => Err(format!("{e}")),
-                        }
-                    })()
-                };
-                match res {
-                    Ok(t) => t,
-                    Err(e) => {
-                        error!("Couldn't find or load published.yaml.\n\n\t\t{e}");
-                        process::exit(1);
-                    }
+            .await;
+        if let Some(list) = cached {
+            return list;
+        }
+        // A list that can't be read or didn't parse is read again on the next request, until then
+        // pages are answered with an error.
+        let list = Arc::new(
+            load_from_files(server_context_mutex.clone())
+                .await
+                .unwrap_or_else(|e| {
+                    error!("{e}");
+                    Vec::new()
+                }),
+        );
+        if !list.is_empty() {
+            let kept = list.clone();
+            server_context_mutex
+                .lock_callback(|a| a.store_published(kept))
+                .await;
+        }
+        list
+    }
+}
+
+/// Stands for the kept publication list when cache entries are invalidated, it goes when a
+/// publication file changes. A `virtual:` key, so it never meets a page id.
+pub(crate) const LIST_CACHE_KEY: &str = "virtual:published";
+
+/// The cache key of a post's generated excerpt, so post lists don't render every post's content on
+/// each render. Dropped along with the post when it changes.
+pub(crate) fn excerpt_cache_key(id: &str) -> String {
+    format!("excerpt:{}", id)
+}
+
+/// Reads and merges the publication files, through the file cache. Fails when there are none, or when
+/// one can't be read or parsed.
+async fn load_from_files(
+    server_context_mutex: Data<Arc<Mutex<ServerContext>>>,
+) -> Result<CynthiaPublicationList, String> {
+    let files = published_files();
+    if files.is_empty() {
+        return Err(String::from("Couldn't find published.jsonc, published.yaml (or either gzipped) or any publication file in published/."));
+    }
+    let mut lists = Vec::new();
+    for path in files {
+        let res = {
+            let server_context = server_context_mutex.lock().await;
+            crate::files::fs_get(
+                server_context,
+                path.clone(),
+                crate::files::FilePriority::High,
+            )
+            .and_then(|b| decode_published(&path, b))
+        };
+        let unparsed =
+            res.map_err(|e| format!("Couldn't find or load {}.\n\n\t\t{e}", path.display()))?;
+        let list = parse_published(&path, &unparsed).map_err(|e| {
+            format!(
+                "{} contains invalid Cynthia-instructions.\n\n\t\t{e}",
+                path.display()
+            )
+        })?;
+        lists.push((path, list));
+    }
+    Ok(merge_published(lists))
+}
+
+/// The first of these that exists is the main publication file. Gzipped ones are for sites whose list is too big to keep as it is.
+const PUBLISHED_MAIN: [&str; 4] = [
+    "./cynthiaFiles/published.jsonc",
+    "./cynthiaFiles/published.jsonc.gz",
+    "./cynthiaFiles/published.yaml",
+    "./cynthiaFiles/published.yaml.gz",
+];
+/// Publications can also be split over files in this directory, like one per post or per category.
+const PUBLISHED_DIR: &str = "./cynthiaFiles/published";
+
+/// The files the publication list is made of: `published.jsonc` (or else `published.jsonc.gz`, `published.yaml`
+/// or `published.yaml.gz`), then every `.jsonc`, `.json`, `.yaml` and `.yml` file under `published/`, gzipped
+/// or not, in alphabetical order.
+pub(crate) fn published_files() -> Vec<PathBuf> {
+    fn walk(dir: &Path, files: &mut Vec<PathBuf>) {
+        let Ok(entries) = std::fs::read_dir(dir) else {
+            return;
+        };
+        let mut paths = entries
+            .flatten()
+            .map(|e| e.path())
+            .collect::<Vec<PathBuf>>();
+        paths.sort();
+        for path in paths {
+            if path.is_dir() {
+                walk(&path, files);
+            } else if matches!(format_of(&path), Some("jsonc" | "json" | "yaml" | "yml")) {
+                files.push(path);
+            }
+        }
+    }
+    let mut files = Vec::new();
+    if let Some(main) = PUBLISHED_MAIN.iter().find(|f| Path::new(f).exists()) {
+        files.push(PathBuf::from(main));
+    }
+    walk(Path::new(PUBLISHED_DIR), &mut files);
+    files
+}
+
+/// The extension telling how a publication file is written, looking past a `.gz`.
+fn format_of(path: &Path) -> Option<&str> {
+    match path.extension().and_then(|e| e.to_str()) {
+        Some("gz") => Path::new(path.file_stem()?)
+            .extension()
+            .and_then(|e| e.to_str()),
+        extension => extension,
+    }
+}
+
+/// The text of a publication file, decompressed in memory when it's gzipped.
+fn decode_published(path: &Path, bytes: Vec<u8>) -> Result<String, String> {
+    if path.extension().is_some_and(|e| e == "gz") {
+        let mut text = String::new();
+        std::io::Read::read_to_string(
+            &mut flate2::read::GzDecoder::new(bytes.as_slice()),
+            &mut text,
+        )
+        .map_err(|e| format!("couldn't decompress it: {e}"))?;
+        return Ok(text);
+    }
+    String::from_utf8(bytes).map_err(|e| format!("{e}"))
+}
+
+/// Parses one publication file, holding a list of publications or, for files in `published/`, just one.
+fn parse_published(path: &Path, unparsed: &str) -> Result<CynthiaPublicationList, String> {
+    if matches!(format_of(path), Some("yaml" | "yml")) {
+        return serde_yaml::from_str::<CynthiaPublicationList>(unparsed).or_else(|e| {
+            serde_yaml::from_str::<CynthiaPublication>(unparsed)
+                .map(|p| vec![p])
+                .map_err(|_| e.to_string())
+        });
+    }
+    let preparsed = preparse_jsonc(unparsed, &Default::default())
+        .map_err(|e| e.to_string())?
+        .unwrap_or_default();
+    if preparsed.is_array() {
+        serde_json::from_value(preparsed).map_err(|e| e.to_string())
+    } else {
+        serde_json::from_value::<CynthiaPublication>(preparsed)
+            .map(|p| vec![p])
+            .map_err(|e| e.to_string())
+    }
+}
+
+/// Puts the lists of all publication files together, in order, reporting ids that more than one file uses.
+fn merge_published(lists: Vec<(PathBuf, CynthiaPublicationList)>) -> CynthiaPublicationList {
+    let mut first_seen: HashMap<String, PathBuf> = HashMap::new();
+    let mut merged = Vec::new();
+    for (path, list) in lists {
+        for publication in list {
+            match first_seen.get(&publication.get_id()) {
+                Some(first) if *first != path => error!(
+                    "Publication id \"{}\" is used in both {} and {}.",
+                    publication.get_id(),
+                    first.display(),
+                    path.display()
+                ),
+                Some(_) => {}
+                None => {
+                    first_seen.insert(publication.get_id(), path.clone());
                 }
-            };
-            serde_yaml::from_str(&unparsed_yaml).unwrap_or_else(|_e| {
-                error!("Published.yaml contains invalid Cynthia-instructions.",);
-                Vec::new()
-            })
-        } else {
-            error!("Couldn't find published.jsonc or published.yaml.");
-            process::exit(1);
+            }
+            merged.push(publication);
+        }
+    }
+    merged
+}
+
+/// Reads the publication list straight from disk, bypassing the file cache.
+/// Returns `None` when the list can't be read, without logging why.
+pub(crate) fn read_published_from_disk() -> Option<CynthiaPublicationList> {
+    let files = published_files();
+    if files.is_empty() {
+        return None;
+    }
+    let mut lists = Vec::new();
+    for path in files {
+        let unparsed = decode_published(&path, std::fs::read(&path).ok()?).ok()?;
+        let list = parse_published(&path, &unparsed).ok()?;
+        lists.push((path, list));
+    }
+    Some(merge_published(lists))
+}
+
+/// The neighbours of a post by publication date, for "previous" and "next" links. Posts without a
+/// category have no neighbours in theirs.
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) struct AdjacentPosts {
+    /// The post published before this one.
+    pub(crate) previous: Option<AdjacentPost>,
+    /// The post published after this one.
+    pub(crate) next: Option<AdjacentPost>,
+    pub(crate) previous_in_category: Option<AdjacentPost>,
+    pub(crate) next_in_category: Option<AdjacentPost>,
+}
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) struct AdjacentPost {
+    pub(crate) id: String,
+    pub(crate) title: String,
+    /// The path of the post on this site.
+    pub(crate) url: String,
+}
+impl AdjacentPost {
+    fn new(post: &PostPublication, trailing_slash: &TrailingSlash) -> Self {
+        AdjacentPost {
+            id: post.id.clone(),
+            title: post.title.clone(),
+            url: crate::helpers::page_url("", &post.id, trailing_slash),
+        }
+    }
+}
+
+/// How many posts carry each tag and category, the most used first. For tag clouds and navigation.
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) struct Taxonomy {
+    pub(crate) tags: Vec<TermCount>,
+    pub(crate) categories: Vec<TermCount>,
+}
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) struct TermCount {
+    pub(crate) name: String,
+    pub(crate) count: usize,
+}
+impl TermCount {
+    /// Counts the terms that are the same by `key`, named after the first one seen.
+    /// Sorted by count, ties alphabetically.
+    fn count<'a>(
+        terms: impl Iterator<Item = &'a String>,
+        key: impl Fn(&str) -> String,
+    ) -> Vec<TermCount> {
+        let mut counts: Vec<TermCount> = vec![];
+        let mut index: std::collections::HashMap<String, usize> = Default::default();
+        for term in terms {
+            match index.get(&key(term)) {
+                Some(&i) => counts[i].count += 1,
+                None => {
+                    index.insert(key(term), counts.len());
+                    counts.push(TermCount {
+                        name: term.clone(),
+                        count: 1,
+                    });
+                }
+            }
         }
+        counts.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.name.cmp(&b.name)));
+        counts
     }
 }
+
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub(crate) struct PostPublication {
     id: String,
@@ -345,6 +661,30 @@ pub(crate) struct PostPublication {
     author: Option<Author>,
     postcontent: PublicationContent,
     scene_override: Option<String>,
+    lang: Option<String>,
+    translation_key: Option<String>,
+    extra_head: Option<String>,
+    canonical: Option<String>,
+    /// `short`, or else generated from the content, see [`Self::excerpt_source`].
+    #[serde(skip_deserializing)]
+    excerpt: Option<String>,
+}
+impl PostPublication {
+    /// The content to generate an excerpt from, for posts that don't have a `short`.
+    pub(crate) fn excerpt_source(&self) -> Option<&PublicationContent> {
+        self.short.is_none().then_some(&self.postcontent)
+    }
+    pub(crate) fn id(&self) -> &str {
+        &self.id
+    }
+    pub(crate) fn set_excerpt(&mut self, excerpt: String) {
+        self.excerpt = Some(excerpt);
+    }
+    pub(crate) fn resolve_author_thumbnail(&mut self, fallback: Option<&String>) {
+        if let Some(author) = &mut self.author {
+            author.resolve_thumbnail(fallback);
+        }
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -361,6 +701,24 @@ pub(crate) enum CynthiaPublication {
         #[serde(alias = "scene")]
         #[serde(alias = "scene-override")]
         scene_override: Option<String>,
+        /// Language of this publication, for sites with translations.
+        #[serde(default)]
+        lang: Option<String>,
+        /// Publications sharing this key are translations of each other, reachable at `/{lang}/{translation_key}`.
+        #[serde(alias = "translation-key")]
+        #[serde(default)]
+        translation_key: Option<String>,
+        /// Trusted HTML added to the page's `<head>`, like extra `<meta>` or `<link>` tags.
+        #[serde(alias = "extra-head")]
+        #[serde(default)]
+        extra_head: Option<String>,
+        /// The URL search engines should index this under, when it isn't the page's own.
+        #[serde(default)]
+        canonical: Option<String>,
+        /// The media type the page is served as. Anything but HTML is served as the content is, without a template.
+        #[serde(alias = "content-type")]
+        #[serde(default)]
+        content_type: Option<String>,
     },
     #[serde(alias = "post")]
     Post {
@@ -378,6 +736,20 @@ pub(crate) enum CynthiaPublication {
         #[serde(alias = "scene")]
         #[serde(alias = "scene-override")]
         scene_override: Option<String>,
+        /// Language of this publication, for sites with translations.
+        #[serde(default)]
+        lang: Option<String>,
+        /// Publications sharing this key are translations of each other, reachable at `/{lang}/{translation_key}`.
+        #[serde(alias = "translation-key")]
+        #[serde(default)]
+        translation_key: Option<String>,
+        /// Trusted HTML added to the page's `<head>`, like extra `<meta>` or `<link>` tags.
+        #[serde(alias = "extra-head")]
+        #[serde(default)]
+        extra_head: Option<String>,
+        /// The URL search engines should index this under, when it isn't the page's own.
+        #[serde(default)]
+        canonical: Option<String>,
     },
     #[serde(alias = "postlist")]
     #[serde(alias = "selection")]
@@ -391,6 +763,10 @@ pub(crate) enum CynthiaPublication {
         #[serde(alias = "scene")]
         #[serde(alias = "scene-override")]
         scene_override: Option<String>,
+        /// Trusted HTML added to the page's `<head>`, like extra `<meta>` or `<link>` tags.
+        #[serde(alias = "extra-head")]
+        #[serde(default)]
+        extra_head: Option<String>,
     },
 }
 impl CynthiaPublication {
@@ -402,6 +778,86 @@ impl CynthiaPublication {
         }
     }
 
+    /// The language and translation key of a publication, when it is part of a set of translations.
+    pub(crate) fn get_translation(&self) -> Option<(String, String)> {
+        match self {
+            CynthiaPublication::Page {
+                lang: Some(lang),
+                translation_key: Some(key),
+                ..
+            }
+            | CynthiaPublication::Post {
+                lang: Some(lang),
+                translation_key: Some(key),
+                ..
+            } => Some((lang.clone(), key.clone())),
+            _ => None,
+        }
+    }
+
+    /// The category of a post, or the one a post list is filtered on.
+    pub(crate) fn get_category(&self) -> Option<String> {
+        match self {
+            CynthiaPublication::Post { category, .. } => category.clone(),
+            CynthiaPublication::PostList {
+                filter: PostListFilter::Category(category),
+                ..
+            } => Some(category.clone()),
+            _ => None,
+        }
+    }
+
+    /// The media type of a page that isn't served as HTML, like `application/json` for a `manifest.json`.
+    pub(crate) fn output_content_type(&self) -> Option<String> {
+        match self {
+            CynthiaPublication::Page {
+                content_type: Some(content_type),
+                ..
+            } if !content_type
+                .trim()
+                .to_ascii_lowercase()
+                .starts_with("text/html") =>
+            {
+                Some(content_type.trim().to_string())
+            }
+            _ => None,
+        }
+    }
+
+    /// The canonical URL the publication sets for itself, overriding the one from its id.
+    pub(crate) fn get_canonical(&self) -> Option<String> {
+        match self {
+            CynthiaPublication::Page { canonical, .. } => canonical.clone(),
+            CynthiaPublication::Post { canonical, .. } => canonical.clone(),
+            CynthiaPublication::PostList { .. } => None,
+        }
+    }
+
+    pub(crate) fn get_extra_head(&self) -> Option<String> {
+        match self {
+            CynthiaPublication::Page { extra_head, .. } => extra_head.clone(),
+            CynthiaPublication::Post { extra_head, .. } => extra_head.clone(),
+            CynthiaPublication::PostList { extra_head, .. } => extra_head.clone(),
+        }
+    }
+
+    /// The content of a page or post, postlists have none of their own.
+    pub(crate) fn get_content(&self) -> Option<&PublicationContent> {
+        match self {
+            CynthiaPublication::Page { pagecontent, .. } => Some(pagecontent),
+            CynthiaPublication::Post { postcontent, .. } => Some(postcontent),
+            CynthiaPublication::PostList { .. } => None,
+        }
+    }
+    /// Post lists don't have dates of their own.
+    pub(crate) fn get_dates(&self) -> Option<CynthiaPublicationDates> {
+        match self {
+            CynthiaPublication::Page { dates, .. } => Some(dates.clone()),
+            CynthiaPublication::Post { dates, .. } => Some(dates.clone()),
+            CynthiaPublication::PostList { .. } => None,
+        }
+    }
+
     pub(crate) fn get_scene_name(&self) -> Option<String> {
         match self {
             CynthiaPublication::Page { scene_override, .. } => scene_override.clone(),
@@ -415,6 +871,47 @@ pub(crate) struct CynthiaPublicationDates {
     pub(crate) altered: u64,
     pub(crate) published: u64,
 }
+impl CynthiaPublicationDates {
+    /// When the publication last changed: `altered`, or `published` when it was never altered.
+    /// `Last-Modified` headers and the dates shown in templates all derive from this, so they agree.
+    pub(crate) fn last_updated(&self) -> Option<SystemTime> {
+        let seconds = if self.altered != 0 {
+            self.altered
+        } else {
+            self.published
+        };
+        (seconds != 0).then(|| UNIX_EPOCH + Duration::from_secs(seconds))
+    }
+
+    /// [`Self::last_updated`] as RFC 3339 in the site's timezone, for `<time datetime>` and for feed and sitemap dates.
+    pub(crate) fn last_updated_rfc3339(&self, timezone: chrono_tz::Tz) -> Option<String> {
+        self.last_updated().map(|time| {
+            chrono::DateTime::<chrono::Utc>::from(time)
+                .with_timezone(&timezone)
+                .to_rfc3339_opts(chrono::SecondsFormat::Secs, true)
+        })
+    }
+
+    /// [`Self::last_updated`] for people to read, like `July 10, 2023`. Near midnight, the day depends on the timezone.
+    pub(crate) fn last_updated_display(&self, timezone: chrono_tz::Tz) -> Option<String> {
+        self.last_updated().map(|time| {
+            chrono::DateTime::<chrono::Utc>::from(time)
+                .with_timezone(&timezone)
+                .format("%B %-d, %Y")
+                .to_string()
+        })
+    }
+
+    /// `published` for people to read, like [`Self::last_updated_display`].
+    pub(crate) fn published_display(&self, timezone: chrono_tz::Tz) -> Option<String> {
+        (self.published != 0).then(|| {
+            chrono::DateTime::<chrono::Utc>::from(UNIX_EPOCH + Duration::from_secs(self.published))
+                .with_timezone(&timezone)
+                .format("%B %-d, %Y")
+                .to_string()
+        })
+    }
+}
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub(crate) enum PostListFilter {
     #[default]
@@ -438,7 +935,15 @@ pub(crate) enum PublicationContent {
     #[serde(alias = "external")]
     External { source: ContentType },
     #[serde(alias = "local")]
-    Local { source: ContentType },
+    Local {
+        source: ContentType,
+        /// The encoding of an older content file, like `windows-1252`. UTF-8 when not given.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        encoding: Option<String>,
+    },
+    /// Inline content, base64-encoded. Decoded when rendering.
+    #[serde(alias = "base64")]
+    Base64(ContentType),
 }
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(tag = "as", content = "value")]
@@ -462,6 +967,91 @@ impl ContentType {
             ContentType::PlainText(c) => c.to_string(),
         }
     }
+    /// The inner value decoded from base64, for `PublicationContent::Base64`. It has to be UTF-8.
+    pub(crate) fn decode_base64(&self) -> Result<String, String> {
+        use base64::Engine;
+        // Long encoded values are often wrapped over several lines.
+        let encoded: String = self
+            .get_inner()
+            .chars()
+            .filter(|ch| !ch.is_ascii_whitespace())
+            .collect();
+        base64::engine::general_purpose::STANDARD
+            .decode(encoded)
+            .map_err(|e| e.to_string())
+            .and_then(|bytes| String::from_utf8(bytes).map_err(|e| e.to_string()))
+    }
+}
+/// Content files are read and decoded this much at a time.
+const READ_CHUNK: usize = 64 * 1024;
+
+/// Reads a content file as text, decoded from `encoding` (a label like `windows-1252` or
+/// `shift_jis`) or else UTF-8. Errors name the file, and for UTF-8 that doesn't decode, the byte
+/// it stops at. The file is decoded as it's read, a chunk at a time, so a big one isn't held in
+/// memory twice, as bytes and as text.
+pub(crate) fn read_content_file(path: &Path, encoding: Option<&str>) -> Result<String, String> {
+    use std::io::Read;
+    let encoding = match encoding {
+        None => encoding_rs::UTF_8,
+        Some(label) => {
+            encoding_rs::Encoding::for_label(label.trim().as_bytes()).ok_or_else(|| {
+                format!(
+                    "`{}` is declared as `{label}`, which is not an encoding Cynthia knows",
+                    path.display()
+                )
+            })?
+        }
+    };
+    let couldnt_read = |e: std::io::Error| format!("couldn't read `{}`: {e}", path.display());
+    let mut file = std::fs::File::open(path).map_err(couldnt_read)?;
+    let size = file.metadata().map_or(0, |m| m.len() as usize);
+    let mut decoder = encoding.new_decoder_without_bom_handling();
+    let mut text = String::with_capacity(
+        decoder
+            .max_utf8_buffer_length_without_replacement(size)
+            .unwrap_or(size),
+    );
+    let mut chunk = vec![0; READ_CHUNK];
+    let mut offset = 0;
+    loop {
+        let read = file.read(&mut chunk).map_err(couldnt_read)?;
+        let last = read == 0;
+        let mut input = &chunk[..read];
+        loop {
+            let (result, consumed) =
+                decoder.decode_to_string_without_replacement(input, &mut text, last);
+            offset += consumed;
+            input = &input[consumed..];
+            match result {
+                encoding_rs::DecoderResult::InputEmpty => break,
+                encoding_rs::DecoderResult::OutputFull => text.reserve(
+                    decoder
+                        .max_utf8_buffer_length_without_replacement(input.len())
+                        .unwrap_or(input.len())
+                        .max(4),
+                ),
+                encoding_rs::DecoderResult::Malformed(bad, extra)
+                    if encoding == encoding_rs::UTF_8 =>
+                {
+                    return Err(format!(
+                        "`{}` is not valid UTF-8 at byte {}, save it as UTF-8 or set the content's `encoding`",
+                        path.display(),
+                        offset - bad as usize - extra as usize
+                    ));
+                }
+                encoding_rs::DecoderResult::Malformed(..) => {
+                    return Err(format!(
+                        "`{}` is not valid {} text",
+                        path.display(),
+                        encoding.name()
+                    ));
+                }
+            }
+        }
+        if last {
+            return Ok(text);
+        }
+    }
 }
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub(crate) struct Author {
@@ -469,3 +1059,204 @@ pub(crate) struct Author {
     pub(crate) thumbnail: Option<String>,
     pub(crate) link: Option<String>,
 }
+impl Author {
+    /// Replaces an unsafe or missing thumbnail with the site's default avatar.
+    pub(crate) fn resolve_thumbnail(&mut self, fallback: Option<&String>) {
+        if let Some(thumbnail) = &self.thumbnail {
+            if safe_url(thumbnail).is_none() {
+                warn!(
+                    "Ignoring the thumbnail of author {}, `{}` is not a URL that can be shown.",
+                    self.name.clone().unwrap_or_default(),
+                    thumbnail
+                );
+            }
+        }
+        self.thumbnail = self
+            .thumbnail
+            .as_deref()
+            .and_then(safe_url)
+            .or_else(|| fallback.cloned());
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use serde_json::json;
+
+    /// A `page` or `post` for tests, with an empty HTML body and dates at 0, and `fields` set over that.
+    pub(crate) fn publication(kind: &str, fields: serde_json::Value) -> CynthiaPublication {
+        let mut publication = json!({
+            "title": "",
+            "dates": {"altered": 0, "published": 0},
+            "content": {"inline": {"as": "html", "value": ""}},
+        });
+        if kind == "post" {
+            publication["tags"] = json!([]);
+        }
+        for (field, value) in fields.as_object().unwrap() {
+            publication[field] = value.clone();
+        }
+        let mut tagged = serde_json::Map::new();
+        tagged.insert(kind.to_string(), publication);
+        serde_json::from_value(serde_json::Value::Object(tagged)).unwrap()
+    }
+
+    #[test]
+    fn counts_tags_and_categories() {
+        let published: CynthiaPublicationList = vec![
+            publication(
+                "post",
+                json!({"id": "a", "tags": ["rust", "web"], "category": "Code"}),
+            ),
+            publication(
+                "post",
+                json!({"id": "b", "tags": ["web"], "category": "code"}),
+            ),
+            publication("post", json!({"id": "c", "tags": ["art"]})),
+        ];
+        let taxonomy = published.only_posts().taxonomy();
+        let counts = |terms: &[TermCount]| {
+            terms
+                .iter()
+                .map(|t| (t.name.clone(), t.count))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            counts(&taxonomy.tags),
+            [
+                (String::from("web"), 2),
+                (String::from("art"), 1),
+                (String::from("rust"), 1)
+            ]
+        );
+        assert_eq!(counts(&taxonomy.categories), [(String::from("Code"), 2)]);
+    }
+
+    #[test]
+    fn merges_publication_files() {
+        let list = parse_published(
+            Path::new("published.jsonc"),
+            r#"[{"page": {"id": "root", "title": "Home", "dates": {"altered": 0, "published": 0},
+                "content": {"inline": {"as": "text", "value": ""}}}}]"#,
+        )
+        .unwrap();
+        // Files in `published/` can hold a single publication.
+        let single = parse_published(
+            Path::new("published/hello.jsonc"),
+            r#"// One post per file.
+            {"post": {"id": "hello", "title": "Hello", "tags": [], "dates": {"altered": 0, "published": 0},
+                "content": {"inline": {"as": "text", "value": ""}}}}"#,
+        )
+        .unwrap();
+        let yaml = parse_published(
+            Path::new("published/more.yaml"),
+            "- !postlist\n  id: blog\n  title: Blog\n  filter: latest\n",
+        )
+        .unwrap();
+        assert!(parse_published(Path::new("published/broken.json"), "{").is_err());
+        // Gzipped files are read by what's under the `.gz`.
+        let mut gzipped = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        std::io::Write::write_all(
+            &mut gzipped,
+            b"- !postlist\n  id: archive\n  title: Archive\n  filter: latest\n",
+        )
+        .unwrap();
+        let path = Path::new("published/archive.yaml.gz");
+        let unzipped = decode_published(path, gzipped.finish().unwrap()).unwrap();
+        assert_eq!(
+            parse_published(path, &unzipped).unwrap()[0].get_id(),
+            "archive"
+        );
+        let merged = merge_published(vec![
+            (PathBuf::from("published.jsonc"), list),
+            (PathBuf::from("published/hello.jsonc"), single),
+            (PathBuf::from("published/more.yaml"), yaml),
+        ]);
+        assert_eq!(
+            merged.iter().map(|p| p.get_id()).collect::<Vec<String>>(),
+            ["root", "hello", "blog"]
+        );
+    }
+
+    #[test]
+    fn finds_adjacent_posts() {
+        let post = |id: &str, published: u64, category: Option<&str>| {
+            publication(
+                "post",
+                json!({"id": id, "category": category, "dates": {"altered": 0, "published": published}}),
+            )
+        };
+        let published: CynthiaPublicationList = vec![
+            post("first", 1, Some("Code")),
+            post("second", 2, None),
+            post("third post", 3, Some("code")),
+        ];
+        let posts = published.only_posts();
+        let ids = |p: &Option<AdjacentPost>| p.as_ref().map(|p| p.id.clone());
+        let first = posts.adjacent("first", &TrailingSlash::Keep);
+        assert_eq!(ids(&first.previous), None);
+        assert_eq!(ids(&first.next), Some(String::from("second")));
+        assert_eq!(
+            first.next_in_category.map(|p| p.url),
+            Some(String::from("/third-post"))
+        );
+        // Linked the way `trailing_slash` redirects to.
+        assert_eq!(
+            posts
+                .adjacent("first", &TrailingSlash::Add)
+                .next
+                .map(|p| p.url),
+            Some(String::from("/second/"))
+        );
+        let second = posts.adjacent("second", &TrailingSlash::Keep);
+        assert_eq!(ids(&second.previous), Some(String::from("first")));
+        assert_eq!(ids(&second.next_in_category), None);
+        let third = posts.adjacent("third post", &TrailingSlash::Keep);
+        assert_eq!(ids(&third.next), None);
+        assert_eq!(
+            ids(&third.previous_in_category),
+            Some(String::from("first"))
+        );
+        assert_eq!(
+            posts.adjacent("missing", &TrailingSlash::Keep),
+            AdjacentPosts::default()
+        );
+    }
+
+    #[test]
+    fn reads_content_that_is_not_utf8() {
+        let dir = std::env::temp_dir().join(format!("cynthia-encodings-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("legacy.md");
+        // "Café" in windows-1252.
+        std::fs::write(&file, b"Caf\xe9").unwrap();
+
+        let error = read_content_file(&file, None).unwrap_err();
+        assert!(error.contains("legacy.md"));
+        assert!(error.contains("not valid UTF-8 at byte 3"));
+        assert_eq!(
+            read_content_file(&file, Some("windows-1252")).unwrap(),
+            "Café"
+        );
+        assert!(read_content_file(&file, Some("klingon"))
+            .unwrap_err()
+            .contains("not an encoding"));
+        assert!(read_content_file(&dir.join("missing.md"), None)
+            .unwrap_err()
+            .starts_with("couldn't read"));
+
+        let published: CynthiaPublicationList = vec![publication(
+            "page",
+            json!({"id": "old", "content": {"local": {
+                "source": {"as": "markdown", "value": "old.md"},
+                "encoding": "latin1",
+            }}}),
+        )];
+        assert!(matches!(
+            published.get_by_id(String::from("old")).and_then(|p| p.get_content().cloned()),
+            Some(PublicationContent::Local { encoding: Some(e), .. }) if e == "latin1"
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -4,17 +4,23 @@
  * Licensed under the GNU AFFERO GENERAL PUBLIC LICENSE Version 3, see the LICENSE file for more information.
  */
 use actix_web::web::Data;
-use log::error;
+use log::{debug, error};
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tokio::sync::Mutex;
 
-use crate::config::CynthiaConfClone;
-use crate::publications::{CynthiaPostList, CynthiaPublicationList, CynthiaPublicationListTrait};
+use crate::config::{CynthiaConfClone, SceneCollectionTrait};
+use crate::publications::{
+    AdjacentPosts, CynthiaPostList, CynthiaPublication, CynthiaPublicationList,
+    CynthiaPublicationListTrait, PostLists, Taxonomy,
+};
 use crate::{LockCallback, ServerContext};
 
 pub(crate) enum PGIDCheckResponse {
-    Ok,
+    /// With the media type the publication is served as, when it isn't HTML.
+    Ok(Option<String>),
     Error,
     NotFound,
 }
@@ -57,6 +63,90 @@ impl RenderrerResponse {
     }
 }
 
+tokio::task_local! {
+    /// The stage the current render is in, so a render that runs past its deadline can tell where it got stuck.
+    static RENDER_STAGE: Arc<std::sync::Mutex<&'static str>>;
+    /// With `--trace-render`, the stages the current render went through and when each of them started.
+    static RENDER_TRACE: Arc<std::sync::Mutex<Vec<(&'static str, Instant)>>>;
+}
+
+/// Marks the stage the current render has reached, does nothing outside of `render_with_deadline`.
+pub(crate) fn render_stage(stage: &'static str) {
+    let _ = RENDER_STAGE.try_with(|s| {
+        if let Ok(mut s) = s.lock() {
+            *s = stage;
+        }
+    });
+    let _ = RENDER_TRACE.try_with(|t| {
+        if let Ok(mut t) = t.lock() {
+            t.push((stage, Instant::now()));
+        }
+    });
+}
+
+/// Wraps the markup a stage produced in comments naming it, when the render is traced.
+fn traced(stage: &str, markup: String) -> String {
+    if RENDER_TRACE.try_with(|_| ()).is_err() {
+        return markup;
+    }
+    format!("<!-- cynthia-trace: {stage} -->{markup}<!-- /cynthia-trace: {stage} -->")
+}
+
+fn milliseconds(duration: Duration) -> String {
+    format!("{:.1} ms", duration.as_secs_f64() * 1000.0)
+}
+
+/// With `--trace-render`, a comment listing the stages of the current render so far and how long
+/// each took. Empty when the render isn't traced.
+fn trace_summary() -> String {
+    let Ok(trace) = RENDER_TRACE.try_with(|t| t.lock().map(|t| t.clone()).unwrap_or_default())
+    else {
+        return String::new();
+    };
+    let Some((_, start)) = trace.first() else {
+        return String::new();
+    };
+    let end = Instant::now();
+    let mut summary = format!(
+        "\n<!-- cynthia-trace: rendered in {}",
+        milliseconds(end - *start)
+    );
+    for (i, (stage, started)) in trace.iter().enumerate() {
+        let ended = trace.get(i + 1).map(|(_, next)| *next).unwrap_or(end);
+        summary.push_str(&format!(
+            "\n\t{}: {}",
+            stage,
+            milliseconds(ended - *started)
+        ));
+    }
+    summary.push_str("\n-->\n");
+    summary
+}
+
+/// Runs a render, giving up after `timeout` seconds (`0` meaning never).
+/// When the deadline passes, the stage the render was in is returned instead.
+pub(crate) async fn render_with_deadline(
+    timeout: u64,
+    render: impl std::future::Future<Output = RenderrerResponse>,
+) -> Result<RenderrerResponse, &'static str> {
+    let stage = Arc::new(std::sync::Mutex::new("starting"));
+    let render = async {
+        if crate::trace_render() {
+            RENDER_TRACE.scope(Arc::default(), render).await
+        } else {
+            render.await
+        }
+    };
+    let render = RENDER_STAGE.scope(stage.clone(), render);
+    if timeout == 0 {
+        return Ok(render.await);
+    }
+    match tokio::time::timeout(std::time::Duration::from_secs(timeout), render).await {
+        Ok(r) => Ok(r),
+        Err(_) => Err(stage.lock().map(|s| *s).unwrap_or("unknown")),
+    }
+}
+
 pub(crate) async fn check_pgid(
     pgid: String,
     server_context_mutex: Data<Arc<Mutex<ServerContext>>>,
@@ -73,7 +163,9 @@ pub(crate) async fn check_pgid(
         return PGIDCheckResponse::Error;
     }
     let publication = published.get_by_id(page_id);
-    if publication.is_none() {
+    if let Some(publication) = publication {
+        PGIDCheckResponse::Ok(publication.output_content_type())
+    } else {
         let publication = published.get_by_id(server_context.config.site.notfound_page.clone());
         if publication.is_none() {
             error!(
@@ -83,14 +175,251 @@ pub(crate) async fn check_pgid(
         } else {
             PGIDCheckResponse::NotFound
         }
+    }
+}
+/// Resolves `{lang}/{translation_key}` to the id of the translation in that language,
+/// or in `site.default_lang` when there is none. Returns `None` for other ids.
+pub(crate) async fn translated_pgid(
+    pgid: &str,
+    server_context_mutex: Data<Arc<Mutex<ServerContext>>>,
+) -> Option<String> {
+    let (lang, translation_key) = pgid.split_once('/')?;
+    let default_lang = server_context_mutex
+        .lock_callback(|a| a.config.site.default_lang.clone())
+        .await;
+    let published = CynthiaPublicationList::load(server_context_mutex.clone()).await;
+    published
+        .find_translation(lang, translation_key, &default_lang)
+        .map(|p| p.get_id())
+}
+
+/// The id of the publication an incoming page id resolves to by its slug, when that isn't the same id.
+/// Pages are cached under their real id this way, not under every spelling of it.
+pub(crate) async fn slugged_pgid(
+    pgid: &str,
+    server_context_mutex: Data<Arc<Mutex<ServerContext>>>,
+) -> Option<String> {
+    if pgid.is_empty() {
+        return None;
+    }
+    let published = CynthiaPublicationList::load(server_context_mutex).await;
+    published
+        .get_by_id(pgid.to_string())
+        .map(|p| p.get_id())
+        .filter(|id| id != pgid)
+}
+
+/// A publication with its content rendered to HTML, for clients asking for JSON instead of a page.
+pub(crate) async fn publication_json(
+    pgid: &str,
+    server_context_mutex: Data<Arc<Mutex<ServerContext>>>,
+) -> Option<serde_json::Value> {
+    let published = CynthiaPublicationList::load(server_context_mutex.clone()).await;
+    let publication = if pgid.is_empty() {
+        published.get_root()
+    } else {
+        published.get_by_id(pgid.to_string())
+    }?;
+    let content = in_renderer::publication_content(&publication, server_context_mutex).await;
+    Some(serde_json::json!({
+        "publication": publication,
+        "content": content,
+    }))
+}
+
+/// Stands in for the nonce in rendered pages, so they can be cached and still get a fresh nonce per response.
+pub(crate) const NONCE_PLACEHOLDER: &str = "CYNTHIANONCEPLACEHOLDER";
+
+/// Puts a nonce on the `<script>` and `<style>` tags of a fragment Cynthia inlines itself, as
+/// `placeholder`. Tags with a `src` are left alone, those are covered by the policy's sources.
+pub(crate) fn with_nonce_attribute(fragment: &str, placeholder: &str) -> String {
+    fragment
+        .replace("<script>", &format!("<script nonce=\"{}\">", placeholder))
+        .replace("<style>", &format!("<style nonce=\"{}\">", placeholder))
+}
+
+/// A placeholder for one render, which content can't guess the way it can [`NONCE_PLACEHOLDER`].
+fn render_nonce_placeholder() -> String {
+    format!("CYNTHIANONCE{:032x}", rand::random::<u128>())
+}
+
+/// Turns the placeholders a render put in `document` into [`NONCE_PLACEHOLDER`], after taking out the
+/// ones that came in with content, templates or plugin output: only tags Cynthia put a nonce on
+/// get one when the page is served.
+fn placed_nonces(document: &str, placeholder: &str) -> String {
+    let mut document = document.to_string();
+    // Taking one out can join the pieces around it into another.
+    while document.contains(NONCE_PLACEHOLDER) {
+        document = document.replace(NONCE_PLACEHOLDER, "");
+    }
+    document.replace(placeholder, NONCE_PLACEHOLDER)
+}
+
+/// The headers to send with a rendered publication, from `site.headers` and those of its scene.
+pub(crate) async fn headers_for_pgid(
+    pgid: &str,
+    server_context_mutex: Data<Arc<Mutex<ServerContext>>>,
+) -> Vec<(String, String)> {
+    let config = server_context_mutex
+        .lock_callback(|a| a.config.clone())
+        .await;
+    let published = CynthiaPublicationList::load(server_context_mutex.clone()).await;
+    let publication = if pgid.is_empty() {
+        published.get_root()
+    } else {
+        published.get_by_id(pgid.to_string())
+    };
+    let scene = publication
+        .and_then(|p| config.scenes.for_publication(&p, &config.site))
+        .unwrap_or_else(|| config.scenes.get_default(&config.site));
+    scene.response_headers(&config.site.headers)
+}
+
+/// When the publication behind a page id last changed, for its `Last-Modified` header.
+pub(crate) async fn last_updated_for_pgid(
+    pgid: &str,
+    server_context_mutex: Data<Arc<Mutex<ServerContext>>>,
+) -> Option<std::time::SystemTime> {
+    let published = CynthiaPublicationList::load(server_context_mutex).await;
+    let publication = if pgid.is_empty() {
+        published.get_root()
+    } else {
+        published.get_by_id(pgid.to_string())
+    };
+    publication?.get_dates()?.last_updated()
+}
+
+/// Whether the publication behind a page id is shown with a `missing_content` placeholder, because its
+/// local content file is missing.
+pub(crate) async fn shows_placeholder(
+    pgid: &str,
+    server_context_mutex: Data<Arc<Mutex<ServerContext>>>,
+) -> bool {
+    let missing_content = server_context_mutex
+        .lock_callback(|a| a.config.missing_content.clone())
+        .await;
+    let published = CynthiaPublicationList::load(server_context_mutex).await;
+    let Some(publication) = (if pgid.is_empty() {
+        published.get_root()
+    } else {
+        published.get_by_id(pgid.to_string())
+    }) else {
+        return false;
+    };
+    match publication.get_content() {
+        Some(crate::publications::PublicationContent::Local { source, .. }) => {
+            missing_content.placeholder_for(source).is_some()
+                && !std::path::Path::new("./cynthiaFiles/publications/")
+                    .join(source.get_inner())
+                    .exists()
+        }
+        _ => false,
+    }
+}
+
+/// The site's [`Taxonomy`], cached as `virtual:taxonomy` so the watcher drops it with the other lists when posts change.
+pub(crate) async fn taxonomy(server_context_mutex: Data<Arc<Mutex<ServerContext>>>) -> Taxonomy {
+    const KEY: &str = "virtual:taxonomy";
+    let cached = server_context_mutex
+        .lock_callback(|a| a.get_cache(KEY, 0))
+        .await
+        .and_then(|c| serde_json::from_slice(&c.0).ok());
+    if let Some(taxonomy) = cached {
+        return taxonomy;
+    }
+    let taxonomy = CynthiaPublicationList::load(server_context_mutex.clone())
+        .await
+        .only_posts()
+        .taxonomy();
+    if let Ok(json) = serde_json::to_vec(&taxonomy) {
+        let mut server_context = server_context_mutex.lock().await;
+        let lifetime = server_context.config.cache.lifetimes.served;
+        let _ = server_context.store_cache(KEY, &json, lifetime);
+    }
+    taxonomy
+}
+
+/// A pre-rendered copy of the page from `prerender_dir`, under its [`prerendered_file`] name.
+/// Copies written before the publication list or the page's content file last changed are out of date,
+/// and so are those older than the publication's own dates, those pages are rendered instead.
+/// Returned with when it was written.
+pub(crate) async fn prerendered_page(
+    pgid: &str,
+    server_context_mutex: Data<Arc<Mutex<ServerContext>>>,
+) -> Option<(Vec<u8>, std::time::SystemTime)> {
+    let dir = server_context_mutex
+        .lock_callback(|a| a.config.prerender_dir.clone())
+        .await?;
+    let published = CynthiaPublicationList::load(server_context_mutex).await;
+    let publication = if pgid.is_empty() {
+        published.get_root()?
+    } else {
+        published.get_by_id(pgid.to_string())?
+    };
+    let file = prerendered_file(&publication, pgid.is_empty())?;
+    let path = std::path::Path::new(&dir).join(file);
+    let written = std::fs::metadata(&path).ok()?.modified().ok()?;
+    let modified = |path: &str| std::fs::metadata(path).and_then(|m| m.modified()).ok();
+    // Adding or removing a file in `published/` changes the directory.
+    let mut sources = vec![
+        modified("./cynthiaFiles/published"),
+        publication.get_dates().and_then(|d| d.last_updated()),
+    ];
+    sources.extend(
+        crate::publications::published_files()
+            .iter()
+            .map(|file| std::fs::metadata(file).and_then(|m| m.modified()).ok()),
+    );
+    if let Some(crate::publications::PublicationContent::Local { source, .. }) =
+        publication.get_content()
+    {
+        sources.push(modified(&format!(
+            "./cynthiaFiles/publications/{}",
+            source.get_inner()
+        )));
+    }
+    if sources.into_iter().flatten().any(|source| source > written) {
+        debug!("The pre-rendered copy of `{}` is out of date.", pgid);
+        return None;
+    }
+    Some((std::fs::read(path).ok()?, written))
+}
+
+/// The file in `prerender_dir` holding a publication's pre-rendered copy: `index.html` for the root
+/// publication served at `/`, otherwise its id, as `{id}.html` for HTML pages and as it is for others.
+pub(crate) fn prerendered_file(publication: &CynthiaPublication, at_root: bool) -> Option<String> {
+    if at_root {
+        return Some(String::from("index.html"));
+    }
+    let name = publication_file_name(&publication.get_id())?;
+    Some(if publication.output_content_type().is_some() {
+        name
     } else {
-        PGIDCheckResponse::Ok
+        format!("{}.html", name)
+    })
+}
+
+/// A publication id as a relative file path, without ways out of the directory it's joined to.
+fn publication_file_name(pgid: &str) -> Option<String> {
+    let parts = pgid
+        .split('/')
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<&str>>();
+    if parts.is_empty()
+        || parts
+            .iter()
+            .any(|part| *part == "." || *part == ".." || part.contains('\\'))
+    {
+        return None;
     }
+    Some(parts.join("/"))
 }
+
 pub(crate) async fn render_from_pgid(
     pgid: String,
     server_context_mutex: Data<Arc<Mutex<ServerContext>>>,
 ) -> RenderrerResponse {
+    render_stage("loading publications");
     let config = server_context_mutex
         .lock_callback(|a| a.config.clone())
         .await;
@@ -124,30 +453,148 @@ struct PublicationScene {
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub(crate) struct PageLikePublicationTemplateData {
     meta: PageLikePublicationTemplateDataMeta,
+    #[serde(flatten)]
+    shortcuts: PublicationTemplateShortcuts,
+    #[serde(flatten)]
+    site_context: SiteTemplateContext,
     content: String,
+    /// For posts, the posts published before and after them.
+    adjacent: Option<Box<AdjacentPosts>>,
 }
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub(crate) struct PostListPublicationTemplateData {
     meta: PageLikePublicationTemplateDataMeta,
+    #[serde(flatten)]
+    shortcuts: PublicationTemplateShortcuts,
+    #[serde(flatten)]
+    site_context: SiteTemplateContext,
     posts: CynthiaPostList,
+    /// Post counts per tag and category across the whole site, not just this list.
+    taxonomy: Taxonomy,
 }
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
-struct PageLikePublicationTemplateDataMeta {
+pub(crate) struct PageLikePublicationTemplateDataMeta {
     id: String,
+    /// `page`, `post` or `postlist`, lets plugins scope their runners.
+    kind: String,
+    /// Name of the scene the publication is rendered in.
+    scene: String,
     title: String,
     desc: Option<String>,
     category: Option<String>,
     tags: Vec<String>,
     author: Option<crate::publications::Author>,
+    /// The author's thumbnail, or the site's default avatar when they have none.
+    author_thumbnail: Option<String>,
     dates: crate::publications::CynthiaPublicationDates,
+    /// When the publication last changed, as RFC 3339 for a `<time datetime>` attribute.
+    updated: Option<String>,
+    /// The same moment, formatted for display.
+    updated_display: Option<String>,
+    /// When the publication was first published, formatted for display.
+    published_display: Option<String>,
     thumbnail: Option<String>,
+    /// How often the publication was visited, when `visits.enabled` is set.
+    visits: Option<u64>,
+}
+/// The parts of `meta` templates use most, repeated at the top level so `{{title}}` works as well as `{{meta.title}}`.
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct PublicationTemplateShortcuts {
+    title: String,
+    author: Option<crate::publications::Author>,
+    tags: Vec<String>,
+    category: Option<String>,
+    published_display: Option<String>,
+    updated_display: Option<String>,
+}
+impl From<&PageLikePublicationTemplateDataMeta> for PublicationTemplateShortcuts {
+    fn from(meta: &PageLikePublicationTemplateDataMeta) -> Self {
+        PublicationTemplateShortcuts {
+            title: meta.title.clone(),
+            author: meta.author.clone(),
+            tags: meta.tags.clone(),
+            category: meta.category.clone(),
+            published_display: meta.published_display.clone(),
+            updated_display: meta.updated_display.clone(),
+        }
+    }
+}
+
+/// `site_context` from the configuration: all of it under `site`, and the keys the publication's
+/// own template data doesn't have at the top level as well. Where they collide, the publication wins.
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct SiteTemplateContext {
+    site: HashMap<String, String>,
+    #[serde(flatten)]
+    top_level: HashMap<String, String>,
+}
+impl SiteTemplateContext {
+    /// The context for template data with the top-level keys `taken`.
+    fn new(context: &HashMap<String, String>, taken: &[&str]) -> Self {
+        if context.is_empty() {
+            return SiteTemplateContext::default();
+        }
+        SiteTemplateContext {
+            site: context.clone(),
+            top_level: context
+                .iter()
+                .filter(|(key, _)| key.as_str() != "site" && !taken.contains(&key.as_str()))
+                .map(|(key, value)| (key.clone(), value.clone()))
+                .collect(),
+        }
+    }
+}
+impl PageLikePublicationTemplateData {
+    /// Its top-level keys, besides those of its `site_context`.
+    const KEYS: [&'static str; 9] = [
+        "meta",
+        "title",
+        "author",
+        "tags",
+        "category",
+        "published_display",
+        "updated_display",
+        "content",
+        "adjacent",
+    ];
+}
+impl PostListPublicationTemplateData {
+    /// Its top-level keys, besides those of its `site_context`.
+    const KEYS: [&'static str; 9] = [
+        "meta",
+        "title",
+        "author",
+        "tags",
+        "category",
+        "published_display",
+        "updated_display",
+        "posts",
+        "taxonomy",
+    ];
+}
+
+/// What the `error_pages` templates are rendered with.
+#[derive(Debug, Serialize)]
+pub(crate) struct ErrorPageData {
+    pub(crate) status: u16,
+    pub(crate) message: String,
+    /// What was logged while the page failed, only filled in in development mode.
+    pub(crate) details: Vec<String>,
+    pub(crate) request_id: Option<String>,
+}
+
+/// Renders `cynthiaFiles/templates/error/{template}.hbs` with the builtin handlebars renderer, as
+/// the plugin runtime may be what failed.
+pub(crate) fn render_error_page(template: &str, data: &ErrorPageData) -> Result<String, String> {
+    let template_path =
+        std::path::Path::new("cynthiaFiles/templates/error/").join(format!("{}.hbs", template));
+    in_renderer::render_template_file(&template_path, data)
 }
 
 mod in_renderer {
     use super::*;
     use crate::externalpluginservers::EPSRequestBody;
     use crate::publications::{CynthiaPostList, CynthiaPublicationListTrait, PostLists};
-    use crate::tell::CynthiaColors;
     use crate::{
         config::{CynthiaConfig, Scene, SceneCollectionTrait},
         publications::{ContentType, CynthiaPublication, PublicationContent},
@@ -162,10 +609,23 @@ mod in_renderer {
         publication: CynthiaPublication,
         server_context_mutex: Data<Arc<Mutex<ServerContext>>>,
     ) -> RenderrerResponse {
-        let config = server_context_mutex
-            .lock_callback(|a| a.config.clone())
+        let (config, visits) = server_context_mutex
+            .lock_callback(|a| (a.config.clone(), a.visits.clone()))
             .await;
+        if publication.output_content_type().is_some() {
+            // Served as it is, without shortcodes, a template or anything in a `<head>`.
+            render_stage("fetching content");
+            let Some(content) = publication.get_content() else {
+                return RenderrerResponse::Error;
+            };
+            return match fetch_content_source(content.clone(), server_context_mutex).await {
+                Some(source) => RenderrerResponse::Ok(source.inner),
+                None => RenderrerResponse::Error,
+            };
+        }
         let scene = fetch_scene(publication.clone(), config.clone());
+        // Checked when the configuration was loaded.
+        let timezone = crate::config::timezone(&config.timezone).unwrap_or(chrono_tz::UTC);
 
         if scene.is_none() {
             error!("No scene found for publication.");
@@ -176,23 +636,56 @@ mod in_renderer {
             CynthiaPublication::Page { .. } => PublicationScene {
                 template: scene.templates.page.clone(),
                 stylesheet: scene.stylefile.clone(),
-                script: scene.script.clone(),
+                script: scene.client_script(&config.site),
                 kind: "page".to_string(),
             },
             CynthiaPublication::Post { .. } => PublicationScene {
-                template: scene.templates.post.clone(),
+                template: scene
+                    .templates
+                    .for_post(publication.get_category().as_deref())
+                    .to_string(),
                 stylesheet: scene.stylefile.clone(),
-                script: scene.script.clone(),
+                script: scene.client_script(&config.site),
                 kind: "post".to_string(),
             },
             CynthiaPublication::PostList { .. } => PublicationScene {
                 template: scene.templates.postlist.clone(),
                 stylesheet: scene.stylefile.clone(),
-                script: scene.script.clone(),
+                script: scene.client_script(&config.site),
                 kind: "postlist".to_string(),
             },
         };
 
+        let extra_head = publication.get_extra_head();
+        let canonical = canonical_link(&publication, &config);
+        // Links to every translation of this publication, including itself.
+        let alternates: Vec<String> = match publication.get_translation() {
+            Some((_, key)) => CynthiaPublicationList::load(server_context_mutex.clone())
+                .await
+                .get_translations(&key)
+                .into_iter()
+                .map(|(lang, _)| {
+                    let href = crate::helpers::page_url(
+                        &config.site.site_baseurl,
+                        &format!("{}/{}", lang, key),
+                        &config.trailing_slash,
+                    );
+                    format!(
+                        "\n\t\t<link rel=\"alternate\" hreflang=\"{}\" href=\"{}\" />",
+                        crate::helpers::escape_html(&lang),
+                        crate::helpers::escape_html(&href)
+                    )
+                })
+                .collect(),
+            None => vec![],
+        };
+
+        let visits = config
+            .visits
+            .enabled
+            .then(|| visits.get(&publication.get_id()));
+
+        render_stage("fetching content");
         let mut pageish_template_data: PageLikePublicationTemplateData =
             PageLikePublicationTemplateData::default();
         let mut postlist_template_data: PostListPublicationTemplateData =
@@ -208,20 +701,34 @@ mod in_renderer {
                 ..
             } => {
                 pageish_template_data = PageLikePublicationTemplateData {
+                    // Filled in below.
+                    shortcuts: PublicationTemplateShortcuts::default(),
+                    site_context: SiteTemplateContext::default(),
                     meta: PageLikePublicationTemplateDataMeta {
                         id: id.clone(),
+                        kind: localscene.kind.clone(),
+                        scene: scene.name.clone(),
                         title: title.clone(),
                         desc: description.clone(),
                         category: None,
                         author: None,
+                        author_thumbnail: None,
                         tags: vec![],
                         dates: dates.clone(),
+                        updated: dates.last_updated_rfc3339(timezone),
+                        updated_display: dates.last_updated_display(timezone),
+                        published_display: dates.published_display(timezone),
                         thumbnail: thumbnail.clone(),
+                        visits,
                     },
-                    content: match fetch_page_ish_content(pagecontent).await.unwrap_html() {
-                        RenderrerResponse::Ok(s) => s,
+                    content: match fetch_page_ish_content(pagecontent, server_context_mutex.clone())
+                        .await
+                        .unwrap_html()
+                    {
+                        RenderrerResponse::Ok(s) => traced("content", s),
                         _ => return RenderrerResponse::Error,
                     },
+                    adjacent: None,
                 }
             }
             CynthiaPublication::Post {
@@ -236,21 +743,46 @@ mod in_renderer {
                 tags,
                 ..
             } => {
+                let author = author.map(|mut author| {
+                    author.resolve_thumbnail(config.site.default_author_thumbnail.as_ref());
+                    author
+                });
                 pageish_template_data = PageLikePublicationTemplateData {
+                    // Filled in below.
+                    shortcuts: PublicationTemplateShortcuts::default(),
+                    site_context: SiteTemplateContext::default(),
                     meta: PageLikePublicationTemplateDataMeta {
                         id: id.clone(),
+                        kind: localscene.kind.clone(),
+                        scene: scene.name.clone(),
                         title: title.clone(),
                         desc: short.clone(),
                         category: category.clone(),
-                        author: author.clone(),
+                        author_thumbnail: author.as_ref().and_then(|a| a.thumbnail.clone()),
+                        author,
                         dates: dates.clone(),
+                        updated: dates.last_updated_rfc3339(timezone),
+                        updated_display: dates.last_updated_display(timezone),
+                        published_display: dates.published_display(timezone),
                         thumbnail: thumbnail.clone(),
+                        visits,
                         tags: tags.clone(),
                     },
-                    content: match fetch_page_ish_content(postcontent).await.unwrap_html() {
-                        RenderrerResponse::Ok(s) => s,
+                    content: match fetch_page_ish_content(postcontent, server_context_mutex.clone())
+                        .await
+                        .unwrap_html()
+                    {
+                        RenderrerResponse::Ok(s) => {
+                            traced("content", crate::helpers::hide_excerpt_marker(&s))
+                        }
                         _ => return RenderrerResponse::Error,
                     },
+                    adjacent: Some(Box::new(
+                        CynthiaPublicationList::load(server_context_mutex.clone())
+                            .await
+                            .only_posts()
+                            .adjacent(&id, &config.trailing_slash),
+                    )),
                 }
             }
             CynthiaPublication::PostList {
@@ -263,28 +795,83 @@ mod in_renderer {
                 let publicationlist: CynthiaPublicationList =
                     CynthiaPublicationList::load(server_context_mutex.clone()).await;
                 let postlist: CynthiaPostList = publicationlist.only_posts();
-                let filtered_postlist = postlist.filter(filter);
+                let mut filtered_postlist = postlist.filter(filter);
+                for post in filtered_postlist.iter_mut() {
+                    post.resolve_author_thumbnail(config.site.default_author_thumbnail.as_ref());
+                    let Some(content) = post.excerpt_source().cloned() else {
+                        continue;
+                    };
+                    let key = crate::publications::excerpt_cache_key(post.id());
+                    let lifetime = config.cache.lifetimes.served;
+                    if let Some(cached) = server_context_mutex
+                        .lock_callback(|a| a.get_cache(&key, lifetime))
+                        .await
+                    {
+                        post.set_excerpt(String::from_utf8_lossy(&cached.0).into_owned());
+                        continue;
+                    }
+                    // A post whose content can't be rendered is still listed, just without an excerpt.
+                    if let RenderrerResponse::Ok(html) =
+                        fetch_page_ish_content(content, server_context_mutex.clone())
+                            .await
+                            .unwrap_html()
+                    {
+                        let excerpt = crate::helpers::excerpt(
+                            &html,
+                            config.site.excerpts.length,
+                            config.site.excerpts.strip_html,
+                        );
+                        let _ = server_context_mutex
+                            .lock_callback(|a| a.store_cache(&key, excerpt.as_bytes(), lifetime))
+                            .await;
+                        post.set_excerpt(excerpt);
+                    }
+                }
                 postlist_template_data = PostListPublicationTemplateData {
+                    // Filled in below.
+                    shortcuts: PublicationTemplateShortcuts::default(),
+                    site_context: SiteTemplateContext::default(),
                     meta: PageLikePublicationTemplateDataMeta {
                         id: id.clone(),
+                        kind: localscene.kind.clone(),
+                        scene: scene.name.clone(),
                         title: title.clone(),
                         desc: short.clone(),
                         category: None,
                         tags: vec![],
                         author: None,
+                        author_thumbnail: None,
                         dates: crate::publications::CynthiaPublicationDates {
                             altered: 0,
                             published: 0,
                         },
+                        updated: None,
+                        updated_display: None,
+                        published_display: None,
                         thumbnail: None,
+                        visits,
                     },
                     posts: filtered_postlist,
+                    taxonomy: taxonomy(server_context_mutex.clone()).await,
                 };
                 pageish_template_data.meta = postlist_template_data.meta.clone();
                 // println!("{}", serde_json::to_string(&postlist_template_data).unwrap());
             }
         };
+        pageish_template_data.shortcuts = (&pageish_template_data.meta).into();
+        postlist_template_data.shortcuts = (&postlist_template_data.meta).into();
+        // The page-like data is what the builtin renderer falls back to, for post lists as well.
+        pageish_template_data.site_context =
+            SiteTemplateContext::new(&config.site_context, &PageLikePublicationTemplateData::KEYS);
+        if localscene.kind == "postlist" {
+            postlist_template_data.site_context = SiteTemplateContext::new(
+                &config.site_context,
+                &PostListPublicationTemplateData::KEYS,
+            );
+        }
 
+        render_stage("rendering template");
+        let nonce_placeholder = render_nonce_placeholder();
         let outerhtml: String = {
             let cwd: PathBuf = std::env::current_dir().unwrap();
             let template_path = cwd.join(
@@ -300,34 +887,11 @@ mod in_renderer {
             }
 
             // A fallback function that uses the builtin handlebars renderer.
-            let builtin_handlebars = |data| {
-                let mut template = Handlebars::new();
-                // streq helper
-                // This helper checks if two strings are equal.
-                // Usage: {{#if (streq postid "sasfs")}} ... {{/if}}
-                handlebars_helper!(streq: |x: str, y: str| x == y);
-                template.register_helper("streq", Box::new(streq));
-                match template.register_template_file("base", template_path.clone()) {
-                    Ok(g) => g,
-                    Err(e) => {
-                        error!(
-                            "Error reading template file '{}':\n\n{}",
-                            template_path.display(),
-                            e.to_string().color_bright_red()
-                        );
-                        return RenderrerResponse::Error;
-                    }
-                };
-                match template.render("base", &data) {
-                    Ok(a) => RenderrerResponse::Ok(a),
-                    Err(e) => {
-                        error!(
-                            "Error rendering template file '{}':\n\n{}",
-                            template_path.display(),
-                            e.to_string().color_bright_red()
-                        );
-                        RenderrerResponse::Error
-                    }
+            let builtin_handlebars = |data| match render_template_file(&template_path, data) {
+                Ok(a) => RenderrerResponse::Ok(a),
+                Err(e) => {
+                    error!("{}", e);
+                    RenderrerResponse::Error
                 }
             };
             let mut htmlbody: String = if !cfg!(feature = "js_runtime") {
@@ -339,6 +903,7 @@ mod in_renderer {
                     return RenderrerResponse::Error;
                 }
             } else if let crate::externalpluginservers::EPSResponseBody::OkString { value } = {
+                render_stage("rendering template through the plugin runtime");
                 if localscene.kind != *"postlist" {
                     crate::externalpluginservers::contact_eps(
                         server_context_mutex.clone(),
@@ -369,10 +934,30 @@ mod in_renderer {
                     return RenderrerResponse::Error;
                 }
             };
+            htmlbody = traced("template", htmlbody);
+            if config.images.enabled {
+                render_stage("optimising images");
+                htmlbody =
+                    crate::images::rewrite(&htmlbody, &config.images, &config.static_mounts).await;
+            }
+            render_stage("inlining assets");
+            let nonced = |fragment: String| {
+                if config.security.uses_nonce() {
+                    with_nonce_attribute(&fragment, &nonce_placeholder)
+                } else {
+                    fragment
+                }
+            };
             let version = env!("CARGO_PKG_VERSION");
             let mut head = String::new();
             head.push_str("\n\t<head>");
-            head.push_str("\n\t\t<meta charset=\"utf-8\" />");
+            head.push_str(&format!(
+                "\n\t\t<meta charset=\"{}\" />",
+                crate::config::charset(&config.charset)
+                    .unwrap_or(encoding_rs::UTF_8)
+                    .name()
+                    .to_ascii_lowercase()
+            ));
             head.push_str(
                 format!(
                     "\n\t\t<title>{}{}</title>",
@@ -387,6 +972,9 @@ mod in_renderer {
             head.push_str("\n\t\t<meta name=\"viewport\" content=\"width=device-width, initial-scale=1.0\" />");
             head.push_str("\n\t\t<meta name=\"generator\" content=\"strawmelonjuice-Cynthia\" />");
             head.push_str("\n\t\t<meta name=\"robots\" content=\"index, follow\" />");
+            if config.site.favicon.is_some() {
+                head.push_str("\n\t\t<link rel=\"icon\" href=\"/favicon.ico\" />");
+            }
             if let Some(stylefile) = localscene.stylesheet {
                 let path: PathBuf = std::env::current_dir()
                     .unwrap()
@@ -395,17 +983,17 @@ mod in_renderer {
                     .join("./cynthiaFiles/assets/".to_string() + stylefile.as_str());
                 if path.exists() {
                     let css = inlines::inline_css(path, server_context_mutex.clone()).await;
-                    head.push_str(&css);
+                    head.push_str(&nonced(css));
                 } else {
                     error!("Stylesheet file '{}' not found.", path.display());
                     return RenderrerResponse::Error;
                 }
             }
             head.push_str(
-&format!("<script>const cynthia = {{version: '{}', publicationdata: JSON.parse(`{}`), kind: '{}'}};</script>",
+&nonced(format!("<script>const cynthia = {{version: '{}', publicationdata: JSON.parse(`{}`), kind: '{}'}};</script>",
                 version,
     serde_json::to_string(&pageish_template_data.meta.clone()).unwrap(),
-                localscene.kind)
+                localscene.kind))
 
 
             );
@@ -416,8 +1004,21 @@ mod in_renderer {
                     .unwrap()
                     .join("./cynthiaFiles/assets/".to_string() + script.as_str());
                 if path.exists() {
-                    let d = inlines::inline_js(path, server_context_mutex.clone()).await;
-                    htmlbody.push_str(&d);
+                    if config.site.inline_scripts {
+                        let d = inlines::inline_js(path, server_context_mutex.clone()).await;
+                        htmlbody.push_str(&nonced(d));
+                    } else {
+                        // Served from `/cynthia/client.js` instead, the hash makes sure browsers
+                        // can hold on to it for as long as it doesn't change.
+                        let d = inlines::minified_js(path.clone(), server_context_mutex.clone())
+                            .await
+                            .unwrap_or_else(|| fs::read_to_string(&path).unwrap_or_default());
+                        htmlbody.push_str(&format!(
+                            "\n<script src=\"/cynthia/client.js?scene={}&v={}\"></script>",
+                            urlencoding::encode(&scene.name),
+                            crate::helpers::content_hash(d.as_bytes())
+                        ));
+                    }
                 } else {
                     error!("Script file '{}' not found.", path.display());
                     return RenderrerResponse::Error;
@@ -427,55 +1028,129 @@ mod in_renderer {
                 if let Some(author_name) = author.name {
                     head.push_str(&format!(
                         "\n\t\t<meta name=\"author\" content=\"{}\" />",
-                        author_name
+                        crate::helpers::escape_html(&author_name)
                     ));
                 }
             }
             if let Some(category) = pageish_template_data.meta.category {
                 head.push_str(&format!(
                     "\n\t\t<meta name=\"category\" content=\"{}\" />",
-                    category
+                    crate::helpers::escape_html(&category)
                 ));
             }
             if let Some(desc) = pageish_template_data.meta.desc {
                 head.push_str(&format!(
                     "\n\t\t<meta name=\"description\" content=\"{}\" />",
-                    desc
+                    crate::helpers::escape_html(&desc)
                 ));
             }
-            if let Some(thumbnail) = pageish_template_data.meta.thumbnail {
+            head.push_str(&format!(
+                "\n\t\t<meta property=\"og:url\" content=\"{}\" />",
+                crate::helpers::escape_html(&crate::helpers::page_url(
+                    &config.site.site_baseurl,
+                    &pageish_template_data.meta.id,
+                    &config.trailing_slash
+                ))
+            ));
+            if let Some(canonical) = canonical {
+                head.push_str(&canonical);
+            }
+            for alternate in alternates {
+                head.push_str(&alternate);
+            }
+            // Posts without a thumbnail of their own are shared with their author's.
+            if let Some(thumbnail) = pageish_template_data
+                .meta
+                .thumbnail
+                .or(pageish_template_data.meta.author_thumbnail)
+            {
                 head.push_str(&format!(
                     "\n\t\t<meta property=\"og:image\" content=\"{}\" />",
-                    thumbnail
+                    crate::helpers::escape_html(&thumbnail)
                 ));
             }
+            // Author input, trusted like the content itself. Its scripts get the nonce too.
+            if let Some(extra_head) = extra_head {
+                head.push_str("\n\t\t");
+                head.push_str(&nonced(extra_head));
+            }
             head.push_str("\n\t</head>");
-            let docurl = "https://github.com/strawmelonjuice/CynthiaWebsiteEngine";
-            format!(
-                "<!DOCTYPE html>\n<html>\n<!--\n\nGenerated and hosted through Cynthia v{version}, by Strawmelonjuice.\nAlso see:	<{docurl}>\n-->\n{head}\n<body>{htmlbody}</body></html>",
+            let head = traced("head", head);
+            html_document(&head, &htmlbody, config.site.generator_comment)
+        };
+        // The plugins' `modifyResponseHTML` runners get the whole page, a stage of its own. Comments
+        // can't go around it, nothing may come before the doctype.
+        let outerhtml = if cfg!(feature = "js_runtime")
+            && config
+                .plugins
+                .iter()
+                .any(|plugin| plugin.runs(&config.plugin_options))
+        {
+            render_stage("running output plugins");
+            match crate::externalpluginservers::contact_eps(
+                server_context_mutex.clone(),
+                EPSRequestBody::OutputRequest {
+                    html: outerhtml.clone(),
+                    meta: pageish_template_data.meta.clone(),
+                },
             )
+            .await
+            {
+                crate::externalpluginservers::EPSResponseBody::OkString { value } => value,
+                _ => outerhtml,
+            }
+        } else {
+            outerhtml
+        };
+        let outerhtml = if config.security.uses_nonce() {
+            placed_nonces(&outerhtml, &nonce_placeholder)
+        } else {
+            outerhtml
         };
 
         // content.unwrap().unwrap_html();
-        RenderrerResponse::Ok(outerhtml)
+        RenderrerResponse::Ok(outerhtml + &trace_summary())
     }
-    fn fetch_scene(publication: CynthiaPublication, config: CynthiaConfClone) -> Option<Scene> {
-        let scene = publication.get_scene_name();
-        match scene {
-            Some(s) => {
-                let fetched_scene = config.scenes.get_by_name(s.as_str());
-                if fetched_scene.is_none() {
-                    error!("Scene \"{}\" not found in the configuration file.", s);
-                    None
-                } else {
-                    fetched_scene
-                }
-            }
+    /// The `<link rel="canonical">` for a publication: its own `canonical`, or else the URL of its id, the
+    /// site root for the home page. Error pages, category and tag listings, and sites without a base URL
+    /// get none.
+    fn canonical_link(
+        publication: &CynthiaPublication,
+        config: &CynthiaConfClone,
+    ) -> Option<String> {
+        let href = match publication.get_canonical() {
+            Some(href) => href,
             None => {
-                let fetched_scene = config.scenes.get_default();
-                Some(fetched_scene)
+                let id = publication.get_id();
+                if config.site.site_baseurl.is_empty()
+                    || id == config.site.notfound_page
+                    || id.contains(':')
+                {
+                    return None;
+                }
+                let id = if config.home.as_ref() == Some(&id) {
+                    String::from("root")
+                } else {
+                    id
+                };
+                crate::helpers::page_url(&config.site.site_baseurl, &id, &config.trailing_slash)
             }
+        };
+        Some(format!(
+            "\n\t\t<link rel=\"canonical\" href=\"{}\" />",
+            crate::helpers::escape_html(&href)
+        ))
+    }
+
+    fn fetch_scene(publication: CynthiaPublication, config: CynthiaConfClone) -> Option<Scene> {
+        let scene = config.scenes.for_publication(&publication, &config.site);
+        if scene.is_none() {
+            error!(
+                "Scene for publication \"{}\" not found in the configuration file.",
+                publication.get_id()
+            );
         }
+        scene
     }
 
     #[derive(Debug)]
@@ -505,13 +1180,112 @@ mod in_renderer {
         inner: String,
         target_type: ContentType,
     }
+    /// The content of a page or post, rendered to HTML. Post lists have none of their own.
+    pub(super) async fn publication_content(
+        publication: &CynthiaPublication,
+        server_context_mutex: Data<Arc<Mutex<ServerContext>>>,
+    ) -> Option<String> {
+        let content = match publication {
+            CynthiaPublication::Page { pagecontent, .. } => pagecontent.clone(),
+            CynthiaPublication::Post { postcontent, .. } => postcontent.clone(),
+            CynthiaPublication::PostList { .. } => return None,
+        };
+        match fetch_page_ish_content(content, server_context_mutex)
+            .await
+            .unwrap_html()
+        {
+            RenderrerResponse::Ok(html) => Some(html),
+            _ => None,
+        }
+    }
+
     #[doc = "Fetches the content of a pageish (a post or a page) publication."]
-    async fn fetch_page_ish_content(content: PublicationContent) -> FetchedContent {
+    async fn fetch_page_ish_content(
+        content: PublicationContent,
+        server_context_mutex: Data<Arc<Mutex<ServerContext>>>,
+    ) -> FetchedContent {
+        let Some(content_output) =
+            fetch_content_source(content, server_context_mutex.clone()).await
+        else {
+            return FetchedContent::Error;
+        };
+        let (emoji, sanitize) = server_context_mutex
+            .lock_callback(|a| (a.config.site.emoji, a.config.sanitize.clone()))
+            .await;
+        let sanitized = |html: String| {
+            if sanitize.enabled {
+                crate::sanitize::clean(&html, &sanitize)
+            } else {
+                html
+            }
+        };
+        // Before the shortcodes are put back, what they expand to is left alone.
+        let with_emoji = |html: String| {
+            if emoji {
+                crate::emoji::replace(&html)
+            } else {
+                html
+            }
+        };
+        let contenttype = match content_output.target_type {
+            Html(_) => {
+                let shortcoded = crate::shortcodes::preprocess(
+                    &content_output.inner,
+                    false,
+                    server_context_mutex,
+                )
+                .await;
+                let html = with_emoji(shortcoded.content.clone());
+                Html(shortcoded.restore_sanitized(html, &sanitize))
+            }
+            ContentType::Markdown(_) => {
+                let shortcoded = crate::shortcodes::preprocess(
+                    &content_output.inner,
+                    true,
+                    server_context_mutex,
+                )
+                .await;
+                render_stage("rendering markdown");
+                let html = match markdown::to_html_with_options(
+                    shortcoded.content.as_str(),
+                    &markdown::Options::gfm(),
+                ) {
+                    Ok(html) => html,
+                    Err(_) => {
+                        error!("An error occurred while rendering the markdown.");
+                        return FetchedContent::Error;
+                    }
+                };
+                Html(shortcoded.restore_sanitized(with_emoji(html), &sanitize))
+            }
+            ContentType::PlainText(_) => Html(sanitized(
+                "<pre>".to_owned() + content_output.inner.as_str() + "</pre>",
+            )),
+        };
+
+        FetchedContent::Ok(contenttype)
+    }
+
+    /// The content of a publication as its author wrote it, before it's rendered to HTML.
+    async fn fetch_content_source(
+        content: PublicationContent,
+        server_context_mutex: Data<Arc<Mutex<ServerContext>>>,
+    ) -> Option<ContentSource> {
         let content_output = match content {
             PublicationContent::Inline(c) => ContentSource {
                 inner: c.get_inner(),
                 target_type: c,
             },
+            PublicationContent::Base64(c) => match c.decode_base64() {
+                Ok(inner) => ContentSource {
+                    inner,
+                    target_type: c,
+                },
+                Err(e) => {
+                    error!("Could not decode base64 content: {e}");
+                    return None;
+                }
+            },
             PublicationContent::External { source } => {
                 let a = reqwest::get(source.get_inner()).await;
                 let output = match a {
@@ -522,7 +1296,7 @@ mod in_renderer {
                                 "Could not fetch external content from {}\n\n{e}",
                                 source.get_inner()
                             );
-                            return FetchedContent::Error;
+                            return None;
                         }
                     },
                     Err(e) => {
@@ -530,7 +1304,7 @@ mod in_renderer {
                             "Could not fetch external content from {}\n\n{e}",
                             source.get_inner()
                         );
-                        return FetchedContent::Error;
+                        return None;
                     }
                 };
                 ContentSource {
@@ -538,21 +1312,43 @@ mod in_renderer {
                     target_type: source,
                 }
             }
-            PublicationContent::Local { source } => {
+            PublicationContent::Local { source, encoding } => {
                 let output = {
                     let mut v = String::from("./cynthiaFiles/publications/");
                     v.push_str(&source.get_inner());
                     if Path::new(v.as_str()).exists() {
-                        match fs::read_to_string(v.clone()) {
+                        match crate::publications::read_content_file(
+                            Path::new(&v),
+                            encoding.as_deref(),
+                        ) {
                             Ok(t) => t,
                             Err(e) => {
-                                error!("Could not read local content at {}\n\n{e}", v);
-                                return FetchedContent::Error;
+                                error!("Could not read local content: {e}");
+                                return None;
                             }
                         }
                     } else {
-                        error!("Could not find local content at {}", v);
-                        return FetchedContent::Error;
+                        let placeholder = server_context_mutex
+                            .lock_callback(|a| {
+                                a.config.missing_content.placeholder_for(&source).cloned()
+                            })
+                            .await;
+                        let Some(placeholder) = placeholder else {
+                            error!("Could not find local content at {}", v);
+                            return None;
+                        };
+                        warn!(
+                            "Could not find local content at {}, showing placeholder `{}` instead.",
+                            v, placeholder
+                        );
+                        let p = format!("./cynthiaFiles/publications/{}", placeholder);
+                        match crate::publications::read_content_file(Path::new(&p), None) {
+                            Ok(t) => t,
+                            Err(e) => {
+                                error!("Could not read placeholder content: {e}");
+                                return None;
+                            }
+                        }
                     }
                 };
                 ContentSource {
@@ -561,31 +1357,228 @@ mod in_renderer {
                 }
             }
         };
-        let contenttype = match content_output.target_type {
-            Html(_) => Html(content_output.inner),
-            ContentType::Markdown(_) => {
-                let html = match markdown::to_html_with_options(
-                    content_output.inner.as_str(),
-                    &markdown::Options::gfm(),
-                ) {
-                    Ok(html) => html,
-                    Err(_) => {
-                        error!("An error occurred while rendering the markdown.");
-                        return FetchedContent::Error;
-                    }
-                };
-                Html(html)
-            }
-            ContentType::PlainText(_) => {
-                Html("<pre>".to_owned() + content_output.inner.as_str() + "</pre>")
-            }
+        Some(content_output)
+    }
+
+    /// Puts together the final document, with the comment crediting Cynthia unless it's been turned off.
+    fn html_document(head: &str, htmlbody: &str, generator_comment: bool) -> String {
+        let comment = if generator_comment {
+            let version = env!("CARGO_PKG_VERSION");
+            let docurl = "https://github.com/strawmelonjuice/CynthiaWebsiteEngine";
+            format!("<!--\n\nGenerated and hosted through Cynthia v{version}, by Strawmelonjuice.\nAlso see:	<{docurl}>\n-->\n")
+        } else {
+            String::new()
         };
+        format!("<!DOCTYPE html>\n<html>\n{comment}{head}\n<body>{htmlbody}</body></html>")
+    }
 
-        FetchedContent::Ok(contenttype)
+    /// Renders a template file with the builtin handlebars renderer. Errors name the template and
+    /// carry handlebars' own explanation.
+    pub(super) fn render_template_file<T: Serialize>(
+        template_path: &Path,
+        data: T,
+    ) -> Result<String, String> {
+        let mut template = Handlebars::new();
+        // streq helper
+        // This helper checks if two strings are equal.
+        // Usage: {{#if (streq postid "sasfs")}} ... {{/if}}
+        handlebars_helper!(streq: |x: str, y: str| x == y);
+        template.register_helper("streq", Box::new(streq));
+        template
+            .register_template_file("base", template_path)
+            .map_err(|e| {
+                format!(
+                    "Could not parse template '{}': {}",
+                    template_path.display(),
+                    e
+                )
+            })?;
+        template.render("base", &data).map_err(|e| {
+            format!(
+                "Could not render template '{}': {}",
+                template_path.display(),
+                e
+            )
+        })
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use serde_json::json;
+
+        #[test]
+        fn pages_link_their_canonical_url() {
+            let mut config = crate::config::CynthiaConf::default();
+            config.site.site_baseurl = String::from("https://example.com");
+            config.home = Some(String::from("latest"));
+            let config = config.clone();
+            let publication =
+                |fields: serde_json::Value| crate::publications::tests::publication("page", fields);
+            assert_eq!(
+                canonical_link(&publication(json!({"id": "about"})), &config).unwrap(),
+                "\n\t\t<link rel=\"canonical\" href=\"https://example.com/about\" />"
+            );
+            assert!(
+                canonical_link(&publication(json!({"id": "latest"})), &config)
+                    .unwrap()
+                    .contains("href=\"https://example.com/\"")
+            );
+            assert!(canonical_link(
+                &publication(json!({"id": "about-us", "canonical": "https://example.org/about"})),
+                &config
+            )
+            .unwrap()
+            .contains("href=\"https://example.org/about\""));
+            // The URL the page is routed at, encoded.
+            assert!(
+                canonical_link(&publication(json!({"id": "Über uns"})), &config)
+                    .unwrap()
+                    .contains("href=\"https://example.com/%C3%BCber-uns\"")
+            );
+            // Not for the not-found page.
+            assert!(canonical_link(&publication(json!({"id": "404"})), &config).is_none());
+        }
+
+        #[test]
+        fn broken_template_is_an_error() {
+            let template_path = std::env::temp_dir().join("cynthia-broken-template.hbs");
+            fs::write(&template_path, "<main>{{#if content}}{{content}}</main>").unwrap();
+            let error =
+                render_template_file(&template_path, PageLikePublicationTemplateData::default())
+                    .unwrap_err();
+            fs::remove_file(&template_path).unwrap();
+            assert!(error.starts_with("Could not parse template '"));
+            assert!(error.contains("cynthia-broken-template.hbs"));
+            assert!(
+                !error.contains('\x1b'),
+                "log messages shouldn't carry colours"
+            );
+        }
+
+        #[test]
+        fn metadata_at_the_top_level() {
+            let template_path = std::env::temp_dir().join("cynthia-shortcuts-template.hbs");
+            fs::write(
+                &template_path,
+                "<h1>{{title}}</h1><p>{{author.name}}</p><p>{{meta.title}}</p>",
+            )
+            .unwrap();
+            let mut data = PageLikePublicationTemplateData::default();
+            data.meta.title = String::from("Hello");
+            data.meta.author = Some(crate::publications::Author {
+                name: Some(String::from("Mar")),
+                thumbnail: None,
+                link: None,
+            });
+            data.shortcuts = (&data.meta).into();
+            let html = render_template_file(&template_path, data).unwrap();
+            fs::remove_file(&template_path).unwrap();
+            assert_eq!(html, "<h1>Hello</h1><p>Mar</p><p>Hello</p>");
+        }
+
+        #[test]
+        fn site_context_in_templates() {
+            let template_path = std::env::temp_dir().join("cynthia-site-context-template.hbs");
+            fs::write(
+                &template_path,
+                "<h1>{{title}}</h1><p>{{site.title}}</p><p>{{description}}</p><p>{{site.description}}</p>",
+            )
+            .unwrap();
+            let mut data = PageLikePublicationTemplateData::default();
+            data.meta.title = String::from("About");
+            data.shortcuts = (&data.meta).into();
+            let context = HashMap::from([
+                (String::from("title"), String::from("My site")),
+                (String::from("description"), String::from("Things I made")),
+            ]);
+            data.site_context =
+                SiteTemplateContext::new(&context, &PageLikePublicationTemplateData::KEYS);
+            let html = render_template_file(&template_path, data).unwrap();
+            fs::remove_file(&template_path).unwrap();
+            // The page's own title wins at the top level.
+            assert_eq!(
+                html,
+                "<h1>About</h1><p>My site</p><p>Things I made</p><p>Things I made</p>"
+            );
+        }
+
+        #[test]
+        fn template_data_keys_are_listed() {
+            let keys = |data: serde_json::Value| {
+                let mut keys: Vec<String> = data.as_object().unwrap().keys().cloned().collect();
+                keys.sort();
+                keys
+            };
+            let listed = |listed: &[&str]| {
+                let mut listed: Vec<String> = listed.iter().map(|key| key.to_string()).collect();
+                listed.push(String::from("site"));
+                listed.sort();
+                listed
+            };
+            assert_eq!(
+                keys(serde_json::to_value(PageLikePublicationTemplateData::default()).unwrap()),
+                listed(&PageLikePublicationTemplateData::KEYS)
+            );
+            assert_eq!(
+                keys(serde_json::to_value(PostListPublicationTemplateData::default()).unwrap()),
+                listed(&PostListPublicationTemplateData::KEYS)
+            );
+        }
+
+        #[test]
+        fn generator_comment() {
+            let with = html_document("<head></head>", "", true);
+            assert!(with.contains("Generated and hosted through Cynthia"));
+            let without = html_document("<head></head>", "", false);
+            assert!(!without.contains("<!--"));
+            assert_eq!(
+                without,
+                "<!DOCTYPE html>\n<html>\n<head></head>\n<body></body></html>"
+            );
+        }
+
+        #[test]
+        fn traces_only_when_asked() {
+            assert_eq!(traced("content", String::from("<p>Hi</p>")), "<p>Hi</p>");
+            assert_eq!(trace_summary(), "");
+            RENDER_TRACE.sync_scope(Arc::default(), || {
+                render_stage("fetching content");
+                render_stage("rendering template");
+                assert_eq!(
+                    traced("content", String::from("<p>Hi</p>")),
+                    "<!-- cynthia-trace: content --><p>Hi</p><!-- /cynthia-trace: content -->"
+                );
+                let summary = trace_summary();
+                assert!(summary.starts_with("\n<!-- cynthia-trace: rendered in "));
+                assert!(summary.contains("\n\tfetching content: "));
+                assert!(summary.contains("\n\trendering template: "));
+                assert!(summary.ends_with("\n-->\n"));
+            });
+        }
+
+        #[test]
+        fn nonces_only_where_cynthia_put_them() {
+            let placeholder = render_nonce_placeholder();
+            let document = format!(
+                "{}<p>{}</p><script nonce=\"{}\">steal()</script><i>CYNTHIANONCEPLACE{}HOLDER</i>",
+                with_nonce_attribute("<script>let a;</script>", &placeholder),
+                NONCE_PLACEHOLDER,
+                NONCE_PLACEHOLDER,
+                NONCE_PLACEHOLDER
+            );
+            assert_eq!(
+                placed_nonces(&document, &placeholder),
+                format!(
+                    "<script nonce=\"{}\">let a;</script><p></p><script nonce=\"\">steal()</script><i></i>",
+                    NONCE_PLACEHOLDER
+                )
+            );
+        }
     }
 }
 #[cfg(feature = "js_runtime")]
-mod inlines {
+pub(crate) mod inlines {
     use crate::tell::CynthiaColors;
     use crate::{LockCallback, ServerContext};
     use actix_web::web::Data;
@@ -599,6 +1592,31 @@ mod inlines {
         scriptfile: PathBuf,
         server_context_mutex: Data<Arc<Mutex<ServerContext>>>,
     ) -> String {
+        if crate::dev_mode() {
+            let file_content = fs::read_to_string(scriptfile).unwrap_or_default();
+            return format!(
+                "<script>\n// Not minified in development mode.\n\n{}</script>",
+                file_content
+            );
+        }
+        if let Some(d) = minified_js(scriptfile.clone(), server_context_mutex).await {
+            return format!(
+                "<script>\n\r// Minified internally by Cynthia using Terser\n\n{d}\n\n\r// Cached after minifying, so might be somewhat behind.\n\r</script>");
+        }
+        warn!("Scriptfile could not be minified, so was instead inlined 1:1.");
+        //     If we got here, we couldn't minify the JS.
+        let file_content = fs::read_to_string(scriptfile).unwrap_or_default();
+        format!("<script>\n// Scriptfile could not be minified, so was instead inlined 1:1. \n\n{}</script>", file_content)
+    }
+
+    /// Minifies a scriptfile using Terser, returns `None` if that didn't work out.
+    pub(crate) async fn minified_js(
+        scriptfile: PathBuf,
+        server_context_mutex: Data<Arc<Mutex<ServerContext>>>,
+    ) -> Option<String> {
+        if crate::dev_mode() {
+            return None;
+        }
         let config_clone = server_context_mutex
             .lock_callback(|a| {
                 a.request_count += 1;
@@ -617,96 +1635,93 @@ mod inlines {
         let cache_result = server_context_mutex
             .lock_callback(|servercontext| servercontext.get_cache(&embed_id, jscachelifetime))
             .await;
-        match cache_result {
-            Some(o) => {
-                let d = std::str::from_utf8(&o.0).unwrap().to_string();
-                return format!(
-                    "<script>\n\r// Minified internally by Cynthia using Terser\n\n{d}\n\n\r// Cached after minifying, so might be somewhat behind.\n\r</script>");
-            }
-            None => {
-                info!("Minifying JS file '{}'...", scriptfile.display());
-                let xargs: Vec<&str>;
-                let scri = scriptfile.clone();
-                let scr = scri.to_str().unwrap();
-                let runner = {
-                    if config_clone.runtimes.ext_js_rt.as_str().contains("bun") {
-                        xargs = [
-                            "terser",
-                            scr,
-                            "--compress",
-                            "--keep-fnames",
-                            "--keep-classnames",
-                        ]
-                        .to_vec();
+        if let Some(o) = cache_result {
+            return Some(std::str::from_utf8(&o.0).unwrap().to_string());
+        }
+        info!("Minifying JS file '{}'...", scriptfile.display());
+        let xargs: Vec<&str>;
+        let scri = scriptfile.clone();
+        let scr = scri.to_str().unwrap();
+        let runner = {
+            if config_clone.runtimes.ext_js_rt.as_str().contains("bun") {
+                xargs = [
+                    "terser",
+                    scr,
+                    "--compress",
+                    "--keep-fnames",
+                    "--keep-classnames",
+                ]
+                .to_vec();
 
-                        "bunx"
-                    } else {
-                        xargs = [
-                            "--yes",
-                            "terser",
-                            scr,
-                            "--compress",
-                            "--keep-fnames",
-                            "--keep-classnames",
-                        ]
-                        .to_vec();
+                "bunx"
+            } else {
+                xargs = [
+                    "--yes",
+                    "terser",
+                    scr,
+                    "--compress",
+                    "--keep-fnames",
+                    "--keep-classnames",
+                ]
+                .to_vec();
 
-                        "npx"
-                    }
-                };
+                "npx"
+            }
+        };
 
-                debug!("Running Terser in {}", runner.color_purple());
-                match std::process::Command::new(runner)
-                    .args(xargs.clone())
-                    .output()
-                {
-                    Ok(output) => {
-                        if output.status.success() {
-                            let d = format!("{}", String::from_utf8_lossy(&output.stdout));
-                            {
-                                let mut server_context = server_context_mutex.lock().await;
-                                server_context
-                                    .store_cache_async(&embed_id, d.as_bytes(), jscachelifetime)
-                                    .await
-                                    .unwrap();
-                            };
-                            return format!(
-                                "<script>\n\r// Minified internally by Cynthia using Terser\n\n{d}\n\n\r// Cached after minifying, so might be somewhat behind.\n\r</script>");
-                        } else {
-                            warn!(
-                                "Failed running Terser in {}, couldn't minify to embed JS.",
-                                config_clone.runtimes.ext_js_rt.as_str().color_purple()
-                            );
-                            println!("Ran command \"{} {}\"", runner.color_purple(), {
-                                let mut s = String::new();
-                                for a in &xargs {
-                                    s.push_str(a);
-                                    s.push(' ');
-                                }
-                                s
-                            })
+        debug!("Running Terser in {}", runner.color_purple());
+        match std::process::Command::new(runner)
+            .args(xargs.clone())
+            .output()
+        {
+            Ok(output) => {
+                if output.status.success() {
+                    let d = format!("{}", String::from_utf8_lossy(&output.stdout));
+                    {
+                        let mut server_context = server_context_mutex.lock().await;
+                        server_context
+                            .store_cache_async(&embed_id, d.as_bytes(), jscachelifetime)
+                            .await
+                            .unwrap();
+                    };
+                    return Some(d);
+                } else {
+                    warn!(
+                        "Failed running Terser in {}, couldn't minify to embed JS.",
+                        config_clone.runtimes.ext_js_rt.as_str().color_purple()
+                    );
+                    println!("Ran command \"{} {}\"", runner.color_purple(), {
+                        let mut s = String::new();
+                        for a in &xargs {
+                            s.push_str(a);
+                            s.push(' ');
                         }
-                    }
-                    Err(why) => {
-                        error!(
-                            "Failed running CleanCSS in {}, couldn't minify to embed JS: {}",
-                            config_clone.runtimes.ext_js_rt.as_str().color_purple(),
-                            why
-                        );
-                    }
+                        s
+                    })
                 }
             }
-        };
-        warn!("Scriptfile could not be minified, so was instead inlined 1:1.");
-        //     If we got here, we couldn't minify the JS.
-        let file_content = fs::read_to_string(scriptfile).unwrap_or_default();
-        format!("<script>\n// Scriptfile could not be minified, so was instead inlined 1:1. \n\n{}</script>", file_content)
+            Err(why) => {
+                error!(
+                    "Failed running CleanCSS in {}, couldn't minify to embed JS: {}",
+                    config_clone.runtimes.ext_js_rt.as_str().color_purple(),
+                    why
+                );
+            }
+        }
+        None
     }
 
     pub(crate) async fn inline_css(
         stylefile: PathBuf,
         server_context_mutex: Data<Arc<Mutex<ServerContext>>>,
     ) -> String {
+        if crate::dev_mode() {
+            let file_content = fs::read_to_string(stylefile).unwrap_or_default();
+            return format!(
+                "<style>\n/* Not minified in development mode. */\n\n{}</style>",
+                file_content
+            );
+        }
         let config_clone = server_context_mutex
             .lock_callback(|a| {
                 a.request_count += 1;
@@ -794,7 +1809,7 @@ mod inlines {
 }
 
 #[cfg(not(feature = "js_runtime"))]
-mod inlines {
+pub(crate) mod inlines {
     pub(crate) async fn inline_js(
         scriptfile: PathBuf,
         _server_context_mutex: Data<Arc<Mutex<ServerContext>>>,
@@ -802,6 +1817,12 @@ mod inlines {
         let file_content = fs::read_to_string(scriptfile).unwrap_or(String::new());
         format!("<script>{}</script>", file_content)
     }
+    pub(crate) async fn minified_js(
+        _scriptfile: PathBuf,
+        _server_context_mutex: Data<Arc<Mutex<ServerContext>>>,
+    ) -> Option<String> {
+        None
+    }
     pub(crate) async fn inline_css(
         stylefile: PathBuf,
         _server_context_mutex: Data<Arc<Mutex<ServerContext>>>,
@@ -1106,3 +2127,67 @@ pub(crate) mod json_html {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::publications::tests::publication;
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn checks_tell_the_content_type() {
+        let published: CynthiaPublicationList = vec![
+            publication("page", json!({"id": "root"})),
+            publication("page", json!({"id": "404"})),
+            publication(
+                "page",
+                json!({"id": "manifest.json", "content_type": "application/manifest+json"}),
+            ),
+        ];
+        let mut server_context = ServerContext {
+            config: Arc::new(crate::config::CynthiaConf::default()),
+            cache: vec![],
+            published: None,
+            request_count: 0,
+            start_time: 0,
+            visits: Arc::default(),
+            #[cfg(feature = "js_runtime")]
+            external_plugin_server: crate::externalpluginservers::EPSCommunicationData::new(
+                tokio::sync::mpsc::channel(1).0,
+            ),
+        };
+        server_context.store_published(Arc::new(published));
+        let server_context_mutex = Data::new(Arc::new(Mutex::new(server_context)));
+        let check = |pgid: &str| check_pgid(pgid.to_string(), server_context_mutex.clone());
+        assert!(matches!(check("").await, PGIDCheckResponse::Ok(None)));
+        assert!(matches!(
+            check("manifest.json").await,
+            PGIDCheckResponse::Ok(Some(content_type)) if content_type == "application/manifest+json"
+        ));
+        assert!(matches!(
+            check("missing").await,
+            PGIDCheckResponse::NotFound
+        ));
+    }
+
+    #[test]
+    fn prerendered_file_names() {
+        let page = |id: &str| publication("page", json!({"id": id}));
+        let root = page("root");
+        assert_eq!(prerendered_file(&root, true).as_deref(), Some("index.html"));
+        assert_eq!(prerendered_file(&root, false).as_deref(), Some("root.html"));
+        assert_eq!(
+            prerendered_file(&page("blog/first-post"), false).as_deref(),
+            Some("blog/first-post.html")
+        );
+        let manifest = publication(
+            "page",
+            json!({"id": "manifest.json", "content_type": "application/manifest+json"}),
+        );
+        assert_eq!(
+            prerendered_file(&manifest, false).as_deref(),
+            Some("manifest.json")
+        );
+        assert_eq!(prerendered_file(&page("../secrets"), false), None);
+    }
+}
@@ -0,0 +1,101 @@
+/*
+ * Copyright (c) 2024, MLC 'Strawmelonjuice' Bloeiman
+ *
+ * Licensed under the GNU AFFERO GENERAL PUBLIC LICENSE Version 3, see the LICENSE file for more information.
+ */
+//! Gives every request an id, sent back as `X-Request-Id` and put in front of every line logged while
+//! handling it, so the lines of concurrent requests can be told apart.
+use std::future::Future;
+
+use actix_web::http::header::HeaderMap;
+use log::{Log, Metadata, Record};
+use tokio::task::futures::TaskLocalFuture;
+
+pub(crate) const HEADER: &str = "x-request-id";
+
+tokio::task_local! {
+    static REQUEST_ID: String;
+}
+
+/// The id of the request being handled, if any.
+pub(crate) fn current() -> Option<String> {
+    REQUEST_ID.try_with(|id| id.clone()).ok()
+}
+
+/// `message` with the id of the current request in front, for lines that don't go through the logger.
+pub(crate) fn tag(message: &str) -> String {
+    match current() {
+        Some(id) => format!("[{}] {}", id, message),
+        None => message.to_string(),
+    }
+}
+
+/// The id a proxy or client sent along, or a new one. Incoming ids end up in the logs, so only
+/// short ones made of letters, digits, `-`, `_`, `.` and `:` are taken.
+pub(crate) fn for_request(headers: &HeaderMap) -> String {
+    headers
+        .get(HEADER)
+        .and_then(|v| v.to_str().ok())
+        .filter(|id| {
+            (1..=128).contains(&id.len())
+                && id
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | ':'))
+        })
+        .map(str::to_string)
+        .unwrap_or_else(|| format!("{:016x}", rand::random::<u64>()))
+}
+
+/// Runs `f`, and then the future it returns, as part of the request with this id.
+pub(crate) fn scope<F: Future>(id: String, f: impl FnOnce() -> F) -> TaskLocalFuture<String, F> {
+    let future = REQUEST_ID.sync_scope(id.clone(), f);
+    REQUEST_ID.scope(id, future)
+}
+
+/// Wraps the actual logger, adding the id of the current request to each record.
+pub(crate) struct TaggingLogger(pub(crate) Box<dyn Log>);
+
+impl Log for TaggingLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.0.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        match current() {
+            Some(id) => self.0.log(
+                &Record::builder()
+                    .args(format_args!("[{}] {}", id, record.args()))
+                    .metadata(record.metadata().clone())
+                    .module_path(record.module_path())
+                    .file(record.file())
+                    .line(record.line())
+                    .build(),
+            ),
+            None => self.0.log(record),
+        }
+    }
+
+    fn flush(&self) {
+        self.0.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn takes_safe_incoming_ids() {
+        let with = |id: &str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(HEADER.parse().unwrap(), id.parse().unwrap());
+            for_request(&headers)
+        };
+        assert_eq!(with("abc-123_x.y:z"), "abc-123_x.y:z");
+        assert_ne!(with("two words"), "two words");
+        assert_ne!(with(&"a".repeat(129)), "a".repeat(129));
+        let new = for_request(&HeaderMap::new());
+        assert_eq!(new.len(), 16);
+        assert_ne!(new, for_request(&HeaderMap::new()));
+    }
+}
@@ -5,22 +5,50 @@ use crate::publications::CynthiaPublication;
  * Licensed under the GNU AFFERO GENERAL PUBLIC LICENSE Version 3, see the LICENSE file for more information.
  */
 use crate::tell::CynthiaColors;
+use actix_web::body::{EitherBody, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header::HttpDate;
+use actix_web::http::StatusCode;
+use actix_web::middleware::Next;
 use actix_web::web::Data;
-use actix_web::{get, post, HttpRequest, HttpResponse, Responder};
-use log::{debug, trace, warn};
+use actix_web::{
+    get, post, web, HttpRequest, HttpResponse, HttpResponseBuilder, Responder, ResponseError,
+};
+use futures::StreamExt;
+use log::{debug, error, trace, warn};
+use std::collections::HashMap;
 use std::path::PathBuf;
 use std::sync::Arc;
+use std::time::SystemTime;
 use tokio::sync::Mutex;
 
+use crate::api::ApiError;
 use crate::cache::CynthiaCacheExtraction;
-use crate::config::CynthiaConfig;
+use crate::config::{CynthiaConfClone, SceneCollectionTrait, TrailingSlash, WellKnown};
 use crate::externalpluginservers::{contact_eps, EPSRequestBody};
 use crate::renders::render_from_pgid;
 use crate::LockCallback;
 use crate::{renders, ServerContext};
 
+/// Paths Cynthia answers itself, which plugin routes can't take over. Sent to the plugin runtime along
+/// with the static mounts, see `externalpluginservers::runtime_config`.
+pub(crate) const RESERVED_PATHS: [&str; 11] = [
+    "/api",
+    "/assets",
+    "/cynthia",
+    "/.well-known",
+    "/healthz",
+    "/favicon.ico",
+    "/category",
+    "/c",
+    "/cat",
+    "/tag",
+    "/t",
+];
+
 fn urlspace() -> (usize, usize) {
-    let fullwidth = termsize::get().unwrap().cols as usize;
+    // Without a terminal (under a service manager, or in tests) there's no width to go by.
+    let fullwidth = termsize::get().map_or(80, |size| size.cols as usize);
 
     let w_a = if fullwidth < 210 {
         fullwidth
@@ -41,8 +69,533 @@ fn urlspace() -> (usize, usize) {
     // (53, 55)
 }
 
+/// The `well_known` entry for a path under `/.well-known/`, with the rest of the path when the entry is a
+/// directory. The longest matching name wins.
+fn well_known_entry<'a>(
+    well_known: &'a HashMap<String, WellKnown>,
+    name: &'a str,
+) -> Option<(&'a WellKnown, Option<&'a str>)> {
+    well_known
+        .iter()
+        .filter_map(|(key, entry)| {
+            let key = key.trim_matches('/');
+            if name == key {
+                return Some((key.len(), entry, None));
+            }
+            let rest = name.strip_prefix(key)?.strip_prefix('/')?;
+            // Only a directory has files below it, and only real ones.
+            (entry.file.is_some()
+                && rest
+                    .split('/')
+                    .all(|part| !part.is_empty() && part != "." && part != ".."))
+            .then_some((key.len(), entry, Some(rest)))
+        })
+        .max_by_key(|(length, _, _)| *length)
+        .map(|(_, entry, rest)| (entry, rest))
+}
+
+/// Answers `/.well-known/` requests from the `well_known` configuration, before anything else gets to them.
+/// Returns `None` for paths it doesn't list, which can still be publications.
+fn well_known(req: &HttpRequest, config: &CynthiaConfClone) -> Option<HttpResponse> {
+    let name = req.path().strip_prefix("/.well-known/")?;
+    let (entry, rest) = well_known_entry(&config.well_known, name)?;
+    let (body, guessed_type) = match (&entry.content, &entry.file) {
+        (Some(content), _) => (Ok(content.clone().into_bytes()), None),
+        (None, Some(file)) => {
+            let path = match rest {
+                Some(rest) => std::path::Path::new(file).join(rest),
+                None => PathBuf::from(file),
+            };
+            let guessed_type = path
+                .extension()
+                .and_then(|e| e.to_str())
+                .map(|e| actix_files::file_extension_to_mime(e).to_string());
+            (std::fs::read(&path), guessed_type)
+        }
+        (None, None) => return None,
+    };
+    let (w_s, w_a) = urlspace();
+    let coninfo = req.connection_info();
+    let ip = coninfo.realip_remote_addr().unwrap_or("<unknown IP>");
+    let Ok(body) = body else {
+        warn!(
+            "{}\t{:>w_s$.w_a$}\t\t\t{}\t{}",
+            "GET:404".color_error_red(),
+            req.uri().to_string(),
+            ip.color_lightblue(),
+            "well-known file missing".color_red()
+        );
+        return Some(HttpResponse::NotFound().body("Not found."));
+    };
+    config.tell(format!(
+        "{}\t{:>w_s$.w_a$}\t\t\t{}\t{}",
+        "GET:200".color_ok_green(),
+        req.uri().to_string(),
+        ip.color_lightblue(),
+        "well-known".color_lime()
+    ));
+    let content_type = entry
+        .content_type
+        .clone()
+        .or(guessed_type)
+        .unwrap_or_else(|| String::from("text/plain; charset=utf-8"));
+    Some(
+        HttpResponse::Ok()
+            .append_header(("Content-Type", content_type))
+            .body(body),
+    )
+}
+
+/// Redirects page URLs to their canonical form, as set by `trailing_slash`.
+/// Returns `None` when the URL already is canonical.
+fn trailing_slash_redirect(req: &HttpRequest, policy: TrailingSlash) -> Option<HttpResponse> {
+    if policy == TrailingSlash::Keep {
+        return None;
+    }
+    // Repeated leading slashes count as one: as a `Location`, `//example.com` leads to another site.
+    let path = format!("/{}", req.path().trim_start_matches('/'));
+    let canonical = match policy {
+        _ if path == "/" => path,
+        TrailingSlash::Add if !path.ends_with('/') => format!("{}/", path),
+        TrailingSlash::Remove if path.ends_with('/') => path.trim_end_matches('/').to_string(),
+        _ => path,
+    };
+    if canonical == req.path() {
+        return None;
+    }
+    let location = match req.query_string() {
+        "" => canonical,
+        q => format!("{}?{}", canonical, q),
+    };
+    Some(
+        HttpResponse::MovedPermanently()
+            .append_header(("Location", location))
+            .finish(),
+    )
+}
+
+/// Why `cached_render` has no page to serve.
+enum RenderFailure {
+    /// The render ran past `render_timeout`, in this stage.
+    TimedOut(&'static str),
+    /// The render went wrong, the reason has already been logged.
+    /// In development mode, this holds what was logged while rendering.
+    Failed(Vec<String>),
+}
+
+/// Takes a page from the cache, or renders (and caches) it within the render deadline.
+/// Returns the page and whether it came from the cache.
+async fn cached_render(
+    page_id: &str,
+    server_context_mutex: Data<Arc<Mutex<ServerContext>>>,
+    config_clone: &CynthiaConfClone,
+) -> Result<(CynthiaCacheExtraction, bool), RenderFailure> {
+    let dev = crate::dev_mode();
+    // A traced page shows the timings of its own render, not those of an earlier one.
+    let uncached = dev || crate::trace_render();
+    let cache_result = server_context_mutex
+        .lock_callback(|servercontext| servercontext.get_cache(page_id, 0))
+        .await;
+    if let Some(c) = cache_result.filter(|_| !uncached) {
+        return Ok((c, true));
+    }
+    // Whatever gets logged from here on, to show with a failed render in development mode.
+    let mut logged = dev.then(|| crate::logtail::subscribe().1);
+    let stopwatch = crate::timing::Stopwatch::start(crate::timing::Part::Render);
+    let page = renders::render_with_deadline(
+        config_clone.render_timeout,
+        render_from_pgid(page_id.parse().unwrap(), server_context_mutex.clone()),
+    )
+    .await
+    .map_err(RenderFailure::TimedOut)?;
+    drop(stopwatch);
+    // A failed render is not cached, so the next request tries again.
+    let renders::RenderrerResponse::Ok(page) = page else {
+        let mut details = vec![];
+        // Other requests log at the same time, their lines carry another id.
+        let own = crate::requestid::current().map(|id| format!("[{}]", id));
+        while let Some(Ok(line)) = logged.as_mut().map(|l| l.try_recv()) {
+            if own.as_ref().is_none_or(|own| line.contains(own.as_str())) {
+                details.push(line);
+            }
+        }
+        return Err(RenderFailure::Failed(details));
+    };
+    if uncached {
+        return Ok((CynthiaCacheExtraction(page.into_bytes(), 0), false));
+    }
+    // Now that we're past the EPS, we can lock the mutex for this scope.
+    let mut server_context = server_context_mutex.lock().await;
+    server_context
+        .store_cache(
+            page_id,
+            page.as_bytes(),
+            config_clone.cache.lifetimes.served,
+        )
+        .unwrap();
+    Ok((
+        server_context
+            .get_cache(page_id, config_clone.cache.lifetimes.served)
+            .unwrap_or(CynthiaCacheExtraction(page.into_bytes(), 0)),
+        false,
+    ))
+}
+
+/// Answers a request for which `cached_render` came up empty.
+fn render_failure(
+    req: &HttpRequest,
+    failure: RenderFailure,
+    config_clone: &CynthiaConfClone,
+) -> HttpResponse {
+    match failure {
+        RenderFailure::TimedOut(stage) => render_timed_out(req, stage, config_clone),
+        RenderFailure::Failed(details) => render_failed(req, &details, config_clone),
+    }
+}
+
+/// Answers a request whose render went wrong. `details` are only shown in development mode.
+fn render_failed(
+    req: &HttpRequest,
+    details: &[String],
+    config_clone: &CynthiaConfClone,
+) -> HttpResponse {
+    let (w_s, w_a) = urlspace();
+    let coninfo = req.connection_info().clone();
+    let ip = coninfo.realip_remote_addr().unwrap_or("<unknown IP>");
+    warn!(
+        "{}\t{:>w_s$.w_a$}\t\t\t{}\t{}",
+        "GET:500".color_error_red(),
+        req.uri().to_string(),
+        ip.color_lightblue(),
+        "render failed, see above".color_red()
+    );
+    server_error(
+        "This page could not be rendered. The error has been logged.",
+        details,
+        config_clone,
+    )
+}
+
+/// A `500` page, from the `error_pages.server_error` template when one is set. Otherwise, or when
+/// that fails too, a plain built-in page: the site's own templates may well be what's broken.
+fn server_error(
+    message: &str,
+    details: &[String],
+    config_clone: &CynthiaConfClone,
+) -> HttpResponse {
+    let mut response = HttpResponse::InternalServerError();
+    response
+        .append_header(("Content-Type", "text/html; charset=utf-8"))
+        .append_header(("Cache-Control", "no-store"));
+    if let Some(template) = &config_clone.error_pages.server_error {
+        let data = renders::ErrorPageData {
+            status: 500,
+            message: message.to_string(),
+            details: details.to_vec(),
+            request_id: crate::requestid::current(),
+        };
+        match renders::render_error_page(template, &data) {
+            Ok(page) => return response.body(page),
+            Err(e) => {
+                error!("Could not render the server error page, showing the built-in one: {e}")
+            }
+        }
+    }
+    let details = if details.is_empty() {
+        String::new()
+    } else {
+        format!(
+            "<h2>Logged while rendering</h2><pre>{}</pre>",
+            crate::helpers::escape_html(&details.join("\n"))
+        )
+    };
+    response.body(format!(
+        "<!DOCTYPE html>\n<html>\n<head><meta charset=\"utf-8\" /><title>500 Internal Server Error</title></head>\n<body><h1>500 Internal Server Error</h1><p>{}</p>{}</body></html>",
+        crate::helpers::escape_html(message),
+        details
+    ))
+}
+
+/// Answers a request for `/` when `home` names a publication that isn't there. Not a `404`: the site
+/// is misconfigured, so this says how, instead of showing the not-found page.
+fn home_missing(req: &HttpRequest, home: &str, config_clone: &CynthiaConfClone) -> HttpResponse {
+    let (w_s, w_a) = urlspace();
+    let coninfo = req.connection_info().clone();
+    let ip = coninfo.realip_remote_addr().unwrap_or("<unknown IP>");
+    warn!(
+        "{}\t{:>w_s$.w_a$}\t\t\t{}\t{}",
+        "GET:500".color_error_red(),
+        req.uri().to_string(),
+        ip.color_lightblue(),
+        format!("`home` is `{}`, which doesn't exist", home).color_red()
+    );
+    let message = format!(
+        "The home page is set to the publication `{}` (`home` in the configuration), but there is no publication with that id. Add it to the publication list, or point `home` at one that exists.",
+        home
+    );
+    if prefers_json(req) {
+        return ApiError::Internal(message).error_response();
+    }
+    server_error(&message, &[], config_clone)
+}
+
+/// Answers a request whose render ran past `render_timeout`.
+fn render_timed_out(
+    req: &HttpRequest,
+    stage: &str,
+    config_clone: &CynthiaConfClone,
+) -> HttpResponse {
+    let (w_s, w_a) = urlspace();
+    let coninfo = req.connection_info().clone();
+    let ip = coninfo.realip_remote_addr().unwrap_or("<unknown IP>");
+    warn!(
+        "{}\t{:>w_s$.w_a$}\t\t\t{}\t{}",
+        "GET:503".color_error_red(),
+        req.uri().to_string(),
+        ip.color_lightblue(),
+        format!("render timed out while {}", stage).color_red()
+    );
+    let body =
+        "503 Service Unavailable: rendering this page took too long, please try again later.";
+    HttpResponse::ServiceUnavailable()
+        .append_header(("Retry-After", config_clone.render_timeout.to_string()))
+        .body(if crate::dev_mode() {
+            format!("{} It got stuck while {}.", body, stage)
+        } else {
+            body.to_string()
+        })
+}
+
+/// An `ETag` for a file on disk, from its size and modification time, so it doesn't have to be read.
+fn file_etag(metadata: &std::fs::Metadata) -> Option<String> {
+    let modified = metadata
+        .modified()
+        .ok()?
+        .duration_since(std::time::UNIX_EPOCH)
+        .ok()?;
+    Some(format!("\"{:x}-{:x}\"", metadata.len(), modified.as_secs()))
+}
+
+/// Whether the client's copy is still current, going by `If-None-Match`, or by `If-Modified-Since`
+/// when there is no `If-None-Match`.
+fn not_modified(req: &HttpRequest, etag: &str, last_modified: Option<SystemTime>) -> bool {
+    if let Some(if_none_match) = req.headers().get("If-None-Match") {
+        // Weak comparison, as GET allows.
+        let strip = |tag: &str| tag.trim().trim_start_matches("W/").to_string();
+        return if_none_match.to_str().is_ok_and(|etags| {
+            etags.trim() == "*" || etags.split(',').any(|tag| strip(tag) == strip(etag))
+        });
+    }
+    match (last_modified, req.headers().get("If-Modified-Since")) {
+        (Some(modified), Some(since)) => since
+            .to_str()
+            .ok()
+            .and_then(|since| since.parse::<HttpDate>().ok())
+            // HTTP dates have no sub-second precision, so neither may the comparison.
+            .is_some_and(|since| HttpDate::from(modified) <= since),
+        _ => false,
+    }
+}
+
+/// Headers a response can carry more than once, which are added to rather than replaced.
+const REPEATABLE_HEADERS: [&str; 4] = ["set-cookie", "link", "vary", "www-authenticate"];
+
+/// Adds headers from the configuration or from a plugin to `response`. Those that can only be sent
+/// once replace any that's there already, so a response never ends up with two `Content-Type`s.
+fn add_headers(response: &mut HttpResponseBuilder, headers: Vec<(String, String)>) {
+    for (name, value) in headers {
+        if REPEATABLE_HEADERS.contains(&name.to_ascii_lowercase().as_str()) {
+            response.append_header((name, value));
+        } else {
+            response.insert_header((name, value));
+        }
+    }
+}
+
+/// A `304 Not Modified` carrying the validators and caching headers the full response would have had.
+fn not_modified_response(etag: &str, extra_headers: Vec<(&str, String)>) -> HttpResponse {
+    let mut response = HttpResponse::NotModified();
+    response.append_header(("ETag", etag.to_string()));
+    for header in extra_headers {
+        response.append_header(header);
+    }
+    response.finish()
+}
+
+/// The part of a file a `Range` header asks for.
+#[derive(Debug, PartialEq)]
+enum ByteRange {
+    /// No usable range, the whole file is sent.
+    Whole,
+    /// The first and last byte to send.
+    Slice(u64, u64),
+    /// The range lies beyond the end of the file.
+    Unsatisfiable,
+}
+
+/// Parses a `Range` header for a file of `len` bytes. Malformed headers are ignored, as are requests
+/// for several ranges, which would need a multipart response: both get the whole file.
+fn byte_range(header: &str, len: u64) -> ByteRange {
+    let Some(spec) = header.trim().strip_prefix("bytes=") else {
+        return ByteRange::Whole;
+    };
+    let Some((start, end)) = spec.split_once('-') else {
+        return ByteRange::Whole;
+    };
+    if spec.contains(',') {
+        return ByteRange::Whole;
+    }
+    let (start, end) = (start.trim(), end.trim());
+    let (first, last) = match (start.parse::<u64>(), end.parse::<u64>()) {
+        (Ok(first), Ok(last)) if first <= last => (first, last),
+        (Ok(first), Err(_)) if end.is_empty() => (first, u64::MAX),
+        // `bytes=-500` asks for the last 500 bytes.
+        (Err(_), Ok(suffix)) if start.is_empty() && suffix > 0 => {
+            (len.saturating_sub(suffix), u64::MAX)
+        }
+        (Err(_), Ok(_)) if start.is_empty() => return ByteRange::Unsatisfiable,
+        _ => return ByteRange::Whole,
+    };
+    if first >= len {
+        ByteRange::Unsatisfiable
+    } else {
+        ByteRange::Slice(first, last.min(len - 1))
+    }
+}
+
+/// Sends the part of `contents` the `Range` header asks for as `206 Partial Content`, or all of it
+/// when there's no usable range or `If-Range` shows the client's copy is outdated.
+fn ranged(
+    req: &HttpRequest,
+    mut response: HttpResponseBuilder,
+    contents: Vec<u8>,
+    etag: Option<&str>,
+) -> HttpResponse {
+    response.append_header(("Accept-Ranges", "bytes"));
+    let Some(range) = req.headers().get("Range").and_then(|r| r.to_str().ok()) else {
+        return response.body(contents);
+    };
+    if let Some(if_range) = req.headers().get("If-Range") {
+        // Only an exact `ETag` counts, dates are too coarse to be sure.
+        if etag.is_none() || if_range.to_str().ok() != etag {
+            return response.body(contents);
+        }
+    }
+    let len = contents.len() as u64;
+    match byte_range(range, len) {
+        ByteRange::Whole => response.body(contents),
+        ByteRange::Slice(start, end) => response
+            .status(StatusCode::PARTIAL_CONTENT)
+            .append_header(("Content-Range", format!("bytes {}-{}/{}", start, end, len)))
+            .body(contents[start as usize..=end as usize].to_vec()),
+        ByteRange::Unsatisfiable => HttpResponse::RangeNotSatisfiable()
+            .append_header(("Content-Range", format!("bytes */{}", len)))
+            .finish(),
+    }
+}
+
+/// Whether the `Accept` header ranks JSON above HTML. Without one, or with just `*/*`, it's HTML.
+fn prefers_json(req: &HttpRequest) -> bool {
+    let Some(accept) = req
+        .headers()
+        .get("Accept")
+        .and_then(|accept| accept.to_str().ok())
+    else {
+        return false;
+    };
+    // The quality of the most specific range matching a type, 0 when none does.
+    let quality = |kind: &str, subtype: &str| {
+        let mut best: Option<(u8, f32)> = None;
+        for range in accept.split(',') {
+            let mut parts = range.split(';').map(str::trim);
+            let Some((range_kind, range_subtype)) = parts.next().and_then(|r| r.split_once('/'))
+            else {
+                continue;
+            };
+            let specificity = match (range_kind, range_subtype) {
+                (k, s) if k == kind && s == subtype => 2,
+                (k, "*") if k == kind => 1,
+                ("*", "*") => 0,
+                _ => continue,
+            };
+            let q = parts
+                .find_map(|p| p.strip_prefix("q="))
+                .and_then(|q| q.parse().ok())
+                .unwrap_or(1.0);
+            if best.is_none_or(|(s, _)| specificity > s) {
+                best = Some((specificity, q));
+            }
+        }
+        best.map(|(_, q)| q).unwrap_or(0.0)
+    };
+    let json = quality("application", "json");
+    json > 0.0 && json > quality("text", "html")
+}
+
+/// Gives a rendered page a fresh nonce when `security.nonce` is on, adding the policy that allows it
+/// unless `headers` already set one.
+fn apply_nonce(
+    page: Vec<u8>,
+    headers: &mut Vec<(String, String)>,
+    config: &CynthiaConfClone,
+) -> Vec<u8> {
+    if !config.security.uses_nonce() {
+        return page;
+    }
+    let nonce = format!("{:032x}", rand::random::<u128>());
+    if !headers
+        .iter()
+        .any(|(name, _)| name.eq_ignore_ascii_case("Content-Security-Policy"))
+    {
+        headers.push((
+            "Content-Security-Policy".to_string(),
+            config.security.policy_with_nonce(&nonce),
+        ));
+    }
+    String::from_utf8_lossy(&page)
+        .replace(renders::NONCE_PLACEHOLDER, &nonce)
+        .into_bytes()
+}
+
+/// The `Content-Type` of a rendered page, and its body in the configured `charset`. HTML, and other
+/// text that doesn't name a charset of its own, is declared and sent in it, with the characters it
+/// doesn't have as HTML character references. Anything else is sent as it is.
+fn in_charset(content_type: Option<String>, body: Vec<u8>, charset: &str) -> (String, Vec<u8>) {
+    let content_type = content_type.unwrap_or_else(|| String::from("text/html"));
+    let lowercase = content_type.to_ascii_lowercase();
+    let essence = lowercase.split(';').next().unwrap_or_default().trim();
+    let textual = essence.starts_with("text/")
+        || essence.ends_with("/xml")
+        || essence.ends_with("+xml")
+        || essence == "application/javascript";
+    if !textual || lowercase.contains("charset=") {
+        return (content_type, body);
+    }
+    // Checked when the configuration was loaded.
+    let encoding = crate::config::charset(charset).unwrap_or(encoding_rs::UTF_8);
+    let body = if encoding == encoding_rs::UTF_8 {
+        body
+    } else {
+        encoding
+            .encode(&String::from_utf8_lossy(&body))
+            .0
+            .into_owned()
+    };
+    (
+        format!(
+            "{}; charset={}",
+            content_type,
+            encoding.name().to_ascii_lowercase()
+        ),
+        body,
+    )
+}
+
+#[actix_web::routes]
 #[get("/{a:.*}")]
+#[head("/{a:.*}")]
 #[doc = r"Serves pages included in CynthiaConfig, or a default page if not found."]
+/// HEAD requests are answered the same way, the body is left out by actix, but not its headers.
 pub(crate) async fn serve(
     server_context_mutex: Data<Arc<Mutex<ServerContext>>>,
     req: HttpRequest,
@@ -50,19 +603,25 @@ pub(crate) async fn serve(
     let (w_s, w_a) = urlspace();
     // We can't lock the mutex here because it wouldn't be usable by EPS, so we need to use a callback.
     // let mut server_context: MutexGuard<ServerContext> = server_context_mutex.lock().await;
-    let config_clone = server_context_mutex
+    let (config_clone, visits) = server_context_mutex
         .lock_callback(|a| {
             a.request_count += 1;
-            a.config.clone()
+            (a.config.clone(), a.visits.clone())
         })
         .await;
+    if let Some(response) = well_known(&req, &config_clone) {
+        return response;
+    }
+    if let Some(redirect) = trailing_slash_redirect(&req, config_clone.trailing_slash) {
+        return redirect;
+    }
 
     let page_uri = if req.uri() == "" {
         "root".to_string()
     } else {
         req.uri().to_string()
     };
-    let page_id = page_uri.trim_start_matches('/');
+    let page_id = page_uri.trim_start_matches('/').trim_end_matches('/');
     let headers = {
         // Transform it into makeshift JSON!
         let json_kinda = format!("{:?}", &req.headers().iter().collect::<Vec<_>>())
@@ -72,16 +631,25 @@ pub(crate) async fn serve(
         serde_json::from_str(&json_kinda).unwrap_or_default()
     };
     trace!("{}", serde_json::to_string(&headers).unwrap());
-    let pluginsresponse = contact_eps(
+    let Some(pluginsresponse) = contact_eps_within_deadline(
         server_context_mutex.clone(),
         EPSRequestBody::WebRequest {
             uri: page_uri.clone(),
             headers,
-            method: "get".to_string(),
+            method: req.method().as_str().to_lowercase(),
         },
+        config_clone.render_timeout,
     )
-    .await;
+    .await
+    else {
+        return render_timed_out(&req, "waiting for the plugins", &config_clone);
+    };
     match pluginsresponse {
+        crate::externalpluginservers::EPSResponseBody::RouteResponse {
+            status,
+            headers,
+            body,
+        } => return route_response(&req, status, headers, body, &config_clone),
         crate::externalpluginservers::EPSResponseBody::WebResponse {
             append_headers,
             response_body,
@@ -103,46 +671,101 @@ pub(crate) async fn serve(
                 "extern".color_pink()
             ));
             let mut response = HttpResponse::build(actix_web::http::StatusCode::OK);
-            for (k, v) in append_headers {
-                response.append_header((k, v));
-            }
+            add_headers(&mut response, append_headers);
             return response.body(response_body);
         }
         crate::externalpluginservers::EPSResponseBody::NoneOk
         | crate::externalpluginservers::EPSResponseBody::Disabled => (),
+        _ if prefers_json(&req) => {
+            return ApiError::Internal(String::from("The plugins could not handle this request."))
+                .error_response()
+        }
+        crate::externalpluginservers::EPSResponseBody::Error {
+            message: Some(message),
+        } if crate::dev_mode() => {
+            return HttpResponse::InternalServerError()
+                .body(format!("Internal server error, from a plugin: {}", message))
+        }
         _ => return HttpResponse::InternalServerError().body("Internal server error."),
     };
+    // `/` serves `home` when it's set, rather than the page with the id `root`.
+    let home = page_id
+        .is_empty()
+        .then(|| config_clone.home.clone())
+        .flatten();
+    let page_id = home.as_deref().unwrap_or(page_id);
+    // `/{lang}/{translation_key}` serves the translation in that language.
+    let translated_id = renders::translated_pgid(page_id, server_context_mutex.clone()).await;
+    let page_id = translated_id.as_deref().unwrap_or(page_id);
+    let slugged_id = renders::slugged_pgid(page_id, server_context_mutex.clone()).await;
+    let page_id = slugged_id.as_deref().unwrap_or(page_id);
     let s = renders::check_pgid(page_id.to_string(), server_context_mutex.clone()).await;
+    // A `home` that isn't there is a mistake in the configuration, not a page that went missing.
+    if let (Some(home), renders::PGIDCheckResponse::NotFound) = (&home, &s) {
+        return home_missing(&req, home, &config_clone);
+    }
     match s {
-        renders::PGIDCheckResponse::Ok => {
-            let from_cache: bool;
-            let cache_result = server_context_mutex
-                .lock_callback(|servercontext| servercontext.get_cache(page_id, 0))
-                .await;
-            let page = match cache_result {
-                Some(c) => {
-                    from_cache = true;
-                    c
-                }
+        // A page that isn't HTML is its own JSON, or whatever else it is.
+        renders::PGIDCheckResponse::Ok(None) if prefers_json(&req) => {
+            let Some(json) = renders::publication_json(page_id, server_context_mutex.clone()).await
+            else {
+                return ApiError::Internal(format!(
+                    "Publication `{}` could not be loaded.",
+                    page_id
+                ))
+                .error_response();
+            };
+            let coninfo = req.connection_info();
+            let ip = coninfo.realip_remote_addr().unwrap_or("<unknown IP>");
+            config_clone.tell(format!(
+                "{}\t{:>w_s$.w_a$}\t\t\t{}\t{}",
+                "GET:200".color_ok_green(),
+                req.uri().to_string(),
+                ip.color_lightblue(),
+                "json".color_yellow()
+            ));
+            HttpResponse::Ok()
+                .append_header(("Vary", "Accept"))
+                .json(json)
+        }
+        renders::PGIDCheckResponse::Ok(content_type) => {
+            if config_clone.visits.enabled {
+                visits.count(page_id);
+            }
+            // Development mode is for seeing changes, not copies made before them.
+            let prerendered = if crate::dev_mode() || crate::trace_render() {
+                None
+            } else {
+                renders::prerendered_page(page_id, server_context_mutex.clone()).await
+            };
+            // When the page was rendered, a page rendered just now and not cached has no timestamp.
+            let rendered_at = |at: u64| match at {
+                0 => SystemTime::now(),
+                at => SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(at),
+            };
+            let (page, served_from, rendered) = match prerendered {
+                Some((page, written)) => (page, "prerendered".color_lime(), written),
                 None => {
-                    from_cache = false;
-                    // Now that we're past the EPS, we can lock the mutex for this scope.
-                    let page =
-                        render_from_pgid(page_id.parse().unwrap(), server_context_mutex.clone())
-                            .await;
-                    let mut server_context = server_context_mutex.lock().await;
-                    server_context
-                        .store_cache(
-                            page_id,
-                            page.clone().unwrap().as_bytes(),
-                            config_clone.clone().cache.lifetimes.served,
-                        )
-                        .unwrap();
-                    server_context
-                        .get_cache(page_id, config_clone.clone().cache.lifetimes.served)
-                        .unwrap_or(CynthiaCacheExtraction(page.unwrap().as_bytes().to_vec(), 0))
+                    match cached_render(page_id, server_context_mutex.clone(), &config_clone).await
+                    {
+                        Ok((page, true)) => (page.0, "cache".color_green(), rendered_at(page.1)),
+                        Ok((page, false)) => {
+                            (page.0, "generated".color_yellow(), rendered_at(page.1))
+                        }
+                        Err(failure) => return render_failure(&req, failure, &config_clone),
+                    }
                 }
             };
+            let headers = renders::headers_for_pgid(page_id, server_context_mutex.clone()).await;
+            let last_modified =
+                renders::last_updated_for_pgid(page_id, server_context_mutex.clone()).await;
+            // Checked each time, a cached page may have been rendered with a placeholder.
+            let status = if renders::shows_placeholder(page_id, server_context_mutex.clone()).await
+            {
+                StatusCode::from_u16(config_clone.missing_content.status).unwrap_or(StatusCode::OK)
+            } else {
+                StatusCode::OK
+            };
 
             let coninfo = req.connection_info();
             let ip = coninfo.realip_remote_addr().unwrap_or("<unknown IP>");
@@ -158,17 +781,46 @@ pub(crate) async fn serve(
                     }
                 },
                 ip.color_lightblue(),
-                {
-                    if from_cache {
-                        "cache".color_green()
-                    } else {
-                        "generated".color_yellow()
-                    }
-                }
+                served_from
             ));
-            HttpResponse::Ok()
-                .append_header(("Content-Type", "text/html; charset=utf-8"))
-                .body(page.0)
+            let mut headers = headers;
+            // A page with a fresh nonce is a different page each time. Only HTML has scripts to allow.
+            let nonced = content_type.is_none() && config_clone.security.uses_nonce();
+            let page = if nonced {
+                apply_nonce(page, &mut headers, &config_clone)
+            } else {
+                page
+            };
+            let etag = format!("\"{}\"", crate::helpers::content_hash(&page));
+            // Like the ETag, going by the page as it's served: the publication's own update, or its
+            // render with the template and plugins of that moment, whichever is later.
+            let last_modified = if nonced {
+                Some(SystemTime::now())
+            } else {
+                last_modified.max(Some(rendered))
+            };
+            if not_modified(&req, &etag, last_modified) {
+                let mut extra_headers = vec![("Vary", String::from("Accept"))];
+                if let Some(modified) = last_modified {
+                    extra_headers.push(("Last-Modified", HttpDate::from(modified).to_string()));
+                }
+                return not_modified_response(&etag, extra_headers);
+            }
+            let (content_type, body) = in_charset(content_type, page, &config_clone.charset);
+            let mut response = HttpResponse::build(status);
+            add_headers(&mut response, headers);
+            if let Some(modified) = last_modified {
+                response.insert_header(("Last-Modified", HttpDate::from(modified)));
+            }
+            response
+                .insert_header(("Content-Type", content_type))
+                .insert_header(("ETag", etag))
+                .append_header(("Vary", "Accept"))
+                .body(body)
+        }
+        renders::PGIDCheckResponse::Error if prefers_json(&req) => {
+            ApiError::Internal(String::from("The publications could not be loaded."))
+                .error_response()
         }
         renders::PGIDCheckResponse::Error => {
             HttpResponse::InternalServerError().body("Internal server error.")
@@ -190,17 +842,26 @@ pub(crate) async fn serve(
                 ip.color_lightblue(),
                 "not found".color_red()
             );
+            if prefers_json(&req) {
+                return ApiError::NotFound(format!("There is no publication at `{}`.", req.uri()))
+                    .error_response();
+            }
 
-            HttpResponse::NotFound()
-                .append_header(("Content-Type", "text/html; charset=utf-8"))
-                .body(
-                    render_from_pgid(
-                        config_clone.site.notfound_page.clone(),
-                        server_context_mutex.clone(),
-                    )
-                    .await
-                    .unwrap(),
-                )
+            match render_from_pgid(
+                config_clone.site.notfound_page.clone(),
+                server_context_mutex.clone(),
+            )
+            .await
+            {
+                renders::RenderrerResponse::Ok(page) => {
+                    let (content_type, body) =
+                        in_charset(None, page.into_bytes(), &config_clone.charset);
+                    HttpResponse::NotFound()
+                        .append_header(("Content-Type", content_type))
+                        .body(body)
+                }
+                _ => render_failed(&req, &[], &config_clone),
+            }
         }
     }
 }
@@ -212,9 +873,59 @@ pub(crate) async fn assets_with_cache(
 ) -> impl Responder {
     let (w_s, w_a) = urlspace();
     let path = req.match_info().get("reqfile").unwrap();
+    let filepath: PathBuf = std::env::current_dir()
+        .unwrap()
+        .canonicalize()
+        .unwrap()
+        .join("cynthiaFiles/assets/")
+        .join(path);
+    // Validators come from the file on disk, also for cached copies, so they change with the file.
+    let validators = std::fs::metadata(&filepath)
+        .ok()
+        .filter(|metadata| metadata.is_file())
+        .and_then(|metadata| Some((file_etag(&metadata)?, metadata.modified().ok()?)));
+    let uri = {
+        let uri = req.uri().to_string();
+        if uri == *"" {
+            "/".to_string()
+        } else {
+            uri
+        }
+    };
+    if let Some((etag, modified)) = &validators {
+        if not_modified(&req, etag, Some(*modified)) {
+            let config_clone = server_context_mutex
+                .lock_callback(|a| {
+                    a.request_count += 1;
+                    a.config.clone()
+                })
+                .await;
+            let coninfo = req.connection_info();
+            let ip = coninfo.realip_remote_addr().unwrap_or("<unknown IP>");
+            config_clone.tell(format!(
+                "{}\t{:>w_s$.w_a$}\t\t\t{}\t{}",
+                "GET:304".color_ok_green(),
+                uri,
+                ip.color_lightblue(),
+                "not modified".color_green()
+            ));
+            return not_modified_response(
+                etag,
+                vec![("Last-Modified", HttpDate::from(*modified).to_string())],
+            );
+        }
+    }
     let cacheresulr = server_context_mutex
         .lock_callback(|servercontext| servercontext.get_cache(path, 0))
         .await;
+    let mut response = HttpResponse::Ok();
+    response.append_header(("Content-Type", "text/html; charset=utf-8"));
+    if let Some((etag, modified)) = &validators {
+        response
+            .append_header(("ETag", etag.as_str()))
+            .append_header(("Last-Modified", HttpDate::from(*modified)));
+    }
+    let etag = validators.map(|(etag, _)| etag);
     match cacheresulr {
         None => {
             let config_clone = server_context_mutex
@@ -223,12 +934,6 @@ pub(crate) async fn assets_with_cache(
                     a.config.clone()
                 })
                 .await;
-            let filepath: PathBuf = std::env::current_dir()
-                .unwrap()
-                .canonicalize()
-                .unwrap()
-                .join("cynthiaFiles/assets/")
-                .join(path);
             debug!("Requested asset: {:?}", filepath);
             if filepath.exists() && filepath.is_file() {
                 let contents: Vec<u8> = std::fs::read(filepath).unwrap();
@@ -241,34 +946,18 @@ pub(crate) async fn assets_with_cache(
                 server_context.tell(format!(
                     "{}\t{:>w_s$.w_a$}\t\t\t{}\t{}",
                     "GET:200".color_ok_green(),
-                    {
-                        let uri = req.uri().to_string();
-                        if uri == *"" {
-                            "/".to_string()
-                        } else {
-                            uri
-                        }
-                    },
+                    uri,
                     ip.color_lightblue(),
                     "filesystem".color_lilac()
                 ));
-                HttpResponse::Ok()
-                    .append_header(("Content-Type", "text/html; charset=utf-8"))
-                    .body(contents)
+                ranged(&req, response, contents, etag.as_deref())
             } else {
                 let coninfo = req.connection_info();
                 let ip = coninfo.realip_remote_addr().unwrap_or("<unknown IP>");
                 config_clone.tell(format!(
                     "{}\t{:>w_s$.w_a$}\t\t\t{}\t{}",
                     "GET:404".color_error_red(),
-                    {
-                        let uri = req.uri().to_string();
-                        if uri == *"" {
-                            "/".to_string()
-                        } else {
-                            uri
-                        }
-                    },
+                    uri,
                     ip.color_lightblue(),
                     "not found".color_red()
                 ));
@@ -287,40 +976,636 @@ pub(crate) async fn assets_with_cache(
             config_clone.tell(format!(
                 "{}\t{:>w_s$.w_a$}\t\t\t{}\t{}",
                 "GET:200".color_ok_green(),
-                {
-                    let uri = req.uri().to_string();
-                    if uri == *"" {
-                        "/".to_string()
-                    } else {
-                        uri
-                    }
-                },
+                uri,
                 ip.color_lightblue(),
                 "cache".color_green()
             ));
+            ranged(&req, response, c.0, etag.as_deref())
+        }
+    }
+}
+
+#[get("/cynthia/client.js")]
+/// Serves the client script of a scene, for when `site.inline_scripts` is disabled.
+/// The scene is picked by the `scene` query parameter, and defaults to the default scene.
+pub(crate) async fn client_script(
+    server_context_mutex: Data<Arc<Mutex<ServerContext>>>,
+    req: HttpRequest,
+) -> impl Responder {
+    let (w_s, w_a) = urlspace();
+    let config_clone = server_context_mutex
+        .lock_callback(|a| {
+            a.request_count += 1;
+            a.config.clone()
+        })
+        .await;
+    let query = web::Query::<HashMap<String, String>>::from_query(req.query_string())
+        .map(|q| q.into_inner())
+        .unwrap_or_default();
+    let scene = match query.get("scene") {
+        Some(name) => config_clone.scenes.get_by_name(name),
+        None => Some(config_clone.scenes.get_default(&config_clone.site)),
+    };
+    let script = scene.and_then(|s| s.client_script(&config_clone.site));
+    let scriptfile: Option<PathBuf> = script.map(|script| {
+        std::env::current_dir()
+            .unwrap()
+            .canonicalize()
+            .unwrap()
+            .join("./cynthiaFiles/assets/".to_string() + script.as_str())
+    });
+    let coninfo = req.connection_info().clone();
+    let ip = coninfo.realip_remote_addr().unwrap_or("<unknown IP>");
+    match scriptfile {
+        Some(path) if path.is_file() => {
+            let script =
+                match renders::inlines::minified_js(path.clone(), server_context_mutex.clone())
+                    .await
+                {
+                    Some(d) => d,
+                    None => std::fs::read_to_string(&path).unwrap_or_default(),
+                };
+            config_clone.tell(format!(
+                "{}\t{:>w_s$.w_a$}\t\t\t{}\t{}",
+                "GET:200".color_ok_green(),
+                req.uri().to_string(),
+                ip.color_lightblue(),
+                "client script".color_lilac()
+            ));
+            // Pages link to this with a content hash, so those links can be cached for as long as browsers like.
+            let cache_control = if query.contains_key("v") {
+                "public, max-age=31536000, immutable".to_string()
+            } else {
+                format!(
+                    "public, max-age={}",
+                    config_clone.cache.lifetimes.javascript
+                )
+            };
+            let etag = format!("\"{}\"", crate::helpers::content_hash(script.as_bytes()));
+            if not_modified(&req, &etag, None) {
+                return not_modified_response(&etag, vec![("Cache-Control", cache_control)]);
+            }
             HttpResponse::Ok()
-                .append_header(("Content-Type", "text/html; charset=utf-8"))
-                .body(c.0)
+                .append_header(("Content-Type", "application/javascript; charset=utf-8"))
+                .append_header(("Cache-Control", cache_control))
+                .append_header(("ETag", etag))
+                .body(script)
+        }
+        _ => {
+            config_clone.tell(format!(
+                "{}\t{:>w_s$.w_a$}\t\t\t{}\t{}",
+                "GET:404".color_error_red(),
+                req.uri().to_string(),
+                ip.color_lightblue(),
+                "not found".color_red()
+            ));
+            HttpResponse::NotFound().body("404 Not Found")
         }
     }
 }
 
-/// Cynthia doesn't respond to POST requests, but it's plugins might.
-/// Support for form data is planned but not yet implemented.
+#[get("/cynthia/images/{file}")]
+/// Serves the smaller copies of images made for `images.enabled`. They're named after their source,
+/// so browsers can hold on to them for as long as they like.
+pub(crate) async fn image_variant(
+    server_context_mutex: Data<Arc<Mutex<ServerContext>>>,
+    req: HttpRequest,
+) -> impl Responder {
+    let (w_s, w_a) = urlspace();
+    let config_clone = server_context_mutex
+        .lock_callback(|a| {
+            a.request_count += 1;
+            a.config.clone()
+        })
+        .await;
+    let name = req.match_info().get("file").unwrap_or_default();
+    let coninfo = req.connection_info().clone();
+    let ip = coninfo.realip_remote_addr().unwrap_or("<unknown IP>");
+    // Only names Cynthia gave out, like `0123abcd.webp`.
+    let variant = name
+        .split_once('.')
+        .filter(|(hash, _)| !hash.is_empty() && hash.chars().all(|c| c.is_ascii_hexdigit()))
+        .and_then(|(_, format)| crate::images::content_type(format))
+        .and_then(|content_type| {
+            let contents =
+                std::fs::read(std::path::Path::new(&config_clone.images.cache_dir).join(name))
+                    .ok()?;
+            Some((content_type, contents))
+        });
+    let Some((content_type, contents)) = variant else {
+        config_clone.tell(format!(
+            "{}\t{:>w_s$.w_a$}\t\t\t{}\t{}",
+            "GET:404".color_error_red(),
+            req.uri().to_string(),
+            ip.color_lightblue(),
+            "not found".color_red()
+        ));
+        return HttpResponse::NotFound().body("404 Not Found");
+    };
+    config_clone.tell(format!(
+        "{}\t{:>w_s$.w_a$}\t\t\t{}\t{}",
+        "GET:200".color_ok_green(),
+        req.uri().to_string(),
+        ip.color_lightblue(),
+        "image".color_lilac()
+    ));
+    HttpResponse::Ok()
+        .append_header(("Content-Type", content_type))
+        .append_header(("Cache-Control", "public, max-age=31536000, immutable"))
+        .body(contents)
+}
+
+#[get("/favicon.ico")]
+/// Serves the icon set in `site.favicon`, or an empty response when there is none, to keep browsers from logging 404s.
+pub(crate) async fn favicon(
+    server_context_mutex: Data<Arc<Mutex<ServerContext>>>,
+    req: HttpRequest,
+) -> impl Responder {
+    let config_clone = server_context_mutex
+        .lock_callback(|a| {
+            a.request_count += 1;
+            a.config.clone()
+        })
+        .await;
+    let Some(favicon) = config_clone.site.favicon.clone() else {
+        return HttpResponse::NoContent().finish();
+    };
+    let path = std::env::current_dir()
+        .unwrap()
+        .join("cynthiaFiles/assets/")
+        .join(favicon.trim_start_matches('/'));
+    let Ok(contents) = std::fs::read(&path) else {
+        let (w_s, w_a) = urlspace();
+        let coninfo = req.connection_info().clone();
+        let ip = coninfo.realip_remote_addr().unwrap_or("<unknown IP>");
+        warn!(
+            "{}\t{:>w_s$.w_a$}\t\t\t{}\t{}",
+            "GET:404".color_error_red(),
+            req.uri().to_string(),
+            ip.color_lightblue(),
+            format!("favicon '{}' not found", path.display()).color_red()
+        );
+        return HttpResponse::NotFound().body("404 Not Found");
+    };
+    let content_type = match path
+        .extension()
+        .map(|e| e.to_string_lossy().to_ascii_lowercase())
+        .as_deref()
+    {
+        Some("png") => "image/png",
+        Some("svg") => "image/svg+xml",
+        Some("gif") => "image/gif",
+        Some("jpg") | Some("jpeg") => "image/jpeg",
+        Some("webp") => "image/webp",
+        _ => "image/x-icon",
+    };
+    HttpResponse::Ok()
+        .append_header(("Content-Type", content_type))
+        .append_header((
+            "Cache-Control",
+            format!("public, max-age={}", config_clone.cache.lifetimes.assets),
+        ))
+        .body(contents)
+}
+
+#[get("/healthz")]
+/// Tells load balancers and uptime checks that Cynthia is up, also during maintenance. Not logged, as these come often.
+pub(crate) async fn healthz() -> impl Responder {
+    HttpResponse::Ok()
+        .append_header(("Cache-Control", "no-store"))
+        .body("ok")
+}
+
+/// The methods Cynthia answers on its routes. `POST` is passed on to plugins.
+pub(crate) const ALLOWED_METHODS: &str = "GET, HEAD, POST";
+
+/// Answers requests with a method no route takes: `OPTIONS` with the methods there are, anything
+/// else with a `405`. Registered as the default service, so only reached when no route matches.
+pub(crate) async fn unsupported_method(req: HttpRequest) -> HttpResponse {
+    if req.method() == actix_web::http::Method::OPTIONS {
+        return HttpResponse::NoContent()
+            .append_header(("Allow", ALLOWED_METHODS))
+            .finish();
+    }
+    debug!("{} {} is not allowed.", req.method(), req.path());
+    if prefers_json(&req) {
+        return ApiError::MethodNotAllowed(format!("{} is not allowed here.", req.method()))
+            .error_response();
+    }
+    HttpResponse::MethodNotAllowed()
+        .append_header(("Allow", ALLOWED_METHODS))
+        .body("Method not allowed.")
+}
+
+const MAINTENANCE_PAGE: &str = "<!DOCTYPE html><html><head><meta charset=\"utf-8\"><title>Down for maintenance</title></head><body><h1>Down for maintenance</h1><p>This site is being worked on, please check back in a few minutes.</p></body></html>";
+
+/// Whether a request is still answered during maintenance: `/healthz` and `/.well-known/` always are, and assets and static files when `maintenance.assets` is set.
+fn passes_maintenance(path: &str, config: &CynthiaConfClone) -> bool {
+    path == "/healthz"
+        || path == "/cynthia/reload"
+        || path.starts_with("/.well-known/")
+        || (config.maintenance.assets
+            && (path.starts_with("/assets/")
+                || path == "/favicon.ico"
+                || path == "/cynthia/client.js"
+                || config.static_mounts.iter().any(|mount| {
+                    path.starts_with(&format!("/{}/", mount.prefix.trim_matches('/')))
+                })))
+}
+
+/// While `maintenance.enabled` is set, answers requests with a `503` and the maintenance page.
+/// It's checked on every request, so reloading the configuration with `SIGHUP` toggles it.
+pub(crate) async fn maintenance<B: MessageBody>(
+    req: ServiceRequest,
+    next: Next<B>,
+) -> Result<ServiceResponse<EitherBody<B>>, actix_web::Error> {
+    let config_clone = match req.app_data::<Data<Arc<Mutex<ServerContext>>>>() {
+        Some(server_context_mutex) => {
+            server_context_mutex
+                .lock_callback(|a| a.config.maintenance.enabled.then(|| a.config.clone()))
+                .await
+        }
+        None => None,
+    };
+    let Some(config_clone) = config_clone.filter(|c| !passes_maintenance(req.path(), c)) else {
+        return next
+            .call(req)
+            .await
+            .map(ServiceResponse::map_into_left_body);
+    };
+    let page = match &config_clone.maintenance.page {
+        Some(path) => std::fs::read_to_string(path).unwrap_or_else(|e| {
+            warn!(
+                "Could not read the maintenance page `{}`, using the built-in one: {}",
+                path, e
+            );
+            String::from(MAINTENANCE_PAGE)
+        }),
+        None => String::from(MAINTENANCE_PAGE),
+    };
+    let (w_s, w_a) = urlspace();
+    let ip = req
+        .connection_info()
+        .realip_remote_addr()
+        .unwrap_or("<unknown IP>")
+        .to_string();
+    config_clone.tell(format!(
+        "{}\t{:>w_s$.w_a$}\t\t\t{}\t{}",
+        format!("{}:503", req.method()).color_error_red(),
+        req.uri().to_string(),
+        ip.color_lightblue(),
+        "maintenance".color_lilac()
+    ));
+    let response = HttpResponse::ServiceUnavailable()
+        .append_header(("Content-Type", "text/html; charset=utf-8"))
+        .append_header(("Cache-Control", "no-store"))
+        .body(page);
+    Ok(req.into_response(response).map_into_right_body())
+}
+
+#[get("/cynthia/build")]
+/// Tells which build of Cynthia is running, to help correlate bug reports with exact builds.
+pub(crate) async fn build_info(
+    server_context_mutex: Data<Arc<Mutex<ServerContext>>>,
+    req: HttpRequest,
+) -> impl Responder {
+    let (w_s, w_a) = urlspace();
+    let config_clone = server_context_mutex
+        .lock_callback(|a| {
+            a.request_count += 1;
+            a.config.clone()
+        })
+        .await;
+    let coninfo = req.connection_info().clone();
+    let ip = coninfo.realip_remote_addr().unwrap_or("<unknown IP>");
+    config_clone.tell(format!(
+        "{}\t{:>w_s$.w_a$}\t\t\t{}\t{}",
+        "GET:200".color_ok_green(),
+        req.uri().to_string(),
+        ip.color_lightblue(),
+        "build info".color_lilac()
+    ));
+    HttpResponse::Ok()
+        .append_header(("Cache-Control", "no-cache"))
+        .json(crate::helpers::build_info())
+}
+
+#[get("/api/taxonomy")]
+/// Post counts per tag and per category, the most used first, for themes to build navigation from.
+pub(crate) async fn taxonomy(
+    server_context_mutex: Data<Arc<Mutex<ServerContext>>>,
+    req: HttpRequest,
+) -> impl Responder {
+    let (w_s, w_a) = urlspace();
+    let config_clone = server_context_mutex
+        .lock_callback(|a| {
+            a.request_count += 1;
+            a.config.clone()
+        })
+        .await;
+    let taxonomy = renders::taxonomy(server_context_mutex.clone()).await;
+    let coninfo = req.connection_info().clone();
+    let ip = coninfo.realip_remote_addr().unwrap_or("<unknown IP>");
+    config_clone.tell(format!(
+        "{}\t{:>w_s$.w_a$}\t\t\t{}\t{}",
+        "GET:200".color_ok_green(),
+        req.uri().to_string(),
+        ip.color_lightblue(),
+        "taxonomy".color_lilac()
+    ));
+    HttpResponse::Ok().json(taxonomy)
+}
+
+#[get("/api/stats")]
+/// Visits per publication, when `visits.enabled` is set.
+pub(crate) async fn stats(
+    server_context_mutex: Data<Arc<Mutex<ServerContext>>>,
+    req: HttpRequest,
+) -> Result<HttpResponse, ApiError> {
+    let (w_s, w_a) = urlspace();
+    let (config_clone, visits) = server_context_mutex
+        .lock_callback(|a| {
+            a.request_count += 1;
+            (a.config.clone(), a.visits.clone())
+        })
+        .await;
+    if !config_clone.visits.enabled {
+        return Err(ApiError::NotFound(String::from(
+            "Visits aren't counted on this site.",
+        )));
+    }
+    let visits = visits.all();
+    let coninfo = req.connection_info().clone();
+    let ip = coninfo.realip_remote_addr().unwrap_or("<unknown IP>");
+    config_clone.tell(format!(
+        "{}\t{:>w_s$.w_a$}\t\t\t{}\t{}",
+        "GET:200".color_ok_green(),
+        req.uri().to_string(),
+        ip.color_lightblue(),
+        "stats".color_lilac()
+    ));
+    Ok(HttpResponse::Ok()
+        .append_header(("Cache-Control", "no-cache"))
+        .json(serde_json::json!({
+            "total": visits.values().sum::<u64>(),
+            "visits": visits,
+        })))
+}
+
+/// Streams recent log lines as server-sent events, for operators without shell access.
+/// Only answers when `debug.log_endpoint` is set, and only to requests bearing that token.
+#[get("/cynthia/logs")]
+pub(crate) async fn logs(
+    server_context_mutex: Data<Arc<Mutex<ServerContext>>>,
+    req: HttpRequest,
+) -> HttpResponse {
+    let (w_s, w_a) = urlspace();
+    let config_clone = server_context_mutex
+        .lock_callback(|a| {
+            a.request_count += 1;
+            a.config.clone()
+        })
+        .await;
+    let coninfo = req.connection_info().clone();
+    let ip = coninfo.realip_remote_addr().unwrap_or("<unknown IP>");
+    let Some(token) = config_clone.debug.log_endpoint.clone() else {
+        return HttpResponse::NotFound().finish();
+    };
+    let bearer = req
+        .headers()
+        .get("Authorization")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .unwrap_or_default();
+    if !tokens_match(bearer, &token) {
+        config_clone.tell(format!(
+            "{}\t{:>w_s$.w_a$}\t\t\t{}\t{}",
+            "GET:401".color_error_red(),
+            req.uri().to_string(),
+            ip.color_lightblue(),
+            "log stream".color_lilac()
+        ));
+        return HttpResponse::Unauthorized()
+            .append_header(("WWW-Authenticate", "Bearer"))
+            .finish();
+    }
+    config_clone.tell(format!(
+        "{}\t{:>w_s$.w_a$}\t\t\t{}\t{}",
+        "GET:200".color_ok_green(),
+        req.uri().to_string(),
+        ip.color_lightblue(),
+        "log stream".color_lilac()
+    ));
+    let (backlog, receiver) = crate::logtail::subscribe();
+    let live = futures::stream::unfold(receiver, |mut receiver| async move {
+        loop {
+            match receiver.recv().await {
+                Ok(line) => return Some((line, receiver)),
+                // Lines lost to a slow listener are simply skipped.
+                Err(tokio::sync::broadcast::error::RecvError::Lagged(_)) => continue,
+                Err(tokio::sync::broadcast::error::RecvError::Closed) => return None,
+            }
+        }
+    });
+    let events = futures::stream::iter(backlog).chain(live).map(move |line| {
+        Ok::<_, actix_web::Error>(web::Bytes::from(format!(
+            "data: {}\n\n",
+            line.replace(&token, "<redacted>")
+        )))
+    });
+    HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .append_header(("Cache-Control", "no-cache"))
+        .streaming(events)
+}
+
+/// What `POST /cynthia/reload` may be told, besides that the publication list changed.
+#[derive(Default, serde::Deserialize)]
+struct ReloadRequest {
+    /// Other files that changed, relative to `./cynthiaFiles`.
+    #[serde(default)]
+    files: Vec<String>,
+}
+
+/// Lets a CMS that pushes content tell Cynthia it changed: rereads the publication list and the files
+/// it names, invalidates what was cached from them and answers with a summary as JSON.
+/// Only answers when `webhooks.reload` is set, and only to requests bearing that secret.
+#[post("/cynthia/reload")]
+pub(crate) async fn reload_webhook(
+    server_context_mutex: Data<Arc<Mutex<ServerContext>>>,
+    req: HttpRequest,
+    body: web::Bytes,
+) -> Result<HttpResponse, ApiError> {
+    let (w_s, w_a) = urlspace();
+    let config_clone = server_context_mutex
+        .lock_callback(|a| {
+            a.request_count += 1;
+            a.config.clone()
+        })
+        .await;
+    let coninfo = req.connection_info().clone();
+    let ip = coninfo.realip_remote_addr().unwrap_or("<unknown IP>");
+    let Some(secret) = config_clone.webhooks.reload.clone() else {
+        return Ok(HttpResponse::NotFound().finish());
+    };
+    let bearer = req
+        .headers()
+        .get("Authorization")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .unwrap_or_default();
+    if !tokens_match(bearer, &secret) {
+        config_clone.tell(format!(
+            "{}\t{:>w_s$.w_a$}\t\t\t{}\t{}",
+            "POST:401".color_error_red(),
+            req.uri().to_string(),
+            ip.color_lightblue(),
+            "reload".color_lilac()
+        ));
+        return Err(ApiError::Unauthorized(String::from(
+            "This webhook needs an `Authorization: Bearer` header with the right secret.",
+        )));
+    }
+    let request = if body.iter().all(u8::is_ascii_whitespace) {
+        ReloadRequest::default()
+    } else {
+        serde_json::from_slice::<ReloadRequest>(&body).map_err(|e| {
+            ApiError::BadRequest(format!(
+                "The request body isn't a valid reload request: {e}"
+            ))
+        })?
+    };
+    let reloaded = crate::watcher::reload(request.files, server_context_mutex.get_ref().clone())
+        .await
+        .map_err(ApiError::Internal)?;
+    config_clone.tell(format!(
+        "{}\t{:>w_s$.w_a$}\t\t\t{}\t{}",
+        "POST:200".color_ok_green(),
+        req.uri().to_string(),
+        ip.color_lightblue(),
+        "reload".color_lilac()
+    ));
+    Ok(HttpResponse::Ok().json(reloaded))
+}
+
+/// Compares without bailing out at the first difference, so the token can't be guessed byte by byte from timings.
+fn tokens_match(given: &str, token: &str) -> bool {
+    given.len() == token.len()
+        && given
+            .bytes()
+            .zip(token.bytes())
+            .fold(0, |difference, (a, b)| difference | (a ^ b))
+            == 0
+}
+
+/// Asks the plugins like [`contact_eps`], giving up after `timeout` seconds (`0` meaning never)
+/// like a render does. `None` when they took too long.
+async fn contact_eps_within_deadline(
+    server_context_mutex: Data<Arc<Mutex<ServerContext>>>,
+    req: EPSRequestBody,
+    timeout: u64,
+) -> Option<crate::externalpluginservers::EPSResponseBody> {
+    let response = contact_eps(server_context_mutex, req);
+    if timeout == 0 {
+        return Some(response.await);
+    }
+    tokio::time::timeout(std::time::Duration::from_secs(timeout), response)
+        .await
+        .ok()
+}
+
+/// Serves the answer of a plugin's `handleRoute` runner.
+fn route_response(
+    req: &HttpRequest,
+    status: u16,
+    headers: Vec<(String, String)>,
+    body: String,
+    config_clone: &CynthiaConfClone,
+) -> HttpResponse {
+    let (w_s, w_a) = urlspace();
+    let status = StatusCode::from_u16(status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+    let coninfo = req.connection_info();
+    let ip = coninfo.realip_remote_addr().unwrap_or("<unknown IP>");
+    let code = format!("{}:{}", req.method(), status.as_u16());
+    config_clone.tell(format!(
+        "{}\t{:>w_s$.w_a$}\t\t\t{}\t{}",
+        if status.is_server_error() {
+            code.color_error_red()
+        } else {
+            code.color_ok_green()
+        },
+        req.uri().to_string(),
+        ip.color_lightblue(),
+        "extern".color_pink()
+    ));
+    let mut response = HttpResponse::build(status);
+    add_headers(&mut response, headers);
+    response.body(body)
+}
+
+/// Cynthia doesn't respond to POST requests, but it's plugins might: the one with a `handleRoute`
+/// prefix the path is under gets the whole request, otherwise `modifyRequest` runners get a go.
 #[post("/{a:.*}")]
 pub(crate) async fn post(
     server_context_mutex: Data<Arc<Mutex<ServerContext>>>,
     req: HttpRequest,
+    body: web::Bytes,
 ) -> impl Responder {
     let (w_s, w_a) = urlspace();
     // We can't lock the mutex here because it wouldn't be usable by EPS, so we need to use a callback.
     // let mut server_context: MutexGuard<ServerContext> = server_context_mutex.lock().await;
-    let config_clone = server_context_mutex
+    let (config_clone, may_route) = server_context_mutex
         .lock_callback(|a| {
             a.request_count += 1;
-            a.config.clone()
+            #[cfg(feature = "js_runtime")]
+            let may_route = a.external_plugin_server.may_route(req.path());
+            #[cfg(not(feature = "js_runtime"))]
+            let may_route = false;
+            (a.config.clone(), may_route)
         })
         .await;
+    // Only asked when a plugin route may hold the path, which saves a round trip for most requests.
+    if may_route {
+        let routed = contact_eps_within_deadline(
+            server_context_mutex.clone(),
+            EPSRequestBody::RouteRequest {
+                method: req.method().to_string(),
+                path: req.path().to_string(),
+                query: req.query_string().to_string(),
+                headers: req
+                    .headers()
+                    .iter()
+                    .map(|(name, value)| {
+                        (
+                            name.to_string(),
+                            value.to_str().unwrap_or_default().to_string(),
+                        )
+                    })
+                    .collect(),
+                body: String::from_utf8_lossy(&body).into_owned(),
+            },
+            config_clone.render_timeout,
+        )
+        .await;
+        match routed {
+            None => return render_timed_out(&req, "waiting for the plugins", &config_clone),
+            Some(crate::externalpluginservers::EPSResponseBody::RouteResponse {
+                status,
+                headers,
+                body,
+            }) => return route_response(&req, status, headers, body, &config_clone),
+            Some(crate::externalpluginservers::EPSResponseBody::Error { message }) => {
+                error!(
+                    "A plugin failed to handle {}: {}",
+                    req.path(),
+                    message.unwrap_or_default()
+                );
+                return HttpResponse::InternalServerError().body("Internal server error.");
+            }
+            // No plugin owns the path.
+            Some(_) => {}
+        }
+    }
 
     let page_uri = if req.uri() == "" {
         "root".to_string()
@@ -367,9 +1652,7 @@ pub(crate) async fn post(
                 "extern".color_pink()
             ));
             let mut response = HttpResponse::build(actix_web::http::StatusCode::OK);
-            for (k, v) in append_headers {
-                response.append_header((k, v));
-            }
+            add_headers(&mut response, append_headers);
             response.body(response_body)
         }
         crate::externalpluginservers::EPSResponseBody::NoneOk
@@ -381,20 +1664,24 @@ pub(crate) async fn post(
 }
 #[actix_web::routes]
 #[get("/category/{c:.*}")]
+#[head("/category/{c:.*}")]
 #[get("/c/{c:.*}")]
+#[head("/c/{c:.*}")]
 #[get("/cat/{c:.*}")]
+#[head("/cat/{c:.*}")]
 async fn category(
     server_context_mutex: Data<Arc<Mutex<ServerContext>>>,
     req: HttpRequest,
 ) -> impl Responder {
     let (w_s, w_a) = urlspace();
-    let c = req.match_info().get("c").unwrap();
+    let c = req.match_info().get("c").unwrap().trim_end_matches('/');
     let virtual_publication = CynthiaPublication::PostList {
         id: format!("category:{}", c),
         title: format!("Category: {}", c),
         short: None,
         filter: crate::publications::PostListFilter::Category(c.to_string()),
         scene_override: None,
+        extra_head: None,
     };
     // We can't lock the mutex here because it wouldn't be usable by EPS, so we need to use a callback.
     // let mut server_context: MutexGuard<ServerContext> = server_context_mutex.lock().await;
@@ -404,6 +1691,9 @@ async fn category(
             a.config.clone()
         })
         .await;
+    if let Some(redirect) = trailing_slash_redirect(&req, config_clone.trailing_slash) {
+        return redirect;
+    }
 
     let page_id_string = format!(
         "virtual:{}",
@@ -429,7 +1719,7 @@ async fn category(
         EPSRequestBody::WebRequest {
             uri: page_uri.clone(),
             headers,
-            method: "get".to_string(),
+            method: req.method().as_str().to_lowercase(),
         },
     )
     .await;
@@ -455,42 +1745,18 @@ async fn category(
                 "extern".color_pink()
             ));
             let mut response = HttpResponse::build(actix_web::http::StatusCode::OK);
-            for (k, v) in append_headers {
-                response.append_header((k, v));
-            }
+            add_headers(&mut response, append_headers);
             return response.body(response_body);
         }
         crate::externalpluginservers::EPSResponseBody::NoneOk
         | crate::externalpluginservers::EPSResponseBody::Disabled => (),
         _ => return HttpResponse::InternalServerError().body("Internal server error."),
     }
-    let from_cache: bool;
-    let cache_result = server_context_mutex
-        .lock_callback(|servercontext| servercontext.get_cache(page_id, 0))
-        .await;
-    let page = match cache_result {
-        Some(c) => {
-            from_cache = true;
-            c
-        }
-        None => {
-            from_cache = false;
-            // Now that we're past the EPS, we can lock the mutex for this scope.
-            let page =
-                render_from_pgid(page_id.parse().unwrap(), server_context_mutex.clone()).await;
-            let mut server_context = server_context_mutex.lock().await;
-            server_context
-                .store_cache(
-                    page_id,
-                    page.clone().unwrap().as_bytes(),
-                    config_clone.clone().cache.lifetimes.served,
-                )
-                .unwrap();
-            server_context
-                .get_cache(page_id, config_clone.clone().cache.lifetimes.served)
-                .unwrap_or(CynthiaCacheExtraction(page.unwrap().as_bytes().to_vec(), 0))
-        }
-    };
+    let (page, from_cache) =
+        match cached_render(page_id, server_context_mutex.clone(), &config_clone).await {
+            Ok(p) => p,
+            Err(failure) => return render_failure(&req, failure, &config_clone),
+        };
 
     let coninfo = req.connection_info();
     let ip = coninfo.realip_remote_addr().unwrap_or("<unknown IP>");
@@ -514,26 +1780,40 @@ async fn category(
             }
         }
     ));
-    HttpResponse::Ok()
-        .append_header(("Content-Type", "text/html; charset=utf-8"))
-        .body(page.0)
+    // Category listings are rendered in the category's scene, if it has one.
+    let mut headers = config_clone
+        .scenes
+        .for_publication(&virtual_publication, &config_clone.site)
+        .unwrap_or_else(|| config_clone.scenes.get_default(&config_clone.site))
+        .response_headers(&config_clone.site.headers);
+    let body = apply_nonce(page.0, &mut headers, &config_clone);
+    let etag = format!("\"{}\"", crate::helpers::content_hash(&body));
+    let mut response = HttpResponse::Ok();
+    add_headers(&mut response, headers);
+    response
+        .insert_header(("Content-Type", "text/html; charset=utf-8"))
+        .insert_header(("ETag", etag))
+        .body(body)
 }
 
 #[actix_web::routes]
 #[get("/tag/{t:.*}")]
+#[head("/tag/{t:.*}")]
 #[get("/t/{t:.*}")]
+#[head("/t/{t:.*}")]
 async fn tags(
     server_context_mutex: Data<Arc<Mutex<ServerContext>>>,
     req: HttpRequest,
 ) -> impl Responder {
     let (w_s, w_a) = urlspace();
-    let t = req.match_info().get("t").unwrap();
+    let t = req.match_info().get("t").unwrap().trim_end_matches('/');
     let virtual_publication = CynthiaPublication::PostList {
         id: format!("tag:{}", t),
         title: format!("Tag: {}", t),
         short: None,
         filter: crate::publications::PostListFilter::Tag(t.to_string()),
         scene_override: None,
+        extra_head: None,
     };
     // We can't lock the mutex here because it wouldn't be usable by EPS, so we need to use a
     // callback.
@@ -544,6 +1824,9 @@ async fn tags(
             a.config.clone()
         })
         .await;
+    if let Some(redirect) = trailing_slash_redirect(&req, config_clone.trailing_slash) {
+        return redirect;
+    }
     let page_id_string = format!(
         "virtual:{}",
         serde_json::to_string(&virtual_publication).unwrap()
@@ -568,7 +1851,7 @@ async fn tags(
         EPSRequestBody::WebRequest {
             uri: page_uri.clone(),
             headers,
-            method: "get".to_string(),
+            method: req.method().as_str().to_lowercase(),
         },
     )
     .await;
@@ -594,42 +1877,18 @@ async fn tags(
                 "extern".color_pink()
             ));
             let mut response = HttpResponse::build(actix_web::http::StatusCode::OK);
-            for (k, v) in append_headers {
-                response.append_header((k, v));
-            }
+            add_headers(&mut response, append_headers);
             return response.body(response_body);
         }
         crate::externalpluginservers::EPSResponseBody::NoneOk
         | crate::externalpluginservers::EPSResponseBody::Disabled => (),
         _ => return HttpResponse::InternalServerError().body("Internal server error."),
     }
-    let from_cache: bool;
-    let cache_result = server_context_mutex
-        .lock_callback(|servercontext| servercontext.get_cache(page_id, 0))
-        .await;
-    let page = match cache_result {
-        Some(c) => {
-            from_cache = true;
-            c
-        }
-        None => {
-            from_cache = false;
-            // Now that we're past the EPS, we can lock the mutex for this scope.
-            let page =
-                render_from_pgid(page_id.parse().unwrap(), server_context_mutex.clone()).await;
-            let mut server_context = server_context_mutex.lock().await;
-            server_context
-                .store_cache(
-                    page_id,
-                    page.clone().unwrap().as_bytes(),
-                    config_clone.clone().cache.lifetimes.served,
-                )
-                .unwrap();
-            server_context
-                .get_cache(page_id, config_clone.clone().cache.lifetimes.served)
-                .unwrap_or(CynthiaCacheExtraction(page.unwrap().as_bytes().to_vec(), 0))
-        }
-    };
+    let (page, from_cache) =
+        match cached_render(page_id, server_context_mutex.clone(), &config_clone).await {
+            Ok(p) => p,
+            Err(failure) => return render_failure(&req, failure, &config_clone),
+        };
 
     let coninfo = req.connection_info();
     let ip = coninfo.realip_remote_addr().unwrap_or("<unknown IP>");
@@ -653,7 +1912,447 @@ async fn tags(
             }
         }
     ));
-    HttpResponse::Ok()
-        .append_header(("Content-Type", "text/html; charset=utf-8"))
-        .body(page.0)
+    // Tag listings are rendered in the default scene.
+    let mut headers = config_clone
+        .scenes
+        .get_default(&config_clone.site)
+        .response_headers(&config_clone.site.headers);
+    let body = apply_nonce(page.0, &mut headers, &config_clone);
+    let etag = format!("\"{}\"", crate::helpers::content_hash(&body));
+    let mut response = HttpResponse::Ok();
+    add_headers(&mut response, headers);
+    response
+        .insert_header(("Content-Type", "text/html; charset=utf-8"))
+        .insert_header(("ETag", etag))
+        .body(body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::CynthiaConf;
+
+    #[test]
+    fn negotiates_json() {
+        let accepting = |accept: Option<&str>| {
+            let mut req = actix_web::test::TestRequest::default();
+            if let Some(accept) = accept {
+                req = req.insert_header(("Accept", accept));
+            }
+            prefers_json(&req.to_http_request())
+        };
+        assert!(!accepting(None));
+        assert!(!accepting(Some("*/*")));
+        assert!(!accepting(Some(
+            "text/html,application/xhtml+xml,*/*;q=0.8"
+        )));
+        assert!(accepting(Some("application/json")));
+        assert!(accepting(Some("application/json, text/html;q=0.5")));
+        assert!(!accepting(Some("application/json;q=0.5, text/html")));
+        assert!(!accepting(Some("application/json;q=0")));
+    }
+
+    #[test]
+    fn redirects_to_local_paths_only() {
+        let location = |path: &str, policy: TrailingSlash| {
+            let req = actix_web::test::TestRequest::with_uri(path).to_http_request();
+            trailing_slash_redirect(&req, policy).map(|response| {
+                response
+                    .headers()
+                    .get("Location")
+                    .unwrap()
+                    .to_str()
+                    .unwrap()
+                    .to_string()
+            })
+        };
+        assert_eq!(
+            location("/about/", TrailingSlash::Remove).as_deref(),
+            Some("/about")
+        );
+        assert_eq!(
+            location("/about", TrailingSlash::Add).as_deref(),
+            Some("/about/")
+        );
+        assert_eq!(location("/about", TrailingSlash::Remove), None);
+        assert_eq!(location("/", TrailingSlash::Remove), None);
+        assert_eq!(
+            location("//evil.com/", TrailingSlash::Remove).as_deref(),
+            Some("/evil.com")
+        );
+        assert_eq!(
+            location("//evil.com", TrailingSlash::Add).as_deref(),
+            Some("/evil.com/")
+        );
+        assert_eq!(location("///", TrailingSlash::Remove).as_deref(), Some("/"));
+        assert_eq!(location("///", TrailingSlash::Add).as_deref(), Some("/"));
+        assert_eq!(location("//evil.com/", TrailingSlash::Keep), None);
+    }
+
+    #[test]
+    fn replaces_single_value_headers() {
+        let mut response = HttpResponse::Ok();
+        add_headers(
+            &mut response,
+            vec![
+                ("Content-Type".to_string(), "text/plain".to_string()),
+                ("Set-Cookie".to_string(), "a=1".to_string()),
+                ("content-type".to_string(), "application/json".to_string()),
+                ("Set-Cookie".to_string(), "b=2".to_string()),
+            ],
+        );
+        let response = response.finish();
+        let content_types: Vec<_> = response.headers().get_all("Content-Type").collect();
+        assert_eq!(content_types, vec!["application/json"]);
+        assert_eq!(response.headers().get_all("Set-Cookie").count(), 2);
+    }
+
+    #[test]
+    fn honours_conditional_headers() {
+        let request = |name: &str, value: &str| {
+            actix_web::test::TestRequest::default()
+                .insert_header((name, value))
+                .to_http_request()
+        };
+        let modified = SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1_700_000_000);
+        let etag = "\"1f-6553f100\"";
+        assert!(not_modified(&request("If-None-Match", etag), etag, None));
+        assert!(not_modified(
+            &request("If-None-Match", "\"other\", W/\"1f-6553f100\""),
+            etag,
+            None
+        ));
+        assert!(!not_modified(
+            &request("If-None-Match", "\"other\""),
+            etag,
+            Some(modified)
+        ));
+        let since = HttpDate::from(modified).to_string();
+        assert!(not_modified(
+            &request("If-Modified-Since", &since),
+            etag,
+            Some(modified)
+        ));
+        let later = modified + std::time::Duration::from_secs(60);
+        assert!(!not_modified(
+            &request("If-Modified-Since", &since),
+            etag,
+            Some(later)
+        ));
+        assert!(!not_modified(
+            &request("If-Modified-Since", &since),
+            etag,
+            None
+        ));
+    }
+
+    #[test]
+    fn last_updated_is_coherent() {
+        use crate::publications::CynthiaPublicationDates;
+        let altered = CynthiaPublicationDates {
+            altered: 1_700_000_000,
+            published: 1_600_000_000,
+        };
+        let never_altered = CynthiaPublicationDates {
+            altered: 0,
+            published: 1_600_000_000,
+        };
+        for dates in [altered, never_altered] {
+            let modified = dates.last_updated().unwrap();
+            let header = HttpDate::from(modified).to_string();
+            let rfc3339 = dates.last_updated_rfc3339(chrono_tz::UTC).unwrap();
+            let from_rfc3339: SystemTime = chrono::DateTime::parse_from_rfc3339(&rfc3339)
+                .unwrap()
+                .into();
+            assert_eq!(
+                SystemTime::from(header.parse::<HttpDate>().unwrap()),
+                modified
+            );
+            assert_eq!(from_rfc3339, modified);
+            assert_eq!(
+                dates.last_updated_display(chrono_tz::UTC).unwrap(),
+                chrono::DateTime::<chrono::Utc>::from(modified)
+                    .format("%B %-d, %Y")
+                    .to_string()
+            );
+            // A client that got the page's date back gets a 304.
+            let req = actix_web::test::TestRequest::default()
+                .insert_header(("If-Modified-Since", header))
+                .to_http_request();
+            assert!(not_modified(&req, "\"other\"", Some(modified)));
+        }
+        assert_eq!(
+            CynthiaPublicationDates::default().last_updated_rfc3339(chrono_tz::UTC),
+            None
+        );
+        // Late in the evening in New York is the next day in UTC.
+        let evening = CynthiaPublicationDates {
+            altered: 0,
+            published: 1_700_000_000 + 3 * 3600,
+        };
+        assert_eq!(
+            evening
+                .last_updated_rfc3339(chrono_tz::America::New_York)
+                .unwrap(),
+            "2023-11-14T20:13:20-05:00"
+        );
+        assert_eq!(
+            evening
+                .last_updated_display(chrono_tz::America::New_York)
+                .unwrap(),
+            "November 14, 2023"
+        );
+        assert_eq!(
+            evening.last_updated_display(chrono_tz::UTC).unwrap(),
+            "November 15, 2023"
+        );
+    }
+
+    #[actix_web::test]
+    async fn answers_other_methods() {
+        let app = actix_web::test::init_service(
+            actix_web::App::new()
+                .service(healthz)
+                .default_service(web::to(unsupported_method)),
+        )
+        .await;
+        let call = |method: actix_web::http::Method| {
+            actix_web::test::TestRequest::default()
+                .method(method)
+                .uri("/healthz")
+                .to_request()
+        };
+        let options =
+            actix_web::test::call_service(&app, call(actix_web::http::Method::OPTIONS)).await;
+        assert_eq!(options.status(), StatusCode::NO_CONTENT);
+        assert_eq!(options.headers().get("Allow").unwrap(), ALLOWED_METHODS);
+        let delete =
+            actix_web::test::call_service(&app, call(actix_web::http::Method::DELETE)).await;
+        assert_eq!(delete.status(), StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(delete.headers().get("Allow").unwrap(), ALLOWED_METHODS);
+        let get = actix_web::test::call_service(&app, call(actix_web::http::Method::GET)).await;
+        assert_eq!(get.status(), StatusCode::OK);
+    }
+
+    #[test]
+    fn maintenance_keeps_assets_and_health_checks() {
+        let mut config = CynthiaConf::default();
+        config.static_mounts = vec![crate::config::StaticMount {
+            prefix: String::from("/files"),
+            path: String::from("./files"),
+        }];
+        assert!(passes_maintenance("/healthz", &config.clone()));
+        assert!(passes_maintenance("/assets/logo.png", &config.clone()));
+        assert!(passes_maintenance("/files/report.pdf", &config.clone()));
+        assert!(!passes_maintenance("/", &config.clone()));
+        assert!(!passes_maintenance("/filesystem", &config.clone()));
+        config.maintenance.assets = false;
+        assert!(passes_maintenance("/healthz", &config.clone()));
+        assert!(passes_maintenance(
+            "/.well-known/acme-challenge/token",
+            &config.clone()
+        ));
+        assert!(!passes_maintenance("/assets/logo.png", &config.clone()));
+    }
+
+    #[test]
+    fn finds_well_known_entries() {
+        let entry = |file: Option<&str>| WellKnown {
+            file: file.map(String::from),
+            content: file
+                .is_none()
+                .then(|| String::from("Contact: mailto:me@example.com")),
+            content_type: None,
+        };
+        let well_known = HashMap::from([
+            (String::from("security.txt"), entry(None)),
+            (String::from("acme-challenge"), entry(Some("./acme"))),
+            (String::from("acme-challenge/special"), entry(None)),
+        ]);
+        let found = |name| well_known_entry(&well_known, name).map(|(e, rest)| (e.clone(), rest));
+        assert_eq!(found("security.txt"), Some((entry(None), None)));
+        assert_eq!(
+            found("acme-challenge/token"),
+            Some((entry(Some("./acme")), Some("token")))
+        );
+        assert_eq!(found("acme-challenge/special"), Some((entry(None), None)));
+        assert_eq!(found("acme-challenge/../Cynthia.toml"), None);
+        assert_eq!(found("security.txt/more"), None);
+        assert_eq!(found("webfinger"), None);
+    }
+
+    #[test]
+    fn json_errors_have_an_envelope() {
+        let response = ApiError::NotFound(String::from("There is no publication at `/nope`."))
+            .error_response();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert_eq!(
+            response.headers().get("Content-Type").unwrap(),
+            "application/json"
+        );
+        let body = actix_web::body::to_bytes(response.into_body());
+        let body: serde_json::Value =
+            serde_json::from_slice(&futures::executor::block_on(body).unwrap()).unwrap();
+        assert_eq!(
+            body,
+            serde_json::json!({
+                "error": {
+                    "code": "not_found",
+                    "message": "There is no publication at `/nope`.",
+                }
+            })
+        );
+        assert_eq!(
+            ApiError::Internal(String::new()).status_code(),
+            StatusCode::INTERNAL_SERVER_ERROR
+        );
+    }
+
+    #[test]
+    fn parses_byte_ranges() {
+        assert_eq!(byte_range("bytes=0-99", 1000), ByteRange::Slice(0, 99));
+        assert_eq!(byte_range("bytes=900-", 1000), ByteRange::Slice(900, 999));
+        assert_eq!(
+            byte_range("bytes=900-5000", 1000),
+            ByteRange::Slice(900, 999)
+        );
+        assert_eq!(byte_range("bytes=-100", 1000), ByteRange::Slice(900, 999));
+        assert_eq!(byte_range("bytes=-5000", 1000), ByteRange::Slice(0, 999));
+        assert_eq!(byte_range("bytes=1000-", 1000), ByteRange::Unsatisfiable);
+        assert_eq!(byte_range("bytes=-0", 1000), ByteRange::Unsatisfiable);
+        assert_eq!(byte_range("bytes=0-1,5-9", 1000), ByteRange::Whole);
+        assert_eq!(byte_range("bytes=9-5", 1000), ByteRange::Whole);
+        assert_eq!(byte_range("lines=0-5", 1000), ByteRange::Whole);
+        assert_eq!(byte_range("bytes=abc", 1000), ByteRange::Whole);
+    }
+
+    #[test]
+    fn pages_declare_their_charset() {
+        let page = "<p>Café, 10 €</p>".as_bytes().to_vec();
+        assert_eq!(
+            in_charset(None, page.clone(), "utf-8"),
+            (String::from("text/html; charset=utf-8"), page.clone())
+        );
+        // The euro sign isn't in ISO-8859-1, which browsers read as windows-1252.
+        assert_eq!(
+            in_charset(None, page.clone(), "iso-8859-1"),
+            (
+                String::from("text/html; charset=windows-1252"),
+                b"<p>Caf\xe9, 10 \x80</p>".to_vec()
+            )
+        );
+        assert_eq!(
+            in_charset(None, page.clone(), "iso-8859-2").1,
+            b"<p>Caf\xe9, 10 &#8364;</p>".to_vec()
+        );
+        assert_eq!(
+            in_charset(
+                Some(String::from("application/rss+xml")),
+                page.clone(),
+                "utf-8"
+            )
+            .0,
+            "application/rss+xml; charset=utf-8"
+        );
+        // Publications that name a charset, or aren't text, are left alone.
+        for content_type in [
+            "text/plain; charset=us-ascii",
+            "image/png",
+            "application/json",
+        ] {
+            assert_eq!(
+                in_charset(Some(content_type.to_string()), page.clone(), "iso-8859-1"),
+                (content_type.to_string(), page.clone())
+            );
+        }
+    }
+
+    #[test]
+    fn tells_when_home_is_missing() {
+        let config = CynthiaConf::default().clone();
+        let body = |response: HttpResponse| {
+            let body = actix_web::body::to_bytes(response.into_body());
+            String::from_utf8(futures::executor::block_on(body).unwrap().to_vec()).unwrap()
+        };
+        let req = actix_web::test::TestRequest::with_uri("/").to_http_request();
+        let response = home_missing(&req, "latest", &config);
+        assert_eq!(
+            response.status(),
+            actix_web::http::StatusCode::INTERNAL_SERVER_ERROR
+        );
+        assert!(body(response).contains("no publication with that id"));
+        let req = actix_web::test::TestRequest::with_uri("/")
+            .insert_header(("Accept", "application/json"))
+            .to_http_request();
+        let json: serde_json::Value =
+            serde_json::from_str(&body(home_missing(&req, "latest", &config))).unwrap();
+        assert_eq!(json["error"]["code"], "internal");
+        assert!(json["error"]["message"]
+            .as_str()
+            .unwrap()
+            .contains("`latest`"));
+    }
+
+    #[test]
+    fn failed_render_is_a_500() {
+        let req = actix_web::test::TestRequest::with_uri("/broken").to_http_request();
+        let config = CynthiaConf::default().clone();
+        let response = render_failed(&req, &[], &config);
+        assert_eq!(
+            response.status(),
+            actix_web::http::StatusCode::INTERNAL_SERVER_ERROR
+        );
+        assert_eq!(
+            response.headers().get("Content-Type").unwrap(),
+            "text/html; charset=utf-8"
+        );
+        let body = |response: HttpResponse| {
+            let body = actix_web::body::to_bytes(response.into_body());
+            String::from_utf8(futures::executor::block_on(body).unwrap().to_vec()).unwrap()
+        };
+        assert!(!body(response).contains("<pre>"));
+        let details = [String::from(
+            "[ERROR] Template file '<default>.hbs' not found.",
+        )];
+        assert!(body(render_failed(&req, &details, &config))
+            .contains("<pre>[ERROR] Template file '&lt;default&gt;.hbs' not found.</pre>"));
+        // A site's error page that doesn't render is replaced by the built-in one.
+        let mut config = CynthiaConf::default();
+        config.error_pages.server_error = Some(String::from("cynthia-missing-error-page"));
+        assert!(body(render_failed(&req, &[], &config.clone()))
+            .contains("<h1>500 Internal Server Error</h1>"));
+    }
+
+    #[test]
+    fn nonce_matches_policy() {
+        let mut config = CynthiaConf::default();
+        config.security.enabled = true;
+        config.security.nonce = true;
+        let config = config.clone();
+        let page = renders::with_nonce_attribute(
+            "<head><style>a{}</style><script>let a;</script><script src=\"/x.js\"></script></head>",
+            renders::NONCE_PLACEHOLDER,
+        );
+        let mut headers = vec![];
+        let body =
+            String::from_utf8(apply_nonce(page.into_bytes(), &mut headers, &config)).unwrap();
+        let policy = &headers
+            .iter()
+            .find(|(name, _)| name == "Content-Security-Policy")
+            .unwrap()
+            .1;
+        let nonce = policy
+            .split("'nonce-")
+            .nth(1)
+            .and_then(|n| n.split('\'').next())
+            .unwrap();
+        assert!(!policy.contains("'unsafe-inline'"));
+        assert_eq!(policy.matches(&format!("'nonce-{}'", nonce)).count(), 2);
+        assert_eq!(body.matches(&format!("nonce=\"{}\"", nonce)).count(), 2);
+        assert!(!body.contains(renders::NONCE_PLACEHOLDER));
+        // Another response gets another nonce.
+        let mut other_headers = vec![];
+        apply_nonce(b"".to_vec(), &mut other_headers, &config);
+        assert_ne!(headers, other_headers);
+    }
 }
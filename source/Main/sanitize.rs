@@ -0,0 +1,65 @@
+/*
+ * Copyright (c) 2024, MLC 'Strawmelonjuice' Bloeiman
+ *
+ * Licensed under the GNU AFFERO GENERAL PUBLIC LICENSE Version 3, see the LICENSE file for more information.
+ */
+//! Cleans the HTML of publications with ammonia, for sites where not every author should be able
+//! to put scripts on a page.
+use crate::config::Sanitize;
+
+/// `html` with only the tags and attributes `sanitize` allows. Scripts and styles are dropped
+/// with their content unless allowed, and links get `rel="noopener noreferrer"`.
+pub(crate) fn clean(html: &str, options: &Sanitize) -> String {
+    let mut builder = ammonia::Builder::default();
+    if !options.tags.is_empty() {
+        builder.tags(options.tags.iter().map(String::as_str).collect());
+    }
+    if !options.attributes.is_empty() {
+        builder.generic_attributes(options.attributes.iter().map(String::as_str).collect());
+    }
+    // ammonia refuses tags that are allowed and dropped with their content at once, and `rel`
+    // attributes next to the one it sets itself.
+    builder.rm_clean_content_tags(&options.tags);
+    if options.attributes.iter().any(|a| a == "rel") {
+        builder.link_rel(None);
+    }
+    builder.clean(html).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strips_scripts_and_handlers() {
+        let options = Sanitize {
+            enabled: true,
+            ..Sanitize::default()
+        };
+        assert_eq!(
+            clean(
+                r#"<p onclick="steal()">Hi <a href="/about" title="About">there</a></p><script>steal()</script>"#,
+                &options
+            ),
+            r#"<p>Hi <a href="/about" title="About" rel="noopener noreferrer">there</a></p>"#
+        );
+        assert_eq!(
+            clean(r#"<a href="javascript:steal()">x</a>"#, &options),
+            r#"<a rel="noopener noreferrer">x</a>"#
+        );
+
+        // With lists of its own, only what's on them stays.
+        let options = Sanitize {
+            enabled: true,
+            tags: vec![String::from("p"), String::from("span")],
+            attributes: vec![String::from("class")],
+        };
+        assert_eq!(
+            clean(
+                r#"<p class="note"><span class="x" style="color: red">a</span> <em>b</em></p>"#,
+                &options
+            ),
+            r#"<p class="note"><span class="x">a</span> b</p>"#
+        );
+    }
+}
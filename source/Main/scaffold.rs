@@ -0,0 +1,201 @@
+/*
+ * Copyright (c) 2024, MLC 'Strawmelonjuice' Bloeiman
+ *
+ * Licensed under the GNU AFFERO GENERAL PUBLIC LICENSE Version 3, see the LICENSE file for more information.
+ */
+//! `cynthiaweb new`: adds a skeleton publication to `published.jsonc`, with an empty content file next to it.
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+use std::{fs, process};
+
+use serde::Serialize;
+use serde_json::json;
+
+use crate::helpers::slugify;
+use crate::publications::{read_published_from_disk, CynthiaPublicationDates};
+use crate::tell::CynthiaColors;
+
+const PUBLISHED: &str = "./cynthiaFiles/published.jsonc";
+
+/// The value following `flag` in the arguments, like `--title "Hello"`.
+fn flag_value(args: &[String], flag: &str) -> Option<String> {
+    args.iter()
+        .position(|a| a == flag)
+        .and_then(|i| args.get(i + 1))
+        .cloned()
+}
+
+fn fail(message: &str) -> ! {
+    eprintln!("{} {}", "error:".color_red(), message);
+    process::exit(1);
+}
+
+/// Runs `cynthiaweb new [post|page] [--title ..] [--id ..] [--mode ..] [--kind ..]`, asking for what's missing.
+pub(crate) fn new_publication(args: &[String]) {
+    let kind = flag_value(args, "--kind")
+        .or_else(|| args.get(2).filter(|a| !a.starts_with("--")).cloned())
+        .unwrap_or_else(|| String::from("post"))
+        .to_ascii_lowercase();
+    if kind != "post" && kind != "page" {
+        fail(&format!(
+            "Can't create a `{kind}`, only a `post` or a `page`."
+        ));
+    }
+    let (markup, extension) = match flag_value(args, "--mode")
+        .unwrap_or_else(|| String::from("markdown"))
+        .to_ascii_lowercase()
+        .as_str()
+    {
+        "markdown" | "md" => ("markdown", "md"),
+        "html" => ("html", "html"),
+        "plaintext" | "text" => ("plaintext", "txt"),
+        other => fail(&format!(
+            "Unknown mode `{other}`, use `markdown`, `html` or `plaintext`."
+        )),
+    };
+    if !Path::new(PUBLISHED).exists() {
+        fail("Couldn't find `./cynthiaFiles/published.jsonc`, `cynthiaweb new` only adds to that file.");
+    }
+    let Some(existing) = read_published_from_disk() else {
+        fail("Couldn't parse `./cynthiaFiles/published.jsonc`, fix it before adding to it.");
+    };
+
+    let title = match flag_value(args, "--title") {
+        Some(title) => title,
+        None => inquire::Text::new(&format!("What is the title of the new {kind}?"))
+            .prompt()
+            .unwrap_or_else(|_| fail("No title given.")),
+    };
+    let id = match flag_value(args, "--id") {
+        Some(id) => id,
+        None => inquire::Text::new("What id should it have?")
+            .with_default(&slugify(&title))
+            .with_help_message("The id is also the publication's URL path.")
+            .prompt()
+            .unwrap_or_else(|_| fail("No id given.")),
+    };
+    if id.is_empty() {
+        fail("The id can't be empty.");
+    }
+    if existing.iter().any(|p| p.get_id() == id) {
+        fail(&format!(
+            "A publication with id `{id}` already exists in `published.jsonc`."
+        ));
+    }
+    let content_file = format!("{id}.{extension}");
+    let content_path = Path::new("./cynthiaFiles/publications/").join(&content_file);
+    if content_path.exists() {
+        fail(&format!(
+            "`{}` already exists, remove it or choose another id.",
+            content_path.display()
+        ));
+    }
+
+    let published = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let skeleton = Skeleton {
+        id: &id,
+        title: &title,
+        short: (kind == "post").then_some(""),
+        description: (kind == "page").then_some(""),
+        tags: (kind == "post").then(Vec::new),
+        content: json!({ "local": { "source": { "as": markup, "value": content_file } } }),
+        dates: CynthiaPublicationDates {
+            altered: 0,
+            published,
+        },
+    };
+    let entry = BTreeMap::from([(kind.as_str(), skeleton)]);
+
+    let jsonc = fs::read_to_string(PUBLISHED)
+        .unwrap_or_else(|e| fail(&format!("Couldn't read `published.jsonc`: {e}")));
+    let Some(jsonc) = append_entry(&jsonc, &entry) else {
+        fail("Couldn't find the end of the list in `published.jsonc`.");
+    };
+    if let Some(parent) = content_path.parent() {
+        fs::create_dir_all(parent)
+            .unwrap_or_else(|e| fail(&format!("Couldn't create `{}`: {e}", parent.display())));
+    }
+    fs::write(&content_path, "").unwrap_or_else(|e| {
+        fail(&format!(
+            "Couldn't create `{}`: {e}",
+            content_path.display()
+        ))
+    });
+    fs::write(PUBLISHED, jsonc)
+        .unwrap_or_else(|e| fail(&format!("Couldn't write `published.jsonc`: {e}")));
+    println!(
+        "Added {} `{}` to `published.jsonc`, write it in `{}`.",
+        kind,
+        id.color_lilac(),
+        content_path.display().to_string().color_lime()
+    );
+}
+
+/// A new publication, in the order its fields are usually written in.
+#[derive(Serialize)]
+struct Skeleton<'a> {
+    id: &'a str,
+    title: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    short: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    description: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tags: Option<Vec<String>>,
+    content: serde_json::Value,
+    dates: CynthiaPublicationDates,
+}
+
+/// Inserts `entry` as the last item of the top-level list in `jsonc`, leaving everything before it untouched.
+/// Returns `None` when the text doesn't end in a list.
+fn append_entry(jsonc: &str, entry: &impl Serialize) -> Option<String> {
+    let end = jsonc.rfind(']')?;
+    let before = jsonc[..end].trim_end();
+    let separator = if before.ends_with('[') || before.ends_with(',') {
+        ""
+    } else {
+        ","
+    };
+    let entry = serde_json::to_string_pretty(entry)
+        .ok()?
+        .lines()
+        .map(|line| {
+            let content = line.trim_start_matches(' ');
+            let depth = (line.len() - content.len()) / 2;
+            format!("\t{}{content}", "\t".repeat(depth))
+        })
+        .collect::<Vec<String>>()
+        .join("\n");
+    Some(format!("{before}{separator}\n{entry}\n{}", &jsonc[end..]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn appends_to_the_list() {
+        let entry = json!({ "page": { "id": "new" } });
+        let appended = append_entry(
+            "// Comment\n[\n\t{ \"page\": { \"id\": \"old\" } }\n]\n",
+            &entry,
+        )
+        .unwrap();
+        assert!(appended.starts_with("// Comment\n[\n\t{ \"page\": { \"id\": \"old\" } },\n\t{"));
+        let parsed: Vec<serde_json::Value> =
+            serde_json::from_str(appended.trim_start_matches("// Comment\n")).unwrap();
+        assert_eq!(parsed.len(), 2);
+        assert_eq!(parsed[1], entry);
+
+        let empty = append_entry("[]", &entry).unwrap();
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&empty).unwrap(),
+            json!([entry])
+        );
+        assert!(append_entry("{}", &entry).is_none());
+    }
+}
@@ -0,0 +1,325 @@
+/*
+ * Copyright (c) 2024, MLC 'Strawmelonjuice' Bloeiman
+ *
+ * Licensed under the GNU AFFERO GENERAL PUBLIC LICENSE Version 3, see the LICENSE file for more information.
+ */
+//! Shortcodes like `{{< youtube dQw4w9WgXcQ >}}` in publication content.
+//!
+//! They are swapped out for placeholders before the content is rendered to HTML, so that Markdown
+//! rendering can't escape or mangle them, and put back in as HTML afterwards.
+use std::collections::BTreeMap;
+use std::ops::Range;
+use std::sync::{Arc, LazyLock};
+
+use actix_web::web::Data;
+use log::warn;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+
+use crate::config::{Sanitize, ShortcodeDelimiters};
+use crate::externalpluginservers::{contact_eps, EPSRequestBody, EPSResponseBody};
+use crate::helpers::escape_html;
+use crate::{LockCallback, ServerContext};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) struct Shortcode {
+    pub(crate) name: String,
+    /// Arguments without a name, in order.
+    pub(crate) positional: Vec<String>,
+    /// Arguments written as `key="value"`.
+    pub(crate) named: BTreeMap<String, String>,
+}
+
+/// Content with its shortcodes replaced by placeholders.
+pub(crate) struct Preprocessed {
+    pub(crate) content: String,
+    expansions: Vec<String>,
+}
+
+impl Preprocessed {
+    /// Puts the expanded shortcodes in place of their placeholders in the rendered HTML.
+    pub(crate) fn restore(self, mut html: String) -> String {
+        for (i, expansion) in self.expansions.iter().enumerate() {
+            let placeholder = placeholder(i);
+            // Shortcodes on their own line end up in a paragraph of their own after Markdown rendering.
+            html = html
+                .replace(&format!("<p>{}</p>", placeholder), expansion)
+                .replace(&placeholder, expansion);
+        }
+        html
+    }
+
+    /// Like [`Preprocessed::restore`], cleaning the result afterwards when `sanitize` is on. What
+    /// shortcodes expand to is cleaned too: their arguments come from the author as well.
+    pub(crate) fn restore_sanitized(self, html: String, sanitize: &Sanitize) -> String {
+        let html = self.restore(html);
+        if sanitize.enabled {
+            crate::sanitize::clean(&html, sanitize)
+        } else {
+            html
+        }
+    }
+}
+
+fn placeholder(i: usize) -> String {
+    format!("CYNTHIASHORTCODE{}X", i)
+}
+
+/// Code in HTML, where shortcodes are shown rather than expanded.
+static HTML_CODE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?is)<pre\b.*?</pre>|<code\b.*?</code>").unwrap());
+/// Fenced code blocks and code spans in Markdown. A fence that isn't closed runs to the end.
+static MARKDOWN_CODE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(
+        r"(?ms)^[ \t]*```.*?(?:^[ \t]*```[ \t]*$|\z)|^[ \t]*~~~.*?(?:^[ \t]*~~~[ \t]*$|\z)|``.+?``|`[^`]+`",
+    )
+    .unwrap()
+});
+
+/// Where the shortcodes in `content` are, delimiters included. Those in code are left out, and so is
+/// Markdown code when `markdown` is set.
+fn find_shortcodes(
+    content: &str,
+    delimiters: &ShortcodeDelimiters,
+    markdown: bool,
+) -> Vec<Range<usize>> {
+    let mut code: Vec<Range<usize>> = HTML_CODE.find_iter(content).map(|m| m.range()).collect();
+    if markdown {
+        code.extend(MARKDOWN_CODE.find_iter(content).map(|m| m.range()));
+    }
+    let mut found = vec![];
+    let mut from = 0;
+    while let Some(offset) = content[from..].find(&delimiters.open) {
+        let start = from + offset;
+        if let Some(skipped) = code.iter().find(|code| code.contains(&start)) {
+            from = skipped.end;
+            continue;
+        }
+        let inside = start + delimiters.open.len();
+        let Some(length) = content[inside..].find(&delimiters.close) else {
+            break;
+        };
+        let end = inside + length + delimiters.close.len();
+        found.push(start..end);
+        from = end;
+    }
+    found
+}
+
+/// Replaces the shortcodes in `content` with placeholders, expanding them through the built-ins
+/// or through plugins. Unknown shortcodes are left as they are, and so are those in code.
+pub(crate) async fn preprocess(
+    content: &str,
+    markdown: bool,
+    server_context_mutex: Data<Arc<Mutex<ServerContext>>>,
+) -> Preprocessed {
+    let delimiters = server_context_mutex
+        .lock_callback(|a| a.config.site.shortcodes.clone())
+        .await;
+    let mut output = String::new();
+    let mut expansions = vec![];
+    let mut copied = 0;
+    for found in find_shortcodes(content, &delimiters, markdown) {
+        let raw = &content[found.clone()];
+        output.push_str(&content[copied..found.start]);
+        copied = found.end;
+        let inner = &raw[delimiters.open.len()..raw.len() - delimiters.close.len()];
+        let expansion = match parse(inner) {
+            Some(shortcode) => match builtin(&shortcode) {
+                Some(html) => Some(html),
+                None => from_plugins(&shortcode, server_context_mutex.clone()).await,
+            },
+            None => None,
+        };
+        match expansion {
+            Some(html) => {
+                output.push_str(&placeholder(expansions.len()));
+                expansions.push(html);
+            }
+            None => {
+                warn!("Unknown shortcode `{}`, leaving it as is.", raw);
+                output.push_str(raw);
+            }
+        }
+    }
+    output.push_str(&content[copied..]);
+    Preprocessed {
+        content: output,
+        expansions,
+    }
+}
+
+async fn from_plugins(
+    shortcode: &Shortcode,
+    server_context_mutex: Data<Arc<Mutex<ServerContext>>>,
+) -> Option<String> {
+    match contact_eps(
+        server_context_mutex,
+        EPSRequestBody::ShortcodeRequest {
+            shortcode: shortcode.clone(),
+        },
+    )
+    .await
+    {
+        EPSResponseBody::OkString { value } => Some(value),
+        _ => None,
+    }
+}
+
+/// Parses the inside of a shortcode: a name followed by arguments, which are either bare words,
+/// quoted strings or `key="value"` pairs.
+fn parse(inner: &str) -> Option<Shortcode> {
+    let mut tokens = vec![];
+    let mut token = String::new();
+    let mut quoted = false;
+    let mut had_quotes = false;
+    for c in inner.trim().chars() {
+        match c {
+            '"' => {
+                quoted = !quoted;
+                had_quotes = true;
+            }
+            c if c.is_whitespace() && !quoted => {
+                if !token.is_empty() || had_quotes {
+                    tokens.push(std::mem::take(&mut token));
+                }
+                had_quotes = false;
+            }
+            c => token.push(c),
+        }
+    }
+    if quoted {
+        return None;
+    }
+    if !token.is_empty() || had_quotes {
+        tokens.push(token);
+    }
+    let mut tokens = tokens.into_iter();
+    let name = tokens.next().filter(|n| !n.is_empty())?;
+    let mut shortcode = Shortcode {
+        name,
+        positional: vec![],
+        named: BTreeMap::new(),
+    };
+    for token in tokens {
+        match token.split_once('=') {
+            Some((key, value)) if !key.is_empty() => {
+                shortcode.named.insert(key.to_string(), value.to_string());
+            }
+            _ => shortcode.positional.push(token),
+        }
+    }
+    Some(shortcode)
+}
+
+/// Expands the shortcodes Cynthia knows by itself.
+fn builtin(shortcode: &Shortcode) -> Option<String> {
+    let arg = |key: &str, position: usize| {
+        shortcode
+            .named
+            .get(key)
+            .or_else(|| shortcode.positional.get(position))
+            .map(|v| escape_html(v))
+    };
+    match shortcode.name.as_str() {
+        "youtube" => {
+            let id = arg("id", 0)?;
+            Some(format!(
+                "<iframe class=\"cynthia-youtube\" src=\"https://www.youtube-nocookie.com/embed/{id}\" title=\"YouTube video\" allowfullscreen loading=\"lazy\"></iframe>"
+            ))
+        }
+        "gist" => {
+            let user = arg("user", 0)?;
+            let id = arg("id", 1)?;
+            Some(format!(
+                "<script src=\"https://gist.github.com/{user}/{id}.js\"></script>"
+            ))
+        }
+        "figure" => {
+            let src = arg("src", 0)?;
+            let alt = arg("alt", 1).unwrap_or_default();
+            let caption = match arg("caption", 2) {
+                Some(c) => format!("<figcaption>{c}</figcaption>"),
+                None => String::new(),
+            };
+            Some(format!(
+                "<figure><img src=\"{src}\" alt=\"{alt}\" />{caption}</figure>"
+            ))
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_arguments() {
+        let shortcode = parse(r#" figure "a b.png" caption="Hello there" "#).unwrap();
+        assert_eq!(shortcode.name, "figure");
+        assert_eq!(shortcode.positional, vec!["a b.png"]);
+        assert_eq!(shortcode.named.get("caption").unwrap(), "Hello there");
+        assert!(parse(r#"figure "unclosed"#).is_none());
+        assert!(parse("  ").is_none());
+    }
+
+    #[test]
+    fn leaves_shortcodes_in_code() {
+        let delimiters = ShortcodeDelimiters::default();
+        let found = |content: &str, markdown: bool| {
+            find_shortcodes(content, &delimiters, markdown)
+                .into_iter()
+                .map(|range| content[range].to_string())
+                .collect::<Vec<String>>()
+        };
+        let markdown = "Use {{< youtube a >}} like `{{< youtube b >}}`.\n\n```\n{{< youtube c >}}\n```\n\n{{< youtube d >}}";
+        assert_eq!(
+            found(markdown, true),
+            vec!["{{< youtube a >}}", "{{< youtube d >}}"]
+        );
+        let html = "<p>{{< youtube a >}}</p><pre><code>{{< youtube b >}}</code></pre><p>`{{< youtube c >}}`</p>";
+        assert_eq!(
+            found(html, false),
+            vec!["{{< youtube a >}}", "{{< youtube c >}}"]
+        );
+        // Unclosed fences take the rest of the content.
+        assert!(found("```\n{{< youtube a >}}", true).is_empty());
+    }
+
+    #[test]
+    fn expands_builtins() {
+        let youtube = builtin(&parse("youtube dQw4w9WgXcQ").unwrap()).unwrap();
+        assert!(youtube.contains("/embed/dQw4w9WgXcQ"));
+        assert!(builtin(&parse("youtube").unwrap()).is_none());
+        assert!(builtin(&parse("unknown thing").unwrap()).is_none());
+    }
+
+    #[test]
+    fn restores_placeholders() {
+        let preprocessed = Preprocessed {
+            content: String::new(),
+            expansions: vec![String::from("<hr />")],
+        };
+        let html = format!("<p>{}</p><p>a {} b</p>", placeholder(0), placeholder(0));
+        assert_eq!(preprocessed.restore(html), "<hr /><p>a <hr /> b</p>");
+    }
+
+    #[test]
+    fn sanitizes_expansions() {
+        let gist = builtin(&parse("gist attacker id").unwrap()).unwrap();
+        let preprocessed = Preprocessed {
+            content: String::new(),
+            expansions: vec![gist],
+        };
+        let sanitize = Sanitize {
+            enabled: true,
+            ..Sanitize::default()
+        };
+        assert_eq!(
+            preprocessed.restore_sanitized(format!("<p>Look:</p>\n{}", placeholder(0)), &sanitize),
+            "<p>Look:</p>\n"
+        );
+    }
+}
@@ -0,0 +1,97 @@
+/*
+ * Copyright (c) 2024, MLC 'Strawmelonjuice' Bloeiman
+ *
+ * Licensed under the GNU AFFERO GENERAL PUBLIC LICENSE Version 3, see the LICENSE file for more information.
+ */
+//! Measures where the time handling a request went, and reports it in response headers when
+//! `debug.timing_header` is on.
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header::{HeaderName, HeaderValue};
+use actix_web::middleware::Next;
+use actix_web::web::Data;
+
+use crate::{LockCallback, ServerContext};
+
+tokio::task_local! {
+    static TIMINGS: Arc<Mutex<Timings>>;
+}
+
+#[derive(Default, Clone, Copy)]
+struct Timings {
+    /// Only set when the page was rendered, not taken from the cache.
+    render: Option<Duration>,
+    plugins: Duration,
+}
+
+/// What a [`Stopwatch`] is timing.
+pub(crate) enum Part {
+    Render,
+    Plugins,
+}
+
+/// Adds the time from its start until it's dropped to a part of the current request, so it also counts
+/// when the future timing it is dropped halfway. Does nothing while the timing header is off.
+pub(crate) struct Stopwatch(Part, Instant);
+
+impl Stopwatch {
+    pub(crate) fn start(part: Part) -> Self {
+        Stopwatch(part, Instant::now())
+    }
+}
+
+impl Drop for Stopwatch {
+    fn drop(&mut self) {
+        let elapsed = self.1.elapsed();
+        let _ = TIMINGS.try_with(|t| {
+            if let Ok(mut t) = t.lock() {
+                match self.0 {
+                    Part::Render => t.render = Some(t.render.unwrap_or_default() + elapsed),
+                    Part::Plugins => t.plugins += elapsed,
+                }
+            }
+        });
+    }
+}
+
+fn milliseconds(duration: Duration) -> HeaderValue {
+    HeaderValue::from_str(&format!("{:.1}", duration.as_secs_f64() * 1000.0))
+        .unwrap_or(HeaderValue::from_static("0"))
+}
+
+/// Adds `X-Cynthia-Render-Time` and `X-Cynthia-Plugin-Time` to responses, in milliseconds, when
+/// `debug.timing_header` is on. Checked on every request, so `SIGHUP` toggles it.
+pub(crate) async fn timing_headers<B: MessageBody>(
+    req: ServiceRequest,
+    next: Next<B>,
+) -> Result<ServiceResponse<B>, actix_web::Error> {
+    let enabled = match req.app_data::<Data<Arc<tokio::sync::Mutex<ServerContext>>>>() {
+        Some(server_context_mutex) => {
+            server_context_mutex
+                .lock_callback(|a| a.config.debug.timing_header)
+                .await
+        }
+        None => false,
+    };
+    if !enabled {
+        return next.call(req).await;
+    }
+    let timings = Arc::new(Mutex::new(Timings::default()));
+    let mut response = TIMINGS.scope(timings.clone(), next.call(req)).await?;
+    let timings = timings.lock().map(|t| *t).unwrap_or_default();
+    let headers = response.headers_mut();
+    if let Some(render) = timings.render {
+        headers.insert(
+            HeaderName::from_static("x-cynthia-render-time"),
+            milliseconds(render),
+        );
+    }
+    headers.insert(
+        HeaderName::from_static("x-cynthia-plugin-time"),
+        milliseconds(timings.plugins),
+    );
+    Ok(response)
+}
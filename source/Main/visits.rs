@@ -0,0 +1,165 @@
+/*
+ * Copyright (c) 2024, MLC 'Strawmelonjuice' Bloeiman
+ *
+ * Licensed under the GNU AFFERO GENERAL PUBLIC LICENSE Version 3, see the LICENSE file for more information.
+ */
+//! Counts visits per publication, kept in memory and written to `visits.file` now and then.
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use log::{debug, error, warn};
+use tokio::sync::Mutex;
+
+use crate::{LockCallback, ServerContext};
+
+/// Visit counts by publication id. Counting a publication seen before only takes a shared lock.
+#[derive(Debug, Default)]
+pub(crate) struct Visits {
+    counts: RwLock<HashMap<String, AtomicU64>>,
+    /// Whether anything was counted since the counts were last written.
+    changed: AtomicBool,
+}
+impl Visits {
+    /// Reads the counts written by an earlier run, starting from zero when there are none.
+    pub(crate) fn load(file: &Path) -> Visits {
+        let counts: HashMap<String, u64> = match std::fs::read_to_string(file) {
+            Ok(json) => serde_json::from_str(&json).unwrap_or_else(|e| {
+                warn!(
+                    "Could not read the visit counts in {}, starting from zero: {}",
+                    file.display(),
+                    e
+                );
+                HashMap::new()
+            }),
+            Err(_) => HashMap::new(),
+        };
+        Visits {
+            counts: RwLock::new(
+                counts
+                    .into_iter()
+                    .map(|(id, count)| (id, AtomicU64::new(count)))
+                    .collect(),
+            ),
+            changed: AtomicBool::new(false),
+        }
+    }
+
+    /// Counts one visit to the publication `id`.
+    pub(crate) fn count(&self, id: &str) {
+        self.changed.store(true, Ordering::Relaxed);
+        if let Some(count) = self.counts.read().unwrap().get(id) {
+            count.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+        self.counts
+            .write()
+            .unwrap()
+            .entry(id.to_string())
+            .or_default()
+            .fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// The visits to the publication `id` so far.
+    pub(crate) fn get(&self, id: &str) -> u64 {
+        self.counts
+            .read()
+            .unwrap()
+            .get(id)
+            .map(|count| count.load(Ordering::Relaxed))
+            .unwrap_or(0)
+    }
+
+    /// Every count, by publication id.
+    pub(crate) fn all(&self) -> BTreeMap<String, u64> {
+        self.counts
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(id, count)| (id.clone(), count.load(Ordering::Relaxed)))
+            .collect()
+    }
+
+    /// Writes the counts to `file` when they changed since the last write. Written atomically, so a
+    /// crash halfway through doesn't lose the counts from before.
+    pub(crate) fn flush(&self, file: &Path) -> std::io::Result<()> {
+        if !self.changed.swap(false, Ordering::Relaxed) {
+            return Ok(());
+        }
+        let json = serde_json::to_string_pretty(&self.all()).unwrap_or_default();
+        if let Some(dir) = file.parent().filter(|d| !d.as_os_str().is_empty()) {
+            std::fs::create_dir_all(dir)?;
+        }
+        let written = crate::helpers::atomic_write(file, json);
+        if written.is_err() {
+            // Tried again on the next flush.
+            self.changed.store(true, Ordering::Relaxed);
+        }
+        written
+    }
+}
+
+/// Writes the counts from the server context to `visits.file`, if persisting them is enabled.
+pub(crate) async fn flush(server_context_mutex: &Arc<Mutex<ServerContext>>) {
+    let (options, visits) = server_context_mutex
+        .lock_callback(|a| (a.config.visits.clone(), a.visits.clone()))
+        .await;
+    if !options.enabled || options.file.is_empty() {
+        return;
+    }
+    match visits.flush(Path::new(&options.file)) {
+        Ok(_) => debug!("Visit counts written to {}.", options.file),
+        Err(e) => error!(
+            "Could not write the visit counts to {}: {}",
+            options.file, e
+        ),
+    }
+}
+
+/// Writes the visit counts every `visits.flush_interval` seconds. Read every round, so a reload changes it.
+pub(crate) async fn flusher(server_context_mutex: Arc<Mutex<ServerContext>>) {
+    loop {
+        let interval = server_context_mutex
+            .lock_callback(|a| a.config.visits.flush_interval)
+            .await;
+        if interval == 0 {
+            // Only written on shutdown then. Checked again now and then, in case a reload sets an interval.
+            tokio::time::sleep(Duration::from_secs(60)).await;
+            continue;
+        }
+        tokio::time::sleep(Duration::from_secs(interval)).await;
+        flush(&server_context_mutex).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_survive_a_restart() {
+        let file = std::env::temp_dir()
+            .join(format!("cynthia-visits-{}", std::process::id()))
+            .join("visits.json");
+        let _ = std::fs::remove_file(&file);
+        let visits = Visits::load(&file);
+        visits.count("about");
+        visits.count("about");
+        visits.count("root");
+        assert_eq!(visits.get("about"), 2);
+        assert_eq!(visits.get("contact"), 0);
+        visits.flush(&file).unwrap();
+
+        let visits = Visits::load(&file);
+        assert_eq!(visits.get("about"), 2);
+        assert_eq!(visits.get("root"), 1);
+        visits.count("root");
+        assert_eq!(
+            visits.all(),
+            BTreeMap::from([(String::from("about"), 2), (String::from("root"), 2)])
+        );
+        let _ = std::fs::remove_dir_all(file.parent().unwrap());
+    }
+}
@@ -0,0 +1,103 @@
+/*
+ * Copyright (c) 2024, MLC 'Strawmelonjuice' Bloeiman
+ *
+ * Licensed under the GNU AFFERO GENERAL PUBLIC LICENSE Version 3, see the LICENSE file for more information.
+ */
+//! Renders every publication into `prerender_dir` ahead of time, so no visitor has to wait for a first render
+//! after a deploy. Used by `cynthiaweb warm`.
+use std::path::Path;
+use std::sync::Arc;
+
+use actix_web::web::Data;
+use futures::{stream, StreamExt};
+use tokio::sync::Mutex;
+
+use crate::publications::{CynthiaPublicationList, CynthiaPublicationListTrait};
+use crate::renders::{prerendered_file, render_from_pgid, render_with_deadline, RenderrerResponse};
+use crate::tell::CynthiaColors;
+use crate::{LockCallback, ServerContext};
+
+/// Renders every page, post and post list, `jobs` at a time, and writes them to `dir` under the names
+/// [`crate::renders::prerendered_page`] looks for. The root publication is written twice, for `/` and
+/// for its own id. Prints what was written and what failed.
+/// Returns whether every publication rendered.
+pub(crate) async fn warm(
+    server_context_mutex: Data<Arc<Mutex<ServerContext>>>,
+    dir: &str,
+    jobs: usize,
+) -> bool {
+    let timeout = server_context_mutex
+        .lock_callback(|a| a.config.render_timeout)
+        .await;
+    let published = CynthiaPublicationList::load(server_context_mutex.clone()).await;
+    let root = published.get_root().map(|p| p.get_id());
+    let results = stream::iter(published.iter())
+        .map(|publication| {
+            let server_context_mutex = server_context_mutex.clone();
+            let id = publication.get_id();
+            let mut files = vec![prerendered_file(publication, false)];
+            if root.as_ref() == Some(&id) {
+                files.insert(0, prerendered_file(publication, true));
+            }
+            let files = files.into_iter().flatten().collect::<Vec<String>>();
+            async move {
+                if files.is_empty() {
+                    return (id, Err(String::from("its id can't be used as a file name")));
+                }
+                let rendered = render_with_deadline(
+                    timeout,
+                    render_from_pgid(id.clone(), server_context_mutex),
+                )
+                .await;
+                let result = match rendered {
+                    Ok(RenderrerResponse::Ok(page)) => files
+                        .iter()
+                        .map(|file| {
+                            let path = Path::new(dir).join(file);
+                            path.parent()
+                                .map_or(Ok(()), std::fs::create_dir_all)
+                                // A running server may be serving from this directory.
+                                .and_then(|_| crate::helpers::atomic_write(&path, &page))
+                                .map_err(|e| format!("couldn't write `{}`: {}", path.display(), e))
+                        })
+                        .collect::<Result<Vec<()>, String>>()
+                        .map(|_| files.join(", ")),
+                    Ok(_) => Err(String::from("it failed to render, see the log")),
+                    Err(stage) => Err(format!("render timed out while {}", stage)),
+                };
+                (id, result)
+            }
+        })
+        .buffer_unordered(jobs.max(1))
+        .collect::<Vec<(String, Result<String, String>)>>()
+        .await;
+    let mut failed = 0;
+    for (id, result) in &results {
+        match result {
+            Ok(file) => println!("\t{}\t{}\t{}", "warmed".color_ok_green(), id, file),
+            Err(e) => {
+                failed += 1;
+                println!("\t{}\t{}\t{}", "failed".color_error_red(), id, e);
+            }
+        }
+    }
+    if failed == 0 {
+        println!(
+            "{}",
+            format!(
+                "Rendered all {} publications into `{}`.",
+                results.len(),
+                dir
+            )
+            .color_ok_green()
+        );
+    } else {
+        eprintln!(
+            "{} {} of {} publications could not be rendered, see above.",
+            "error:".color_red(),
+            failed,
+            results.len()
+        );
+    }
+    failed == 0
+}
@@ -0,0 +1,410 @@
+/*
+ * Copyright (c) 2024, MLC 'Strawmelonjuice' Bloeiman
+ *
+ * Licensed under the GNU AFFERO GENERAL PUBLIC LICENSE Version 3, see the LICENSE file for more information.
+ */
+//! Watches `./cynthiaFiles` and `./cynthiaPlugins` when Cynthia is started with `--watch`.
+//! Changed files are mapped to the cache entries that were built from them, and only those are
+//! invalidated, so editing one post doesn't throw away every other rendered page.
+//! Changed plugins are checked again and the plugin runtime is restarted to load them.
+//! `POST /cynthia/reload` goes through the same mapping for the files it's told about, see [`reload`].
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use actix_web::web::Data;
+use log::{debug, info, warn};
+use serde::Serialize;
+use tokio::sync::Mutex;
+
+use crate::config::{Scene, SceneCollection, SceneCollectionTrait, Site};
+use crate::publications::{
+    published_files, read_published_from_disk, CynthiaPublication, CynthiaPublicationList,
+    CynthiaPublicationListTrait, PublicationContent,
+};
+use crate::{externalpluginservers, plugincheck, LockCallback, ServerContext};
+
+/// Modification times of every file under a directory, keyed by their path relative to it.
+type Snapshot = HashMap<String, SystemTime>;
+
+/// Written by installing a plugin's dependencies, which the plugin runtime does on every start.
+const INSTALLED: [&str; 3] = ["node_modules", "package-lock.json", "bun.lockb"];
+
+pub(crate) async fn main(server_context_mutex: Arc<Mutex<ServerContext>>) {
+    if !std::env::args().any(|a| a == "--watch") {
+        return;
+    }
+    info!("Watching ./cynthiaFiles and ./cynthiaPlugins for changes.");
+    let mut snapshot = take_snapshot(Path::new("./cynthiaFiles"));
+    let mut plugin_snapshot = take_snapshot(Path::new("./cynthiaPlugins"));
+    let mut publications = read_published_from_disk().unwrap_or_default();
+    let mut interval = tokio::time::interval(Duration::from_secs(1));
+    loop {
+        interval.tick().await;
+        let new_plugin_snapshot = take_snapshot(Path::new("./cynthiaPlugins"));
+        let changed_plugins: HashSet<String> = changes(&plugin_snapshot, &new_plugin_snapshot)
+            .into_iter()
+            .filter_map(|file| file.split('/').next().map(str::to_string))
+            .collect();
+        plugin_snapshot = new_plugin_snapshot;
+        if !changed_plugins.is_empty()
+            && reload_changed_plugins(changed_plugins, &publications, server_context_mutex.clone())
+                .await
+        {
+            // Starting the plugins may have built files of their own, those aren't changes to reload for.
+            plugin_snapshot = take_snapshot(Path::new("./cynthiaPlugins"));
+        }
+        let new_snapshot = take_snapshot(Path::new("./cynthiaFiles"));
+        let changed = changes(&snapshot, &new_snapshot);
+        snapshot = new_snapshot;
+        if changed.is_empty() {
+            continue;
+        }
+        debug!("Changed files: {:?}", changed);
+        // Keep the old list when the new one doesn't parse (yet), it's probably still being edited.
+        let new_publications = read_published_from_disk().unwrap_or_else(|| publications.clone());
+        let (scenes, site) = server_context_mutex
+            .lock_callback(|a| (a.config.scenes.clone(), a.config.site.clone()))
+            .await;
+        let affected = Affected::of(&changed, &publications, &new_publications, &scenes, &site);
+        publications = new_publications;
+        let (invalidated, retained) = server_context_mutex
+            .lock_callback(|a| a.invalidate_cache(|key| affected.contains(key)))
+            .await;
+        info!(
+            "{} file(s) changed, invalidated {} cache entries and kept {}.",
+            changed.len(),
+            invalidated,
+            retained
+        );
+    }
+}
+
+/// What a reload through `POST /cynthia/reload` did, sent back as its response.
+#[derive(Debug, Serialize)]
+pub(crate) struct Reloaded {
+    /// The files that were reread, relative to `./cynthiaFiles`.
+    files: Vec<String>,
+    /// Ids of the publications that were added to, removed from or changed in the publication list.
+    publications: Vec<String>,
+    invalidated: usize,
+    retained: usize,
+}
+
+/// Rereads the publication list, and invalidates the cache entries built from it and from `files` (relative
+/// to `./cynthiaFiles`, like `publications/hello.md`), as if `--watch` saw them change. When the publication
+/// list doesn't parse, the cache is left alone.
+pub(crate) async fn reload(
+    files: Vec<String>,
+    server_context_mutex: Arc<Mutex<ServerContext>>,
+) -> Result<Reloaded, String> {
+    let Some(new_publications) = read_published_from_disk() else {
+        return Err(String::from(
+            "The publication list couldn't be read, nothing was reloaded.",
+        ));
+    };
+    // Still the list as the server knows it, from the cached files.
+    let publications = CynthiaPublicationList::load(Data::new(server_context_mutex.clone())).await;
+    let mut changed = published_files()
+        .iter()
+        .filter_map(|file| {
+            file.to_str()?
+                .strip_prefix("./cynthiaFiles/")
+                .map(str::to_string)
+        })
+        .collect::<Vec<String>>();
+    changed.extend(files.iter().map(|file| {
+        file.trim_start_matches("./")
+            .trim_start_matches('/')
+            .to_string()
+    }));
+    changed.sort();
+    changed.dedup();
+    let mut ids = publications
+        .iter()
+        .filter(|p| !new_publications.contains(p))
+        .chain(
+            new_publications
+                .iter()
+                .filter(|p| !publications.contains(p)),
+        )
+        .map(|p| p.get_id())
+        .collect::<Vec<String>>();
+    ids.sort();
+    ids.dedup();
+    let (scenes, site) = server_context_mutex
+        .lock_callback(|a| (a.config.scenes.clone(), a.config.site.clone()))
+        .await;
+    let affected = Affected::of(&changed, &publications, &new_publications, &scenes, &site);
+    let (invalidated, retained) = server_context_mutex
+        .lock_callback(|a| a.invalidate_cache(|key| affected.contains(key)))
+        .await;
+    info!(
+        "Reloaded through the webhook, invalidated {} cache entries and kept {}.",
+        invalidated, retained
+    );
+    Ok(Reloaded {
+        files: changed,
+        publications: ids,
+        invalidated,
+        retained,
+    })
+}
+
+/// Checks the plugins that changed on disk and restarts the plugin runtime to load them again.
+/// Every page passes through the plugins, so all rendered pages and kept plugin results are invalidated.
+/// Returns whether the plugins were reloaded.
+async fn reload_changed_plugins(
+    changed: HashSet<String>,
+    publications: &CynthiaPublicationList,
+    server_context_mutex: Arc<Mutex<ServerContext>>,
+) -> bool {
+    let (config, runtime) = server_context_mutex
+        .lock_callback(|a| (a.config.clone(), plugincheck::runtime_binary(&a.config)))
+        .await;
+    let mut reload = false;
+    for name in changed {
+        let loaded = config
+            .plugins
+            .iter()
+            .any(|plugin| plugin.get_name() == name && plugin.runs(&config.plugin_options));
+        if !loaded {
+            debug!(
+                "Plugin {} changed, but isn't enabled in the configuration.",
+                name
+            );
+            continue;
+        }
+        reload = true;
+        if !Path::new("./cynthiaPlugins").join(&name).is_dir() {
+            warn!(
+                "Plugin {} was removed, but is still enabled in the configuration.",
+                name
+            );
+        } else if let Err(e) = plugincheck::check_plugin(&name, &runtime) {
+            warn!("Plugin {} changed, but won't load: {}", name, e);
+        } else {
+            info!("Plugin {} changed, reloading the plugins.", name);
+        }
+    }
+    if !reload {
+        return false;
+    }
+    let mut affected = Affected {
+        virtual_lists: true,
+        ..Affected::default()
+    };
+    for publication in publications {
+        affected.publication(publication);
+    }
+    let (invalidated, _) = server_context_mutex
+        .lock_callback(|a| {
+            a.invalidate_cache(|key| {
+                affected.contains(key)
+                    || key.starts_with(externalpluginservers::RESULT_CACHE_PREFIX)
+            })
+        })
+        .await;
+    externalpluginservers::reload_plugins(Data::new(server_context_mutex)).await;
+    info!(
+        "Plugins reloaded, invalidated {} cache entries.",
+        invalidated
+    );
+    true
+}
+
+/// The files that were added, removed or modified between two snapshots.
+fn changes(old: &Snapshot, new: &Snapshot) -> Vec<String> {
+    old.keys()
+        .chain(new.keys())
+        .filter(|file| old.get(*file) != new.get(*file))
+        .cloned()
+        .collect::<HashSet<String>>()
+        .into_iter()
+        .collect()
+}
+
+fn take_snapshot(root: &Path) -> Snapshot {
+    fn walk(dir: &Path, prefix: &str, snapshot: &mut Snapshot) {
+        let Ok(entries) = std::fs::read_dir(dir) else {
+            return;
+        };
+        for entry in entries.flatten() {
+            let name = format!("{}{}", prefix, entry.file_name().to_string_lossy());
+            if INSTALLED.contains(&&*entry.file_name().to_string_lossy()) {
+                continue;
+            }
+            let Ok(metadata) = entry.metadata() else {
+                continue;
+            };
+            if metadata.is_dir() {
+                walk(&entry.path(), &format!("{}/", name), snapshot);
+            } else if let Ok(modified) = metadata.modified() {
+                snapshot.insert(name, modified);
+            }
+        }
+    }
+    let mut snapshot = Snapshot::new();
+    walk(root, "", &mut snapshot);
+    snapshot
+}
+
+/// The cache entries affected by a set of changed files.
+#[derive(Default)]
+struct Affected {
+    keys: HashSet<String>,
+    /// Category and tag listings are cached under `virtual:` keys.
+    virtual_lists: bool,
+    posts_changed: bool,
+}
+
+impl Affected {
+    fn of(
+        changed: &[String],
+        old: &CynthiaPublicationList,
+        new: &CynthiaPublicationList,
+        scenes: &SceneCollection,
+        site: &Site,
+    ) -> Self {
+        let mut affected = Affected::default();
+        for file in changed {
+            // Anything read through `files::fs_get` is stale now.
+            affected.keys.insert(format!("fs:./cynthiaFiles/{}", file));
+            if file.starts_with("published.") || file.starts_with("published/") {
+                affected
+                    .keys
+                    .insert(crate::publications::LIST_CACHE_KEY.to_string());
+                for publication in old
+                    .iter()
+                    .filter(|p| !new.contains(p))
+                    .chain(new.iter().filter(|p| !old.contains(p)))
+                {
+                    affected.publication(publication);
+                }
+            } else if let Some(source) = file.strip_prefix("publications/") {
+                for publication in new
+                    .iter()
+                    .filter(|p| local_source(p).as_deref() == Some(source))
+                {
+                    affected.publication(publication);
+                }
+            } else if let Some(template) = file.strip_prefix("templates/") {
+                // Templates live at `templates/{kind}/{name}.hbs`.
+                if let Some((kind, name)) = template
+                    .strip_suffix(".hbs")
+                    .and_then(|t| t.split_once('/'))
+                {
+                    affected.scenes_using(new, scenes, site, Some(kind), |scene| match kind {
+                        "page" => scene.templates.page == name,
+                        "post" => {
+                            scene.templates.post == name
+                                || scene.templates.categories.values().any(|t| t == name)
+                        }
+                        "postlist" => scene.templates.postlist == name,
+                        _ => false,
+                    });
+                }
+            } else if let Some(asset) = file.strip_prefix("assets/") {
+                affected.keys.insert(asset.to_string());
+                if let Some(stem) = Path::new(asset).file_stem() {
+                    let stem = stem.to_string_lossy();
+                    affected.keys.insert(format!("css:{}", stem));
+                    affected.keys.insert(format!("script:{}", stem));
+                }
+                // Stylesheets and scripts are inlined into the pages of the scenes using them.
+                affected.scenes_using(new, scenes, site, None, |scene| {
+                    [&scene.stylefile, &scene.client_script(site)]
+                        .iter()
+                        .any(|f| f.as_deref().map(|f| f.trim_start_matches('/')) == Some(asset))
+                });
+            }
+        }
+        if affected.posts_changed {
+            affected.virtual_lists = true;
+            for publication in new {
+                if let CynthiaPublication::PostList { .. } = publication {
+                    affected.publication(publication);
+                }
+            }
+        }
+        affected
+    }
+
+    fn publication(&mut self, publication: &CynthiaPublication) {
+        let id = publication.get_id();
+        if matches!(id.as_str(), "root" | "" | "/") {
+            // The root page is cached under whatever its request came in as.
+            self.keys
+                .extend(["root", "", "/"].iter().map(|k| k.to_string()));
+        } else {
+            self.keys.insert(id);
+        }
+        if let CynthiaPublication::Post { id, .. } = publication {
+            self.keys.insert(crate::publications::excerpt_cache_key(id));
+            self.posts_changed = true;
+        }
+    }
+
+    /// Marks the publications rendered in a scene that `uses` the changed file, optionally only those of `kind`.
+    fn scenes_using(
+        &mut self,
+        publications: &CynthiaPublicationList,
+        scenes: &SceneCollection,
+        site: &Site,
+        kind: Option<&str>,
+        uses: impl Fn(&Scene) -> bool,
+    ) {
+        for publication in publications {
+            let scene = scenes.for_publication(publication, site);
+            if scene.is_some_and(|s| uses(&s)) && kind.is_none_or(|k| kind_of(publication) == k) {
+                self.publication(publication);
+            }
+        }
+        // Category and tag listings are rendered in the default scene, or in a category's own.
+        let mut listing_scenes = std::iter::once(scenes.get_default(site)).chain(
+            site.category_scenes
+                .values()
+                .filter_map(|name| scenes.get_by_name(name)),
+        );
+        if !scenes.is_empty()
+            && listing_scenes.any(|s| uses(&s))
+            && kind.is_none_or(|k| k == "postlist")
+        {
+            self.virtual_lists = true;
+        }
+    }
+
+    fn contains(&self, key: &str) -> bool {
+        self.keys.contains(key) || (self.virtual_lists && key.starts_with("virtual:"))
+    }
+}
+
+fn kind_of(publication: &CynthiaPublication) -> &'static str {
+    match publication {
+        CynthiaPublication::Page { .. } => "page",
+        CynthiaPublication::Post { .. } => "post",
+        CynthiaPublication::PostList { .. } => "postlist",
+    }
+}
+
+/// The path of a publication's content file, relative to `./cynthiaFiles/publications/`.
+fn local_source(publication: &CynthiaPublication) -> Option<String> {
+    match publication {
+        CynthiaPublication::Page {
+            pagecontent: PublicationContent::Local { source, .. },
+            ..
+        }
+        | CynthiaPublication::Post {
+            postcontent: PublicationContent::Local { source, .. },
+            ..
+        } => Some(
+            source
+                .get_inner()
+                .trim_start_matches("./")
+                .trim_start_matches('/')
+                .to_string(),
+        ),
+        _ => None,
+    }
+}
@@ -4,6 +4,9 @@
  * Licensed under the GNU AFFERO GENERAL PUBLIC LICENSE Version 3, see the LICENSE file for more information.
  */
 
+import { AsyncLocalStorage } from "node:async_hooks";
+import { gzipSync } from "node:zlib";
+
 export interface CynthiaPlugin {
   modifyResponseHTML?: (htmlin: string,
                         metadata: ContentMetaDataType,
@@ -15,6 +18,44 @@ export interface CynthiaPlugin {
                    Cynthia: CynthiaApiPoints) => void;
   onLoad?: (Cynthia: CynthiaApiPoints) => void;
   onClearInterval?: (Cynthia: CynthiaApiPoints) => void;
+  /**
+   * Runs once, before Cynthia starts accepting requests. The working directory is the plugin's own directory.
+   * Throwing (or rejecting) marks the start as failed.
+   */
+  onStart?: (Cynthia: CynthiaApiPoints) => void | Promise<void>;
+  /**
+   * Shortcodes this plugin expands, by name. The returned HTML replaces the shortcode in the rendered content.
+   */
+  shortcodes?: Record<string, (shortcode: Shortcode, Cynthia: CynthiaApiPoints) => string>;
+  /**
+   * Answers `GET`, `HEAD` and `POST` requests for `prefix` and the paths below it, like `/comments`.
+   * Cynthia serves no pages there. The prefix can't overlap those of other plugins or paths Cynthia
+   * answers itself, like `/api` and `/assets`.
+   */
+  handleRoute?: {
+    prefix: string;
+    handle: (req: IncomingRoute,
+             Cynthia: CynthiaApiPoints) => RouteAnswer | Promise<RouteAnswer>;
+  };
+  /**
+   * Limits the content modifiers of this plugin to matching publications.
+   * Plugins without it apply to every publication.
+   */
+  applies_to?: AppliesTo;
+  /**
+   * The protocol version this plugin was written against. When it differs from the one Cynthia speaks,
+   * a warning is logged. Plugins that can't adapt can check `Cynthia.protocol` in `onLoad` and throw.
+   */
+  protocol?: number;
+}
+
+/**
+ * Every filter that is set has to match, a publication matches a filter when its value is listed in it.
+ */
+export interface AppliesTo {
+  kind?: Array<"page" | "post" | "postlist">;
+  scene?: Array<string>;
+  category?: Array<string>;
 }
 
 export interface Request {
@@ -23,6 +64,10 @@ export interface Request {
 }
 export interface GenericRequest {
   id: number;
+  /**
+   * The id of the web request this request is made for, if any.
+   */
+  request_id?: string;
   body: {
     for: string;
   };
@@ -44,13 +89,28 @@ export interface ContentRenderRequest {
 export interface ContentRenderRequestBody {
   for: "ContentRenderRequest";
   template_path: string;
-  template_data: {
+  template_data: TemplateShortcuts & {
     meta: ContentMetaDataType;
     content: string;
+    adjacent: AdjacentPosts | null;
   };
 }
+/** For posts, the posts published before and after them. */
+export interface AdjacentPosts {
+  previous: AdjacentPost | null;
+  next: AdjacentPost | null;
+  previous_in_category: AdjacentPost | null;
+  next_in_category: AdjacentPost | null;
+}
+export interface AdjacentPost {
+  id: string;
+  title: string;
+  url: string;
+}
 export interface ContentMetaDataType {
   id: string;
+  kind: string;
+  scene: string;
   title: string;
   desc?: string;
   tags: Array<string>;
@@ -60,11 +120,85 @@ export interface ContentMetaDataType {
     link?: string;
     thumbnail?: string;
   };
+  /** The author's thumbnail, or the site's default avatar. */
+  author_thumbnail?: string;
   dates: {
     altered: number;
     published: number;
   };
+  /** When the publication last changed, as RFC 3339. */
+  updated?: string;
+  /** The same moment, formatted for display. */
+  updated_display?: string;
+  /** When the publication was first published, formatted for display. */
+  published_display?: string;
   thumbnail?: string;
+  /** How often the publication was visited, when Cynthia counts visits. */
+  visits?: number;
+}
+/** The parts of `meta` templates use most, repeated at the top level of the template data. */
+export interface TemplateShortcuts {
+  title: string;
+  author?: ContentMetaDataType["author"];
+  tags: Array<string>;
+  category?: string;
+  published_display?: string;
+  updated_display?: string;
+  /** `site_context` from Cynthia's configuration. Its keys are also at the top level, where they don't collide. */
+  site: Record<string, string>;
+}
+
+/**
+ * A shortcode like `{{< figure src="a.png" >}}`, as found in publication content.
+ */
+export interface Shortcode {
+  name: string;
+  positional: Array<string>;
+  named: Record<string, string>;
+}
+export interface ShortcodeRequest {
+  id: number;
+  body: {
+    for: "ShortcodeRequest";
+    shortcode: Shortcode;
+  };
+}
+
+/** A whole rendered page, for the `modifyResponseHTML` runners. */
+export interface OutputRequest {
+  id: number;
+  body: {
+    for: "OutputRequest";
+    html: string;
+    meta: ContentMetaDataType;
+  };
+}
+
+/** A request for a path a plugin owns through `handleRoute`. */
+export interface IncomingRoute {
+  /** Uppercase, like `POST`. */
+  method: string;
+  path: string;
+  /** Without the `?`, empty when there is none. */
+  query: string;
+  headers: Array<[string, string]>;
+  /** As text, empty for `GET` and `HEAD`. */
+  body: string;
+}
+export interface RouteRequest {
+  id: number;
+  body: { for: "RouteRequest" } & IncomingRoute;
+}
+/** What a `handleRoute` runner answers with. Without a `status`, it's `200`. */
+export interface RouteAnswer {
+  status?: number;
+  headers?: Array<[string, string]>;
+  body?: string;
+  /**
+   * Set when `body` is gzipped and base64-encoded already, like a file kept that way. Cynthia
+   * unpacks it before sending it on. Big bodies are packed like that for the way to Cynthia anyway.
+   */
+  encoding?: Encoding;
 }
 
 export interface PostlistRenderRequest {
@@ -75,19 +209,26 @@ export interface PostlistRenderRequest {
 export interface PostlistRenderRequestBody {
   for: "PostlistRenderRequest";
   template_path: string;
-  template_data: {
+  template_data: TemplateShortcuts & {
     meta: {
       id: string;
+      kind: string;
+      scene: string;
       title: string;
       desc?: string;
       category?: string;
       tags: Array<string>;
       author: undefined;
+      author_thumbnail: undefined;
       dates: {
         altered: number;
         published: number;
       };
+      updated: undefined;
+      updated_display: undefined;
+      published_display: undefined;
       thumbnail?: string;
+      visits?: number;
     };
     posts: Array<{
       id: string;
@@ -111,10 +252,19 @@ export interface PostlistRenderRequestBody {
             as: string;
             value: string;
           };
+          encoding?: string;
         };
       };
       scene_override: string;
+      /**
+       * `short`, or an excerpt of the post's content when it has none.
+       */
+      excerpt?: string;
     }>;
+    taxonomy: {
+      tags: Array<{ name: string; count: number }>;
+      categories: Array<{ name: string; count: number }>;
+    };
   };
 }
 
@@ -139,17 +289,35 @@ export interface OkStringResponseType {
   body: {
     as: "OkString";
     value: string;
+    encoding?: Encoding;
+  };
+}
+
+/** How output is packed for the way to Cynthia, which unpacks it before use. */
+export type Encoding = "gzip-base64";
+/** Output from this many characters on goes to Cynthia gzipped. */
+const ENCODE_FROM = 64 * 1024;
+/** `output`, gzipped and base64-encoded when it's big enough for that to pay off. */
+function encoded(output: string): { output: string; encoding?: Encoding } {
+  if (output.length < ENCODE_FROM) {
+    return { output };
+  }
+  return {
+    output: gzipSync(output).toString("base64"),
+    encoding: "gzip-base64",
   };
 }
 
 export class OkStringResponse implements OkStringResponseType {
-  body: { as: "OkString"; value: string };
+  body: { as: "OkString"; value: string; encoding?: Encoding };
   id: number;
   constructor(id: number, value: string) {
+    const { output, encoding } = encoded(value);
     this.id = id;
     this.body = {
       as: "OkString",
-      value: value,
+      value: output,
+      encoding,
     };
   }
 }
@@ -160,6 +328,7 @@ export interface WebResponse {
     as: "WebResponse";
     append_headers: Array<[string, string]>;
     response_body: string;
+    encoding?: Encoding;
   };
 }
 
@@ -170,6 +339,46 @@ export interface OkJSONResponse {
     value: unknown;
   };
 }
+/** Answers a start request, with the prefixes the plugins' routes took and the `onStart` hooks that failed. */
+export class StartedResponse {
+  id: number;
+  body: {
+    as: "Started";
+    routes: Array<string>;
+    failures: Array<string>;
+  };
+  constructor(id: number, routes: Array<string>, failures: Array<string>) {
+    this.id = id;
+    this.body = {
+      as: "Started",
+      routes,
+      failures,
+    };
+  }
+}
+export class RouteResponse {
+  id: number;
+  body: {
+    as: "RouteResponse";
+    status: number;
+    headers: Array<[string, string]>;
+    body: string;
+    encoding?: Encoding;
+  };
+  constructor(id: number, answer: RouteAnswer) {
+    const { output, encoding } = answer.encoding
+      ? { output: answer.body ?? "", encoding: answer.encoding }
+      : encoded(answer.body ?? "");
+    this.id = id;
+    this.body = {
+      as: "RouteResponse",
+      status: answer.status ?? 200,
+      headers: answer.headers ?? [],
+      body: output,
+      encoding,
+    };
+  }
+}
 export class ErrorResponse {
   id: number;
   body: { as: "Error"; message: string };
@@ -188,23 +397,47 @@ export class ErrorResponse {
     };
   }
 }
+/** What the plugin runner keeps about a request while it handles it, see `requestContext`. */
+export interface RequestContext {
+  /** The web request id, log lines get it in front the same way Cynthia's own log lines for that request do. */
+  id?: string;
+  /** Milliseconds spent in each plugin's runners for this request, for `plugin_options.timing`. */
+  pluginTimes: Map<string, number>;
+}
+/**
+ * The request the code running now is for. Requests are handled side by side, so this follows each
+ * one through its awaits instead of being set and reset around it.
+ */
+export const requestContext = new AsyncLocalStorage<RequestContext>();
+function tagged(str: unknown): string {
+  const id = requestContext.getStore()?.id;
+  return id ? `[${id}] ${str}` : `${str}`;
+}
 export namespace terminalOut {
   export function log(str: unknown) {
-    console.log(`log: ${str}`);
+    console.log(`log: ${tagged(str)}`);
   }
   export function error(str: unknown) {
-    console.log(`error: ${str}`);
+    console.log(`error: ${tagged(str)}`);
   }
   export function warn(str: unknown) {
-    console.log(`warn: ${str}`);
+    console.log(`warn: ${tagged(str)}`);
   }
   export function info(str: unknown) {
-    console.log(`info: ${str}`);
+    console.log(`info: ${tagged(str)}`);
   }
   export function debug(str: unknown) {
-    console.log(`debug: ${str}`);
+    console.log(`debug: ${tagged(str)}`);
+  }
+  export function trace(str: unknown) {
+    console.log(`trace: ${tagged(str)}`);
   }
 }
+/**
+ * The version of the protocol between Cynthia and its plugin runners this API describes.
+ * Keep in sync with `EPS_PROTOCOL_VERSION` in `externalpluginservers.rs`.
+ */
+export const ProtocolVersion = 5;
 export const Cynthia = {
   send: (
       res:
@@ -212,7 +445,9 @@ export const Cynthia = {
           | OkStringResponseType
           | OkJSONResponse
           | ErrorResponse
-          | WebResponse,
+          | WebResponse
+          | RouteResponse
+          | StartedResponse,
   ) => {
     console.log(`parse: ${JSON.stringify(res)}`);
   },
@@ -230,13 +465,19 @@ export type Responder = () => ResponderResponse | string;
  */
 export class CynthiaApiPoints {
   public console: typeof terminalOut;
+  /**
+   * The protocol version Cynthia speaks with its plugin runner.
+   */
+  public protocol: number;
   constructor() {
+    this.protocol = ProtocolVersion;
     this.console = {
       log: terminalOut.log,
       error: terminalOut.error,
       warn: terminalOut.warn,
       info: terminalOut.info,
       debug: terminalOut.debug,
+      trace: terminalOut.trace,
     };
   }
 }
@@ -295,12 +536,14 @@ export class WebRequest {
       }
       return res;
     })();
+    const { output, encoding } = encoded(responder_answ.body);
     const response: WebResponse = {
       id: this.id,
       body: {
         as: "WebResponse",
         append_headers: responder_answ.headers,
-        response_body: responder_answ.body,
+        response_body: output,
+        encoding,
       },
     };
     Cynthia.send(response);
@@ -9,18 +9,31 @@ import {
   type ContentRenderRequest,
   Cynthia,
   CynthiaPassed,
+  EmptyOKResponse,
   ErrorResponse,
   type GenericRequest,
+  type IncomingRoute,
   type IncomingWebRequest,
   OkStringResponse,
+  type OutputRequest,
   type PostlistRenderRequest,
+  requestContext,
+  type RouteAnswer,
+  type RouteRequest,
+  RouteResponse,
+  type ShortcodeRequest,
+  StartedResponse,
   terminalOut as console,
   type TestRequest,
   WebRequest,
 } from "cynthia-plugin-api/main";
 import * as handlebars from "handlebars";
 import * as fs from "node:fs";
-import type { PluginBase } from "./types/internal_plugins";
+import {
+  logPluginTimes,
+  type PluginBase,
+  under,
+} from "./types/internal_plugins";
 
 export default async function handle(
   incoming: string,
@@ -41,86 +54,196 @@ export default async function handle(
       );
       return Cynthia.send(response);
     }
-    switch (request.body.for) {
-      case "Exit": {
-        console.error("Exiting...");
-        return process.exit(0);
-      }
-      case "WebRequest": {
-        const request: IncomingWebRequest = JSON.parse(requestAsString);
-        const req: WebRequest = new WebRequest(request.id, {
-          method: request.body.method,
-          uri: request.body.uri,
-          headers: request.body.headers,
-        });
-        for (const modifier of cynthiabase.modifyRequest) {
-          modifier(req, CynthiaPassed);
-        }
-        return req.escalate();
-      }
-      case "PostlistRenderRequest": {
+    // Log lines and plugin times from here on are about this request, also while other requests
+    // are handled in between its awaits.
+    return requestContext.run(
+      { id: request.request_id, pluginTimes: new Map() },
+      async () => {
         try {
-          // streq helper
-          // This helper checks if two strings are equal.
-          // Usage: {{#if (streq postid "sasfs")}} ... {{/if}}
-          handlebars.registerHelper("streq", (a: string, b: string) => a === b);
+          switch (request.body.for) {
+            case "Exit": {
+              console.error("Exiting...");
+              return process.exit(0);
+            }
+            case "WebRequest": {
+              const request: IncomingWebRequest = JSON.parse(requestAsString);
+              // Pages under a plugin's route are the plugin's to answer, not Cynthia's.
+              const [routePath, query = ""] = request.body.uri.split(/\?(.*)/);
+              const route = cynthiabase.routes.find((r) =>
+                under(routePath, r.prefix),
+              );
+              if (route) {
+                try {
+                  const answer = await answerRoute(route.handle, {
+                    method: request.body.method.toUpperCase(),
+                    path: routePath,
+                    query,
+                    headers: request.body.headers,
+                    body: "",
+                  });
+                  return Cynthia.send(new RouteResponse(request.id, answer));
+                } catch (e) {
+                  console.error(
+                    `Route ${route.prefix} of plugin ${route.plugin} failed: ${e}`,
+                  );
+                  return Cynthia.send(new ErrorResponse(request.id, `${e}`));
+                }
+              }
+              const req: WebRequest = new WebRequest(request.id, {
+                method: request.body.method,
+                uri: request.body.uri,
+                headers: request.body.headers,
+              });
+              for (const modifier of cynthiabase.modifyRequest) {
+                modifier(req, CynthiaPassed);
+              }
+              return req.escalate();
+            }
+            case "PostlistRenderRequest": {
+              try {
+                // streq helper
+                // This helper checks if two strings are equal.
+                // Usage: {{#if (streq postid "sasfs")}} ... {{/if}}
+                handlebars.registerHelper("streq", (a: string, b: string) => a === b);
 
-          const request: PostlistRenderRequest = JSON.parse(requestAsString);
-          const template = fs.readFileSync(request.body.template_path, "utf8");
-          const compiled = handlebars.compile(template);
-          let htmlBody = compiled(request.body.template_data);
-          for (const modifier of cynthiabase.modifyResponseHTMLBodyFragment) {
-            htmlBody = modifier(
-              htmlBody,
-              request.body.template_data.meta,
-              CynthiaPassed,
-            );
-          }
-          const response = new OkStringResponse(request.id, htmlBody);
-          return Cynthia.send(response);
-        } catch (e) {
-          console.error(e);
-          const response = new ErrorResponse(request.id, "");
-          return Cynthia.send(response);
-        }
-      }
-      case "ContentRenderRequest": {
-        try {
-          // streq helper
-          // This helper checks if two strings are equal.
-          // Usage: {{#if (streq postid "sasfs")}} ... {{/if}}
-          handlebars.registerHelper("streq", (a: string, b: string) => a === b);
+                const request: PostlistRenderRequest = JSON.parse(requestAsString);
+                const template = fs.readFileSync(request.body.template_path, "utf8");
+                const compiled = handlebars.compile(template);
+                let htmlBody = compiled(request.body.template_data);
+                for (const modifier of cynthiabase.modifyResponseHTMLBodyFragment) {
+                  htmlBody = modifier(
+                    htmlBody,
+                    request.body.template_data.meta,
+                    CynthiaPassed,
+                  );
+                }
+                const response = new OkStringResponse(request.id, htmlBody);
+                return Cynthia.send(response);
+              } catch (e) {
+                console.error(e);
+                const response = new ErrorResponse(request.id, "");
+                return Cynthia.send(response);
+              }
+            }
+            case "ContentRenderRequest": {
+              try {
+                // streq helper
+                // This helper checks if two strings are equal.
+                // Usage: {{#if (streq postid "sasfs")}} ... {{/if}}
+                handlebars.registerHelper("streq", (a: string, b: string) => a === b);
 
-          const request: ContentRenderRequest = JSON.parse(requestAsString);
-          const template = fs.readFileSync(request.body.template_path, "utf8");
-          const compiled = handlebars.compile(template);
-          let htmlBody = compiled(request.body.template_data);
-          for (const modifier of cynthiabase.modifyResponseHTMLBodyFragment) {
-            htmlBody = modifier(
-              htmlBody,
-              request.body.template_data.meta,
-              CynthiaPassed,
-            );
+                const request: ContentRenderRequest = JSON.parse(requestAsString);
+                const template = fs.readFileSync(request.body.template_path, "utf8");
+                const compiled = handlebars.compile(template);
+                let htmlBody = compiled(request.body.template_data);
+                for (const modifier of cynthiabase.modifyResponseHTMLBodyFragment) {
+                  htmlBody = modifier(
+                    htmlBody,
+                    request.body.template_data.meta,
+                    CynthiaPassed,
+                  );
+                }
+                const response = new OkStringResponse(request.id, htmlBody);
+                return Cynthia.send(response);
+              } catch (e) {
+                console.error(e);
+                const response = new ErrorResponse(request.id, "");
+                return Cynthia.send(response);
+              }
+            }
+            case "OutputRequest": {
+              try {
+                const request: OutputRequest = JSON.parse(requestAsString);
+                let html = request.body.html;
+                for (const modifier of cynthiabase.modifyResponseHTML) {
+                  html = modifier(html, request.body.meta, CynthiaPassed);
+                }
+                return Cynthia.send(new OkStringResponse(request.id, html));
+              } catch (e) {
+                console.error(e);
+                return Cynthia.send(new ErrorResponse(request.id, ""));
+              }
+            }
+            case "StartRequest": {
+              const failures: Array<string> = [];
+              const cwd = process.cwd();
+              for (const hook of cynthiabase.onStart) {
+                try {
+                  process.chdir(hook.directory);
+                  await hook.run(CynthiaPassed);
+                } catch (e) {
+                  console.error(`onStart hook of plugin ${hook.plugin} failed: ${e}`);
+                  failures.push(`${hook.plugin}: ${e}`);
+                } finally {
+                  process.chdir(cwd);
+                }
+              }
+              // Cynthia only asks about paths under these, so requests elsewhere don't wait on plugins.
+              const routes = cynthiabase.routes.map((route) => route.prefix);
+              return Cynthia.send(new StartedResponse(request.id, routes, failures));
+            }
+            case "ShortcodeRequest": {
+              const request: ShortcodeRequest = JSON.parse(requestAsString);
+              const expand = cynthiabase.shortcodes[request.body.shortcode.name];
+              if (!expand) {
+                // Not ours, Cynthia leaves it as it is.
+                return Cynthia.send(new EmptyOKResponse(request.id));
+              }
+              try {
+                const html = expand(request.body.shortcode, CynthiaPassed);
+                return Cynthia.send(new OkStringResponse(request.id, html));
+              } catch (e) {
+                console.error(
+                  `Shortcode '${request.body.shortcode.name}' failed: ${e}`,
+                );
+                return Cynthia.send(new ErrorResponse(request.id, `${e}`));
+              }
+            }
+            case "RouteRequest": {
+              const request: RouteRequest = JSON.parse(requestAsString);
+              const route = cynthiabase.routes.find((r) =>
+                under(request.body.path, r.prefix),
+              );
+              if (!route) {
+                // No plugin handles this path, Cynthia answers it itself.
+                return Cynthia.send(new EmptyOKResponse(request.id));
+              }
+              try {
+                const answer = await answerRoute(route.handle, request.body);
+                return Cynthia.send(new RouteResponse(request.id, answer));
+              } catch (e) {
+                console.error(
+                  `Route ${route.prefix} of plugin ${route.plugin} failed: ${e}`,
+                );
+                return Cynthia.send(new ErrorResponse(request.id, `${e}`));
+              }
+            }
+            case "Test": {
+              const request: TestRequest = JSON.parse(requestAsString);
+              // {"id":0,"body":{"as":"OkString","value":"Yes."}}
+              const response = new OkStringResponse(
+                request.id,
+                `Successfully received test request. Test passed with echo: "${request.body.test}"`,
+              );
+              return Cynthia.send(response);
+            }
           }
-          const response = new OkStringResponse(request.id, htmlBody);
-          return Cynthia.send(response);
-        } catch (e) {
-          console.error(e);
-          const response = new ErrorResponse(request.id, "");
-          return Cynthia.send(response);
+        } finally {
+          // With `plugin_options.timing`, one line with what each plugin took for this request.
+          logPluginTimes();
         }
-      }
-      case "Test": {
-        const request: TestRequest = JSON.parse(requestAsString);
-        // {"id":0,"body":{"as":"OkString","value":"Yes."}}
-        const response = new OkStringResponse(
-          request.id,
-          `Successfully received test request. Test passed with echo: "${request.body.test}"`,
-        );
-        return Cynthia.send(response);
-      }
-    }
+      },
+    );
   } else {
     console.log(`Got: ${incoming}`);
   }
 }
+
+/** Runs a `handleRoute` runner with the parts of the request it gets to see. */
+async function answerRoute(
+  handle: PluginBase["routes"][number]["handle"],
+  req: IncomingRoute,
+): Promise<RouteAnswer> {
+  const { method, path, query, headers, body } = req;
+  return await handle({ method, path, query, headers, body }, CynthiaPassed);
+}
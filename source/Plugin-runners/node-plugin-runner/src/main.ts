@@ -10,6 +10,8 @@ import {
   Cynthia,
   CynthiaPassed,
   type CynthiaPlugin,
+  EmptyOKResponse,
+  ProtocolVersion,
 } from "cynthia-plugin-api/main";
 import type Config from "./types/config";
 const config: Config = (() => {
@@ -21,13 +23,27 @@ const config: Config = (() => {
   }
   return JSON.parse(conf);
 })();
+// The protocol version Cynthia speaks, older Cynthia versions don't pass it at all.
+const protocol: number = (() => {
+  for (let i = 0; i < process.argv.length; i++) {
+    if (process.argv[i] === "--protocol") {
+      return Number.parseInt(process.argv[i + 1]);
+    }
+  }
+  return 0;
+})();
 
 import path from "node:path";
 import {
   type PluginBase,
   type PluginPackageJson,
   Plugincompat,
+  logPluginTimes,
   newPluginBase,
+  routeConflict,
+  scoped,
+  timed,
+  traced,
 } from "./types/internal_plugins";
 import handle from "./handler";
 Cynthia.console.debug(`Starting in cwd: ${process.cwd()}`);
@@ -36,6 +52,26 @@ Cynthia.console.debug(`Config: ${JSON.stringify(config)}`);
 Cynthia.console.info(
   `External Javascript Runtime Server starting in: ${process.argv0}`,
 );
+if (protocol !== ProtocolVersion) {
+  Cynthia.console.warn(
+    `Cynthia speaks plugin protocol version ${protocol}, this runner speaks version ${ProtocolVersion}. Plugins may misbehave.`,
+  );
+}
+CynthiaPassed.protocol = protocol;
+// Responses past Cynthia's size limit would be dropped on the other end anyway, so don't send them.
+{
+  const send = Cynthia.send;
+  const max = config.plugin_options.max_output_bytes ?? 0;
+  Cynthia.send = (res) => {
+    if (max !== 0 && Buffer.byteLength(JSON.stringify(res)) > max) {
+      Cynthia.console.error(
+        `A response to request ${res.id} is larger than \`plugin_options.max_output_bytes\` (${max} bytes), sending an empty one instead.`,
+      );
+      return send(new EmptyOKResponse(res.id));
+    }
+    return send(res);
+  };
+}
 const cynthiaPluginFoundation: PluginBase = newPluginBase;
 
 for (const pluginIndex in config.plugins) {
@@ -113,30 +149,126 @@ for (const pluginIndex in config.plugins) {
         );
         return require(pluginEntryJs);
       })();
+      if (plugin.protocol !== undefined && plugin.protocol !== protocol) {
+        Cynthia.console.warn(
+          `Plugin ${pluginName} expects plugin protocol version ${plugin.protocol}, Cynthia speaks version ${protocol}.`,
+        );
+      }
+      // With `plugin_options.debug`, every call to a runner logs what went in and came out.
+      // With `plugin_options.timing`, the time it takes counts towards the plugin's total.
+      const trace = <A extends Array<unknown>, R>(
+        runner: string,
+        run: (...args: A) => R,
+      ) => {
+        const measured = config.plugin_options.timing
+          ? timed(pluginName, run)
+          : run;
+        return config.plugin_options.debug
+          ? traced(pluginName, runner, measured)
+          : measured;
+      };
       if (typeof plugin.modifyResponseHTML === "function") {
         cynthiaPluginFoundation.modifyResponseHTML.push(
-          plugin.modifyResponseHTML,
+          scoped(
+            plugin.applies_to,
+            trace("modifyResponseHTML", plugin.modifyResponseHTML),
+          ),
         );
       }
       if (typeof plugin.modifyRequest === "function") {
-        cynthiaPluginFoundation.modifyRequest.push(plugin.modifyRequest);
+        cynthiaPluginFoundation.modifyRequest.push(
+          trace("modifyRequest", plugin.modifyRequest),
+        );
       }
       if (typeof plugin.modifyResponseHTMLBodyFragment === "function") {
         cynthiaPluginFoundation.modifyResponseHTMLBodyFragment.push(
-          plugin.modifyResponseHTMLBodyFragment,
+          scoped(
+            plugin.applies_to,
+            trace(
+              "modifyResponseHTMLBodyFragment",
+              plugin.modifyResponseHTMLBodyFragment,
+            ),
+          ),
         );
       }
       if (typeof plugin.onClearInterval === "function") {
-        cynthiaPluginFoundation.onClearInterval.push(plugin.onClearInterval);
+        cynthiaPluginFoundation.onClearInterval.push(
+          trace("onClearInterval", plugin.onClearInterval),
+        );
+      }
+      if (typeof plugin.onStart === "function") {
+        cynthiaPluginFoundation.onStart.push({
+          plugin: pluginName,
+          directory: path.join(process.cwd(), "cynthiaPlugins/", pluginName),
+          run: trace("onStart", plugin.onStart),
+        });
+      }
+      if (plugin.shortcodes) {
+        for (const [name, expand] of Object.entries(plugin.shortcodes)) {
+          if (cynthiaPluginFoundation.shortcodes[name]) {
+            Cynthia.console.warn(
+              `Plugin ${pluginName} redefines the shortcode '${name}', replacing the earlier definition.`,
+            );
+          }
+          cynthiaPluginFoundation.shortcodes[name] = trace(
+            `shortcode '${name}'`,
+            expand,
+          );
+        }
+      }
+      if (plugin.handleRoute) {
+        const prefix = `/${plugin.handleRoute.prefix.replace(/^\/+|\/+$/g, "")}`;
+        const conflict = routeConflict(
+          prefix,
+          cynthiaPluginFoundation.routes,
+          config.reserved_paths,
+        );
+        if (conflict) {
+          Cynthia.console.error(
+            `Plugin ${pluginName} can't handle ${prefix}: ${conflict}. Skipping its route.`,
+          );
+        } else {
+          cynthiaPluginFoundation.routes.push({
+            plugin: pluginName,
+            prefix,
+            handle: trace("handleRoute", plugin.handleRoute.handle),
+          });
+        }
       }
       if (typeof plugin.onLoad === "function") {
-        plugin.onLoad(CynthiaPassed);
+        trace("onLoad", plugin.onLoad)(CynthiaPassed);
       }
+      const runners = (
+        [
+          "modifyResponseHTML",
+          "modifyRequest",
+          "modifyResponseHTMLBodyFragment",
+          "onClearInterval",
+          "onStart",
+          "onLoad",
+        ] as Array<keyof CynthiaPlugin>
+      )
+        .filter((runner) => typeof plugin[runner] === "function")
+        .concat(
+          Object.keys(plugin.shortcodes ?? {}).map(
+            (name) => `shortcode '${name}'`,
+          ),
+        )
+        .concat(
+          cynthiaPluginFoundation.routes
+            .filter((route) => route.plugin === pluginName)
+            .map((route) => `handleRoute ${route.prefix}`),
+        );
+      Cynthia.console.info(
+        `Loaded plugin ${pluginName} with runners: ${runners.join(", ") || "none"}.`,
+      );
     } catch (e) {
       Cynthia.console.error(`Error loading plugin ${pluginName}: ${e}`);
     }
   }
 }
+// What the `onLoad` runners took, the handler logs the times of later runners per request.
+logPluginTimes();
 
 function clean() {
   switch (true) {
@@ -173,6 +305,7 @@ function cleanInterval() {
   for (const fn of cynthiaPluginFoundation.onClearInterval) {
     fn(CynthiaPassed);
   }
+  logPluginTimes();
   clean();
 }
 setInterval(cleanInterval, 300000);
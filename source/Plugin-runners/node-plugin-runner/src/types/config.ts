@@ -22,6 +22,24 @@ export default interface Config {
     meta: {
       enable_tags: boolean;
     };
+    inline_scripts: boolean;
+    favicon?: string;
+    generator_comment: boolean;
+    default_lang: string;
+    shortcodes: {
+      open: string;
+      close: string;
+    };
+    excerpts: {
+      length: number;
+      strip_html: boolean;
+    };
+    emoji: boolean;
+    headers: Record<string, string>;
+    default_scene: string;
+    category_scenes: Record<string, string>;
+    default_author_thumbnail?: string;
+    default_script?: string;
   };
   logs: {
     file_loglevel: number;
@@ -40,11 +58,105 @@ export default interface Config {
       post: string;
       page: string;
       postlist: string;
+      categories?: Record<string, string>;
     };
+    headers: Record<string, string>;
   }>;
   plugins: Array<{
     plugin_name: string;
     plugin_enabled: boolean;
     plugin_runtime: string;
+    order: number;
+    cacheable?: boolean;
+  }>;
+  plugin_options: {
+    enabled: boolean;
+    allow: Array<string>;
+    deny: Array<string>;
+    abort_on_start_failure: boolean;
+    max_concurrent: number;
+    max_output_bytes: number;
+    optional: boolean;
+    debug: boolean;
+    timing?: boolean;
+  };
+  render_timeout: number;
+  workers?: number;
+  prerender_dir?: string;
+  timezone: string;
+  charset: string;
+  root_dir: string;
+  home?: string;
+  site_context: Record<string, string>;
+  trusted_proxies: string[];
+  well_known: Record<
+    string,
+    {
+      file?: string;
+      content?: string;
+      content_type?: string;
+    }
+  >;
+  missing_content: {
+    markdown?: string;
+    html?: string;
+    plaintext?: string;
+    status: number;
+  };
+  maintenance: {
+    enabled: boolean;
+    page?: string;
+    assets: boolean;
+  };
+  trailing_slash: "keep" | "add" | "remove";
+  security: {
+    enabled: boolean;
+    content_type_options: string;
+    referrer_policy: string;
+    content_security_policy: string;
+    frame_options: string;
+    nonce: boolean;
+  };
+  static_mounts: Array<{
+    prefix: string;
+    path: string;
   }>;
+  /** Not in `CynthiaConf`: the paths Cynthia answers itself and its static mounts, which plugin routes can't take over. */
+  reserved_paths: Array<string>;
+  server: {
+    keep_alive: number;
+    http2: boolean;
+    canonical_redirect: boolean;
+  };
+  debug: {
+    /** Always null here, Cynthia doesn't pass the token on to plugins. */
+    log_endpoint: null;
+    timing_header: boolean;
+  };
+  webhooks: {
+    /** Always null here, Cynthia doesn't pass the secret on to plugins. */
+    reload: null;
+  };
+  visits: {
+    enabled: boolean;
+    file: string;
+    flush_interval: number;
+  };
+  images: {
+    enabled: boolean;
+    formats: Array<"avif" | "webp">;
+    max_width: number;
+    responsive: boolean;
+    quality: number;
+    converter: string;
+    cache_dir: string;
+  };
+  sanitize: {
+    enabled: boolean;
+    tags: Array<string>;
+    attributes: Array<string>;
+  };
+  error_pages: {
+    server_error?: string;
+  };
 }
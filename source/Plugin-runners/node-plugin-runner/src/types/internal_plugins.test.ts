@@ -0,0 +1,129 @@
+/*
+ * Copyright (c) 2024, MLC 'Strawmelonjuice' Bloeiman
+ *
+ * Licensed under the GNU AFFERO GENERAL PUBLIC LICENSE Version 3, see the LICENSE file for more information.
+ */
+
+import { describe, expect, spyOn, test } from "bun:test";
+import {
+  type ContentMetaDataType,
+  CynthiaPassed,
+  requestContext,
+} from "cynthia-plugin-api/main";
+import {
+  appliesTo,
+  logPluginTimes,
+  routeConflict,
+  scoped,
+  timed,
+  under,
+} from "./internal_plugins";
+
+const meta = (kind: string, category?: string): ContentMetaDataType => ({
+  id: "test",
+  kind,
+  scene: "default",
+  title: "Test",
+  tags: [],
+  category,
+  dates: { altered: 0, published: 0 },
+});
+const modifier = (htmlin: string) => `${htmlin}<!-- modified -->`;
+
+describe("applies_to", () => {
+  test("unscoped modifiers apply everywhere", () => {
+    const run = scoped(undefined, modifier);
+    expect(run("", meta("page"), CynthiaPassed)).toBe("<!-- modified -->");
+    expect(run("", meta("post"), CynthiaPassed)).toBe("<!-- modified -->");
+  });
+  test("scoped modifiers only apply to matching publications", () => {
+    const run = scoped({ kind: ["post"] }, modifier);
+    expect(run("", meta("post"), CynthiaPassed)).toBe("<!-- modified -->");
+    expect(run("", meta("page"), CynthiaPassed)).toBe("");
+  });
+  test("all set filters have to match", () => {
+    const filter = { kind: ["post" as const], category: ["blog"] };
+    expect(appliesTo(filter, meta("post", "blog"))).toBe(true);
+    expect(appliesTo(filter, meta("post", "news"))).toBe(false);
+    expect(appliesTo(filter, meta("post"))).toBe(false);
+    expect(appliesTo({ scene: ["other"] }, meta("post"))).toBe(false);
+  });
+});
+
+describe("handleRoute", () => {
+  const reserved = ["/api", "/assets", "/t", "/downloads"];
+  const route = (plugin: string, prefix: string) => ({
+    plugin,
+    prefix,
+    handle: () => ({ body: "" }),
+  });
+  test("paths are under themselves and their parents, not their namesakes", () => {
+    expect(under("/comments", "/comments")).toBe(true);
+    expect(under("/comments/42", "/comments")).toBe(true);
+    expect(under("/commentsfeed", "/comments")).toBe(false);
+    expect(under("/comments", "/comments/42")).toBe(false);
+  });
+  test("routes can't take the whole site", () => {
+    expect(routeConflict("/", [], reserved)).toBeDefined();
+  });
+  test("routes can't overlap reserved paths or static mounts either way", () => {
+    expect(routeConflict("/api", [], reserved)).toBeDefined();
+    expect(routeConflict("/api/comments", [], reserved)).toBeDefined();
+    expect(routeConflict("/downloads/latest", [], reserved)).toBeDefined();
+    expect(routeConflict("/tags", [], reserved)).toBeUndefined();
+    expect(routeConflict("/comments", [], reserved)).toBeUndefined();
+  });
+  test("routes can't overlap each other either way", () => {
+    const routes = [route("comments", "/comments")];
+    expect(routeConflict("/comments", routes, reserved)).toContain("comments");
+    expect(routeConflict("/comments/admin", routes, reserved)).toBeDefined();
+    expect(
+      routeConflict("/guestbook", [route("admin", "/guestbook/admin")], reserved),
+    ).toBeDefined();
+    expect(routeConflict("/commentsfeed", routes, reserved)).toBeUndefined();
+  });
+});
+
+describe("plugin_options.timing", () => {
+  /** Runs `f`, returning what it logged. */
+  const logs = async (f: () => Promise<unknown>) => {
+    const logged: Array<string> = [];
+    const log = spyOn(console, "log").mockImplementation((line: string) => {
+      logged.push(line);
+    });
+    try {
+      await f();
+    } finally {
+      log.mockRestore();
+    }
+    return logged;
+  };
+  const ms = (line: string) => Number(line.match(/slow ([\d.]+)ms/)?.[1]);
+  test("overlapping requests each log their own times", async () => {
+    const wait = timed(
+      "slow",
+      (ms: number) => new Promise<void>((resolve) => setTimeout(resolve, ms)),
+    );
+    const request = (id: string, ms: number) =>
+      requestContext.run({ id, pluginTimes: new Map() }, async () => {
+        await wait(ms);
+        logPluginTimes();
+      });
+    const logged = await logs(() =>
+      Promise.all([request("a", 40), request("b", 5)]),
+    );
+    expect(logged).toHaveLength(2);
+    expect(logged[0]).toMatch(/^debug: \[b\] Plugin time: slow [\d.]+ms$/);
+    expect(logged[1]).toMatch(/^debug: \[a\] Plugin time: slow [\d.]+ms$/);
+    expect(ms(logged[0])).toBeLessThan(ms(logged[1]));
+  });
+  test("times outside of requests are logged once", async () => {
+    const logged = await logs(async () => {
+      timed("slow", () => undefined)();
+      logPluginTimes();
+      logPluginTimes();
+    });
+    expect(logged).toHaveLength(1);
+    expect(logged[0]).toMatch(/^debug: Plugin time: slow [\d.]+ms$/);
+  });
+});
@@ -8,12 +8,179 @@ import {
   type WebRequest,
   IncomingWebRequest,
   type ResponderResponse,
+  CynthiaPassed,
+  requestContext,
+  terminalOut,
 } from "cynthia-plugin-api/main";
 import type {
+  AppliesTo,
   ContentMetaDataType,
   CynthiaApiPoints,
+  IncomingRoute,
+  RouteAnswer,
+  Shortcode,
 } from "../../../node-plugin-api/main";
 export const Plugincompat = 3.2;
+type ContentModifier = (
+  htmlin: string,
+  metadata: ContentMetaDataType,
+  Cynthia: CynthiaApiPoints,
+) => string;
+/** Checks if a publication falls within a plugin's `applies_to` filters. */
+export function appliesTo(
+  filter: AppliesTo | undefined,
+  metadata: ContentMetaDataType,
+): boolean {
+  if (filter === undefined) {
+    return true;
+  }
+  if (filter.kind && !(filter.kind as Array<string>).includes(metadata.kind)) {
+    return false;
+  }
+  if (filter.scene && !filter.scene.includes(metadata.scene)) {
+    return false;
+  }
+  if (
+    filter.category &&
+    (metadata.category === undefined ||
+      !filter.category.includes(metadata.category))
+  ) {
+    return false;
+  }
+  return true;
+}
+/** Wraps a content modifier so it leaves publications outside of `filter` untouched. */
+export function scoped(
+  filter: AppliesTo | undefined,
+  modifier: ContentModifier,
+): ContentModifier {
+  if (filter === undefined) {
+    return modifier;
+  }
+  return (htmlin, metadata, Cynthia) =>
+    appliesTo(filter, metadata) ? modifier(htmlin, metadata, Cynthia) : htmlin;
+}
+/** Whether `path` is `prefix` or a path below it. */
+export function under(path: string, prefix: string): boolean {
+  return path === prefix || path.startsWith(`${prefix}/`);
+}
+/**
+ * Why a plugin can't take `prefix` for its route, `undefined` when it can. Overlapping prefixes
+ * are refused both ways, so which plugin answers a request never depends on the loading order.
+ */
+export function routeConflict(
+  prefix: string,
+  routes: PluginBase["routes"],
+  reservedPaths: Array<string>,
+): string | undefined {
+  if (prefix === "/") {
+    return "it would take over the whole site";
+  }
+  for (const reserved of reservedPaths) {
+    if (under(prefix, reserved) || under(reserved, prefix)) {
+      return `Cynthia serves ${reserved} itself`;
+    }
+  }
+  for (const route of routes) {
+    if (under(prefix, route.prefix) || under(route.prefix, prefix)) {
+      return `plugin ${route.plugin} already handles ${route.prefix}`;
+    }
+  }
+  return undefined;
+}
+/** How much of a runner's input or output `plugin_options.debug` logs. */
+const TRACE_LENGTH = 500;
+function shortened(value: unknown): string {
+  let text: string;
+  try {
+    text = JSON.stringify(value) ?? "undefined";
+  } catch (_e) {
+    text = String(value);
+  }
+  return text.length > TRACE_LENGTH
+    ? `${text.slice(0, TRACE_LENGTH)}... (${text.length} characters)`
+    : text;
+}
+/**
+ * Wraps a runner so every call logs its input and output at trace level, for `plugin_options.debug`.
+ * The Cynthia object passed to every runner is left out, and long values are shortened.
+ */
+export function traced<A extends Array<unknown>, R>(
+  plugin: string,
+  runner: string,
+  run: (...args: A) => R,
+): (...args: A) => R {
+  return (...args: A) => {
+    const input = shortened(args.filter((arg) => arg !== CynthiaPassed));
+    const started = performance.now();
+    const done = (output: string) =>
+      terminalOut.trace(
+        `${plugin}'s ${runner} took ${(performance.now() - started).toFixed(1)}ms. In: ${input}, out: ${output}`,
+      );
+    let result: R;
+    try {
+      result = run(...args);
+    } catch (e) {
+      done(`threw ${e}`);
+      throw e;
+    }
+    if (result instanceof Promise) {
+      result.then(
+        (output) => done(shortened(output)),
+        (e) => done(`rejected with ${e}`),
+      );
+    } else {
+      done(shortened(result));
+    }
+    return result;
+  };
+}
+/** Milliseconds spent in each plugin's runners outside of requests, such as `onLoad`, since they were last logged. */
+const startupTimes = new Map<string, number>();
+/** Where the time a runner takes now counts: the request it runs for, or `startupTimes` outside of one. */
+function pluginTimes(): Map<string, number> {
+  return requestContext.getStore()?.pluginTimes ?? startupTimes;
+}
+/** Wraps a runner so the time it takes is added to its plugin's total, for `plugin_options.timing`. */
+export function timed<A extends Array<unknown>, R>(
+  plugin: string,
+  run: (...args: A) => R,
+): (...args: A) => R {
+  return (...args: A) => {
+    const times = pluginTimes();
+    const started = performance.now();
+    const done = () =>
+      times.set(plugin, (times.get(plugin) ?? 0) + performance.now() - started);
+    let result: R;
+    try {
+      result = run(...args);
+    } catch (e) {
+      done();
+      throw e;
+    }
+    if (result instanceof Promise) {
+      result.then(done, done);
+    } else {
+      done();
+    }
+    return result;
+  };
+}
+/**
+ * Logs the time each plugin's runners took for the current request on one debug line, or outside of
+ * a request what they took since the last call, and starts over.
+ */
+export function logPluginTimes() {
+  const times = pluginTimes();
+  if (times.size === 0) {
+    return;
+  }
+  const line = Array.from(times)
+    .map(([plugin, ms]) => `${plugin} ${ms.toFixed(1)}ms`)
+    .join(", ");
+  times.clear();
+  terminalOut.debug(`Plugin time: ${line}`);
+}
 export interface PluginBase {
   modifyResponseHTML: Array<
     (
@@ -32,6 +199,23 @@ export interface PluginBase {
   modifyRequest: Array<(req: WebRequest, Cynthia: CynthiaApiPoints) => void>;
   onLoad: Array<(Cynthia: CynthiaApiPoints) => void>;
   onClearInterval: Array<(Cynthia: CynthiaApiPoints) => void>;
+  onStart: Array<{
+    plugin: string;
+    directory: string;
+    run: (Cynthia: CynthiaApiPoints) => void | Promise<void>;
+  }>;
+  shortcodes: Record<
+    string,
+    (shortcode: Shortcode, Cynthia: CynthiaApiPoints) => string
+  >;
+  routes: Array<{
+    plugin: string;
+    prefix: string;
+    handle: (
+      req: IncomingRoute,
+      Cynthia: CynthiaApiPoints,
+    ) => RouteAnswer | Promise<RouteAnswer>;
+  }>;
 }
 export const newPluginBase: PluginBase = {
   modifyResponseHTML: [
@@ -67,6 +251,9 @@ export const newPluginBase: PluginBase = {
       // Do nothing.
     },
   ],
+  onStart: [],
+  shortcodes: {},
+  routes: [],
 };
 export interface PluginPackageJson {
   scripts: {